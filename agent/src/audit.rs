@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use axum::http::StatusCode;
+use port_sentinel_shared::AuditRecord;
+use crate::auth::Caller;
+
+/// Append-only JSON-lines file of the changes made through this agent (`audit_log` in the config).
+/// The file is opened for each record, so logrotate can move it away without `copytruncate`:
+/// the next record starts a new file.
+pub struct AuditLog {
+    path: String,
+    /// Keeps records from different requests from interleaving
+    write: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: &str) -> Self {
+        AuditLog { path: path.to_string(), write: Mutex::new(()) }
+    }
+
+    /// Appends the outcome of `action` on `target`. A failing write is reported on stdout but
+    /// doesn't undo or fail the action, which has already happened.
+    pub fn record<T>(
+        &self,
+        caller: &Caller,
+        action: &str,
+        target: &str,
+        params: &[(&str, String)],
+        result: &Result<T, (StatusCode, String)>,
+    ) where
+        T: Describe,
+    {
+        let (outcome, message) = match result {
+            Ok(answer) => ("ok", answer.describe()),
+            Err((StatusCode::FORBIDDEN, reason)) => ("denied", reason.clone()),
+            Err((_, e)) => ("failed", e.clone()),
+        };
+        let record = AuditRecord {
+            ts: chrono::Utc::now().timestamp(),
+            master: caller.master.clone(),
+            user: caller.user.clone(),
+            action: action.to_string(),
+            target: target.to_string(),
+            params: params.iter().map(|(k, v)| (k.to_string(), v.clone())).collect::<BTreeMap<_, _>>(),
+            outcome: outcome.to_string(),
+            message,
+        };
+        if let Err(e) = self.append(&record) {
+            println!("⚠️ Could not write audit log '{}': {}", self.path, e);
+        }
+    }
+
+    fn append(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let _guard = self.write.lock().unwrap();
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+        // A record cut short by a crash must not swallow this one too
+        if file.metadata()?.len() > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                line.insert(0, '\n');
+            }
+        }
+        // One write per record, so a reader never sees half a line
+        file.write_all(line.as_bytes())
+    }
+
+    /// The last `limit` records, oldest first, optionally only those from `since` on.
+    /// Only the current file is read, rotated ones are left to the admin.
+    pub fn tail(&self, limit: usize, since: Option<i64>) -> Result<Vec<AuditRecord>, String> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Cannot read audit log '{}': {}", self.path, e)),
+        };
+        let mut records = VecDeque::with_capacity(limit);
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            // A line cut short by a crash is skipped rather than failing the whole log
+            let Ok(record) = serde_json::from_str::<AuditRecord>(&line) else { continue };
+            if since.is_some_and(|since| record.ts < since) {
+                continue;
+            }
+            if records.len() == limit {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+        Ok(records.into())
+    }
+}
+
+/// What a successful action answered, as the audit record's message.
+pub trait Describe {
+    fn describe(&self) -> String;
+}
+
+impl Describe for axum::Json<String> {
+    fn describe(&self) -> String {
+        self.0.clone()
+    }
+}
+
+impl Describe for axum::Json<port_sentinel_shared::PruneReport> {
+    fn describe(&self) -> String {
+        let report = &self.0;
+        let mut message = format!("{} removed, {} bytes reclaimed", report.items.len(), report.reclaimable_bytes);
+        if !report.errors.is_empty() {
+            message += &format!(", errors: {}", report.errors.join("; "));
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audit_log(name: &str) -> AuditLog {
+        let path = std::env::temp_dir().join(format!("ps-audit-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        AuditLog::new(path.to_str().unwrap())
+    }
+
+    fn caller() -> Caller {
+        Caller { master: "master-1".to_string(), user: Some("alice".to_string()) }
+    }
+
+    #[test]
+    fn records_the_outcome_of_each_action() {
+        let log = audit_log("outcome");
+        log.record(&caller(), "kill", "4242", &[], &Ok(axum::Json("Signal Sent".to_string())));
+        log.record::<axum::Json<String>>(&caller(), "kill", "1", &[], &Err((StatusCode::FORBIDDEN, "kill is disabled".to_string())));
+        log.record::<axum::Json<String>>(&caller(), "kill", "4194305", &[("signal", "9".to_string())],
+            &Err((StatusCode::INTERNAL_SERVER_ERROR, "No such process".to_string())));
+
+        let records = log.tail(10, None).unwrap();
+        std::fs::remove_file(&log.path).unwrap();
+        let outcomes: Vec<(&str, &str, &str)> = records.iter()
+            .map(|r| (r.target.as_str(), r.outcome.as_str(), r.message.as_str()))
+            .collect();
+        assert_eq!(outcomes, [
+            ("4242", "ok", "Signal Sent"),
+            ("1", "denied", "kill is disabled"),
+            ("4194305", "failed", "No such process"),
+        ]);
+        assert_eq!(records[0].master, "master-1");
+        assert_eq!(records[0].user.as_deref(), Some("alice"));
+        assert_eq!(records[2].params.get("signal").map(String::as_str), Some("9"));
+    }
+
+    #[test]
+    fn tail_keeps_the_newest_and_skips_broken_lines() {
+        let log = audit_log("tail");
+        assert!(log.tail(10, None).unwrap().is_empty());
+
+        for pid in ["1", "2", "3"] {
+            log.record(&caller(), "kill", pid, &[], &Ok(axum::Json(String::new())));
+        }
+        // A record cut short by a crash
+        OpenOptions::new().append(true).open(&log.path).unwrap().write_all(b"{\"ts\": 1, \"mas").unwrap();
+        log.record(&caller(), "kill", "4", &[], &Ok(axum::Json(String::new())));
+
+        let targets = |records: Vec<AuditRecord>| records.into_iter().map(|r| r.target).collect::<Vec<_>>();
+        assert_eq!(targets(log.tail(2, None).unwrap()), ["3", "4"]);
+        assert_eq!(targets(log.tail(10, None).unwrap()), ["1", "2", "3", "4"]);
+        assert!(log.tail(10, Some(chrono::Utc::now().timestamp() + 60)).unwrap().is_empty());
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[test]
+    fn prune_reports_are_summarized() {
+        let report = port_sentinel_shared::PruneReport {
+            kind: "images".to_string(),
+            dry_run: false,
+            items: vec![],
+            reclaimable_bytes: 0,
+            errors: vec!["1111: in use".to_string()],
+        };
+        assert_eq!(axum::Json(report).describe(), "0 removed, 0 bytes reclaimed, errors: 1111: in use");
+    }
+}
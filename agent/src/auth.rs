@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::http::request::Parts;
+use port_sentinel_shared::signing;
+use crate::config::AgentConfig;
+
+/// Nonces of the signed requests accepted within the clock-skew window. Older ones don't need
+/// remembering, their timestamp alone gets them rejected.
+pub struct NonceCache {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl NonceCache {
+    pub fn new() -> Self {
+        NonceCache { seen: Mutex::new(HashMap::new()) }
+    }
+
+    /// Records `nonce`; false if it was already used.
+    fn insert(&self, nonce: &str, timestamp: i64, now: i64, max_skew: i64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, ts| *ts >= now - max_skew);
+        if seen.contains_key(nonce) {
+            return false;
+        }
+        seen.insert(nonce.to_string(), timestamp);
+        true
+    }
+}
+
+/// Who a request came from, for the audit log.
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub master: String,
+    pub user: Option<String>,
+}
+
+/// Checks that a request was signed by the master with this agent's credential, recently and
+/// only once. Plain `Authorization` tokens are accepted only with `allow_unsigned_requests`.
+pub fn verify(config: &AgentConfig, nonces: &NonceCache, parts: &Parts, body: &[u8]) -> Result<Caller, &'static str> {
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    // Anyone can sign with an empty key
+    if config.auth_token.is_empty() {
+        return Err("This agent has no credential, enroll it");
+    }
+
+    let Some(signature) = header(signing::HEADER_SIGNATURE) else {
+        return match header("Authorization") {
+            // Nothing vouches for who is behind a bare token
+            Some(token) if config.allow_unsigned_requests && signing::secrets_equal(token, &config.auth_token) => {
+                Ok(Caller { master: "token".to_string(), user: None })
+            }
+            Some(_) if !config.allow_unsigned_requests => Err("Unsigned requests are not accepted"),
+            _ => Err("Unauthorized"),
+        };
+    };
+    let timestamp = header(signing::HEADER_TIMESTAMP).and_then(|t| t.parse::<i64>().ok()).ok_or("Missing request timestamp")?;
+    let nonce = header(signing::HEADER_NONCE).filter(|n| !n.is_empty()).ok_or("Missing request nonce")?;
+    let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let master = header(signing::HEADER_MASTER).unwrap_or_default();
+    let user = header(signing::HEADER_USER).unwrap_or_default();
+
+    let signed = signing::RequestParts { method: parts.method.as_str(), path, body, timestamp, nonce, master, user };
+    if !signing::verify(&config.auth_token, &signed, signature) {
+        return Err("Invalid request signature");
+    }
+    let now = chrono::Utc::now().timestamp();
+    let max_skew = config.max_clock_skew_secs as i64;
+    if (now - timestamp).abs() > max_skew {
+        println!("🚫 Rejected {} {}: timestamp {}s off, check the clocks", parts.method, path, now - timestamp);
+        return Err("Request timestamp is outside the allowed clock skew");
+    }
+    if !nonces.insert(nonce, timestamp, now, max_skew) {
+        println!("🚫 Rejected replayed request {} {}", parts.method, path);
+        return Err("Request was already used");
+    }
+    Ok(Caller {
+        master: if master.is_empty() { "master".to_string() } else { master.to_string() },
+        user: Some(user.to_string()).filter(|u| !u.is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    const KEY: &str = "9e4c2d7a1f";
+
+    fn config() -> AgentConfig {
+        let mut config = AgentConfig::load("/nonexistent/port_sentinel/config.json");
+        config.auth_token = KEY.to_string();
+        config
+    }
+
+    /// Request parts as the master sends them, signed with `key` at `timestamp`.
+    fn signed(key: &str, path: &str, body: &[u8], timestamp: i64, nonce: &str) -> Parts {
+        let parts = signing::RequestParts { method: "POST", path, body, timestamp, nonce, master: "master-1", user: "alice" };
+        Request::builder()
+            .method("POST")
+            .uri(path)
+            .header(signing::HEADER_TIMESTAMP, timestamp)
+            .header(signing::HEADER_NONCE, nonce)
+            .header(signing::HEADER_MASTER, "master-1")
+            .header(signing::HEADER_USER, "alice")
+            .header(signing::HEADER_SIGNATURE, signing::sign(key, &parts))
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    #[test]
+    fn signed_requests_name_the_caller() {
+        let parts = signed(KEY, "/api/v1/containers/web/stop?t=5", b"", now(), "n-1");
+        let caller = verify(&config(), &NonceCache::new(), &parts, b"").unwrap();
+        assert_eq!(caller.master, "master-1");
+        assert_eq!(caller.user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn signature_mismatches_are_refused() {
+        let (config, nonces) = (config(), NonceCache::new());
+        let parts = signed("another-key", "/api/v1/audit", b"", now(), "n-1");
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Invalid request signature");
+
+        // Signed for another body
+        let parts = signed(KEY, "/api/v1/audit", b"{}", now(), "n-2");
+        assert_eq!(verify(&config, &nonces, &parts, b"{\"all\":true}").unwrap_err(), "Invalid request signature");
+
+        // Signed for one user, sent for another
+        let mut parts = signed(KEY, "/api/v1/audit", b"", now(), "n-3");
+        parts.headers.insert(signing::HEADER_USER, "mallory".parse().unwrap());
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Invalid request signature");
+
+        let mut parts = signed(KEY, "/api/v1/audit", b"", now(), "n-4");
+        parts.headers.insert(signing::HEADER_SIGNATURE, "not-hex".parse().unwrap());
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Invalid request signature");
+    }
+
+    #[test]
+    fn timestamps_outside_the_skew_are_refused() {
+        let (config, nonces) = (config(), NonceCache::new());
+        let skew = config.max_clock_skew_secs as i64;
+        for timestamp in [now() - skew - 10, now() + skew + 10] {
+            let parts = signed(KEY, "/api/v1/audit", b"", timestamp, &timestamp.to_string());
+            assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Request timestamp is outside the allowed clock skew");
+        }
+        let parts = signed(KEY, "/api/v1/audit", b"", now() - skew + 10, "n-1");
+        assert!(verify(&config, &nonces, &parts, b"").is_ok());
+
+        let mut parts = signed(KEY, "/api/v1/audit", b"", now(), "n-2");
+        parts.headers.remove(signing::HEADER_TIMESTAMP);
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Missing request timestamp");
+    }
+
+    #[test]
+    fn replayed_nonces_are_refused() {
+        let (config, nonces) = (config(), NonceCache::new());
+        let parts = signed(KEY, "/api/v1/audit", b"", now(), "n-1");
+        assert!(verify(&config, &nonces, &parts, b"").is_ok());
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Request was already used");
+
+        // Even re-signed at another time
+        let parts = signed(KEY, "/api/v1/audit", b"", now() - 1, "n-1");
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Request was already used");
+
+        let mut parts = signed(KEY, "/api/v1/audit", b"", now(), "");
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Missing request nonce");
+        parts.headers.remove(signing::HEADER_NONCE);
+        assert_eq!(verify(&config, &nonces, &parts, b"").unwrap_err(), "Missing request nonce");
+    }
+
+    #[test]
+    fn nonces_are_forgotten_once_their_timestamp_is_refused_anyway() {
+        let nonces = NonceCache::new();
+        assert!(nonces.insert("n-1", 1_000, 1_000, 300));
+        assert!(!nonces.insert("n-1", 1_100, 1_100, 300));
+        assert!(nonces.insert("n-2", 1_400, 1_400, 300));
+        assert!(nonces.insert("n-1", 1_401, 1_401, 300));
+    }
+
+    #[test]
+    fn bare_tokens_need_allow_unsigned_requests() {
+        let mut config = config();
+        let nonces = NonceCache::new();
+        let bare = |token: &str| Request::builder().uri("/api/v1/audit").header("Authorization", token).body(()).unwrap().into_parts().0;
+        assert_eq!(verify(&config, &nonces, &bare(KEY), b"").unwrap_err(), "Unsigned requests are not accepted");
+
+        config.allow_unsigned_requests = true;
+        assert_eq!(verify(&config, &nonces, &bare(KEY), b"").unwrap().master, "token");
+        assert_eq!(verify(&config, &nonces, &bare("9e4c2d7a1"), b"").unwrap_err(), "Unauthorized");
+    }
+
+    #[test]
+    fn an_agent_without_credential_refuses_everything() {
+        let mut config = config();
+        config.auth_token.clear();
+        config.allow_unsigned_requests = true;
+        let parts = signed("", "/api/v1/audit", b"", now(), "n-1");
+        assert!(verify(&config, &NonceCache::new(), &parts, b"").is_err());
+        let bare = Request::builder().uri("/api/v1/audit").header("Authorization", "").body(()).unwrap().into_parts().0;
+        assert!(verify(&config, &NonceCache::new(), &bare, b"").is_err());
+    }
+}
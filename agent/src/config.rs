@@ -6,17 +6,111 @@ use std::path::Path;
 pub struct AgentConfig {
     pub port: u16,
     pub hostname: String,
+    /// Node credential from enrollment; empty until then, which refuses every request
+    #[serde(default)]
     pub auth_token: String,
+    /// Path to the Docker Engine API unix socket
+    #[serde(default = "default_docker_socket")]
+    pub docker_socket: String,
+    /// Regexes (case-insensitive) for env keys whose values are masked in container inspect
+    #[serde(default = "default_redact_env_patterns")]
+    pub redact_env_patterns: Vec<String>,
+    /// "auto" (detect Docker, then Podman), "docker" or "podman"
+    #[serde(default = "default_container_runtime")]
+    pub container_runtime: String,
+    /// Master to dial out to (push mode), e.g. "https://master.example.com"
+    #[serde(default)]
+    pub master_url: Option<String>,
+    /// Allow an `http://` `master_url`, over which the node credential and all commands travel in cleartext
+    #[serde(default)]
+    pub allow_insecure_master: bool,
+    /// Name the master knows this agent by in push mode (node URL `push://<node_id>`), defaults to `hostname`
+    #[serde(default)]
+    pub node_id: Option<String>,
+    /// Seconds between stats pushed to the master
+    #[serde(default = "default_push_interval")]
+    pub push_interval: u64,
+    /// Serve the HTTP API on `port`. Push-only agents behind NAT can turn this off.
+    #[serde(default = "default_listen")]
+    pub listen: bool,
+    /// PEM certificate and key to serve the API over HTTPS (written by `enroll`)
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    #[serde(default)]
+    pub tls_key: Option<String>,
+    /// PEM CA that callers' client certificates must chain to; unset accepts any caller
+    #[serde(default)]
+    pub tls_client_ca: Option<String>,
+    /// Also accept a bare `auth_token` in `Authorization` (scripts, masters older than request signing)
+    #[serde(default)]
+    pub allow_unsigned_requests: bool,
+    /// How far the timestamp of a signed request may be from this agent's clock
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: u64,
+    /// Which actions the master may trigger here
+    #[serde(default)]
+    pub actions: crate::policy::ActionPolicy,
+    /// JSON-lines file every kill, service and Docker action is appended to
+    #[serde(default = "default_audit_log")]
+    pub audit_log: String,
+}
+
+fn default_docker_socket() -> String {
+    crate::system::docker::DEFAULT_DOCKER_SOCKET.to_string()
+}
+
+fn default_container_runtime() -> String {
+    "auto".to_string()
+}
+
+fn default_push_interval() -> u64 {
+    5
+}
+
+fn default_max_clock_skew_secs() -> u64 {
+    300
+}
+
+fn default_audit_log() -> String {
+    "audit.jsonl".to_string()
+}
+
+fn default_listen() -> bool {
+    true
+}
+
+fn default_redact_env_patterns() -> Vec<String> {
+    crate::system::docker::DEFAULT_REDACT_PATTERNS.iter().map(|p| p.to_string()).collect()
 }
 
 impl AgentConfig {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| format!("Could not write '{}': {}", path, e))
+    }
+
     // Now accepts a path argument
     pub fn load(path: &str) -> Self {
         // Default values
         let default_config = AgentConfig {
             port: 3001,
             hostname: "localhost".to_string(),
-            auth_token: "change_me_please".to_string(),
+            auth_token: String::new(),
+            docker_socket: default_docker_socket(),
+            redact_env_patterns: default_redact_env_patterns(),
+            container_runtime: default_container_runtime(),
+            master_url: None,
+            allow_insecure_master: false,
+            node_id: None,
+            push_interval: default_push_interval(),
+            listen: default_listen(),
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            allow_unsigned_requests: false,
+            max_clock_skew_secs: default_max_clock_skew_secs(),
+            actions: Default::default(),
+            audit_log: default_audit_log(),
         };
 
         if Path::new(path).exists() {
@@ -0,0 +1,198 @@
+use port_sentinel_shared::{EnrollRequest, EnrollResponse, join_token_ca_pin};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use crate::config::AgentConfig;
+
+/// Node URL scheme the master uses for push-mode agents.
+const PUSH_SCHEME: &str = "push://";
+
+/// `port_sentinel_agent enroll`: trades a one-time join token from the master for this node's
+/// own credential and writes it to the config file. With `tls`, also has the master's CA sign a
+/// fresh TLS key, stored next to the config file. Returns the node URL the master uses.
+/// The CA the master sends back is only trusted over https or when it matches the join token's pin.
+pub async fn run(config_path: &str, master_url: &str, join_token: &str, tls: bool) -> Result<String, String> {
+    let mut config = AgentConfig::load(config_path);
+    let master_url = master_url.trim_end_matches('/');
+    let join_token = join_token.trim();
+    let ca_pin = join_token_ca_pin(join_token);
+    if tls && ca_pin.is_none() && !master_url.starts_with("https://") {
+        return Err("Over http:// the master's CA can't be told from a forged one: use an https:// master URL, \
+            a join token from this master version (it carries the CA fingerprint) or --no-tls".to_string());
+    }
+
+    let tls_key = if tls { Some(crate::tls::generate_csr(&config.hostname)?) } else { None };
+    let req = EnrollRequest {
+        join_token: join_token.to_string(),
+        hostname: config.hostname.clone(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        csr: tls_key.as_ref().map(|(csr, _)| csr.clone()),
+    };
+    let resp = reqwest::Client::new()
+        .post(format!("{}/api/agent/enroll", master_url))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|e| format!("Cannot reach master at {}: {}", master_url, e))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let reason = resp.text().await.unwrap_or_default();
+        return Err(format!("Master refused enrollment ({}): {}", status, reason));
+    }
+    let enrolled: EnrollResponse = resp.json().await.map_err(|e| format!("Invalid answer from master: {}", e))?;
+    if let (Some(ca_pem), Some(pin)) = (&enrolled.ca_certificate, ca_pin) {
+        check_ca_pin(ca_pem, pin)?;
+    }
+
+    config.auth_token = enrolled.credential;
+    // Push-mode nodes dial the master themselves, point them at the one that enrolled them
+    if let Some(node_id) = enrolled.node_url.strip_prefix(PUSH_SCHEME) {
+        config.master_url = Some(master_url.to_string());
+        config.node_id = Some(node_id.to_string());
+        if master_url.starts_with("http://") {
+            println!("⚠️ Push mode won't connect to an http:// master unless \"allow_insecure_master\" is set in the config");
+        }
+    }
+    if let (Some((_, key_pem)), Some(cert_pem), Some(ca_pem)) = (tls_key, &enrolled.certificate, &enrolled.ca_certificate) {
+        let dir = Path::new(config_path).parent().unwrap_or(Path::new("."));
+        let (cert, key, ca) = (dir.join("agent.pem"), dir.join("agent-key.pem"), dir.join("ca.pem"));
+        write_file(&key, &key_pem, 0o600)?;
+        write_file(&cert, cert_pem, 0o644)?;
+        write_file(&ca, ca_pem, 0o644)?;
+        // Only the master holding a certificate from this CA may call the agent from now on
+        config.tls_cert = Some(cert.display().to_string());
+        config.tls_key = Some(key.display().to_string());
+        config.tls_client_ca = Some(ca.display().to_string());
+    }
+    config.save(config_path)?;
+
+    Ok(enrolled.node_url)
+}
+
+/// The CA must be the one the join token was issued with, or someone between here and the
+/// master could have the agent trust their own CA instead.
+fn check_ca_pin(ca_pem: &str, pin: &str) -> Result<(), String> {
+    let certs = rustls_pemfile::certs(&mut ca_pem.as_bytes()).map_err(|e| format!("Invalid CA from master: {}", e))?;
+    let fingerprint = certs.first().map(|der| hex::encode(Sha256::digest(der))).ok_or("Master sent no CA certificate")?;
+    if !fingerprint.eq_ignore_ascii_case(pin) {
+        return Err(format!(
+            "The CA received ({}) is not the one in the join token ({}), the connection to the master may be intercepted. Nothing was saved.",
+            fingerprint, pin
+        ));
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, content: &str, mode: u32) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+    file.write_all(content.as_bytes()).map_err(|e| format!("Could not write '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use axum::{routing::post, Json, Router};
+
+    /// A self-signed CA certificate and its SHA-256.
+    fn ca() -> (String, String) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["ca".to_string()]).unwrap().self_signed(&key).unwrap();
+        (cert.pem(), hex::encode(Sha256::digest(cert.der())))
+    }
+
+    /// Answers every enrollment with `ca_pem` as the CA; returns its URL and the requests it got.
+    async fn fake_master(ca_pem: String) -> (String, Arc<Mutex<Vec<EnrollRequest>>>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let requests = seen.clone();
+        let app = Router::new().route("/api/agent/enroll", post(move |Json(req): Json<EnrollRequest>| {
+            let ca_pem = ca_pem.clone();
+            let tls = req.csr.is_some();
+            requests.lock().unwrap().push(req);
+            async move {
+                Json(EnrollResponse {
+                    node_id: "web1".to_string(),
+                    node_url: format!("{}://10.0.0.5:3001", if tls { "https" } else { "http" }),
+                    credential: "issued-credential".to_string(),
+                    certificate: tls.then(|| ca_pem.clone()),
+                    ca_certificate: tls.then_some(ca_pem),
+                })
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, seen)
+    }
+
+    fn config_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("ps-enroll-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json").display().to_string();
+        AgentConfig::load(&path).save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn join_token_pins() {
+        let pin = "ab".repeat(32);
+        assert_eq!(join_token_ca_pin(&format!("psj_0123.{}", pin)), Some(pin.as_str()));
+        assert_eq!(join_token_ca_pin("psj_0123"), None);
+        assert_eq!(join_token_ca_pin("psj_0123.abcd"), None);
+        assert_eq!(join_token_ca_pin(&format!("psj_0123.{}", "zz".repeat(32))), None);
+    }
+
+    #[tokio::test]
+    async fn pinned_ca_is_installed_over_http() {
+        let (ca_pem, pin) = ca();
+        let (master, _) = fake_master(ca_pem.clone()).await;
+        let path = config_path("pinned");
+
+        let node_url = run(&path, &master, &format!("psj_0123.{}", pin.to_uppercase()), true).await.unwrap();
+        assert_eq!(node_url, "https://10.0.0.5:3001");
+        let config = AgentConfig::load(&path);
+        assert_eq!(config.auth_token, "issued-credential");
+        let ca_path = config.tls_client_ca.unwrap();
+        assert_eq!(std::fs::read_to_string(ca_path).unwrap(), ca_pem);
+    }
+
+    #[tokio::test]
+    async fn swapped_ca_is_refused_and_nothing_saved() {
+        let (_, pin) = ca();
+        let (forged_pem, _) = ca();
+        let (master, _) = fake_master(forged_pem).await;
+        let path = config_path("swapped");
+
+        let err = run(&path, &master, &format!("psj_0123.{}", pin), true).await.unwrap_err();
+        assert!(err.contains("not the one in the join token"), "{}", err);
+        let config = AgentConfig::load(&path);
+        assert!(config.auth_token.is_empty());
+        assert_eq!(config.tls_client_ca, None);
+    }
+
+    #[tokio::test]
+    async fn unpinned_tls_enrollment_needs_https() {
+        let (ca_pem, _) = ca();
+        let (master, seen) = fake_master(ca_pem).await;
+        let path = config_path("unpinned");
+
+        let err = run(&path, &master, "psj_0123", true).await.unwrap_err();
+        assert!(err.contains("https://"), "{}", err);
+        assert!(seen.lock().unwrap().is_empty());
+
+        // Without TLS there is no CA to trust
+        assert_eq!(run(&path, &master, "psj_0123", false).await.unwrap(), "http://10.0.0.5:3001");
+        assert_eq!(AgentConfig::load(&path).auth_token, "issued-credential");
+        assert_eq!(seen.lock().unwrap()[0].join_token, "psj_0123");
+    }
+}
@@ -2,27 +2,43 @@ use axum::{
     routing::{get, post},
     Router,
     Json,
-    extract::{Path, Query, Request},
+    extract::{Extension, Path, Query, Request, State},
     middleware::{self, Next},
-    response::Response,
-    http::StatusCode,
+    response::{IntoResponse, Response},
+    http::{header, StatusCode},
+    body::Body,
 };
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use std::net::SocketAddr;
-use port_sentinel_shared::{SystemStats, ProcessInfo};
+use port_sentinel_shared::{
+    SystemStats, ProcessInfo, ContainerInfo, ContainerStats, ContainerDetails, ImageInfo, VolumeInfo, NetworkInfo, PruneReport,
+    ContainerEventBatch, ScheduledJob, RuntimeInfo, AgentInfo, AuditRecord, API_VERSION,
+};
 use tower_http::cors::CorsLayer;
 use serde::Deserialize;
 use std::sync::Arc;
-use clap::Parser; // Import Clap
+use std::sync::atomic::{AtomicBool, Ordering};
+use clap::{Parser, Subcommand}; // Import Clap
 
 mod system;
 mod config; 
+mod push;
+mod enroll;
+mod tls;
+mod auth;
+mod policy;
+mod audit;
 
 use crate::system::monitor::get_system_stats;
-use crate::system::process::{scan_ports, kill_process};
+use crate::system::process::{scan_ports, kill_process, valid_pid};
 use crate::system::logs::{find_process_logs, tail_log_file};
 use crate::system::services::{get_service_status, start_service, stop_service, restart_service};
-use crate::system::docker::{list_containers, get_container_logs, control_container, ContainerInfo};
+use crate::system::docker::{DockerError, LogOptions};
+use crate::system::runtime::{self as container_runtime, ContainerRuntime, EventBuffer};
+use crate::system::schedules::{list_timers, list_cron_jobs};
 use crate::config::AgentConfig;
+use crate::policy::Action;
+use crate::auth::Caller;
 
 // === CLI ARGUMENTS ===
 #[derive(Parser, Debug)]
@@ -31,11 +47,38 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long, default_value = "config.json")]
     config: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Exchange a one-time join token from the master for this node's credential
+    Enroll {
+        /// Master dashboard URL, e.g. http://192.168.1.10:7878
+        #[arg(long)]
+        master: String,
+        /// Join token created on the master (🔑 next to the node)
+        #[arg(long)]
+        token: String,
+        /// Keep serving plain HTTP instead of requesting a TLS certificate from the master
+        #[arg(long)]
+        no_tls: bool,
+    },
 }
 
 // Share config across threads
 struct AppState {
     config: AgentConfig,
+    /// Where `config` was loaded from, rewritten when the master revokes the credential
+    config_path: String,
+    /// Set once the master revoked the credential, which no request passes with from then on
+    revoked: AtomicBool,
+    docker: Box<dyn ContainerRuntime>,
+    events: EventBuffer,
+    nonces: auth::NonceCache,
+    audit: audit::AuditLog,
 }
 
 #[tokio::main]
@@ -43,6 +86,17 @@ async fn main() {
     // 1. Parse Command Line Arguments
     let args = Args::parse();
 
+    if let Some(Command::Enroll { master, token, no_tls }) = &args.command {
+        match enroll::run(&args.config, master, token, !no_tls).await {
+            Ok(node_url) => println!("✅ Enrolled as {}. Credential saved to '{}', restart the agent to use it.", node_url, args.config),
+            Err(e) => {
+                println!("❌ Enrollment failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // 2. Load Configuration using the path provided (or default)
     let config = AgentConfig::load(&args.config);
     let port = config.port;
@@ -50,55 +104,152 @@ async fn main() {
     println!("🔐 Agent Config Loaded from: '{}'", args.config);
     println!("   - Hostname: {}", config.hostname);
     println!("   - Port: {}", port);
+    println!("   - Docker Socket: {}", config.docker_socket);
+    if let Some(master) = &config.master_url {
+        println!("   - Push to Master: {}", master);
+    }
     // println!("   - Auth Token: {}", config.auth_token); 
+    if config.auth_token.is_empty() {
+        println!("⚠️  No credential yet: every request is refused until this agent is enrolled");
+    }
 
-    let shared_state = Arc::new(AppState { config });
+    let docker = container_runtime::detect(&config).await;
+    let runtime = docker.info();
+    println!(
+        "   - Container Runtime: {} {} ({} via {})",
+        runtime.runtime, runtime.version.as_deref().unwrap_or("unavailable"), runtime.mode, runtime.endpoint
+    );
+    println!("   - Audit Log: {}", config.audit_log);
+    let audit = audit::AuditLog::new(&config.audit_log);
+    let shared_state = Arc::new(AppState {
+        config,
+        config_path: args.config.clone(),
+        revoked: AtomicBool::new(false),
+        docker,
+        events: EventBuffer::new(),
+        nonces: auth::NonceCache::new(),
+        audit,
+    });
 
-    let cors = CorsLayer::permissive();
+    // Follow container events for the lifetime of the agent, reconnecting when the runtime goes away
+    let events_state = shared_state.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = events_state.docker.watch_events(&events_state.events).await {
+                println!("⚠️ Container events stream: {}", e);
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        }
+    });
 
-    let app = Router::new()
-        .route("/api/stats", get(stats_api))
-        .route("/api/processes", get(processes_api))
-        .route("/api/kill/:pid", post(kill_api))
-        .route("/api/logs/check/:pid", get(logs_check_api))
-        .route("/api/logs/read", get(logs_read_api))
-        // === Service Control API ===
-        .route("/api/service/status/:name", get(service_status_api))
-        .route("/api/service/start/:name", post(service_start_api))
-        .route("/api/service/stop/:name", post(service_stop_api))
-        .route("/api/service/restart/:name", post(service_restart_api))
-        // === Docker API ===
-        .route("/api/docker/containers", get(docker_list_api))
-        .route("/api/docker/logs/:id", get(docker_logs_api))
-        .route("/api/docker/:action/:id", post(docker_control_api))
-        .layer(cors)
-        .layer(middleware::from_fn_with_state(shared_state.clone(), auth_middleware))
-        .with_state(shared_state);
+    let app = router(shared_state.clone());
+
+    // Push mode: dial out to the master, the HTTP listener becomes optional
+    let push_enabled = shared_state.config.master_url.is_some();
+    if push_enabled {
+        tokio::spawn(push::run(shared_state.clone(), app.clone()));
+    }
+
+    if !shared_state.config.listen {
+        if push_enabled {
+            println!("🕵️ Agent Node Active in push mode only (no HTTP listener)");
+            std::future::pending::<()>().await;
+        }
+        println!("⚠️  'listen' is off but no 'master_url' is set, listening anyway");
+    }
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let tls = match tls::server_config(&shared_state.config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            println!("❌ TLS setup failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some((tls_config, fingerprint)) = tls {
+        println!("🕵️ Agent Node Active on https://{}", addr);
+        println!("   - Certificate SHA-256: {}", fingerprint);
+        if shared_state.config.tls_client_ca.is_some() {
+            println!("   - Client certificates required (mutual TLS)");
+        }
+        tls::serve(addr, app, tls_config).await;
+        return;
+    }
+
     println!("🕵️ Agent Node Active on http://{}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
+/// The agent API, under `/api/v1` and the unversioned `/api` aliases, behind the signature check.
+fn router(state: Arc<AppState>) -> Router {
+    let cors = CorsLayer::permissive();
+
+    let api = Router::new()
+        .route("/info", get(info_api))
+        .route("/stats", get(stats_api))
+        .route("/processes", get(processes_api))
+        .route("/kill/:pid", post(kill_api))
+        .route("/logs/check/:pid", get(logs_check_api))
+        .route("/logs/read", get(logs_read_api))
+        // === Service Control API ===
+        .route("/service/status/:name", get(service_status_api))
+        .route("/service/start/:name", post(service_start_api))
+        .route("/service/stop/:name", post(service_stop_api))
+        .route("/service/restart/:name", post(service_restart_api))
+        .route("/capabilities", get(capabilities_api))
+        .route("/audit", get(audit_api))
+        .route("/credential/revoke", post(credential_revoke_api))
+        // === Docker API ===
+        .route("/docker/containers", get(docker_list_api))
+        .route("/docker/stats", get(docker_stats_api))
+        .route("/docker/logs/:id", get(docker_logs_api))
+        .route("/docker/inspect/:id", get(docker_inspect_api))
+        .route("/docker/:action/:id", post(docker_control_api))
+        .route("/docker/images", get(docker_images_api))
+        .route("/docker/images/rm/:id", post(docker_image_rm_api))
+        .route("/docker/volumes", get(docker_volumes_api))
+        .route("/docker/volumes/rm/:name", post(docker_volume_rm_api))
+        .route("/docker/networks", get(docker_networks_api))
+        .route("/docker/prune/:kind", post(docker_prune_api))
+        .route("/docker/compose/:project/:action", post(docker_project_control_api))
+        .route("/docker/compose/:project/recreate/:service", post(docker_recreate_service_api))
+        .route("/docker/events", get(docker_events_api))
+        // === Scheduled Jobs API ===
+        .route("/schedules", get(schedules_api));
+
+    Router::new()
+        .nest("/api/v1", api.clone())
+        // Paths from before versioning, for older masters and scripts
+        .nest("/api", api)
+        .layer(cors)
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
+        .with_state(state)
+}
+
 // === AUTH MIDDLEWARE ===
+/// Largest request body the signature check buffers; the API only takes small JSON bodies.
+const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+
 async fn auth_middleware(
     axum::extract::State(state): axum::extract::State<Arc<AppState>>,
     req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    let auth_header = req.headers().get("Authorization");
-
-    match auth_header {
-        Some(header) if header == &state.config.auth_token => {
-            Ok(next.run(req).await)
-        }
-        _ => {
-            // println!("🚫 Unauthorized access attempt"); 
-            Err(StatusCode::UNAUTHORIZED)
-        }
+) -> Result<Response, (StatusCode, &'static str)> {
+    if state.revoked.load(Ordering::Relaxed) {
+        return Err((StatusCode::UNAUTHORIZED, "This agent's credential was revoked, enroll it again"));
     }
+    // The signature covers the body, so it is read here and handed on afterwards
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES).await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"))?;
+    let caller = auth::verify(&state.config, &state.nonces, &parts, &body)
+        .map_err(|reason| (StatusCode::UNAUTHORIZED, reason))?;
+    let mut req = Request::from_parts(parts, Body::from(body));
+    req.extensions_mut().insert(caller);
+    Ok(next.run(req).await)
 }
 
 // === API HANDLERS ===
@@ -107,28 +258,63 @@ async fn stats_api() -> Json<SystemStats> {
     Json(get_system_stats())
 }
 
-async fn processes_api() -> Json<Vec<ProcessInfo>> {
-    Json(scan_ports())
+async fn processes_api(State(state): State<Arc<AppState>>) -> Json<Vec<ProcessInfo>> {
+    let mut procs = scan_ports();
+    // Best effort: without Docker the table just shows the raw processes
+    let _ = state.docker.attach_containers(&mut procs).await;
+    Json(procs)
 }
 
-async fn kill_api(Path(pid): Path<String>) -> Json<String> {
-    let dummy_line = format!("fake_name {}", pid);
-    kill_process(&dummy_line);
-    Json("Signal Sent".to_string())
+async fn kill_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(pid): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = match state.config.actions.check(Action::Kill) {
+        Ok(()) if !valid_pid(&pid) => Err((StatusCode::BAD_REQUEST, format!("Invalid PID '{}'", pid))),
+        Ok(()) => {
+            let dummy_line = format!("fake_name {}", pid);
+            kill_process(&dummy_line)
+                .map(|()| Json("Signal Sent".to_string()))
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+        }
+        Err(denied) => Err(denied.into()),
+    };
+    state.audit.record(&caller, "kill", &pid, &[], &result);
+    result
 }
 
-async fn logs_check_api(Path(pid): Path<String>) -> Json<Vec<String>> {
+/// The master revoked this node's credential: it stops working here right away, so a leaked
+/// copy is as useless against this agent as against the master. Enrolling again issues a new one.
+async fn credential_revoke_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    state.revoked.store(true, Ordering::Relaxed);
+    let mut config = state.config.clone();
+    config.auth_token = String::new();
+    let result = config.save(&state.config_path)
+        .map(|()| Json("Credential revoked".to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Revoked until restart, but {}", e)));
+    state.audit.record(&caller, "credential_revoke", &state.config.hostname, &[], &result);
+    println!("⛔ The master revoked this agent's credential, enroll it again to reconnect");
+    result
+}
+
+async fn logs_check_api(State(state): State<Arc<AppState>>, Path(pid): Path<String>) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    state.config.actions.check(Action::LogRead)?;
     let files = find_process_logs(&pid);
-    Json(files)
+    Ok(Json(files))
 }
 
 #[derive(Deserialize)]
 struct LogReadParams { path: String, lines: Option<usize> }
 
-async fn logs_read_api(Query(params): Query<LogReadParams>) -> Json<Vec<String>> {
+async fn logs_read_api(State(state): State<Arc<AppState>>, Query(params): Query<LogReadParams>) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    state.config.actions.check(Action::LogRead)?;
     let count = params.lines.unwrap_or(50);
     let lines = tail_log_file(&params.path, count);
-    Json(lines)
+    Ok(Json(lines))
 }
 
 // === SERVICE HANDLERS ===
@@ -140,43 +326,386 @@ async fn service_status_api(Path(name): Path<String>) -> Result<Json<String>, St
     }
 }
 
-async fn service_start_api(Path(name): Path<String>) -> Result<Json<String>, StatusCode> {
-    match start_service(&name) {
-        Ok(msg) => Ok(Json(msg)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+async fn service_start_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(name): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = match state.config.actions.check_service(&name) {
+        Ok(()) => start_service(&name).map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Err(denied) => Err(denied.into()),
+    };
+    state.audit.record(&caller, "service_start", &name, &[], &result);
+    result
+}
+
+async fn service_stop_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(name): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = match state.config.actions.check_service(&name) {
+        Ok(()) => stop_service(&name).map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Err(denied) => Err(denied.into()),
+    };
+    state.audit.record(&caller, "service_stop", &name, &[], &result);
+    result
+}
+
+async fn service_restart_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(name): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = match state.config.actions.check_service(&name) {
+        Ok(()) => restart_service(&name).map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Err(denied) => Err(denied.into()),
+    };
+    state.audit.record(&caller, "service_restart", &name, &[], &result);
+    result
 }
 
-async fn service_stop_api(Path(name): Path<String>) -> Result<Json<String>, StatusCode> {
-    match stop_service(&name) {
-        Ok(msg) => Ok(Json(msg)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+// === INFO ===
+
+/// Version, host and the collectors and actions available on this node, for the master to
+/// adapt its UI. Docker ones depend on the detected runtime, service ones on systemd; the
+/// action policy decides which of them are denied.
+async fn info_api(State(state): State<Arc<AppState>>) -> Json<AgentInfo> {
+    let runtime = state.docker.info();
+    let systemd = system::services::systemd_available();
+    let docker = runtime.version.is_some();
+    let supports = |feature: &str| docker && runtime.features.iter().any(|f| f == feature);
+
+    let collectors = [
+        ("system", true),
+        ("processes", true),
+        ("logs", true),
+        ("schedules", true),
+        ("services", systemd),
+        ("docker", docker),
+        ("docker_events", supports("events")),
+    ];
+    let actions = [
+        ("kill", true),
+        ("log_read", true),
+        ("service_control", systemd),
+        ("docker_control", supports("control")),
+        ("docker_prune", supports("prune")),
+        ("docker_recreate", supports("recreate")),
+    ];
+    let enabled = |list: &[(&str, bool)]| list.iter().filter(|(_, on)| *on).map(|(name, _)| name.to_string()).collect();
+
+    Json(AgentInfo {
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        api_version: API_VERSION,
+        hostname: sysinfo::System::host_name().unwrap_or_else(|| state.config.hostname.clone()),
+        os: sysinfo::System::long_os_version().unwrap_or_else(|| std::env::consts::OS.to_string()),
+        kernel: sysinfo::System::kernel_version().unwrap_or_default(),
+        collectors: enabled(&collectors),
+        actions: enabled(&actions),
+        denied: state.config.actions.denied(),
+        allowed_services: state.config.actions.allowed_services.clone(),
+        allowed_containers: state.config.actions.allowed_containers.clone(),
+        runtime,
+    })
+}
+
+// === DOCKER HANDLERS ===
+
+/// Which container runtime this agent drives and what it supports.
+async fn capabilities_api(State(state): State<Arc<AppState>>) -> Json<RuntimeInfo> {
+    Json(state.docker.info())
+}
+
+fn docker_error(e: DockerError) -> (StatusCode, String) {
+    (e.status_code(), e.to_string())
+}
+
+/// Applies the action policy to the containers an operation would touch.
+async fn check_containers(state: &AppState, targets: impl Fn(&ContainerInfo) -> bool) -> Result<(), (StatusCode, String)> {
+    state.config.actions.check(Action::DockerControl)?;
+    if state.config.actions.allowed_containers.is_none() {
+        return Ok(());
     }
+    let containers = state.docker.list_containers().await.map_err(docker_error)?;
+    let targets: Vec<&ContainerInfo> = containers.iter().filter(|c| targets(c)).collect();
+    Ok(state.config.actions.check_containers(&targets)?)
 }
 
-async fn service_restart_api(Path(name): Path<String>) -> Result<Json<String>, StatusCode> {
-    match restart_service(&name) {
-        Ok(msg) => Ok(Json(msg)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+async fn docker_list_api(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ContainerInfo>>, (StatusCode, String)> {
+    state.docker.list_containers().await.map(Json).map_err(docker_error)
+}
+
+async fn docker_stats_api(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ContainerStats>>, (StatusCode, String)> {
+    state.docker.list_container_stats().await.map(Json).map_err(docker_error)
+}
+
+#[derive(Deserialize)]
+struct DockerLogParams {
+    tail: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    timestamps: Option<bool>,
+    follow: Option<bool>,
+}
+
+/// Returns the lines as a JSON array, or with `follow=true` keeps the response open and
+/// sends one JSON line per log line as the container writes them.
+async fn docker_logs_api(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<DockerLogParams>,
+) -> Result<Response, (StatusCode, String)> {
+    state.config.actions.check(Action::LogRead)?;
+    let opts = LogOptions {
+        tail: params.tail,
+        since: params.since,
+        until: params.until,
+        timestamps: params.timestamps.unwrap_or(false),
+    };
+
+    if !params.follow.unwrap_or(false) {
+        let lines = state.docker.get_container_logs(&id, &opts).await.map_err(docker_error)?;
+        return Ok(Json(lines).into_response());
     }
+
+    let rx = state.docker.follow_container_logs(&id, &opts).await.map_err(docker_error)?;
+    // Dropping the stream when the client goes away ends the follow on the runtime side too
+    let lines = ReceiverStream::new(rx).map(|line| Ok::<_, std::convert::Infallible>(serde_json::to_string(&line).unwrap_or_default() + "\n"));
+    let body = Body::from_stream(lines);
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response())
 }
 
-// === DOCKER HANDLERS ===
+async fn docker_inspect_api(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ContainerDetails>, (StatusCode, String)> {
+    state.docker.inspect_container(&id).await.map(Json).map_err(docker_error)
+}
 
-async fn docker_list_api() -> Json<Vec<ContainerInfo>> {
-    Json(list_containers())
+async fn docker_control_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((action, id)): Path<(String, String)>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = async {
+        check_containers(&state, |c| c.id.starts_with(&id) || c.names.split(',').any(|n| n == id)).await?;
+        state.docker.control_container(&id, &action).await.map(Json).map_err(docker_error)
+    }.await;
+    state.audit.record(&caller, &format!("docker_{}", action), &id, &[], &result);
+    result
 }
 
-async fn docker_logs_api(Path(id): Path<String>) -> Result<Json<Vec<String>>, StatusCode> {
-    match get_container_logs(&id) {
-        Ok(logs) => Ok(Json(logs)),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
+async fn docker_images_api(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ImageInfo>>, (StatusCode, String)> {
+    state.docker.list_images().await.map(Json).map_err(docker_error)
+}
+
+async fn docker_image_rm_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = async {
+        state.config.actions.check(Action::DockerControl)?;
+        state.docker.remove_image(&id).await.map(Json).map_err(docker_error)
+    }.await;
+    state.audit.record(&caller, "docker_image_rm", &id, &[], &result);
+    result
+}
+
+async fn docker_volumes_api(State(state): State<Arc<AppState>>) -> Result<Json<Vec<VolumeInfo>>, (StatusCode, String)> {
+    state.docker.list_volumes().await.map(Json).map_err(docker_error)
+}
+
+async fn docker_volume_rm_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(name): Path<String>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = async {
+        state.config.actions.check(Action::DockerControl)?;
+        state.docker.remove_volume(&name).await.map(Json).map_err(docker_error)
+    }.await;
+    state.audit.record(&caller, "docker_volume_rm", &name, &[], &result);
+    result
+}
+
+async fn docker_networks_api(State(state): State<Arc<AppState>>) -> Result<Json<Vec<NetworkInfo>>, (StatusCode, String)> {
+    state.docker.list_networks().await.map(Json).map_err(docker_error)
+}
+
+#[derive(Deserialize)]
+struct PruneParams { dry_run: Option<bool> }
+
+async fn docker_prune_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(kind): Path<String>,
+    Query(params): Query<PruneParams>,
+) -> Result<Json<PruneReport>, (StatusCode, String)> {
+    // Default to a preview, deleting must be asked for explicitly
+    let dry_run = params.dry_run.unwrap_or(true);
+    if dry_run {
+        return state.docker.prune(&kind, true).await.map(Json).map_err(docker_error);
     }
+    let result = async {
+        state.config.actions.check(Action::DockerControl)?;
+        // Stopped containers outside the allowlist would go too
+        if kind == "containers" && state.config.actions.allowed_containers.is_some() {
+            return Err((StatusCode::FORBIDDEN, "Pruning containers is disabled while allowed_containers is set".to_string()));
+        }
+        state.docker.prune(&kind, false).await.map(Json).map_err(docker_error)
+    }.await;
+    state.audit.record(&caller, "docker_prune", &kind, &[], &result);
+    result
 }
 
-async fn docker_control_api(Path((action, id)): Path<(String, String)>) -> Result<Json<String>, StatusCode> {
-    match control_container(&id, &action) {
-        Ok(msg) => Ok(Json(msg)),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
+async fn docker_project_control_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((project, action)): Path<(String, String)>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let result = async {
+        check_containers(&state, |c| c.project.as_deref() == Some(project.as_str())).await?;
+        state.docker.control_project(&project, &action).await.map(Json).map_err(docker_error)
+    }.await;
+    state.audit.record(&caller, &format!("compose_{}", action), &project, &[], &result);
+    result
+}
+
+#[derive(Deserialize)]
+struct RecreateParams { pull: Option<bool> }
+
+async fn docker_recreate_service_api(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((project, service)): Path<(String, String)>,
+    Query(params): Query<RecreateParams>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let pull = params.pull.unwrap_or(false);
+    let result = async {
+        check_containers(&state, |c| {
+            c.project.as_deref() == Some(project.as_str()) && c.service.as_deref() == Some(service.as_str())
+        }).await?;
+        state.docker.recreate_service(&project, &service, pull).await.map(Json).map_err(docker_error)
+    }.await;
+    let params = [("service", service.clone()), ("pull", pull.to_string())];
+    state.audit.record(&caller, "compose_recreate", &project, &params, &result);
+    result
+}
+
+#[derive(Deserialize)]
+struct EventParams { since: Option<u64> }
+
+async fn docker_events_api(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EventParams>,
+) -> Json<ContainerEventBatch> {
+    Json(state.events.since(params.since.unwrap_or(0)))
+}
+
+// === AUDIT ===
+
+#[derive(Deserialize)]
+struct AuditParams { lines: Option<usize>, since: Option<i64> }
+
+/// The latest audit records, for the master to pull. `since` (Unix time) skips older ones.
+async fn audit_api(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditRecord>>, (StatusCode, String)> {
+    let limit = params.lines.unwrap_or(200).min(5000);
+    state.audit.tail(limit, params.since).map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+// === SCHEDULE HANDLERS ===
+
+async fn schedules_api() -> Json<Vec<ScheduledJob>> {
+    let mut jobs = list_timers();
+    jobs.extend(list_cron_jobs());
+    Json(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use port_sentinel_shared::signing;
+    use tower::ServiceExt;
+    use crate::system::docker::DockerClient;
+
+    const CREDENTIAL: &str = "3b1f0c5e9d2a";
+
+    /// An agent with `auth_token`, its config and audit log in a fresh temporary directory.
+    fn state(name: &str, auth_token: &str) -> Arc<AppState> {
+        let dir = std::env::temp_dir().join(format!("ps-agent-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.json").display().to_string();
+
+        let mut config = AgentConfig::load(&config_path);
+        config.auth_token = auth_token.to_string();
+        config.audit_log = dir.join("audit.jsonl").display().to_string();
+        config.save(&config_path).unwrap();
+        Arc::new(AppState {
+            audit: audit::AuditLog::new(&config.audit_log),
+            docker: Box::new(DockerClient::new(dir.join("docker.sock"), &[])),
+            config,
+            config_path,
+            revoked: AtomicBool::new(false),
+            events: EventBuffer::new(),
+            nonces: auth::NonceCache::new(),
+        })
+    }
+
+    /// A request signed by the master with `key`, as `AgentClient` sends it.
+    fn signed(method: &str, path: &str, key: &str) -> Request {
+        static NONCE: AtomicU64 = AtomicU64::new(0);
+        let timestamp = chrono::Utc::now().timestamp();
+        let nonce = NONCE.fetch_add(1, Ordering::Relaxed).to_string();
+        let parts = signing::RequestParts { method, path, body: b"", timestamp, nonce: &nonce, master: "master-1", user: "alice" };
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(signing::HEADER_TIMESTAMP, timestamp)
+            .header(signing::HEADER_NONCE, &nonce)
+            .header(signing::HEADER_MASTER, "master-1")
+            .header(signing::HEADER_USER, "alice")
+            .header(signing::HEADER_SIGNATURE, signing::sign(key, &parts))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn status(state: &Arc<AppState>, request: Request) -> StatusCode {
+        router(state.clone()).oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn a_fresh_config_has_no_credential() {
+        let config = AgentConfig::load("/nonexistent/port_sentinel/config.json");
+        assert!(config.auth_token.is_empty());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn unenrolled_agent_refuses_everything() {
+        let state = state("unenrolled", "");
+        assert_eq!(status(&state, signed("GET", "/api/v1/audit", "")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&state, signed("GET", "/api/v1/audit", "change_me_please")).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn revoked_credential_is_dropped_for_good() {
+        let state = state("revoke", CREDENTIAL);
+        assert_eq!(status(&state, signed("GET", "/api/v1/audit", CREDENTIAL)).await, StatusCode::OK);
+
+        assert_eq!(status(&state, signed("POST", "/api/v1/credential/revoke", CREDENTIAL)).await, StatusCode::OK);
+        assert_eq!(status(&state, signed("GET", "/api/v1/audit", CREDENTIAL)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&state, signed("POST", "/api/v1/credential/revoke", CREDENTIAL)).await, StatusCode::UNAUTHORIZED);
+
+        // Also after a restart
+        assert!(AgentConfig::load(&state.config_path).auth_token.is_empty());
+        let records = state.audit.tail(10, None).unwrap();
+        assert_eq!(records.last().map(|r| (r.action.as_str(), r.outcome.as_str())), Some(("credential_revoke", "ok")));
+        assert_eq!(records.last().and_then(|r| r.user.as_deref()), Some("alice"));
+    }
+}
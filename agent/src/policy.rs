@@ -0,0 +1,123 @@
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use port_sentinel_shared::ContainerInfo;
+
+/// What the master may do on this node, the `actions` section of the config. Everything is
+/// allowed by default; production nodes can turn categories off or go `read_only`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ActionPolicy {
+    /// Refuse every change (kill, service and Docker control), whatever the switches below say
+    pub read_only: bool,
+    pub kill: bool,
+    /// Start, stop and restart systemd services
+    pub service_control: bool,
+    /// Start, stop, restart and remove containers, compose projects, prune and image/volume removal
+    pub docker_control: bool,
+    /// Process log files and container output
+    pub log_read: bool,
+    /// Services that may be controlled; unset allows all
+    pub allowed_services: Option<Vec<String>>,
+    /// Containers (by name) that may be controlled; unset allows all
+    pub allowed_containers: Option<Vec<String>>,
+}
+
+impl Default for ActionPolicy {
+    fn default() -> Self {
+        ActionPolicy {
+            read_only: false,
+            kill: true,
+            service_control: true,
+            docker_control: true,
+            log_read: true,
+            allowed_services: None,
+            allowed_containers: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Action {
+    Kill,
+    ServiceControl,
+    DockerControl,
+    LogRead,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Kill => "kill",
+            Action::ServiceControl => "service_control",
+            Action::DockerControl => "docker_control",
+            Action::LogRead => "log_read",
+        }
+    }
+
+    fn changes_node(self) -> bool {
+        !matches!(self, Action::LogRead)
+    }
+}
+
+/// A call the policy refuses, answered with 403 and the reason.
+pub struct Denied(String);
+
+impl From<Denied> for (StatusCode, String) {
+    fn from(denied: Denied) -> Self {
+        (StatusCode::FORBIDDEN, denied.0)
+    }
+}
+
+impl ActionPolicy {
+    pub fn check(&self, action: Action) -> Result<(), Denied> {
+        if self.read_only && action.changes_node() {
+            return Err(Denied(format!("This node is read-only, '{}' is disabled", action.name())));
+        }
+        let enabled = match action {
+            Action::Kill => self.kill,
+            Action::ServiceControl => self.service_control,
+            Action::DockerControl => self.docker_control,
+            Action::LogRead => self.log_read,
+        };
+        if enabled {
+            Ok(())
+        } else {
+            Err(Denied(format!("'{}' is disabled on this node", action.name())))
+        }
+    }
+
+    pub fn check_service(&self, name: &str) -> Result<(), Denied> {
+        self.check(Action::ServiceControl)?;
+        match &self.allowed_services {
+            Some(allowed) if !allowed.iter().any(|s| s == name || s.strip_suffix(".service") == Some(name)) => {
+                Err(Denied(format!("Service '{}' is not in this node's allowed_services", name)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks Docker control over every container in `targets`, e.g. all of a compose project.
+    pub fn check_containers(&self, targets: &[&ContainerInfo]) -> Result<(), Denied> {
+        self.check(Action::DockerControl)?;
+        let Some(allowed) = &self.allowed_containers else {
+            return Ok(());
+        };
+        // Nothing we know matches: don't let the runtime resolve the name some other way
+        if targets.is_empty() {
+            return Err(Denied("No container in this node's allowed_containers matches".to_string()));
+        }
+        match targets.iter().find(|c| !c.names.split(',').any(|n| allowed.iter().any(|a| a == n))) {
+            Some(c) => Err(Denied(format!("Container '{}' is not in this node's allowed_containers", c.names))),
+            None => Ok(()),
+        }
+    }
+
+    /// Actions of `/api/v1/info` this policy turns off, for the master to grey out.
+    pub fn denied(&self) -> Vec<String> {
+        [Action::Kill, Action::ServiceControl, Action::DockerControl, Action::LogRead]
+            .into_iter()
+            .filter(|a| self.check(*a).is_err())
+            .map(|a| a.name().to_string())
+            .collect()
+    }
+}
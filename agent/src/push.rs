@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{Router, body::Body, http::Request};
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tower::ServiceExt;
+use port_sentinel_shared::TunnelMessage;
+use crate::AppState;
+use crate::system::monitor::get_system_stats;
+
+/// Reconnect delay after a failed or dropped connection, doubled up to `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Largest answer sent back through the tunnel.
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Push mode: keeps a WebSocket open to the master, pushes stats and container events, and
+/// answers the master's API calls by running them through `app`, the same router that serves HTTP.
+pub async fn run(state: Arc<AppState>, app: Router) {
+    let Some(master_url) = state.config.master_url.clone() else { return };
+    let url = connect_url(&master_url);
+    if url.starts_with("ws://") && !state.config.allow_insecure_master {
+        println!("❌ Push mode needs an https:// master_url, '{}' would send the node credential in cleartext", master_url);
+        println!("   Set \"allow_insecure_master\": true to connect anyway (e.g. on a trusted network)");
+        return;
+    }
+    let node_id = state.config.node_id.clone().unwrap_or_else(|| state.config.hostname.clone());
+
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match session(&state, &app, &url, &node_id).await {
+            Ok(()) => {
+                println!("🔌 Connection to master closed, reconnecting");
+                backoff = MIN_BACKOFF;
+            }
+            Err(e) => println!("⚠️ Push connection to {}: {}", url, e),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// "https://master:7878" -> "wss://master:7878/api/agent/connect"
+fn connect_url(master_url: &str) -> String {
+    let base = master_url.trim_end_matches('/');
+    let base = if let Some(rest) = base.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        base.to_string()
+    };
+    format!("{}/api/agent/connect", base)
+}
+
+/// One connection, from the hello until the master or the network drops it.
+async fn session(state: &Arc<AppState>, app: &Router, url: &str, node_id: &str) -> Result<(), String> {
+    let (ws, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    let (mut sink, mut stream) = ws.split();
+
+    let hello = TunnelMessage::Hello {
+        node_id: node_id.to_string(),
+        token: state.config.auth_token.clone(),
+        hostname: state.config.hostname.clone(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    send(&mut sink, &hello).await?;
+
+    match stream.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<TunnelMessage>(&text) {
+            Ok(TunnelMessage::Accepted) => {}
+            Ok(TunnelMessage::Rejected { reason }) => return Err(format!("Master rejected node '{}': {}", node_id, reason)),
+            _ => return Err("Unexpected answer to hello".to_string()),
+        },
+        Some(Err(e)) => return Err(e.to_string()),
+        _ => return Err("Master closed the connection".to_string()),
+    }
+    println!("🔌 Connected to master at {} as '{}'", url, node_id);
+
+    // Answers are produced by spawned tasks and written here, so slow calls don't block the socket
+    let (tx, mut rx) = mpsc::channel::<TunnelMessage>(64);
+    let mut stats_tick = tokio::time::interval(Duration::from_secs(state.config.push_interval.max(1)));
+    let mut events_tick = tokio::time::interval(Duration::from_secs(1));
+    // Everything still buffered is sent again after a reconnect, the master drops duplicates
+    let mut last_seq = 0;
+
+    loop {
+        tokio::select! {
+            Some(msg) = rx.recv() => send(&mut sink, &msg).await?,
+            _ = stats_tick.tick() => {
+                let stats = tokio::task::spawn_blocking(get_system_stats).await.map_err(|e| e.to_string())?;
+                send(&mut sink, &TunnelMessage::Stats { stats }).await?;
+            }
+            _ = events_tick.tick() => {
+                let batch = state.events.since(last_seq);
+                if let Some(last) = batch.events.iter().map(|e| e.seq).max() {
+                    last_seq = last;
+                    send(&mut sink, &TunnelMessage::Events { batch }).await?;
+                }
+            }
+            incoming = stream.next() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Err(e)) => return Err(e.to_string()),
+                    Some(Ok(_)) => continue,
+                };
+                if let Ok(TunnelMessage::Request { id, method, path, headers, .. }) = serde_json::from_str(&text) {
+                    let app = app.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        let _ = tx.send(serve(app, id, &method, &path, headers).await).await;
+                    });
+                }
+            }
+        }
+    }
+}
+
+async fn send<S>(sink: &mut S, msg: &TunnelMessage) -> Result<(), String>
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    let text = serde_json::to_string(msg).map_err(|e| e.to_string())?;
+    sink.send(Message::Text(text)).await.map_err(|e| e.to_string())
+}
+
+/// Runs one tunneled API call through the router. It carries the master's signing headers and
+/// `auth_middleware` checks them as for HTTP calls: the connection alone doesn't prove who the
+/// peer is.
+async fn serve(app: Router, id: u64, method: &str, path: &str, headers: BTreeMap<String, String>) -> TunnelMessage {
+    let failed = |status: u16, body: String| TunnelMessage::Response { id, status, body };
+
+    // Streaming answers never end, the tunnel only carries complete ones
+    let follows = path.split_once('?').is_some_and(|(_, q)| q.split('&').any(|p| p == "follow=true"));
+    if follows {
+        return failed(400, "Following logs is not available for push-mode agents".to_string());
+    }
+
+    let mut builder = Request::builder().method(method).uri(path);
+    for (name, value) in &headers {
+        builder = builder.header(name, value);
+    }
+    let req = match builder.body(Body::empty()) {
+        Ok(req) => req,
+        Err(e) => return failed(400, e.to_string()),
+    };
+    let resp = match app.oneshot(req).await {
+        Ok(resp) => resp,
+        Err(e) => match e {},
+    };
+
+    let status = resp.status().as_u16();
+    match axum::body::to_bytes(resp.into_body(), MAX_RESPONSE_BYTES).await {
+        Ok(bytes) => TunnelMessage::Response { id, status, body: String::from_utf8_lossy(&bytes).to_string() },
+        Err(e) => failed(500, e.to_string()),
+    }
+}
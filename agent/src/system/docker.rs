@@ -1,91 +1,1953 @@
-use std::process::Command;
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::body::Incoming;
+use hyper::{Method, Request, Response};
+use hyper_util::rt::TokioIo;
 use regex::Regex;
+use serde::Deserialize;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use port_sentinel_shared::{
+    ProcessInfo, ContainerRef, ContainerInfo, ContainerStats, ContainerDetails, PortBinding, MountInfo, NetworkAttachment, HealthInfo, HealthProbe,
+    ImageInfo, VolumeInfo, NetworkInfo, PruneReport, PruneItem, ContainerEvent, ContainerLogLine, RuntimeInfo,
+};
+use crate::system::runtime::{ContainerRuntime, EventBuffer, RunningContainer, match_processes};
+use serde::de::DeserializeOwned;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ContainerInfo {
-    pub id: String,
-    pub image: String,
-    pub status: String,
-    pub names: String,
-    pub state: String, // running, exited, etc.
+pub const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Env keys matching any of these (case-insensitive) have their values masked in inspect output.
+pub const DEFAULT_REDACT_PATTERNS: [&str; 6] = ["PASS", "SECRET", "TOKEN", "KEY", "CREDENTIAL", "AUTH"];
+
+const REDACTED: &str = "********";
+
+pub(crate) const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+pub(crate) const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+#[derive(Debug)]
+pub enum DockerError {
+    /// The socket is missing or the daemon did not answer.
+    Unreachable(String),
+    /// The daemon answered with a non-2xx status.
+    Api { status: u16, message: String },
+    /// The request was rejected before reaching the daemon.
+    InvalidInput(String),
+    /// The detected runtime can't do this.
+    Unsupported(String),
+}
+
+impl fmt::Display for DockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerError::Unreachable(e) => write!(f, "Docker daemon unreachable: {}", e),
+            DockerError::Api { status, message } => write!(f, "Docker API error ({}): {}", status, message),
+            DockerError::InvalidInput(e) => write!(f, "{}", e),
+            DockerError::Unsupported(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl DockerError {
+    /// The status the agent should answer with for this error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            DockerError::Unreachable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            DockerError::Api { status, .. } if (400..500).contains(status) => {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_REQUEST)
+            }
+            DockerError::Api { .. } => StatusCode::BAD_GATEWAY,
+            DockerError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            DockerError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
 }
 
 /// Validates that the container ID/Name only contains alphanumeric characters, underscores, dots, and hyphens.
-fn validate_container_id(id: &str) -> bool {
+/// Image IDs (without "sha256:") and volume names go through the same check.
+pub(crate) fn validate_container_id(id: &str) -> bool {
     let re = Regex::new(r"^[a-zA-Z0-9\-\._]+$").unwrap();
     re.is_match(id)
 }
 
-pub fn list_containers() -> Vec<ContainerInfo> {
-    // Format: {{.ID}}|{{.Image}}|{{.Status}}|{{.Names}}|{{.State}}
-    let output = Command::new("docker")
-        .args(["ps", "-a", "--format", "{{.ID}}|{{.Image}}|{{.Status}}|{{.Names}}|{{.State}}"])
-        .output();
-
-    match output {
-        Ok(out) if out.status.success() => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            stdout.lines().filter_map(|line| {
-                let parts: Vec<&str> = line.split('|').collect();
-                if parts.len() >= 5 {
-                    Some(ContainerInfo {
-                        id: parts[0].to_string(),
-                        image: parts[1].to_string(),
-                        status: parts[2].to_string(),
-                        names: parts[3].to_string(),
-                        state: parts[4].to_string(),
+// === ENGINE API PAYLOADS ===
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerSummary {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    image: String,
+    #[serde(default)]
+    state: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    ports: Vec<SummaryPort>,
+    #[serde(default, rename = "ImageID")]
+    image_id: String,
+    #[serde(default)]
+    mounts: Vec<SummaryMount>,
+    #[serde(default)]
+    network_settings: Option<SummaryNetworkSettings>,
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SummaryPort {
+    #[serde(default)]
+    public_port: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SummaryMount {
+    #[serde(default, rename = "Type")]
+    kind: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SummaryNetworkSettings {
+    #[serde(default)]
+    networks: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+impl ContainerSummary {
+    fn display_name(&self) -> String {
+        self.names.first().map(|n| n.trim_start_matches('/').to_string()).unwrap_or_else(|| self.id.clone())
+    }
+
+    fn to_ref(&self) -> ContainerRef {
+        ContainerRef {
+            id: self.id.clone(),
+            name: self.display_name(),
+            image: self.image.clone(),
+        }
+    }
+
+    fn label(&self, key: &str) -> Option<String> {
+        self.labels.as_ref().and_then(|l| l.get(key)).cloned()
+    }
+
+    fn uses_volume(&self, name: &str) -> bool {
+        self.mounts.iter().any(|m| m.kind == "volume" && m.name.as_deref() == Some(name))
+    }
+
+    fn uses_network(&self, name: &str) -> bool {
+        self.network_settings.as_ref()
+            .and_then(|n| n.networks.as_ref())
+            .map(|n| n.contains_key(name))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ImageSummary {
+    id: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+    #[serde(default)]
+    size: i64,
+    #[serde(default)]
+    created: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VolumeList {
+    #[serde(default)]
+    volumes: Option<Vec<VolumeSummary>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VolumeSummary {
+    name: String,
+    #[serde(default)]
+    driver: String,
+    #[serde(default)]
+    mountpoint: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSummary {
+    id: String,
+    name: String,
+    #[serde(default)]
+    driver: String,
+    #[serde(default)]
+    scope: String,
+}
+
+/// Subset of `GET /system/df`, the only place the daemon reports volume and writable layer sizes.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct DiskUsage {
+    #[serde(default)]
+    volumes: Option<Vec<DfVolume>>,
+    #[serde(default)]
+    containers: Option<Vec<DfContainer>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DfVolume {
+    name: String,
+    #[serde(default)]
+    usage_data: Option<DfUsage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DfUsage {
+    #[serde(default)]
+    size: i64, // -1 when unknown
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DfContainer {
+    id: String,
+    #[serde(default)]
+    size_rw: Option<i64>,
+}
+
+/// One line of `GET /events`.
+#[derive(Deserialize)]
+struct EventMessage {
+    #[serde(rename = "Action", default)]
+    action: String,
+    #[serde(rename = "Actor", default)]
+    actor: EventActor,
+    #[serde(rename = "timeNano", default)]
+    time_nano: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct EventActor {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+}
+
+impl From<EventMessage> for ContainerEvent {
+    fn from(msg: EventMessage) -> Self {
+        // "health_status: unhealthy" -> action health_status, detail unhealthy
+        let (action, detail) = match msg.action.split_once(": ") {
+            Some((action, status)) => (action.to_string(), Some(status.to_string())),
+            None => {
+                let exit_code = msg.actor.attributes.get("exitCode").cloned();
+                (msg.action, exit_code)
+            }
+        };
+
+        ContainerEvent {
+            seq: 0,
+            time_nano: msg.time_nano,
+            container_name: msg.actor.attributes.get("name").cloned().unwrap_or_else(|| msg.actor.id.clone()),
+            image: msg.actor.attributes.get("image").cloned().unwrap_or_default(),
+            container_id: msg.actor.id,
+            action,
+            detail,
+        }
+    }
+}
+
+fn api_error(status: StatusCode, body: &[u8]) -> DockerError {
+    let message = serde_json::from_slice::<ApiErrorBody>(body)
+        .map(|b| b.message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(body).trim().to_string());
+    DockerError::Api { status: status.as_u16(), message }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Splits an image reference into the repository and the tag or digest to pull.
+/// A colon before the last `/` belongs to a registry port, not a tag.
+pub(crate) fn split_image_ref(image: &str) -> (&str, &str) {
+    if let Some((repo, digest)) = image.split_once('@') {
+        return (repo, digest);
+    }
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    match image[name_start..].rfind(':') {
+        Some(i) => (&image[..name_start + i], &image[name_start + i + 1..]),
+        None => (image, "latest"),
+    }
+}
+
+pub(crate) fn short_image_id(id: &str) -> String {
+    id.trim_start_matches("sha256:").to_string()
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, DockerError> {
+    serde_json::from_slice(body)
+        .map_err(|e| DockerError::Api { status: 502, message: format!("Unexpected response: {}", e) })
+}
+
+impl From<ContainerSummary> for ContainerInfo {
+    fn from(c: ContainerSummary) -> Self {
+        ContainerInfo {
+            health: health_from_status(&c.status),
+            project: c.label(COMPOSE_PROJECT_LABEL),
+            service: c.label(COMPOSE_SERVICE_LABEL),
+            id: c.id,
+            image: c.image,
+            status: c.status,
+            // The API prefixes names with "/", the CLI never showed it
+            names: c.names.iter().map(|n| n.trim_start_matches('/')).collect::<Vec<_>>().join(","),
+            state: c.state,
+        }
+    }
+}
+
+/// The list endpoint only reports health inside the status text, e.g. "Up 2 hours (healthy)".
+pub(crate) fn health_from_status(status: &str) -> Option<String> {
+    if status.contains("(healthy)") {
+        Some("healthy".to_string())
+    } else if status.contains("(unhealthy)") {
+        Some("unhealthy".to_string())
+    } else if status.contains("(health: starting)") {
+        Some("starting".to_string())
+    } else {
+        None
+    }
+}
+
+/// Subset of `GET /containers/{id}/stats?stream=false`.
+#[derive(Deserialize, Default)]
+struct StatsResponse {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    cpu_stats: CpuStats,
+    #[serde(default)]
+    precpu_stats: CpuStats,
+    #[serde(default)]
+    memory_stats: MemoryStats,
+    #[serde(default)]
+    networks: HashMap<String, NetworkStats>,
+    #[serde(default)]
+    blkio_stats: BlkioStats,
+    #[serde(default)]
+    pids_stats: PidsStats,
+}
+
+#[derive(Deserialize, Default)]
+struct CpuStats {
+    #[serde(default)]
+    cpu_usage: CpuUsage,
+    #[serde(default)]
+    system_cpu_usage: u64,
+    #[serde(default)]
+    online_cpus: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct CpuUsage {
+    #[serde(default)]
+    total_usage: u64,
+    #[serde(default)]
+    percpu_usage: Vec<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct MemoryStats {
+    #[serde(default)]
+    usage: u64,
+    #[serde(default)]
+    limit: u64,
+    #[serde(default)]
+    stats: HashMap<String, u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct NetworkStats {
+    #[serde(default)]
+    rx_bytes: u64,
+    #[serde(default)]
+    tx_bytes: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct BlkioStats {
+    #[serde(default)]
+    io_service_bytes_recursive: Option<Vec<BlkioEntry>>,
+}
+
+#[derive(Deserialize)]
+struct BlkioEntry {
+    op: String,
+    value: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct PidsStats {
+    #[serde(default)]
+    current: u64,
+}
+
+impl StatsResponse {
+    /// Same formulas as `docker stats`.
+    fn into_stats(self, id: String) -> ContainerStats {
+        let cpu_delta = self.cpu_stats.cpu_usage.total_usage.saturating_sub(self.precpu_stats.cpu_usage.total_usage);
+        let system_delta = self.cpu_stats.system_cpu_usage.saturating_sub(self.precpu_stats.system_cpu_usage);
+        let online_cpus = if self.cpu_stats.online_cpus > 0 {
+            self.cpu_stats.online_cpus
+        } else {
+            self.cpu_stats.cpu_usage.percpu_usage.len() as u64
+        };
+        let cpu_percent = if system_delta > 0 && cpu_delta > 0 {
+            cpu_delta as f64 / system_delta as f64 * online_cpus as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        // Page cache is reclaimable, docker CLI subtracts it too (cgroup v2 / v1 keys)
+        let cache = self.memory_stats.stats.get("inactive_file")
+            .or_else(|| self.memory_stats.stats.get("total_inactive_file"))
+            .copied()
+            .unwrap_or(0);
+
+        let (block_read, block_write) = self.blkio_stats.io_service_bytes_recursive.unwrap_or_default()
+            .iter()
+            .fold((0, 0), |(r, w), e| match e.op.to_lowercase().as_str() {
+                "read" => (r + e.value, w),
+                "write" => (r, w + e.value),
+                _ => (r, w),
+            });
+
+        ContainerStats {
+            id,
+            name: self.name.trim_start_matches('/').to_string(),
+            cpu_percent,
+            mem_usage_bytes: self.memory_stats.usage.saturating_sub(cache),
+            mem_limit_bytes: self.memory_stats.limit,
+            net_rx_bytes: self.networks.values().map(|n| n.rx_bytes).sum(),
+            net_tx_bytes: self.networks.values().map(|n| n.tx_bytes).sum(),
+            block_read_bytes: block_read,
+            block_write_bytes: block_write,
+            pids: self.pids_stats.current,
+        }
+    }
+}
+
+/// Subset of `GET /containers/{id}/json`, also what `podman inspect` prints per container.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct InspectResponse {
+    id: String,
+    name: String,
+    #[serde(default)]
+    created: String,
+    config: InspectConfig,
+    state: InspectState,
+    host_config: InspectHostConfig,
+    network_settings: InspectNetworkSettings,
+    #[serde(default)]
+    mounts: Vec<InspectMount>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectConfig {
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    env: Option<Vec<String>>,
+    #[serde(default)]
+    labels: Option<BTreeMap<String, String>>,
+    #[serde(default, deserialize_with = "string_or_list")]
+    entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    cmd: Option<Vec<String>>,
+}
+
+/// Podman 4 reports the entrypoint as a single string, Docker and Podman 5 as a list.
+fn string_or_list<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entrypoint { One(String), List(Vec<String>) }
+
+    Ok(match Option::<Entrypoint>::deserialize(de)? {
+        Some(Entrypoint::One(s)) if s.is_empty() => None,
+        Some(Entrypoint::One(s)) => Some(vec![s]),
+        Some(Entrypoint::List(l)) => Some(l),
+        None => None,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectState {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    pid: u32,
+    #[serde(default)]
+    health: Option<InspectHealth>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHealth {
+    status: String,
+    #[serde(default)]
+    failing_streak: u64,
+    #[serde(default)]
+    log: Option<Vec<InspectHealthLog>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHealthLog {
+    #[serde(default)]
+    start: String,
+    #[serde(default)]
+    exit_code: i64,
+    #[serde(default)]
+    output: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHostConfig {
+    #[serde(default)]
+    restart_policy: Option<InspectRestartPolicy>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectRestartPolicy {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    maximum_retry_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectNetworkSettings {
+    #[serde(default)]
+    ports: Option<BTreeMap<String, Option<Vec<InspectHostPort>>>>,
+    #[serde(default)]
+    networks: Option<BTreeMap<String, InspectNetwork>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHostPort {
+    #[serde(default)]
+    host_ip: String,
+    #[serde(default)]
+    host_port: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectNetwork {
+    #[serde(default, rename = "IPAddress")]
+    ip_address: String,
+    #[serde(default)]
+    gateway: String,
+    #[serde(default)]
+    mac_address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectMount {
+    #[serde(default, rename = "Type")]
+    kind: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    source: String,
+    #[serde(default)]
+    destination: String,
+    #[serde(default, rename = "RW")]
+    rw: bool,
+}
+
+impl InspectResponse {
+    pub(crate) fn into_details(self, redact: &[Regex]) -> ContainerDetails {
+        let env = self.config.env.unwrap_or_default().into_iter().map(|entry| {
+            let (key, value) = entry.split_once('=').map(|(k, v)| (k.to_string(), v.to_string())).unwrap_or((entry, String::new()));
+            let value = if redact.iter().any(|re| re.is_match(&key)) { REDACTED.to_string() } else { value };
+            (key, value)
+        }).collect();
+
+        let mut ports = Vec::new();
+        for (container_port, bindings) in self.network_settings.ports.unwrap_or_default() {
+            match bindings {
+                Some(list) if !list.is_empty() => {
+                    for b in list {
+                        ports.push(PortBinding {
+                            container_port: container_port.clone(),
+                            host_ip: Some(b.host_ip).filter(|s| !s.is_empty()),
+                            host_port: Some(b.host_port).filter(|s| !s.is_empty()),
+                        });
+                    }
+                }
+                // Exposed but not published
+                _ => ports.push(PortBinding { container_port, host_ip: None, host_port: None }),
+            }
+        }
+
+        let restart_policy = match self.host_config.restart_policy {
+            Some(p) if p.name == "on-failure" && p.maximum_retry_count > 0 => format!("on-failure:{}", p.maximum_retry_count),
+            Some(p) if !p.name.is_empty() => p.name,
+            _ => "no".to_string(),
+        };
+
+        ContainerDetails {
+            id: self.id,
+            name: self.name.trim_start_matches('/').to_string(),
+            image: self.config.image,
+            created: self.created,
+            state: self.state.status,
+            restart_policy,
+            entrypoint: self.config.entrypoint.unwrap_or_default(),
+            command: self.config.cmd.unwrap_or_default(),
+            env,
+            labels: self.config.labels.unwrap_or_default(),
+            ports,
+            mounts: self.mounts.into_iter().map(|m| MountInfo {
+                kind: m.kind,
+                name: m.name.filter(|n| !n.is_empty()),
+                source: m.source,
+                destination: m.destination,
+                read_write: m.rw,
+            }).collect(),
+            networks: self.network_settings.networks.unwrap_or_default().into_iter().map(|(name, n)| NetworkAttachment {
+                name,
+                ip_address: n.ip_address,
+                gateway: n.gateway,
+                mac_address: n.mac_address,
+            }).collect(),
+            health: self.state.health.map(|h| HealthInfo {
+                status: h.status,
+                failing_streak: h.failing_streak,
+                log: h.log.unwrap_or_default().into_iter().map(|l| HealthProbe {
+                    start: l.start,
+                    exit_code: l.exit_code,
+                    output: l.output.trim().to_string(),
+                }).collect(),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiErrorBody {
+    message: String,
+}
+
+// === CLIENT ===
+
+/// Compiles the env redaction patterns case-insensitively; invalid patterns are reported and skipped.
+pub(crate) fn redact_regexes(patterns: &[String]) -> Vec<Regex> {
+    patterns.iter().filter_map(|p| {
+        match Regex::new(&format!("(?i){}", p)) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                println!("⚠️  Ignoring invalid env redaction pattern '{}': {}", p, e);
+                None
+            }
+        }
+    }).collect()
+}
+
+/// Minimal Docker Engine API client speaking HTTP/1.1 over the daemon's unix socket.
+/// Podman's Docker-compatible socket is served by the same client.
+#[derive(Clone, Debug)]
+pub struct DockerClient {
+    socket: PathBuf,
+    redact_env: Vec<Regex>,
+    /// "docker" or "podman", as reported by `GET /version`
+    runtime: String,
+    version: Option<String>,
+}
+
+/// Subset of `GET /version`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersionResponse {
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    platform: Option<VersionPlatform>,
+    #[serde(default)]
+    components: Option<Vec<VersionPlatform>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersionPlatform {
+    #[serde(default)]
+    name: String,
+}
+
+impl DockerClient {
+    /// `redact_patterns` are regexes matched case-insensitively against env keys;
+    /// invalid patterns are reported and skipped.
+    pub fn new(socket: impl Into<PathBuf>, redact_patterns: &[String]) -> Self {
+        DockerClient {
+            socket: socket.into(),
+            redact_env: redact_regexes(redact_patterns),
+            runtime: "docker".to_string(),
+            version: None,
+        }
+    }
+
+    /// Asks the socket which engine is behind it. None when nothing answers.
+    pub async fn probe(mut self) -> Option<Self> {
+        let body = self.request(Method::GET, "/version").await.ok()?;
+        let version: VersionResponse = decode(&body).ok()?;
+
+        let names = version.platform.iter()
+            .chain(version.components.iter().flatten())
+            .map(|p| p.name.to_lowercase());
+        let is_podman = names.into_iter().any(|n| n.contains("podman"));
+
+        self.runtime = if is_podman { "podman" } else { "docker" }.to_string();
+        self.version = version.version;
+        Some(self)
+    }
+
+    /// Sends one request on a fresh connection and returns the full response body.
+    async fn request(&self, method: Method, path: &str) -> Result<Bytes, DockerError> {
+        self.send(method, path, None).await
+    }
+
+    async fn request_json(&self, method: Method, path: &str, body: &serde_json::Value) -> Result<Bytes, DockerError> {
+        self.send(method, path, Some(body.to_string())).await
+    }
+
+    async fn send(&self, method: Method, path: &str, json_body: Option<String>) -> Result<Bytes, DockerError> {
+        let resp = self.open(method, path, json_body).await?;
+        let status = resp.status();
+        let body = resp.into_body().collect().await
+            .map_err(|e| DockerError::Unreachable(e.to_string()))?
+            .to_bytes();
+
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            Ok(body)
+        } else {
+            Err(api_error(status, &body))
+        }
+    }
+
+    /// Sends the request and returns as soon as the headers arrived, for streaming endpoints.
+    async fn open(&self, method: Method, path: &str, json_body: Option<String>) -> Result<Response<Incoming>, DockerError> {
+        let stream = UnixStream::connect(&self.socket).await
+            .map_err(|e| DockerError::Unreachable(format!("{}: {}", self.socket.display(), e)))?;
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await
+            .map_err(|e| DockerError::Unreachable(e.to_string()))?;
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Host", "docker");
+        if json_body.is_some() {
+            builder = builder.header("Content-Type", "application/json");
+        }
+        let req = builder
+            .body(Full::new(Bytes::from(json_body.unwrap_or_default())))
+            .map_err(|e| DockerError::InvalidInput(e.to_string()))?;
+
+        sender.send_request(req).await
+            .map_err(|e| DockerError::Unreachable(e.to_string()))
+    }
+
+    async fn container_summaries(&self, all: bool) -> Result<Vec<ContainerSummary>, DockerError> {
+        let body = self.request(Method::GET, &format!("/containers/json?all={}", all)).await?;
+        serde_json::from_slice(&body)
+            .map_err(|e| DockerError::Api { status: 502, message: format!("Unexpected response: {}", e) })
+    }
+
+    async fn disk_usage(&self) -> DiskUsage {
+        // Sizes are a nice-to-have, listing still works if df is slow or unsupported
+        match self.request(Method::GET, "/system/df").await {
+            Ok(body) => decode(&body).unwrap_or_default(),
+            Err(_) => DiskUsage::default(),
+        }
+    }
+
+    async fn inspect_raw(&self, id: &str) -> Result<InspectResponse, DockerError> {
+        if !validate_container_id(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+
+        let body = self.request(Method::GET, &format!("/containers/{}/json", id)).await?;
+        serde_json::from_slice(&body)
+            .map_err(|e| DockerError::Api { status: 502, message: format!("Unexpected response: {}", e) })
+    }
+
+    async fn get_container_stats(&self, id: &str) -> Result<ContainerStats, DockerError> {
+        if !validate_container_id(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+
+        // stream=false waits for a second sample so precpu_stats is filled in
+        let path = format!("/containers/{}/stats?stream=false", id);
+        let body = self.request(Method::GET, &path).await?;
+        let stats: StatsResponse = serde_json::from_slice(&body)
+            .map_err(|e| DockerError::Api { status: 502, message: format!("Unexpected response: {}", e) })?;
+        Ok(stats.into_stats(id.to_string()))
+    }
+
+    async fn project_containers(&self, project: &str, service: Option<&str>) -> Result<Vec<ContainerSummary>, DockerError> {
+        if !validate_container_id(project) || !service.map(validate_container_id).unwrap_or(true) {
+            return Err(DockerError::InvalidInput("Invalid project or service name".to_string()));
+        }
+        let containers: Vec<ContainerSummary> = self.container_summaries(true).await?
+            .into_iter()
+            .filter(|c| c.label(COMPOSE_PROJECT_LABEL).as_deref() == Some(project))
+            .filter(|c| service.is_none() || c.label(COMPOSE_SERVICE_LABEL).as_deref() == service)
+            .collect();
+        if containers.is_empty() {
+            return Err(DockerError::Api { status: 404, message: format!("No containers found for {}", project) });
+        }
+        Ok(containers)
+    }
+
+    /// Replaces a container with a fresh one built from the same config, host config and networks
+    /// (picking up a newer image for the same tag when `pull` is set). The old container is renamed
+    /// and only removed once the new one started; on failure it is put back as it was.
+    async fn recreate_container(&self, id: &str, pull: bool) -> Result<String, DockerError> {
+        if !validate_container_id(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+
+        let old: serde_json::Value = decode(&self.request(Method::GET, &format!("/containers/{}/json", id)).await?)?;
+        let id = old["Id"].as_str().unwrap_or(id).to_string();
+        let name = old["Name"].as_str().unwrap_or_default().trim_start_matches('/').to_string();
+        let was_running = old["State"]["Running"].as_bool().unwrap_or(false);
+        if !validate_container_id(&name) {
+            return Err(DockerError::InvalidInput("Invalid container name".to_string()));
+        }
+
+        if pull && let Some(image) = old["Config"]["Image"].as_str().filter(|i| !i.starts_with("sha256:")) {
+            self.pull_image(image).await?;
+        }
+
+        let (body, extra_networks) = recreate_payload(&old);
+
+        if was_running {
+            self.control_container(&id, "stop").await?;
+        }
+        let backup = format!("{}_old_{}", name, chrono::Utc::now().timestamp());
+        self.request(Method::POST, &format!("/containers/{}/rename?name={}", id, backup)).await?;
+
+        let result = self.create_and_start(&name, &body, &extra_networks).await;
+        match result {
+            Ok(new_id) => {
+                // Anonymous volumes were carried over, so don't pass v=true here
+                self.request(Method::DELETE, &format!("/containers/{}", id)).await?;
+                Ok(format!("{} ({})", name, &new_id[..new_id.len().min(12)]))
+            }
+            Err(e) => {
+                let _ = self.request(Method::POST, &format!("/containers/{}/rename?name={}", id, name)).await;
+                if was_running {
+                    let _ = self.control_container(&id, "start").await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn create_and_start(
+        &self,
+        name: &str,
+        body: &serde_json::Value,
+        extra_networks: &[(String, serde_json::Value)],
+    ) -> Result<String, DockerError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Created { id: String }
+
+        let created: Created = decode(&self.request_json(Method::POST, &format!("/containers/create?name={}", name), body).await?)?;
+
+        let started = async {
+            // Older engines accept a single network at create time, attach the rest before starting
+            for (net, endpoint) in extra_networks {
+                let connect = serde_json::json!({ "Container": created.id, "EndpointConfig": endpoint });
+                self.request_json(Method::POST, &format!("/networks/{}/connect", net), &connect).await?;
+            }
+            self.control_container(&created.id, "start").await
+        }.await;
+
+        match started {
+            Ok(_) => Ok(created.id),
+            Err(e) => {
+                let _ = self.request(Method::DELETE, &format!("/containers/{}?force=true", created.id)).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn pull_image(&self, image: &str) -> Result<(), DockerError> {
+        let (repo, tag) = split_image_ref(image);
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("fromImage", repo)
+            .append_pair("tag", tag)
+            .finish();
+        let body = self.request(Method::POST, &format!("/images/create?{}", query)).await?;
+
+        // The pull reports failures inside its progress stream, with a 200 status
+        for line in body.split(|b| *b == b'\n') {
+            if let Ok(msg) = serde_json::from_slice::<serde_json::Value>(line)
+                && let Some(err) = msg["error"].as_str()
+            {
+                return Err(DockerError::Api { status: 502, message: format!("Pull of {} failed: {}", image, err) });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for DockerClient {
+    fn info(&self) -> RuntimeInfo {
+        let features = ["containers", "stats", "logs", "logs_follow", "inspect", "control", "images", "volumes", "networks", "prune", "compose", "recreate", "events"];
+        RuntimeInfo {
+            runtime: self.runtime.clone(),
+            version: self.version.clone(),
+            mode: "engine-api".to_string(),
+            endpoint: self.socket.display().to_string(),
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerInfo>, DockerError> {
+        Ok(self.container_summaries(true).await?.into_iter().map(ContainerInfo::from).collect())
+    }
+
+    /// Proxy processes (docker-proxy, containerd-shim, rootlessport, conmon) are matched by the
+    /// published host port; everything else by the PID namespace of the container's init process.
+    async fn attach_containers(&self, procs: &mut [ProcessInfo]) -> Result<(), DockerError> {
+        let summaries = self.container_summaries(false).await?;
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for c in summaries {
+            let client = self.clone();
+            tasks.spawn(async move {
+                let init_pid = client.inspect_raw(&c.id).await.map(|i| i.state.pid).unwrap_or(0);
+                RunningContainer {
+                    host_ports: c.ports.iter().filter_map(|p| p.public_port).collect(),
+                    container: c.to_ref(),
+                    init_pid,
+                }
+            });
+        }
+        let mut running = Vec::new();
+        while let Some(res) = tasks.join_next().await {
+            if let Ok(c) = res {
+                running.push(c);
+            }
+        }
+
+        match_processes(procs, &running);
+        Ok(())
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerDetails, DockerError> {
+        Ok(self.inspect_raw(id).await?.into_details(&self.redact_env))
+    }
+
+    /// Samples every running container concurrently (each sample takes about a second).
+    async fn list_container_stats(&self) -> Result<Vec<ContainerStats>, DockerError> {
+        let running: Vec<ContainerInfo> = self.list_containers().await?
+            .into_iter()
+            .filter(|c| c.state == "running")
+            .collect();
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for c in running {
+            let client = self.clone();
+            tasks.spawn(async move { client.get_container_stats(&c.id).await });
+        }
+
+        let mut stats = Vec::new();
+        while let Some(res) = tasks.join_next().await {
+            // A container stopping mid-sample is not an error for the whole list
+            if let Ok(Ok(s)) = res {
+                stats.push(s);
+            }
+        }
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(stats)
+    }
+
+    async fn get_container_logs(&self, id: &str, opts: &LogOptions) -> Result<Vec<ContainerLogLine>, DockerError> {
+        let body = self.request(Method::GET, &logs_path(id, opts, false)?).await?;
+
+        let mut decoder = LogDecoder::new(opts.timestamps);
+        let mut lines = decoder.feed(&body);
+        lines.extend(decoder.finish());
+        Ok(lines)
+    }
+
+    /// Opens a `follow` log stream. Errors (unknown container, bad options) surface here,
+    /// before anything was sent to the caller.
+    async fn follow_container_logs(&self, id: &str, opts: &LogOptions) -> Result<mpsc::Receiver<ContainerLogLine>, DockerError> {
+        let resp = self.open(Method::GET, &logs_path(id, opts, true)?, None).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.into_body().collect().await.map(|b| b.to_bytes()).unwrap_or_default();
+            return Err(api_error(status, &body));
+        }
+
+        let mut stream = LogStream { body: resp.into_body(), decoder: LogDecoder::new(opts.timestamps) };
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            while let Some(lines) = stream.next_lines().await {
+                for line in lines {
+                    // The client went away
+                    if tx.send(line).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn control_container(&self, id: &str, action: &str) -> Result<String, DockerError> {
+        if !validate_container_id(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+
+        let (method, path) = match action {
+            "start" | "stop" | "restart" => (Method::POST, format!("/containers/{}/{}", id, action)),
+            "rm" => (Method::DELETE, format!("/containers/{}", id)),
+            _ => return Err(DockerError::InvalidInput("Invalid action".to_string())),
+        };
+
+        self.request(method, &path).await?;
+        Ok(format!("Successfully executed {} on {}", action, id))
+    }
+
+    async fn list_images(&self) -> Result<Vec<ImageInfo>, DockerError> {
+        let images: Vec<ImageSummary> = decode(&self.request(Method::GET, "/images/json").await?)?;
+        let containers = self.container_summaries(true).await?;
+
+        Ok(images.into_iter().map(|img| {
+            let tags: Vec<String> = img.repo_tags.unwrap_or_default()
+                .into_iter()
+                .filter(|t| t != "<none>:<none>")
+                .collect();
+            let users = containers.iter()
+                .filter(|c| c.image_id == img.id)
+                .map(|c| c.display_name())
+                .collect();
+            ImageInfo {
+                id: short_image_id(&img.id),
+                dangling: tags.is_empty(),
+                tags,
+                size_bytes: img.size.max(0) as u64,
+                created: img.created,
+                containers: users,
+            }
+        }).collect())
+    }
+
+    async fn list_volumes(&self) -> Result<Vec<VolumeInfo>, DockerError> {
+        let list: VolumeList = decode(&self.request(Method::GET, "/volumes").await?)?;
+        let containers = self.container_summaries(true).await?;
+        let df = self.disk_usage().await;
+        let sizes: HashMap<String, i64> = df.volumes.unwrap_or_default().into_iter()
+            .filter_map(|v| v.usage_data.map(|u| (v.name, u.size)))
+            .collect();
+
+        Ok(list.volumes.unwrap_or_default().into_iter().map(|v| VolumeInfo {
+            size_bytes: sizes.get(&v.name).filter(|s| **s >= 0).map(|s| *s as u64),
+            containers: containers.iter().filter(|c| c.uses_volume(&v.name)).map(|c| c.display_name()).collect(),
+            name: v.name,
+            driver: v.driver,
+            mountpoint: v.mountpoint,
+        }).collect())
+    }
+
+    async fn list_networks(&self) -> Result<Vec<NetworkInfo>, DockerError> {
+        let networks: Vec<NetworkSummary> = decode(&self.request(Method::GET, "/networks").await?)?;
+        let containers = self.container_summaries(true).await?;
+
+        Ok(networks.into_iter().map(|n| NetworkInfo {
+            containers: containers.iter().filter(|c| c.uses_network(&n.name)).map(|c| c.display_name()).collect(),
+            id: n.id,
+            name: n.name,
+            driver: n.driver,
+            scope: n.scope,
+        }).collect())
+    }
+
+    async fn remove_image(&self, id: &str) -> Result<String, DockerError> {
+        if !validate_container_id(id) {
+            return Err(DockerError::InvalidInput("Invalid image ID".to_string()));
+        }
+        self.request(Method::DELETE, &format!("/images/{}", id)).await?;
+        Ok(format!("Removed image {}", id))
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<String, DockerError> {
+        if !validate_container_id(name) {
+            return Err(DockerError::InvalidInput("Invalid volume name".to_string()));
+        }
+        self.request(Method::DELETE, &format!("/volumes/{}", name)).await?;
+        Ok(format!("Removed volume {}", name))
+    }
+
+    /// Lists what a prune of `kind` would remove and, unless `dry_run`, removes exactly those items
+    /// one by one so the preview always matches the outcome:
+    /// - images: dangling images no container uses
+    /// - containers: stopped containers
+    /// - volumes: volumes no container (running or stopped) references
+    async fn prune(&self, kind: &str, dry_run: bool) -> Result<PruneReport, DockerError> {
+        let candidates: Vec<PruneItem> = match kind {
+            "images" => self.list_images().await?.into_iter()
+                .filter(|i| i.dangling && i.containers.is_empty())
+                .map(|i| PruneItem { name: i.id.chars().take(12).collect(), id: i.id, size_bytes: i.size_bytes })
+                .collect(),
+            "containers" => {
+                let sizes: HashMap<String, i64> = self.disk_usage().await.containers.unwrap_or_default()
+                    .into_iter()
+                    .map(|c| (c.id, c.size_rw.unwrap_or(0)))
+                    .collect();
+                self.container_summaries(true).await?.into_iter()
+                    .filter(|c| matches!(c.state.as_str(), "exited" | "created" | "dead"))
+                    .map(|c| PruneItem {
+                        name: c.display_name(),
+                        size_bytes: sizes.get(&c.id).copied().unwrap_or(0).max(0) as u64,
+                        id: c.id,
                     })
-                } else {
-                    None
+                    .collect()
+            }
+            "volumes" => self.list_volumes().await?.into_iter()
+                .filter(|v| v.containers.is_empty())
+                .map(|v| PruneItem { id: v.name.clone(), name: v.name, size_bytes: v.size_bytes.unwrap_or(0) })
+                .collect(),
+            _ => return Err(DockerError::InvalidInput("Invalid prune target".to_string())),
+        };
+
+        if dry_run {
+            return Ok(PruneReport {
+                kind: kind.to_string(),
+                dry_run,
+                reclaimable_bytes: candidates.iter().map(|i| i.size_bytes).sum(),
+                items: candidates,
+                errors: vec![],
+            });
+        }
+
+        let mut removed = Vec::new();
+        let mut errors = Vec::new();
+        for item in candidates {
+            let result = match kind {
+                "images" => self.remove_image(&item.id).await,
+                "containers" => self.control_container(&item.id, "rm").await,
+                _ => self.remove_volume(&item.id).await,
+            };
+            match result {
+                Ok(_) => removed.push(item),
+                Err(e) => errors.push(format!("{}: {}", item.name, e)),
+            }
+        }
+
+        Ok(PruneReport {
+            kind: kind.to_string(),
+            dry_run,
+            reclaimable_bytes: removed.iter().map(|i| i.size_bytes).sum(),
+            items: removed,
+            errors,
+        })
+    }
+
+    /// Recreates every container of a compose service from its own configuration, so the
+    /// compose file doesn't need to be on the dashboard (or even the node).
+    async fn recreate_service(&self, project: &str, service: &str, pull: bool) -> Result<String, DockerError> {
+        let containers = self.project_containers(project, Some(service)).await?;
+        let mut recreated = Vec::new();
+        for c in containers {
+            recreated.push(self.recreate_container(&c.id, pull).await?);
+        }
+        Ok(format!("Recreated {}", recreated.join(", ")))
+    }
+
+    /// Follows the daemon's event stream and records container state changes into `buffer`.
+    /// Returns when the stream ends (e.g. the daemon restarted), the caller reconnects.
+    async fn watch_events(&self, buffer: &EventBuffer) -> Result<(), DockerError> {
+        let filters = r#"{"type":["container"],"event":["die","oom","restart","health_status","start","stop"]}"#;
+        let resp = self.open(Method::GET, &format!("/events?filters={}", percent_encode(filters)), None).await?;
+        let status = resp.status();
+        let mut body = resp.into_body();
+        if !status.is_success() {
+            let body = body.collect().await.map(|b| b.to_bytes()).unwrap_or_default();
+            return Err(api_error(status, &body));
+        }
+
+        let mut pending: Vec<u8> = Vec::new();
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| DockerError::Unreachable(e.to_string()))?;
+            let Ok(data) = frame.into_data() else { continue };
+            pending.extend_from_slice(&data);
+
+            // One JSON object per line
+            while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=pos).collect();
+                if let Ok(msg) = serde_json::from_slice::<EventMessage>(&line) {
+                    buffer.push(msg.into());
                 }
-            }).collect()
-        },
-        _ => vec![] // Return empty if docker fails or not installed
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn get_container_logs(id: &str) -> Result<Vec<String>, String> {
-    if !validate_container_id(id) {
-        return Err("Invalid container ID".to_string());
+/// Builds the `POST /containers/create` body from an inspect result, plus the networks
+/// that have to be connected after creation.
+fn recreate_payload(old: &serde_json::Value) -> (serde_json::Value, Vec<(String, serde_json::Value)>) {
+    let short_id: String = old["Id"].as_str().unwrap_or_default().chars().take(12).collect();
+
+    let mut body = old["Config"].clone();
+    // Docker defaults the hostname to the short container id, let the new one get its own
+    if body["Hostname"].as_str() == Some(short_id.as_str()) {
+        body.as_object_mut().map(|o| o.remove("Hostname"));
+    }
+
+    let mut host_config = old["HostConfig"].clone();
+    // Keep anonymous volumes: they are not in the host config and would otherwise start empty
+    let bound: Vec<String> = host_config["Binds"].as_array().into_iter().flatten()
+        .filter_map(|b| b.as_str().and_then(|b| b.split(':').nth(1)).map(|s| s.to_string()))
+        .chain(host_config["Mounts"].as_array().into_iter().flatten()
+            .filter_map(|m| m["Target"].as_str().map(|s| s.to_string())))
+        .collect();
+    let mut binds: Vec<serde_json::Value> = host_config["Binds"].as_array().cloned().unwrap_or_default();
+    for m in old["Mounts"].as_array().into_iter().flatten() {
+        let (Some(vol), Some(dest)) = (m["Name"].as_str(), m["Destination"].as_str()) else { continue };
+        if m["Type"].as_str() == Some("volume") && !bound.iter().any(|b| b == dest) {
+            let mode = if m["RW"].as_bool().unwrap_or(true) { "" } else { ":ro" };
+            binds.push(serde_json::Value::String(format!("{}:{}{}", vol, dest, mode)));
+        }
+    }
+    host_config["Binds"] = serde_json::Value::Array(binds);
+
+    let network_mode = host_config["NetworkMode"].as_str().unwrap_or_default().to_string();
+    body["HostConfig"] = host_config;
+
+    let mut networks: Vec<(String, serde_json::Value)> = old["NetworkSettings"]["Networks"].as_object()
+        .map(|nets| nets.iter().map(|(net, ep)| {
+            // Only user settings, the runtime addresses are assigned again
+            let aliases: Vec<serde_json::Value> = ep["Aliases"].as_array().into_iter().flatten()
+                .filter(|a| a.as_str().map(|a| !short_id.starts_with(a)).unwrap_or(false))
+                .cloned()
+                .collect();
+            (net.clone(), serde_json::json!({
+                "Aliases": aliases,
+                "IPAMConfig": ep["IPAMConfig"],
+                "Links": ep["Links"],
+            }))
+        }).collect())
+        .unwrap_or_default();
+
+    // host/none/container:<id> modes come from HostConfig.NetworkMode alone
+    if network_mode == "host" || network_mode == "none" || network_mode.starts_with("container:") || networks.is_empty() {
+        return (body, vec![]);
     }
 
-    let output = Command::new("docker")
-        .args(["logs", "--tail", "100", id]) // Fetch last 100 lines
-        .output()
-        .map_err(|e| e.to_string())?;
+    let primary = networks.iter().position(|(n, _)| *n == network_mode).unwrap_or(0);
+    let (net, ep) = networks.remove(primary);
+    body["NetworkingConfig"] = serde_json::json!({ "EndpointsConfig": { net: ep } });
+    (body, networks)
+}
 
-    // Docker logs often go to stderr/stdout mixed
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    
-    // Combine and split lines
-    let mut lines: Vec<String> = stdout.lines().map(|s| s.to_string()).collect();
-    lines.extend(stderr.lines().map(|s| s.to_string()));
-    
-    Ok(lines)
+/// Query options for container logs, as accepted from the master.
+#[derive(Default)]
+pub struct LogOptions {
+    /// Number of lines from the end, or "all"
+    pub tail: Option<String>,
+    /// Unix timestamp, RFC 3339 time or a duration back from now ("10m", "2h", "1d")
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub timestamps: bool,
 }
 
-pub fn control_container(id: &str, action: &str) -> Result<String, String> {
+fn logs_path(id: &str, opts: &LogOptions, follow: bool) -> Result<String, DockerError> {
     if !validate_container_id(id) {
-        return Err("Invalid container ID".to_string());
+        return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
     }
 
-    let valid_actions = ["start", "stop", "restart", "rm"];
-    if !valid_actions.contains(&action) {
-        return Err("Invalid action".to_string());
+    let tail = opts.tail.as_deref().unwrap_or("100");
+    if tail != "all" && tail.parse::<u32>().is_err() {
+        return Err(DockerError::InvalidInput("tail must be a number or 'all'".to_string()));
     }
-    
-    // For 'rm', usually we want -f if it's convenient, or just rm. using simple rm for now.
-    let output = Command::new("docker")
-        .arg(action)
-        .arg(id)
-        .output()
-        .map_err(|e| e.to_string())?;
 
-    if output.status.success() {
-        Ok(format!("Successfully executed {} on {}", action, id))
-    } else {
-        Err(String::from_utf8_lossy(&output.stderr).to_string())
+    let mut path = format!(
+        "/containers/{}/logs?stdout=true&stderr=true&tail={}&timestamps={}&follow={}",
+        id, tail, opts.timestamps, follow
+    );
+    for (key, value) in [("since", &opts.since), ("until", &opts.until)] {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            let ts = parse_log_time(value)
+                .ok_or_else(|| DockerError::InvalidInput(format!("Invalid {} value '{}'", key, value)))?;
+            path.push_str(&format!("&{}={}", key, ts));
+        }
+    }
+    Ok(path)
+}
+
+/// Converts the accepted time formats to the Unix seconds the Engine API expects.
+pub(crate) fn parse_log_time(value: &str) -> Option<i64> {
+    if let Ok(ts) = value.parse::<i64>() {
+        return Some(ts);
+    }
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(t.timestamp());
+    }
+
+    let (at, unit) = value.char_indices().last()?;
+    let secs: i64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return None,
+    };
+    let ago = value[..at].parse::<u32>().ok()?;
+    Some(chrono::Utc::now().timestamp() - i64::from(ago) * secs)
+}
+
+/// A followed log stream, read in chunks as the container writes.
+struct LogStream {
+    body: Incoming,
+    decoder: LogDecoder,
+}
+
+impl LogStream {
+    /// Returns the next complete lines, or None once the container stopped.
+    async fn next_lines(&mut self) -> Option<Vec<ContainerLogLine>> {
+        loop {
+            match self.body.frame().await {
+                Some(Ok(frame)) => {
+                    let Ok(data) = frame.into_data() else { continue };
+                    let lines = self.decoder.feed(&data);
+                    if !lines.is_empty() {
+                        return Some(lines);
+                    }
+                }
+                _ => {
+                    let rest = self.decoder.finish();
+                    return if rest.is_empty() { None } else { Some(rest) };
+                }
+            }
+        }
+    }
+}
+
+/// Turns the raw log body into lines tagged with their stream, in the order they were written.
+///
+/// Containers without a TTY multiplex stdout/stderr into frames with an 8 byte header
+/// `[type, 0, 0, 0, size (u32 BE)]` followed by `size` bytes; with a TTY the body is plain
+/// output. Frames don't align with lines, so partial lines are kept per stream until completed.
+struct LogDecoder {
+    timestamps: bool,
+    multiplexed: Option<bool>,
+    pending: Vec<u8>,
+    partial: [Vec<u8>; 2],
+}
+
+impl LogDecoder {
+    fn new(timestamps: bool) -> Self {
+        LogDecoder { timestamps, multiplexed: None, pending: Vec::new(), partial: [Vec::new(), Vec::new()] }
+    }
+
+    fn feed(&mut self, data: &[u8]) -> Vec<ContainerLogLine> {
+        self.pending.extend_from_slice(data);
+        let mut lines = Vec::new();
+
+        if self.multiplexed.is_none() {
+            if self.pending.len() < 8 {
+                return lines;
+            }
+            self.multiplexed = Some(self.pending[0] <= 2 && self.pending[1..4] == [0, 0, 0]);
+        }
+
+        if self.multiplexed == Some(true) {
+            while self.pending.len() >= 8 {
+                let size = u32::from_be_bytes([self.pending[4], self.pending[5], self.pending[6], self.pending[7]]) as usize;
+                if self.pending.len() < 8 + size {
+                    break;
+                }
+                // 0 is stdin, only ever seen with attach; count it as stdout
+                let stream = if self.pending[0] == 2 { 1 } else { 0 };
+                let payload: Vec<u8> = self.pending.drain(..8 + size).skip(8).collect();
+                self.split_lines(stream, &payload, &mut lines);
+            }
+        } else {
+            let payload = std::mem::take(&mut self.pending);
+            self.split_lines(0, &payload, &mut lines);
+        }
+        lines
+    }
+
+    /// Flushes lines that never got their trailing newline.
+    fn finish(&mut self) -> Vec<ContainerLogLine> {
+        let mut lines = Vec::new();
+        if self.multiplexed != Some(true) && !self.pending.is_empty() {
+            let payload = std::mem::take(&mut self.pending);
+            self.split_lines(0, &payload, &mut lines);
+        }
+        for stream in 0..2 {
+            if !self.partial[stream].is_empty() {
+                let raw = std::mem::take(&mut self.partial[stream]);
+                lines.push(self.to_line(stream, &raw));
+            }
+        }
+        lines
+    }
+
+    fn split_lines(&mut self, stream: usize, payload: &[u8], out: &mut Vec<ContainerLogLine>) {
+        for byte in payload {
+            if *byte == b'\n' {
+                let raw = std::mem::take(&mut self.partial[stream]);
+                out.push(self.to_line(stream, &raw));
+            } else {
+                self.partial[stream].push(*byte);
+            }
+        }
+    }
+
+    fn to_line(&self, stream: usize, raw: &[u8]) -> ContainerLogLine {
+        let text = String::from_utf8_lossy(raw).trim_end_matches('\r').to_string();
+        // With timestamps=true every line starts with "<RFC 3339 nano> "
+        let (timestamp, text) = match text.split_once(' ') {
+            Some((ts, rest)) if self.timestamps => (Some(ts.to_string()), rest.to_string()),
+            _ => (None, text),
+        };
+        ContainerLogLine {
+            stream: if stream == 1 { "stderr" } else { "stdout" }.to_string(),
+            timestamp,
+            text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A unix socket answering like the Engine API with canned bodies, keyed by
+    /// "METHOD /path" without the query. Requests are recorded with their query.
+    struct FakeDaemon {
+        socket: PathBuf,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Drop for FakeDaemon {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.socket);
+        }
+    }
+
+    fn socket_path() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("ps-fake-docker-{}-{}.sock", std::process::id(), n))
+    }
+
+    fn fake_daemon(routes: &[(&str, u16, &str)]) -> FakeDaemon {
+        let socket = socket_path();
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let routes: Arc<HashMap<String, (u16, String)>> = Arc::new(routes.iter()
+            .map(|(route, status, body)| (route.to_string(), (*status, body.to_string())))
+            .collect());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let routes = routes.clone();
+                let seen = seen.clone();
+                let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+                    seen.lock().unwrap().push(format!("{} {}", req.method(), req.uri()));
+                    let (status, body) = routes.get(&format!("{} {}", req.method(), req.uri().path()))
+                        .cloned()
+                        .unwrap_or((404, r#"{"message":"page not found"}"#.to_string()));
+                    async move {
+                        Response::builder()
+                            .status(status)
+                            .header("Content-Type", "application/json")
+                            .body(Full::new(Bytes::from(body)))
+                    }
+                });
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        FakeDaemon { socket, requests }
+    }
+
+    /// One multiplexed log frame: 8 byte header, then the payload.
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let mut bytes = vec![stream, 0, 0, 0];
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(payload.as_bytes());
+        bytes
+    }
+
+    fn texts(lines: &[ContainerLogLine]) -> Vec<(&str, &str)> {
+        lines.iter().map(|l| (l.stream.as_str(), l.text.as_str())).collect()
+    }
+
+    #[test]
+    fn demux_tags_stdout_and_stderr() {
+        let mut decoder = LogDecoder::new(false);
+        let mut body = frame(1, "listening on :80\n");
+        body.extend(frame(2, "warning: no config\n"));
+        body.extend(frame(1, "ready\n"));
+        let lines = decoder.feed(&body);
+        assert_eq!(texts(&lines), [("stdout", "listening on :80"), ("stderr", "warning: no config"), ("stdout", "ready")]);
+    }
+
+    #[test]
+    fn demux_reassembles_frames_split_across_reads() {
+        let mut decoder = LogDecoder::new(false);
+        let body = [frame(1, "first\nsec"), frame(2, "oops\n"), frame(1, "ond\n")].concat();
+        // Cut inside a header and inside a payload
+        let mut lines = decoder.feed(&body[..5]);
+        lines.extend(decoder.feed(&body[5..13]));
+        lines.extend(decoder.feed(&body[13..]));
+        assert_eq!(texts(&lines), [("stdout", "first"), ("stderr", "oops"), ("stdout", "second")]);
+    }
+
+    #[test]
+    fn demux_keeps_partial_lines_until_finished() {
+        let mut decoder = LogDecoder::new(false);
+        assert!(decoder.feed(&frame(2, "no newline")).is_empty());
+        assert_eq!(texts(&decoder.finish()), [("stderr", "no newline")]);
+    }
+
+    #[test]
+    fn tty_output_is_plain_stdout() {
+        let mut decoder = LogDecoder::new(false);
+        let lines = decoder.feed(b"hello from a tty\r\nbye\n");
+        assert_eq!(texts(&lines), [("stdout", "hello from a tty"), ("stdout", "bye")]);
+    }
+
+    #[test]
+    fn demux_splits_off_timestamps() {
+        let mut decoder = LogDecoder::new(true);
+        let lines = decoder.feed(&frame(1, "2024-05-01T10:00:00.123456789Z started\n"));
+        assert_eq!(lines[0].timestamp.as_deref(), Some("2024-05-01T10:00:00.123456789Z"));
+        assert_eq!(lines[0].text, "started");
+    }
+
+    #[test]
+    fn image_references() {
+        assert_eq!(split_image_ref("nginx"), ("nginx", "latest"));
+        assert_eq!(split_image_ref("nginx:1.25"), ("nginx", "1.25"));
+        assert_eq!(split_image_ref("registry:5000/team/img"), ("registry:5000/team/img", "latest"));
+        assert_eq!(split_image_ref("registry:5000/team/img:v2"), ("registry:5000/team/img", "v2"));
+        assert_eq!(split_image_ref("img@sha256:abc123"), ("img", "sha256:abc123"));
+        assert_eq!(split_image_ref("registry:5000/img:v2@sha256:abc123"), ("registry:5000/img:v2", "sha256:abc123"));
+    }
+
+    #[test]
+    fn log_times() {
+        assert_eq!(parse_log_time("1714557600"), Some(1714557600));
+        assert_eq!(parse_log_time("2024-05-01T10:00:00Z"), Some(1714557600));
+        let now = chrono::Utc::now().timestamp();
+        let ago = now - parse_log_time("5m").unwrap();
+        assert!((300..=301).contains(&ago));
+        let ago = now - parse_log_time("2d").unwrap();
+        assert!((172800..=172801).contains(&ago));
+    }
+
+    #[test]
+    fn bad_log_times_are_refused() {
+        for value in ["", "m", "5é", "é", "5w", "-5m", "5.5h", "99999999999d", "yesterday"] {
+            assert_eq!(parse_log_time(value), None, "{}", value);
+        }
+    }
+
+    /// Trimmed `GET /containers/json?all=true` answer of Docker 24.
+    const CONTAINERS_JSON: &str = r#"[
+        {
+            "Id": "8dfafdbc3a40b1f3b2a7c6d2e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3",
+            "Names": ["/shop-web-1"],
+            "Image": "nginx:1.25",
+            "ImageID": "sha256:a8758716bb6aa4d90071160d27028fe4eaee7ce8166221a97d30440c8eac2be6",
+            "Command": "/docker-entrypoint.sh nginx -g 'daemon off;'",
+            "Created": 1714557600,
+            "Ports": [{"IP": "0.0.0.0", "PrivatePort": 80, "PublicPort": 8080, "Type": "tcp"}],
+            "Labels": {"com.docker.compose.project": "shop", "com.docker.compose.service": "web"},
+            "State": "running",
+            "Status": "Up 2 hours (healthy)",
+            "HostConfig": {"NetworkMode": "shop_default"},
+            "NetworkSettings": {"Networks": {"shop_default": {"IPAddress": "172.18.0.2"}}},
+            "Mounts": [{"Type": "volume", "Name": "shop_data", "Destination": "/data", "RW": true}]
+        },
+        {
+            "Id": "1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d",
+            "Names": ["/old-job"],
+            "Image": "busybox",
+            "ImageID": "sha256:ba5dc23f65d4cc4a4535bce55cf9e63b068eb02946e3422d3587e8ce803b6aab",
+            "Ports": [],
+            "Labels": null,
+            "State": "exited",
+            "Status": "Exited (0) 3 days ago",
+            "NetworkSettings": {"Networks": null},
+            "Mounts": []
+        }
+    ]"#;
+
+    #[test]
+    fn parses_container_list() {
+        let summaries: Vec<ContainerSummary> = decode(CONTAINERS_JSON.as_bytes()).unwrap();
+        assert_eq!(summaries.len(), 2);
+
+        let web = &summaries[0];
+        assert_eq!(web.display_name(), "shop-web-1");
+        assert!(web.uses_volume("shop_data"));
+        assert!(web.uses_network("shop_default"));
+        assert!(!summaries[1].uses_network("shop_default"));
+
+        let infos: Vec<ContainerInfo> = summaries.into_iter().map(ContainerInfo::from).collect();
+        assert_eq!(infos[0].names, "shop-web-1");
+        assert_eq!(infos[0].state, "running");
+        assert_eq!(infos[0].health.as_deref(), Some("healthy"));
+        assert_eq!(infos[0].project.as_deref(), Some("shop"));
+        assert_eq!(infos[0].service.as_deref(), Some("web"));
+        assert_eq!(infos[1].names, "old-job");
+        assert_eq!(infos[1].health, None);
+        assert_eq!(infos[1].project, None);
+    }
+
+    #[tokio::test]
+    async fn lists_containers_over_the_socket() {
+        let daemon = fake_daemon(&[("GET /containers/json", 200, CONTAINERS_JSON)]);
+        let client = DockerClient::new(&daemon.socket, &[]);
+
+        let containers = client.list_containers().await.unwrap();
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].names, "shop-web-1");
+        assert_eq!(containers[0].image, "nginx:1.25");
+        assert_eq!(daemon.requests.lock().unwrap().as_slice(), ["GET /containers/json?all=true"]);
+    }
+
+    #[tokio::test]
+    async fn probe_tells_podman_from_docker() {
+        let docker = fake_daemon(&[("GET /version", 200, r#"{"Version":"24.0.7","Platform":{"Name":"Docker Engine - Community"}}"#)]);
+        let client = DockerClient::new(&docker.socket, &[]).probe().await.unwrap();
+        assert_eq!(client.info().runtime, "docker");
+        assert_eq!(client.info().version.as_deref(), Some("24.0.7"));
+
+        let podman = fake_daemon(&[("GET /version", 200, r#"{"Version":"4.9.3","Components":[{"Name":"Podman Engine"}]}"#)]);
+        let client = DockerClient::new(&podman.socket, &[]).probe().await.unwrap();
+        assert_eq!(client.info().runtime, "podman");
+    }
+
+    #[tokio::test]
+    async fn api_errors_keep_the_daemon_message_and_status() {
+        let daemon = fake_daemon(&[
+            ("POST /containers/web/start", 404, r#"{"message":"No such container: web"}"#),
+            ("POST /containers/db/stop", 500, r#"{"message":"driver failed"}"#),
+        ]);
+        let client = DockerClient::new(&daemon.socket, &[]);
+
+        let err = client.control_container("web", "start").await.unwrap_err();
+        assert!(matches!(&err, DockerError::Api { status: 404, message } if message == "No such container: web"));
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+
+        let err = client.control_container("db", "stop").await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+
+        // Rejected before anything is sent
+        let err = client.control_container("web; rm -rf /", "start").await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(daemon.requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn missing_socket_is_reported_as_unreachable() {
+        let socket = socket_path();
+        let client = DockerClient::new(&socket, &[]);
+
+        let err = client.list_containers().await.unwrap_err();
+        assert!(matches!(err, DockerError::Unreachable(_)));
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(err.to_string().starts_with("Docker daemon unreachable"));
+        assert!(err.to_string().contains(&socket.display().to_string()));
+
+        assert!(DockerClient::new(&socket, &[]).probe().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn daemon_hanging_up_is_reported_as_unreachable() {
+        let socket = socket_path();
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+
+        let err = DockerClient::new(&socket, &[]).list_containers().await.unwrap_err();
+        let _ = std::fs::remove_file(&socket);
+        assert!(matches!(err, DockerError::Unreachable(_)));
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn stats_use_the_docker_cli_formulas() {
+        let body = r#"{
+            "name": "/shop-web-1",
+            "cpu_stats": {"cpu_usage": {"total_usage": 3000000000}, "system_cpu_usage": 120000000000, "online_cpus": 4},
+            "precpu_stats": {"cpu_usage": {"total_usage": 2000000000}, "system_cpu_usage": 100000000000},
+            "memory_stats": {"usage": 104857600, "limit": 536870912, "stats": {"inactive_file": 4194304}},
+            "networks": {"eth0": {"rx_bytes": 1000, "tx_bytes": 200}, "eth1": {"rx_bytes": 24, "tx_bytes": 6}},
+            "blkio_stats": {"io_service_bytes_recursive": [
+                {"major": 8, "minor": 0, "op": "read", "value": 4096},
+                {"major": 8, "minor": 0, "op": "write", "value": 8192},
+                {"major": 8, "minor": 16, "op": "Read", "value": 1}
+            ]},
+            "pids_stats": {"current": 7}
+        }"#;
+        let stats = decode::<StatsResponse>(body.as_bytes()).unwrap().into_stats("abc".to_string());
+        assert_eq!(stats.name, "shop-web-1");
+        // 1e9 of 20e9 system ticks on 4 CPUs
+        assert!((stats.cpu_percent - 20.0).abs() < 1e-9);
+        assert_eq!(stats.mem_usage_bytes, 96 * 1024 * 1024);
+        assert_eq!(stats.mem_limit_bytes, 512 * 1024 * 1024);
+        assert_eq!((stats.net_rx_bytes, stats.net_tx_bytes), (1024, 206));
+        assert_eq!((stats.block_read_bytes, stats.block_write_bytes), (4097, 8192));
+        assert_eq!(stats.pids, 7);
+    }
+
+    #[test]
+    fn stats_of_a_stopped_container_are_zero() {
+        let body = r#"{"name": "/old-job", "cpu_stats": {"cpu_usage": {"total_usage": 0, "percpu_usage": [0, 0]}},
+            "precpu_stats": {}, "memory_stats": {}, "blkio_stats": {"io_service_bytes_recursive": null}}"#;
+        let stats = decode::<StatsResponse>(body.as_bytes()).unwrap().into_stats("def".to_string());
+        assert_eq!(stats.cpu_percent, 0.0);
+        assert_eq!(stats.mem_usage_bytes, 0);
+        assert_eq!((stats.block_read_bytes, stats.block_write_bytes, stats.pids), (0, 0, 0));
+    }
+
+    /// Trimmed `GET /containers/{id}/json` answer of Docker 24.
+    const INSPECT_JSON: &str = r#"{
+        "Id": "8dfafdbc3a40",
+        "Name": "/shop-web-1",
+        "Created": "2024-05-01T10:00:00Z",
+        "Config": {
+            "Image": "nginx:1.25",
+            "Env": ["DB_PASSWORD=hunter2", "api_token=abc", "LANG=C.UTF-8", "EMPTY="],
+            "Labels": {"com.docker.compose.project": "shop"},
+            "Entrypoint": ["/docker-entrypoint.sh"],
+            "Cmd": ["nginx", "-g", "daemon off;"]
+        },
+        "State": {"Status": "running", "Pid": 4242, "Health": {"Status": "unhealthy", "FailingStreak": 3,
+            "Log": [{"Start": "2024-05-01T10:05:00Z", "ExitCode": 1, "Output": "curl: (7) refused\n"}]}},
+        "HostConfig": {"RestartPolicy": {"Name": "on-failure", "MaximumRetryCount": 5}},
+        "NetworkSettings": {
+            "Ports": {"80/tcp": [{"HostIp": "0.0.0.0", "HostPort": "8080"}, {"HostIp": "::", "HostPort": "8080"}], "443/tcp": null},
+            "Networks": {"shop_default": {"IPAddress": "172.18.0.2", "Gateway": "172.18.0.1", "MacAddress": "02:42:ac:12:00:02"}}
+        },
+        "Mounts": [{"Type": "volume", "Name": "shop_data", "Source": "/var/lib/docker/volumes/shop_data/_data", "Destination": "/data", "RW": true},
+                   {"Type": "bind", "Source": "/etc/nginx", "Destination": "/etc/nginx", "RW": false}]
+    }"#;
+
+    fn default_redaction() -> Vec<Regex> {
+        redact_regexes(&DEFAULT_REDACT_PATTERNS.map(String::from))
+    }
+
+    #[test]
+    fn inspect_redacts_env_by_key() {
+        let details = decode::<InspectResponse>(INSPECT_JSON.as_bytes()).unwrap().into_details(&default_redaction());
+        let env: Vec<(&str, &str)> = details.env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(env, [("DB_PASSWORD", REDACTED), ("api_token", REDACTED), ("LANG", "C.UTF-8"), ("EMPTY", "")]);
+
+        let details = decode::<InspectResponse>(INSPECT_JSON.as_bytes()).unwrap().into_details(&redact_regexes(&["^LANG$".to_string()]));
+        assert_eq!(details.env[0].1, "hunter2");
+        assert_eq!(details.env[2].1, REDACTED);
+    }
+
+    #[test]
+    fn invalid_redaction_patterns_are_skipped() {
+        assert_eq!(redact_regexes(&["(unclosed".to_string(), "KEY".to_string()]).len(), 1);
+    }
+
+    #[test]
+    fn inspect_wiring() {
+        let details = decode::<InspectResponse>(INSPECT_JSON.as_bytes()).unwrap().into_details(&default_redaction());
+        assert_eq!(details.name, "shop-web-1");
+        assert_eq!(details.restart_policy, "on-failure:5");
+        assert_eq!(details.entrypoint, ["/docker-entrypoint.sh"]);
+        assert_eq!(details.command, ["nginx", "-g", "daemon off;"]);
+
+        let ports: Vec<(&str, Option<&str>)> = details.ports.iter()
+            .map(|p| (p.container_port.as_str(), p.host_port.as_deref()))
+            .collect();
+        assert_eq!(ports, [("443/tcp", None), ("80/tcp", Some("8080")), ("80/tcp", Some("8080"))]);
+
+        assert_eq!(details.mounts[0].name.as_deref(), Some("shop_data"));
+        assert_eq!(details.mounts[1].name, None);
+        assert!(!details.mounts[1].read_write);
+        assert_eq!(details.networks[0].ip_address, "172.18.0.2");
+
+        let health = details.health.unwrap();
+        assert_eq!((health.status.as_str(), health.failing_streak), ("unhealthy", 3));
+        assert_eq!(health.log[0].output, "curl: (7) refused");
+    }
+
+    #[test]
+    fn podman_4_entrypoint_string() {
+        let json = INSPECT_JSON.replace(r#"["/docker-entrypoint.sh"]"#, r#""/entrypoint.sh""#)
+            .replace(r#""RestartPolicy": {"Name": "on-failure", "MaximumRetryCount": 5}"#, r#""RestartPolicy": {"Name": ""}"#);
+        let details = decode::<InspectResponse>(json.as_bytes()).unwrap().into_details(&[]);
+        assert_eq!(details.entrypoint, ["/entrypoint.sh"]);
+        assert_eq!(details.restart_policy, "no");
+    }
+
+    #[test]
+    fn ids_and_names_are_validated() {
+        for ok in ["8dfafdbc3a40", "shop-web-1", "shop_data", "my.volume"] {
+            assert!(validate_container_id(ok), "{}", ok);
+        }
+        for bad in ["", "../etc", "a/b", "web?force=true", "web 1", "sha256:abc"] {
+            assert!(!validate_container_id(bad), "{}", bad);
+        }
+    }
+
+    /// An unused dangling image, one in use by the stopped `old-job`, and a tagged one.
+    const IMAGES_JSON: &str = r#"[
+        {"Id": "sha256:1111", "RepoTags": ["<none>:<none>"], "Size": 1000, "Created": 1},
+        {"Id": "sha256:ba5dc23f65d4cc4a4535bce55cf9e63b068eb02946e3422d3587e8ce803b6aab", "RepoTags": null, "Size": 2000, "Created": 2},
+        {"Id": "sha256:2222", "RepoTags": ["nginx:1.25"], "Size": 4000, "Created": 3}
+    ]"#;
+
+    const VOLUMES_JSON: &str = r#"{"Volumes": [{"Name": "shop_data", "Driver": "local"}, {"Name": "orphan", "Driver": "local"}]}"#;
+
+    const DF_JSON: &str = r#"{"Volumes": [{"Name": "orphan", "UsageData": {"Size": 512}}, {"Name": "shop_data", "UsageData": {"Size": -1}}],
+        "Containers": [{"Id": "1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d", "SizeRw": 300}]}"#;
+
+    fn prune_daemon(extra: &[(&'static str, u16, &'static str)]) -> FakeDaemon {
+        let mut routes = vec![
+            ("GET /images/json", 200, IMAGES_JSON),
+            ("GET /containers/json", 200, CONTAINERS_JSON),
+            ("GET /volumes", 200, VOLUMES_JSON),
+            ("GET /system/df", 200, DF_JSON),
+        ];
+        routes.extend_from_slice(extra);
+        fake_daemon(&routes)
+    }
+
+    fn deletes(daemon: &FakeDaemon) -> Vec<String> {
+        daemon.requests.lock().unwrap().iter().filter(|r| r.starts_with("DELETE")).cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn prune_preview_lists_only_unused_items() {
+        let daemon = prune_daemon(&[]);
+        let client = DockerClient::new(&daemon.socket, &[]);
+
+        let images = client.prune("images", true).await.unwrap();
+        assert_eq!(images.items.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), ["1111"]);
+        assert_eq!(images.reclaimable_bytes, 1000);
+
+        let containers = client.prune("containers", true).await.unwrap();
+        assert_eq!(containers.items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>(), ["old-job"]);
+        assert_eq!(containers.reclaimable_bytes, 300);
+
+        let volumes = client.prune("volumes", true).await.unwrap();
+        assert_eq!(volumes.items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>(), ["orphan"]);
+        assert_eq!(volumes.reclaimable_bytes, 512);
+
+        assert!(deletes(&daemon).is_empty());
+    }
+
+    #[tokio::test]
+    async fn prune_removes_what_the_preview_showed() {
+        let daemon = prune_daemon(&[
+            ("DELETE /volumes/orphan", 204, ""),
+            ("DELETE /images/1111", 409, r#"{"message":"image is being used"}"#),
+        ]);
+        let client = DockerClient::new(&daemon.socket, &[]);
+
+        let volumes = client.prune("volumes", false).await.unwrap();
+        assert_eq!(volumes.items.len(), 1);
+        assert!(volumes.errors.is_empty());
+
+        let images = client.prune("images", false).await.unwrap();
+        assert!(images.items.is_empty());
+        assert_eq!(images.reclaimable_bytes, 0);
+        assert_eq!(images.errors, ["1111: Docker API error (409): image is being used"]);
+
+        assert_eq!(deletes(&daemon), ["DELETE /volumes/orphan", "DELETE /images/1111"]);
+    }
+
+    #[tokio::test]
+    async fn prune_refuses_unknown_kinds_and_bad_ids() {
+        let daemon = prune_daemon(&[]);
+        let client = DockerClient::new(&daemon.socket, &[]);
+
+        for kind in ["networks", "system", ""] {
+            assert!(matches!(client.prune(kind, true).await, Err(DockerError::InvalidInput(_))));
+        }
+        assert!(matches!(client.remove_image("../containers/web").await, Err(DockerError::InvalidInput(_))));
+        assert!(matches!(client.remove_volume("a/b").await, Err(DockerError::InvalidInput(_))));
+        assert!(daemon.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn events_are_parsed_into_the_buffer() {
+        let events = concat!(
+            r#"{"Type":"container","Action":"die","Actor":{"ID":"8dfa","Attributes":{"name":"shop-web-1","image":"nginx:1.25","exitCode":"137"}},"timeNano":1714557600000000001}"#, "\n",
+            r#"{"Type":"container","Action":"health_status: unhealthy","Actor":{"ID":"8dfa","Attributes":{"name":"shop-web-1","image":"nginx:1.25"}},"timeNano":1714557601000000000}"#, "\n",
+            "not json\n",
+            r#"{"Type":"container","Action":"oom","Actor":{"ID":"1c2d","Attributes":{}},"timeNano":1714557602000000000}"#, "\n",
+        );
+        let daemon = fake_daemon(&[("GET /events", 200, events)]);
+        let buffer = EventBuffer::new();
+        DockerClient::new(&daemon.socket, &[]).watch_events(&buffer).await.unwrap();
+
+        let batch = buffer.since(0);
+        let summary: Vec<(u64, &str, &str, Option<&str>)> = batch.events.iter()
+            .map(|e| (e.seq, e.container_name.as_str(), e.action.as_str(), e.detail.as_deref()))
+            .collect();
+        assert_eq!(summary, [
+            (1, "shop-web-1", "die", Some("137")),
+            (2, "shop-web-1", "health_status", Some("unhealthy")),
+            (3, "1c2d", "oom", None),
+        ]);
+        assert_eq!(batch.events[0].image, "nginx:1.25");
+        assert_eq!(batch.events[0].time_nano, 1714557600000000001);
+
+        let request = daemon.requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("GET /events?filters=%7B%22type%22%3A%5B%22container%22%5D"), "{}", request);
     }
 }
@@ -2,4 +2,7 @@ pub mod logs;
 pub mod monitor;
 pub mod process;
 pub mod services;
-pub mod docker;
\ No newline at end of file
+pub mod docker;
+pub mod podman;
+pub mod runtime;
+pub mod schedules;
\ No newline at end of file
@@ -0,0 +1,720 @@
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use port_sentinel_shared::{
+    ProcessInfo, ContainerRef, ContainerInfo, ContainerStats, ContainerDetails, ContainerLogLine, ContainerEvent, ImageInfo, VolumeInfo,
+    NetworkInfo, RuntimeInfo,
+};
+use crate::system::docker::{
+    DockerError, InspectResponse, LogOptions, COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL,
+    validate_container_id, decode, short_image_id, health_from_status, parse_log_time, redact_regexes,
+};
+use crate::system::runtime::{ContainerRuntime, EventBuffer, RunningContainer, match_processes};
+
+const PODMAN_BIN: &str = "podman";
+
+/// IDs and names are passed as arguments, so on top of the usual check they must not look like flags.
+fn validate_arg(id: &str) -> bool {
+    validate_container_id(id) && !id.starts_with('-')
+}
+
+// === CLI OUTPUT ===
+// Podman's JSON output changed casing between releases, hence the aliases.
+
+/// One entry of `podman ps --format json`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PsEntry {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default, rename = "ImageID")]
+    image_id: String,
+    #[serde(default)]
+    state: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pid: u32,
+    #[serde(default)]
+    ports: Option<Vec<PsPort>>,
+}
+
+#[derive(Deserialize)]
+struct PsPort {
+    #[serde(default)]
+    host_port: u16,
+    #[serde(default)]
+    range: u16,
+}
+
+impl PsEntry {
+    fn display_name(&self) -> String {
+        self.names.first().cloned().unwrap_or_else(|| self.id.clone())
+    }
+
+    fn label(&self, key: &str) -> Option<String> {
+        self.labels.as_ref().and_then(|l| l.get(key)).cloned()
+    }
+
+    fn to_ref(&self) -> ContainerRef {
+        ContainerRef { id: self.id.clone(), name: self.display_name(), image: self.image.clone() }
+    }
+}
+
+impl From<PsEntry> for ContainerInfo {
+    fn from(c: PsEntry) -> Self {
+        ContainerInfo {
+            names: c.display_name(),
+            health: health_from_status(&c.status),
+            project: c.label(COMPOSE_PROJECT_LABEL),
+            service: c.label(COMPOSE_SERVICE_LABEL),
+            id: c.id,
+            image: c.image,
+            status: c.status,
+            state: c.state,
+        }
+    }
+}
+
+/// One entry of `podman stats --no-stream --format json`; values come pre-formatted.
+#[derive(Deserialize)]
+struct StatsEntry {
+    id: String,
+    name: String,
+    #[serde(default)]
+    cpu_percent: String, // "1.23%"
+    #[serde(default)]
+    mem_usage: String, // "12.5MB / 2.1GB"
+    #[serde(default)]
+    net_io: String,
+    #[serde(default)]
+    block_io: String,
+    #[serde(default)]
+    pids: String,
+}
+
+impl StatsEntry {
+    fn into_stats(self) -> ContainerStats {
+        let (mem_usage, mem_limit) = parse_size_pair(&self.mem_usage);
+        let (net_rx, net_tx) = parse_size_pair(&self.net_io);
+        let (block_read, block_write) = parse_size_pair(&self.block_io);
+        ContainerStats {
+            id: self.id,
+            name: self.name,
+            cpu_percent: self.cpu_percent.trim_end_matches('%').trim().parse().unwrap_or(0.0),
+            mem_usage_bytes: mem_usage,
+            mem_limit_bytes: mem_limit,
+            net_rx_bytes: net_rx,
+            net_tx_bytes: net_tx,
+            block_read_bytes: block_read,
+            block_write_bytes: block_write,
+            pids: self.pids.trim().parse().unwrap_or(0),
+        }
+    }
+}
+
+/// Parses "12.5MB / 2.1GB" as printed by `podman stats`.
+fn parse_size_pair(value: &str) -> (u64, u64) {
+    let mut parts = value.split('/').map(parse_size);
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+/// Parses human sizes like "1.5kB", "12MiB" or "0B" into bytes.
+fn parse_size(value: &str) -> u64 {
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let (num, unit) = value.split_at(split);
+    let Ok(num) = num.trim().parse::<f64>() else { return 0 };
+    let factor: f64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024f64.powi(2),
+        "gib" => 1024f64.powi(3),
+        "tib" => 1024f64.powi(4),
+        _ => return 0,
+    };
+    (num * factor) as u64
+}
+
+#[derive(Deserialize)]
+struct ImageEntry {
+    #[serde(alias = "Id")]
+    id: String,
+    #[serde(default, alias = "RepoTags", alias = "Names")]
+    names: Option<Vec<String>>,
+    #[serde(default, alias = "Size")]
+    size: i64,
+    #[serde(default, alias = "Created")]
+    created: i64,
+}
+
+#[derive(Deserialize)]
+struct VolumeEntry {
+    #[serde(alias = "Name")]
+    name: String,
+    #[serde(default, alias = "Driver")]
+    driver: String,
+    #[serde(default, alias = "Mountpoint")]
+    mountpoint: String,
+}
+
+#[derive(Deserialize)]
+struct NetworkEntry {
+    #[serde(default, alias = "Id", alias = "ID")]
+    id: String,
+    #[serde(alias = "Name")]
+    name: String,
+    #[serde(default, alias = "Driver")]
+    driver: String,
+}
+
+/// The mounts and networks of a container, from `podman container inspect`.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct Attachments {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    mounts: Vec<AttachedMount>,
+    #[serde(default)]
+    network_settings: AttachedNetworks,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AttachedMount {
+    #[serde(default, rename = "Type")]
+    kind: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct AttachedNetworks {
+    #[serde(default)]
+    networks: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+/// One line of `podman events --format json`.
+#[derive(Deserialize)]
+struct PodmanEvent {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "Name", default)]
+    name: String,
+    #[serde(rename = "Image", default)]
+    image: String,
+    #[serde(rename = "Status", default)]
+    status: String,
+    #[serde(rename = "Type", default)]
+    kind: String,
+    #[serde(rename = "Time", default)]
+    time: String,
+    #[serde(rename = "timeNano", default)]
+    time_nano: Option<i64>,
+    #[serde(rename = "ContainerExitCode", default)]
+    exit_code: Option<i64>,
+    #[serde(rename = "HealthStatus", default)]
+    health_status: Option<String>,
+}
+
+impl PodmanEvent {
+    /// Maps to the Docker event names the master understands; None for events it doesn't track.
+    fn into_event(self) -> Option<ContainerEvent> {
+        if self.kind != "container" {
+            return None;
+        }
+        let (action, detail) = match self.status.as_str() {
+            "died" => ("die", self.exit_code.map(|c| c.to_string())),
+            "health_status" => ("health_status", self.health_status.clone()),
+            "start" | "stop" | "restart" | "oom" => (self.status.as_str(), None),
+            _ => return None,
+        };
+        let time_nano = self.time_nano
+            .or_else(|| chrono::DateTime::parse_from_rfc3339(&self.time).ok().and_then(|t| t.timestamp_nanos_opt()))
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0));
+
+        Some(ContainerEvent {
+            seq: 0,
+            time_nano,
+            action: action.to_string(),
+            detail,
+            container_name: if self.name.is_empty() { self.id.clone() } else { self.name },
+            container_id: self.id,
+            image: self.image,
+        })
+    }
+}
+
+// === CLIENT ===
+
+/// Drives the `podman` CLI, for hosts without the Podman API socket enabled.
+pub struct PodmanCli {
+    redact_env: Vec<Regex>,
+    version: Option<String>,
+}
+
+impl PodmanCli {
+    /// Returns the CLI runtime when `podman` is installed and answers.
+    pub async fn detect(redact_patterns: &[String]) -> Option<Self> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Version { client: VersionClient }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct VersionClient { version: String }
+
+        let cli = PodmanCli { redact_env: redact_regexes(redact_patterns), version: None };
+        let out = cli.run(&["version", "--format", "json"]).await.ok()?;
+        let version: Option<Version> = serde_json::from_slice(&out).ok();
+        Some(PodmanCli { version: version.map(|v| v.client.version), ..cli })
+    }
+
+    /// Runs podman and returns stdout, turning a failed command into an API-like error.
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>, DockerError> {
+        let output = Command::new(PODMAN_BIN)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| DockerError::Unreachable(format!("Failed to execute podman: {}", e)))?;
+
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(command_error(&output.stderr))
+        }
+    }
+
+    async fn ps(&self, all: bool) -> Result<Vec<PsEntry>, DockerError> {
+        let mut args = vec!["ps", "--format", "json"];
+        if all {
+            args.push("--all");
+        }
+        let out = self.run(&args).await?;
+        // Older releases print nothing at all when there are no containers
+        if out.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(vec![]);
+        }
+        decode(&out)
+    }
+
+    /// Mounts and networks of every container, for the volume and network lists.
+    async fn attachments(&self) -> Result<Vec<Attachments>, DockerError> {
+        let ids: Vec<String> = self.ps(true).await?.into_iter().map(|c| c.id).collect();
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut args = vec!["container", "inspect", "--"];
+        args.extend(ids.iter().map(|s| s.as_str()));
+        decode(&self.run(&args).await?)
+    }
+
+    fn logs_args(id: &str, opts: &LogOptions, follow: bool) -> Result<Vec<String>, DockerError> {
+        if !validate_arg(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+        let tail = opts.tail.as_deref().unwrap_or("100");
+        let tail = match tail {
+            "all" => "-1",
+            t if t.parse::<u32>().is_ok() => t,
+            _ => return Err(DockerError::InvalidInput("tail must be a number or 'all'".to_string())),
+        };
+
+        // Timestamps are always requested so stdout and stderr can be merged in order
+        let mut args = vec!["logs".to_string(), "--timestamps".to_string(), "--tail".to_string(), tail.to_string()];
+        for (key, value) in [("since", &opts.since), ("until", &opts.until)] {
+            if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+                let ts = parse_log_time(value)
+                    .ok_or_else(|| DockerError::InvalidInput(format!("Invalid {} value '{}'", key, value)))?;
+                args.push(format!("--{}", key));
+                args.push(ts.to_string());
+            }
+        }
+        if follow {
+            args.push("--follow".to_string());
+        }
+        args.push("--".to_string());
+        args.push(id.to_string());
+        Ok(args)
+    }
+}
+
+/// Maps podman's "Error: ..." output to the status the Engine API would have answered with.
+fn command_error(stderr: &[u8]) -> DockerError {
+    let message = String::from_utf8_lossy(stderr).trim().trim_start_matches("Error: ").to_string();
+    let lower = message.to_lowercase();
+    let status = if lower.contains("no such") || lower.contains("not found") || lower.contains("no container with") {
+        404
+    } else if lower.contains("in use") || lower.contains("being used") {
+        409
+    } else {
+        500
+    };
+    DockerError::Api { status, message }
+}
+
+/// Splits "<RFC 3339 time> <text>" and drops the time again unless it was asked for.
+fn log_line(stream: &str, raw: &str, timestamps: bool) -> (i64, ContainerLogLine) {
+    let raw = raw.trim_end_matches('\r');
+    let (ts, text) = match raw.split_once(' ') {
+        Some((ts, text)) if chrono::DateTime::parse_from_rfc3339(ts).is_ok() => (Some(ts), text),
+        _ => (None, raw),
+    };
+    let sort_key = ts.and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .and_then(|t| t.timestamp_nanos_opt())
+        .unwrap_or(i64::MAX);
+
+    (sort_key, ContainerLogLine {
+        stream: stream.to_string(),
+        timestamp: ts.filter(|_| timestamps).map(|t| t.to_string()),
+        text: text.to_string(),
+    })
+}
+
+#[async_trait]
+impl ContainerRuntime for PodmanCli {
+    fn info(&self) -> RuntimeInfo {
+        let features = ["containers", "stats", "logs", "logs_follow", "inspect", "control", "images", "volumes", "networks", "compose", "events"];
+        RuntimeInfo {
+            runtime: "podman".to_string(),
+            version: self.version.clone(),
+            mode: "cli".to_string(),
+            endpoint: PODMAN_BIN.to_string(),
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerInfo>, DockerError> {
+        Ok(self.ps(true).await?.into_iter().map(ContainerInfo::from).collect())
+    }
+
+    async fn attach_containers(&self, procs: &mut [ProcessInfo]) -> Result<(), DockerError> {
+        let running: Vec<RunningContainer> = self.ps(false).await?.iter().map(|c| RunningContainer {
+            container: c.to_ref(),
+            // A published range covers host_port..host_port+range
+            host_ports: c.ports.iter().flatten()
+                .flat_map(|p| (0..p.range.max(1)).map(move |i| p.host_port.saturating_add(i)))
+                .filter(|p| *p != 0)
+                .collect(),
+            init_pid: c.pid,
+        }).collect();
+
+        match_processes(procs, &running);
+        Ok(())
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerDetails, DockerError> {
+        if !validate_arg(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+        let mut list: Vec<InspectResponse> = decode(&self.run(&["container", "inspect", "--", id]).await?)?;
+        if list.is_empty() {
+            return Err(DockerError::Api { status: 404, message: format!("No such container: {}", id) });
+        }
+        Ok(list.remove(0).into_details(&self.redact_env))
+    }
+
+    async fn list_container_stats(&self) -> Result<Vec<ContainerStats>, DockerError> {
+        let out = self.run(&["stats", "--no-stream", "--format", "json"]).await?;
+        if out.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(vec![]);
+        }
+        let entries: Vec<StatsEntry> = decode(&out)?;
+        let mut stats: Vec<ContainerStats> = entries.into_iter().map(StatsEntry::into_stats).collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(stats)
+    }
+
+    /// podman mirrors the container's stdout/stderr on its own, the two are merged by timestamp.
+    async fn get_container_logs(&self, id: &str, opts: &LogOptions) -> Result<Vec<ContainerLogLine>, DockerError> {
+        let args = PodmanCli::logs_args(id, opts, false)?;
+        let output = Command::new(PODMAN_BIN)
+            .args(&args)
+            .output()
+            .await
+            .map_err(|e| DockerError::Unreachable(format!("Failed to execute podman: {}", e)))?;
+        if !output.status.success() {
+            return Err(command_error(&output.stderr));
+        }
+
+        let mut lines: Vec<(i64, ContainerLogLine)> = Vec::new();
+        for (stream, raw) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            for line in String::from_utf8_lossy(raw).lines() {
+                lines.push(log_line(stream, line, opts.timestamps));
+            }
+        }
+        // Stable, so lines with equal (or missing) timestamps keep their order within a stream
+        lines.sort_by_key(|(ts, _)| *ts);
+        Ok(lines.into_iter().map(|(_, l)| l).collect())
+    }
+
+    async fn follow_container_logs(&self, id: &str, opts: &LogOptions) -> Result<mpsc::Receiver<ContainerLogLine>, DockerError> {
+        // Unknown containers fail fast here instead of ending an empty stream
+        self.run(&["container", "exists", "--", id]).await
+            .map_err(|_| DockerError::Api { status: 404, message: format!("No such container: {}", id) })?;
+
+        let args = PodmanCli::logs_args(id, opts, true)?;
+        let mut child = Command::new(PODMAN_BIN)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| DockerError::Unreachable(format!("Failed to execute podman: {}", e)))?;
+
+        let (tx, rx) = mpsc::channel(64);
+        let timestamps = opts.timestamps;
+        let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+        tokio::spawn(async move {
+            let (mut out_open, mut err_open) = (true, true);
+            while out_open || err_open {
+                let (stream, line) = tokio::select! {
+                    line = stdout.next_line(), if out_open => ("stdout", line),
+                    line = stderr.next_line(), if err_open => ("stderr", line),
+                };
+                match line {
+                    Ok(Some(line)) => {
+                        // The client went away; dropping `child` stops podman
+                        if tx.send(log_line(stream, &line, timestamps).1).await.is_err() {
+                            return;
+                        }
+                    }
+                    _ if stream == "stdout" => out_open = false,
+                    _ => err_open = false,
+                }
+            }
+            let _ = child.wait().await;
+        });
+        Ok(rx)
+    }
+
+    async fn control_container(&self, id: &str, action: &str) -> Result<String, DockerError> {
+        if !validate_arg(id) {
+            return Err(DockerError::InvalidInput("Invalid container ID".to_string()));
+        }
+        if !matches!(action, "start" | "stop" | "restart" | "rm") {
+            return Err(DockerError::InvalidInput("Invalid action".to_string()));
+        }
+
+        self.run(&[action, "--", id]).await?;
+        Ok(format!("Successfully executed {} on {}", action, id))
+    }
+
+    async fn list_images(&self) -> Result<Vec<ImageInfo>, DockerError> {
+        let images: Vec<ImageEntry> = decode(&self.run(&["images", "--format", "json"]).await?)?;
+        let containers = self.ps(true).await?;
+
+        Ok(images.into_iter().map(|img| {
+            let id = short_image_id(&img.id);
+            let tags: Vec<String> = img.names.unwrap_or_default()
+                .into_iter()
+                .filter(|t| !t.starts_with("<none>"))
+                .collect();
+            let users = containers.iter()
+                .filter(|c| !c.image_id.is_empty() && id.starts_with(short_image_id(&c.image_id).as_str()))
+                .map(|c| c.display_name())
+                .collect();
+            ImageInfo {
+                dangling: tags.is_empty(),
+                id,
+                tags,
+                size_bytes: img.size.max(0) as u64,
+                created: img.created,
+                containers: users,
+            }
+        }).collect())
+    }
+
+    async fn list_volumes(&self) -> Result<Vec<VolumeInfo>, DockerError> {
+        let volumes: Vec<VolumeEntry> = decode(&self.run(&["volume", "ls", "--format", "json"]).await?)?;
+        let attachments = self.attachments().await?;
+
+        Ok(volumes.into_iter().map(|v| VolumeInfo {
+            containers: attachments.iter()
+                .filter(|a| a.mounts.iter().any(|m| m.kind == "volume" && m.name.as_deref() == Some(v.name.as_str())))
+                .map(|a| a.name.trim_start_matches('/').to_string())
+                .collect(),
+            // The CLI has no cheap way to size a volume
+            size_bytes: None,
+            name: v.name,
+            driver: v.driver,
+            mountpoint: v.mountpoint,
+        }).collect())
+    }
+
+    async fn list_networks(&self) -> Result<Vec<NetworkInfo>, DockerError> {
+        let networks: Vec<NetworkEntry> = decode(&self.run(&["network", "ls", "--format", "json"]).await?)?;
+        let attachments = self.attachments().await?;
+
+        Ok(networks.into_iter().map(|n| NetworkInfo {
+            containers: attachments.iter()
+                .filter(|a| a.network_settings.networks.as_ref().map(|nets| nets.contains_key(&n.name)).unwrap_or(false))
+                .map(|a| a.name.trim_start_matches('/').to_string())
+                .collect(),
+            id: n.id,
+            name: n.name,
+            driver: n.driver,
+            // Podman networks are always local to the host
+            scope: "local".to_string(),
+        }).collect())
+    }
+
+    async fn remove_image(&self, id: &str) -> Result<String, DockerError> {
+        if !validate_arg(id) {
+            return Err(DockerError::InvalidInput("Invalid image ID".to_string()));
+        }
+        self.run(&["rmi", "--", id]).await?;
+        Ok(format!("Removed image {}", id))
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<String, DockerError> {
+        if !validate_arg(name) {
+            return Err(DockerError::InvalidInput("Invalid volume name".to_string()));
+        }
+        self.run(&["volume", "rm", "--", name]).await?;
+        Ok(format!("Removed volume {}", name))
+    }
+
+    async fn watch_events(&self, buffer: &EventBuffer) -> Result<(), DockerError> {
+        let mut child = Command::new(PODMAN_BIN)
+            .args(["events", "--format", "json", "--filter", "type=container"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| DockerError::Unreachable(format!("Failed to execute podman: {}", e)))?;
+
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(event) = serde_json::from_str::<PodmanEvent>(&line).ok().and_then(PodmanEvent::into_event) {
+                buffer.push(event);
+            }
+        }
+        let _ = child.wait().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[test]
+    fn arguments_must_not_look_like_flags() {
+        assert!(validate_arg("web-1"));
+        assert!(!validate_arg("--rm"));
+        assert!(!validate_arg("-f"));
+        assert!(!validate_arg("web;ls"));
+    }
+
+    #[test]
+    fn parses_podman_ps() {
+        let out = r#"[{"Id": "3f2a", "Names": ["shop-db-1"], "Image": "docker.io/library/postgres:16", "ImageID": "9b1c",
+            "State": "running", "Status": "Up 5 minutes (healthy)", "Pid": 4242,
+            "Labels": {"com.docker.compose.project": "shop", "com.docker.compose.service": "db"},
+            "Ports": [{"host_ip": "", "container_port": 5432, "host_port": 5432, "range": 1, "protocol": "tcp"}]}]"#;
+        let info: Vec<ContainerInfo> = decode::<Vec<PsEntry>>(out.as_bytes()).unwrap().into_iter().map(ContainerInfo::from).collect();
+        assert_eq!(info[0].names, "shop-db-1");
+        assert_eq!(info[0].health.as_deref(), Some("healthy"));
+        assert_eq!(info[0].project.as_deref(), Some("shop"));
+        assert_eq!(info[0].service.as_deref(), Some("db"));
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(parse_size("0B"), 0);
+        assert_eq!(parse_size("1.5kB"), 1500);
+        assert_eq!(parse_size("12MiB"), 12 * 1024 * 1024);
+        assert_eq!(parse_size(" 2.1GB "), 2_100_000_000);
+        assert_eq!(parse_size("lots"), 0);
+        assert_eq!(parse_size("3 parsecs"), 0);
+        assert_eq!(parse_size_pair("12.5MB / 2.1GB"), (12_500_000, 2_100_000_000));
+        assert_eq!(parse_size_pair("--"), (0, 0));
+    }
+
+    #[test]
+    fn parses_podman_stats() {
+        let out = r#"[{"id": "3f2a", "name": "shop-db-1", "cpu_percent": "12.50%", "mem_usage": "64MiB / 1GiB",
+            "net_io": "1kB / 2kB", "block_io": "0B / 4MB", "pids": "9"}]"#;
+        let stats = decode::<Vec<StatsEntry>>(out.as_bytes()).unwrap().remove(0).into_stats();
+        assert_eq!(stats.cpu_percent, 12.5);
+        assert_eq!((stats.mem_usage_bytes, stats.mem_limit_bytes), (64 * 1024 * 1024, 1024 * 1024 * 1024));
+        assert_eq!((stats.net_rx_bytes, stats.net_tx_bytes), (1000, 2000));
+        assert_eq!((stats.block_read_bytes, stats.block_write_bytes), (0, 4_000_000));
+        assert_eq!(stats.pids, 9);
+    }
+
+    #[test]
+    fn events_use_docker_names() {
+        let event = |json: &str| serde_json::from_str::<PodmanEvent>(json).unwrap().into_event();
+
+        let died = event(r#"{"ID": "3f2a", "Name": "db", "Image": "postgres", "Status": "died", "Type": "container",
+            "Time": "2024-05-01T10:00:00Z", "ContainerExitCode": 137}"#).unwrap();
+        assert_eq!((died.action.as_str(), died.detail.as_deref()), ("die", Some("137")));
+        assert_eq!(died.time_nano, 1714557600 * 1_000_000_000);
+
+        let health = event(r#"{"ID": "3f2a", "Status": "health_status", "Type": "container", "timeNano": 5, "HealthStatus": "unhealthy"}"#).unwrap();
+        assert_eq!((health.action.as_str(), health.detail.as_deref(), health.time_nano), ("health_status", Some("unhealthy"), 5));
+        assert_eq!(health.container_name, "3f2a");
+
+        assert!(event(r#"{"ID": "3f2a", "Status": "attach", "Type": "container"}"#).is_none());
+        assert!(event(r#"{"ID": "9b1c", "Status": "pull", "Type": "image"}"#).is_none());
+    }
+
+    #[test]
+    fn command_errors_map_to_engine_statuses() {
+        let status = |stderr: &str| command_error(stderr.as_bytes()).status_code();
+        assert_eq!(status("Error: no container with name or ID \"web\" found: no such container"), StatusCode::NOT_FOUND);
+        assert_eq!(status("Error: image used by 3f2a: image is in use by a container"), StatusCode::CONFLICT);
+        assert_eq!(status("Error: OCI runtime error"), StatusCode::BAD_GATEWAY);
+        assert_eq!(command_error(b"Error: boom\n").to_string(), "Docker API error (500): boom");
+    }
+
+    #[test]
+    fn log_arguments() {
+        let opts = LogOptions { tail: Some("all".to_string()), since: Some("1714557600".to_string()), ..Default::default() };
+        assert_eq!(PodmanCli::logs_args("web", &opts, true).unwrap(),
+            ["logs", "--timestamps", "--tail", "-1", "--since", "1714557600", "--follow", "--", "web"]);
+
+        let bad_tail = LogOptions { tail: Some("-1".to_string()), ..Default::default() };
+        assert!(PodmanCli::logs_args("web", &bad_tail, false).is_err());
+        assert!(PodmanCli::logs_args("--privileged", &LogOptions::default(), false).is_err());
+    }
+
+    #[test]
+    fn log_lines_keep_order_keys_and_drop_unasked_timestamps() {
+        let (key, line) = log_line("stderr", "2024-05-01T10:00:00Z oops\r", false);
+        assert_eq!(key, 1714557600 * 1_000_000_000);
+        assert_eq!((line.stream.as_str(), line.timestamp, line.text.as_str()), ("stderr", None, "oops"));
+
+        let (key, line) = log_line("stdout", "no time here", true);
+        assert_eq!(key, i64::MAX);
+        assert_eq!((line.timestamp, line.text.as_str()), (None, "no time here"));
+    }
+
+    #[tokio::test]
+    async fn missing_features_report_the_runtime() {
+        let cli = PodmanCli { redact_env: vec![], version: Some("4.9.3".to_string()) };
+        let err = cli.prune("images", true).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(err.to_string(), "Prune is not supported by podman (cli)");
+        assert!(!cli.info().features.contains(&"prune".to_string()));
+    }
+}
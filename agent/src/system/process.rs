@@ -36,13 +36,19 @@ pub fn scan_ports() -> Vec<ProcessInfo> {
                 name,
                 port,
                 raw_line: line.to_string(),
+                container: None,
             });
         }
     }
     bucket
 }
 
-pub fn kill_process(line: &str) {
+/// A PID `kill` can take: a positive number, not a process group like "-1" (every process).
+pub fn valid_pid(pid: &str) -> bool {
+    !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()) && pid.bytes().any(|b| b != b'0')
+}
+
+pub fn kill_process(line: &str) -> Result<(), String> {
     let mut parts = line.split_whitespace();
     let pid_options = if cfg!(target_os = "windows") {
         parts.last()
@@ -50,16 +56,79 @@ pub fn kill_process(line: &str) {
         parts.nth(1)
     };
 
-    if let Some(pid) = pid_options {
-        let mut command = if cfg!(target_os = "windows") {
-            let mut cmd = Command::new("taskkill");
-            cmd.args(["/F", "/PID"]);
-            cmd
-        } else {
-            let mut cmd = Command::new("kill");
-            cmd.arg("-9");
-            cmd
-        };
-        let _ = command.arg(pid).output();
+    let pid = pid_options.filter(|pid| valid_pid(pid)).ok_or("Invalid PID")?;
+    let mut command = if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("taskkill");
+        cmd.args(["/F", "/PID"]);
+        cmd
+    } else {
+        let mut cmd = Command::new("kill");
+        cmd.arg("-9");
+        cmd
+    };
+    let output = command.arg(pid).output().map_err(|e| format!("Cannot run kill: {}", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    // e.g. "kill: (4242) - No such process", "Operation not permitted"
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(if stderr.is_empty() { format!("kill failed ({})", output.status) } else { stderr })
+}
+
+/// Extracts the local port from an lsof NAME column like "*:8080 (LISTEN)"
+/// or "10.0.0.5:443->10.0.0.9:51234 (ESTABLISHED)".
+pub fn local_port(info: &ProcessInfo) -> Option<u16> {
+    let name = info.raw_line.split_whitespace().find(|t| t.contains(':'))?;
+    let local = name.split("->").next()?;
+    local.rsplit(':').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lsof(line: &str) -> ProcessInfo {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        ProcessInfo {
+            pid: parts[1].to_string(),
+            name: parts[0].to_string(),
+            port: parts.last().unwrap().to_string(),
+            raw_line: line.to_string(),
+            container: None,
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn local_ports_from_lsof_names() {
+        assert_eq!(local_port(&lsof("docker-pr 1234 root 4u IPv4 0x1 0t0 TCP *:8080 (LISTEN)")), Some(8080));
+        assert_eq!(local_port(&lsof("docker-pr 1235 root 4u IPv6 0x2 0t0 TCP [::]:8080 (LISTEN)")), Some(8080));
+        assert_eq!(local_port(&lsof("nginx 77 www 9u IPv4 0x3 0t0 TCP 10.0.0.5:443->10.0.0.9:51234 (ESTABLISHED)")), Some(443));
+        assert_eq!(local_port(&lsof("chronyd 600 chrony 5u IPv4 0x4 0t0 UDP localhost:323")), Some(323));
+        assert_eq!(local_port(&lsof("odd 1 root 4u unix 0x5 0t0 socket")), None);
+    }
+
+    #[test]
+    fn pids_kill_can_take() {
+        for ok in ["1", "4242", "007"] {
+            assert!(valid_pid(ok), "{}", ok);
+        }
+        for bad in ["", "0", "000", "-1", "-9", "12a", "1 2", "+5"] {
+            assert!(!valid_pid(bad), "{}", bad);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kill_reports_the_real_outcome() {
+        assert_eq!(kill_process("fake_name -1"), Err("Invalid PID".to_string()));
+        assert_eq!(kill_process("fake_name"), Err("Invalid PID".to_string()));
+
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        assert_eq!(kill_process(&format!("fake_name {}", child.id())), Ok(()));
+        assert!(!child.wait().unwrap().success());
+
+        // Above the kernel's PID limit, so nothing can have it
+        let err = kill_process("fake_name 4194305").unwrap_err();
+        assert!(err.contains("No such process"), "{}", err);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use port_sentinel_shared::{
+    ProcessInfo, ContainerRef, ContainerInfo, ContainerStats, ContainerDetails, ContainerLogLine, ImageInfo, VolumeInfo, NetworkInfo,
+    PruneReport, ContainerEvent, ContainerEventBatch, RuntimeInfo,
+};
+use crate::config::AgentConfig;
+use crate::system::docker::{DockerClient, DockerError, LogOptions};
+use crate::system::podman::PodmanCli;
+
+/// Well-known locations of Podman's Docker-compatible API socket (rootful, then rootless).
+const PODMAN_SOCKETS: [&str; 2] = ["/run/podman/podman.sock", "/var/run/podman/podman.sock"];
+
+/// Everything the agent needs from a container engine. Docker and Podman (through its
+/// compatible socket) are served by `DockerClient`, Podman without the socket by `PodmanCli`.
+/// Optional features default to an "unsupported" error and are left out of `info().features`.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    /// Which runtime this is and what it supports, for the capabilities endpoint.
+    fn info(&self) -> RuntimeInfo;
+
+    async fn list_containers(&self) -> Result<Vec<ContainerInfo>, DockerError>;
+
+    /// Fills `ProcessInfo::container` for processes (or port proxies) belonging to a container.
+    async fn attach_containers(&self, procs: &mut [ProcessInfo]) -> Result<(), DockerError>;
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerDetails, DockerError>;
+
+    async fn list_container_stats(&self) -> Result<Vec<ContainerStats>, DockerError>;
+
+    async fn get_container_logs(&self, id: &str, opts: &LogOptions) -> Result<Vec<ContainerLogLine>, DockerError>;
+
+    /// Streams new log lines until the container stops or the receiver is dropped.
+    async fn follow_container_logs(&self, _id: &str, _opts: &LogOptions) -> Result<mpsc::Receiver<ContainerLogLine>, DockerError> {
+        Err(unsupported(self.info(), "Following logs"))
+    }
+
+    async fn control_container(&self, id: &str, action: &str) -> Result<String, DockerError>;
+
+    async fn list_images(&self) -> Result<Vec<ImageInfo>, DockerError>;
+    async fn list_volumes(&self) -> Result<Vec<VolumeInfo>, DockerError>;
+    async fn list_networks(&self) -> Result<Vec<NetworkInfo>, DockerError>;
+    async fn remove_image(&self, id: &str) -> Result<String, DockerError>;
+    async fn remove_volume(&self, name: &str) -> Result<String, DockerError>;
+
+    async fn prune(&self, _kind: &str, _dry_run: bool) -> Result<PruneReport, DockerError> {
+        Err(unsupported(self.info(), "Prune"))
+    }
+
+    /// Runs start/stop/restart on every container of a compose project.
+    async fn control_project(&self, project: &str, action: &str) -> Result<String, DockerError> {
+        if !matches!(action, "start" | "stop" | "restart") {
+            return Err(DockerError::InvalidInput("Invalid action".to_string()));
+        }
+
+        let containers: Vec<ContainerInfo> = self.list_containers().await?
+            .into_iter()
+            .filter(|c| c.project.as_deref() == Some(project))
+            .collect();
+        if containers.is_empty() {
+            return Err(DockerError::Api { status: 404, message: format!("No containers found for {}", project) });
+        }
+
+        let mut errors = Vec::new();
+        for c in &containers {
+            if let Err(e) = self.control_container(&c.id, action).await {
+                errors.push(format!("{}: {}", c.names, e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(format!("Successfully executed {} on {} containers of {}", action, containers.len(), project))
+        } else {
+            Err(DockerError::Api { status: 502, message: errors.join("; ") })
+        }
+    }
+
+    async fn recreate_service(&self, _project: &str, _service: &str, _pull: bool) -> Result<String, DockerError> {
+        Err(unsupported(self.info(), "Recreating services"))
+    }
+
+    /// Follows the runtime's event stream into `buffer`. Returns when the stream ends, the caller reconnects.
+    async fn watch_events(&self, _buffer: &EventBuffer) -> Result<(), DockerError> {
+        Err(unsupported(self.info(), "Events"))
+    }
+}
+
+fn unsupported(info: RuntimeInfo, what: &str) -> DockerError {
+    DockerError::Unsupported(format!("{} is not supported by {} ({})", what, info.runtime, info.mode))
+}
+
+/// Picks the container runtime at startup. With `container_runtime` set to "auto" this tries the
+/// configured Docker socket, then Podman's compatible sockets, then the `podman` CLI. When nothing
+/// answers, the Docker client is kept so requests explain that the daemon is unreachable.
+pub async fn detect(config: &AgentConfig) -> Box<dyn ContainerRuntime> {
+    let choice = config.container_runtime.as_str();
+
+    let mut sockets: Vec<String> = Vec::new();
+    if choice != "podman" {
+        sockets.push(config.docker_socket.clone());
+    }
+    if choice != "docker" {
+        sockets.extend(PODMAN_SOCKETS.iter().map(|s| s.to_string()));
+        if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+            sockets.push(format!("{}/podman/podman.sock", dir));
+        }
+    }
+
+    for socket in sockets {
+        if !std::path::Path::new(&socket).exists() {
+            continue;
+        }
+        if let Some(client) = DockerClient::new(&socket, &config.redact_env_patterns).probe().await {
+            return Box::new(client);
+        }
+    }
+
+    if choice != "docker" && let Some(cli) = PodmanCli::detect(&config.redact_env_patterns).await {
+        return Box::new(cli);
+    }
+
+    Box::new(DockerClient::new(&config.docker_socket, &config.redact_env_patterns))
+}
+
+// === EVENTS ===
+
+/// Number of events kept for the master to collect.
+const EVENT_BUFFER_SIZE: usize = 1000;
+
+/// Recent container events, numbered so the master can ask for everything after the last one it saw.
+pub struct EventBuffer {
+    stream_id: String,
+    inner: Mutex<(u64, VecDeque<ContainerEvent>)>,
+}
+
+impl EventBuffer {
+    pub fn new() -> Self {
+        EventBuffer {
+            stream_id: chrono::Utc::now().timestamp_millis().to_string(),
+            inner: Mutex::new((0, VecDeque::with_capacity(EVENT_BUFFER_SIZE))),
+        }
+    }
+
+    /// Records an event; `seq` is assigned here.
+    pub fn push(&self, mut event: ContainerEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.0 += 1;
+        event.seq = inner.0;
+        if inner.1.len() == EVENT_BUFFER_SIZE {
+            inner.1.pop_front();
+        }
+        inner.1.push_back(event);
+    }
+
+    pub fn since(&self, seq: u64) -> ContainerEventBatch {
+        let inner = self.inner.lock().unwrap();
+        ContainerEventBatch {
+            stream_id: self.stream_id.clone(),
+            events: inner.1.iter().filter(|e| e.seq > seq).cloned().collect(),
+        }
+    }
+}
+
+// === PROCESS -> CONTAINER MATCHING ===
+
+/// A running container as needed to find its processes.
+pub struct RunningContainer {
+    pub container: ContainerRef,
+    pub host_ports: Vec<u16>,
+    /// PID of the container's init process on the host, 0 when unknown
+    pub init_pid: u32,
+}
+
+/// Marks processes that belong to one of `running`: port proxies by the host port they listen on,
+/// everything else by sharing the PID namespace of the container's init process.
+pub fn match_processes(procs: &mut [ProcessInfo], running: &[RunningContainer]) {
+    if running.is_empty() {
+        return;
+    }
+
+    let mut by_host_port: HashMap<u16, &ContainerRef> = HashMap::new();
+    for c in running {
+        for port in &c.host_ports {
+            by_host_port.insert(*port, &c.container);
+        }
+    }
+
+    // Containers run with --pid=host share our namespace and can't be told apart that way
+    let host_ns = std::fs::read_link("/proc/self/ns/pid").ok().map(|p| p.to_string_lossy().to_string());
+    let by_pid_ns: HashMap<String, &ContainerRef> = running.iter()
+        .filter_map(|c| Some((pid_namespace(c.init_pid)?, &c.container)))
+        .filter(|(ns, _)| Some(ns) != host_ns.as_ref())
+        .collect();
+
+    // lsof truncates COMMAND to 9 characters
+    let proxies = ["docker-pr", "container", "rootlessp", "conmon"];
+
+    for p in procs.iter_mut() {
+        let is_proxy = proxies.iter().any(|prefix| p.name.starts_with(prefix));
+        let by_port = if is_proxy {
+            crate::system::process::local_port(p).and_then(|port| by_host_port.get(&port))
+        } else {
+            None
+        };
+        let by_ns = || p.pid.parse::<u32>().ok().and_then(pid_namespace).and_then(|ns| by_pid_ns.get(&ns));
+
+        p.container = by_port.or_else(by_ns).map(|c| (*c).clone());
+    }
+}
+
+/// Reads the PID namespace of a process, e.g. "pid:[4026532281]". Needs the agent to share the host PID namespace.
+fn pid_namespace(pid: u32) -> Option<String> {
+    if pid == 0 {
+        return None;
+    }
+    std::fs::read_link(format!("/proc/{}/ns/pid", pid)).ok().map(|p| p.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lsof(line: &str) -> ProcessInfo {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        ProcessInfo {
+            pid: parts[1].to_string(),
+            name: parts[0].to_string(),
+            port: parts.last().unwrap().to_string(),
+            raw_line: line.to_string(),
+            container: None,
+        }
+    }
+
+    fn running(name: &str, host_ports: &[u16], init_pid: u32) -> RunningContainer {
+        RunningContainer {
+            container: ContainerRef { id: format!("{}-id", name), name: name.to_string(), image: format!("{}:latest", name) },
+            host_ports: host_ports.to_vec(),
+            init_pid,
+        }
+    }
+
+    fn owners(procs: &[ProcessInfo]) -> Vec<Option<&str>> {
+        procs.iter().map(|p| p.container.as_ref().map(|c| c.name.as_str())).collect()
+    }
+
+    #[test]
+    fn port_proxies_are_matched_by_published_port() {
+        let mut procs = [
+            lsof("docker-pr 1234 root 4u IPv4 0x1 0t0 TCP *:8080 (LISTEN)"),
+            lsof("docker-pr 1235 root 4u IPv6 0x2 0t0 TCP [::]:5432 (LISTEN)"),
+            lsof("rootlessp 1236 me 10u IPv4 0x3 0t0 TCP *:9000 (LISTEN)"),
+            lsof("docker-pr 1237 root 4u IPv4 0x4 0t0 TCP *:9999 (LISTEN)"),
+            lsof("nginx 1238 root 6u IPv4 0x5 0t0 TCP *:8080 (LISTEN)"),
+        ];
+        match_processes(&mut procs, &[running("api-gateway", &[8080], 0), running("db", &[5432, 9000], 0)]);
+        assert_eq!(owners(&procs), [Some("api-gateway"), Some("db"), Some("db"), None, None]);
+        assert_eq!(procs[0].container.as_ref().unwrap().image, "api-gateway:latest");
+    }
+
+    #[test]
+    fn containers_sharing_the_host_pid_namespace_are_not_matched() {
+        // Our own PID stands in for a container started with --pid=host
+        let me = std::process::id();
+        let mut procs = [lsof(&format!("agent {} root 7u IPv4 0x1 0t0 TCP *:3001 (LISTEN)", me))];
+        match_processes(&mut procs, &[running("host-pid", &[], me)]);
+        assert_eq!(owners(&procs), [None]);
+    }
+
+    #[test]
+    fn nothing_running_leaves_processes_alone() {
+        let mut procs = [lsof("docker-pr 1234 root 4u IPv4 0x1 0t0 TCP *:8080 (LISTEN)")];
+        match_processes(&mut procs, &[]);
+        assert_eq!(owners(&procs), [None]);
+    }
+
+    fn event(action: &str) -> ContainerEvent {
+        ContainerEvent {
+            seq: 0,
+            time_nano: 0,
+            container_id: "8dfa".to_string(),
+            container_name: "web".to_string(),
+            image: "nginx".to_string(),
+            action: action.to_string(),
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn event_buffer_numbers_events_and_returns_the_newer_ones() {
+        let buffer = EventBuffer::new();
+        for action in ["start", "die", "restart"] {
+            buffer.push(event(action));
+        }
+        let all = buffer.since(0);
+        assert_eq!(all.events.iter().map(|e| e.seq).collect::<Vec<_>>(), [1, 2, 3]);
+        let newer = buffer.since(2);
+        assert_eq!(newer.events.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(), ["restart"]);
+        assert_eq!(newer.stream_id, all.stream_id);
+        assert!(buffer.since(3).events.is_empty());
+    }
+
+    #[test]
+    fn event_buffer_drops_the_oldest_when_full() {
+        let buffer = EventBuffer::new();
+        for _ in 0..EVENT_BUFFER_SIZE + 5 {
+            buffer.push(event("die"));
+        }
+        let events = buffer.since(0).events;
+        assert_eq!(events.len(), EVENT_BUFFER_SIZE);
+        assert_eq!(events[0].seq, 6);
+        assert_eq!(events.last().unwrap().seq, EVENT_BUFFER_SIZE as u64 + 5);
+    }
+}
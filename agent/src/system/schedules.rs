@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use chrono::{Datelike, Duration, Local, TimeZone, Timelike};
+use regex::Regex;
+use port_sentinel_shared::ScheduledJob;
+
+/// A timer whose next elapse is this far in the past is reported as missed.
+const MISSED_GRACE_SECS: i64 = 120;
+
+// === SYSTEMD TIMERS ===
+
+pub fn list_timers() -> Vec<ScheduledJob> {
+    let output = Command::new("systemctl")
+        .args(["list-units", "--type=timer", "--all", "--no-legend", "--plain"])
+        .output();
+
+    let units: Vec<String> = match output {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .filter_map(|l| l.split_whitespace().next())
+            .filter(|u| u.ends_with(".timer"))
+            .map(|u| u.to_string())
+            .collect(),
+        _ => return vec![], // No systemd on this host
+    };
+
+    if units.is_empty() {
+        return vec![];
+    }
+
+    let timers = systemctl_show(
+        &units,
+        "Id,Unit,ActiveState,NextElapseUSecRealtime,LastTriggerUSec,TimersCalendar,TimersMonotonic",
+    );
+
+    let services: Vec<String> = timers.values()
+        .filter_map(|t| t.get("Unit").cloned())
+        .collect();
+    let service_props = systemctl_show(&services, "Id,ActiveState,Result,ExecMainStatus");
+
+    let now = Local::now().timestamp();
+
+    units.iter().filter_map(|unit| {
+        let props = timers.get(unit)?;
+        let service = props.get("Unit").cloned().unwrap_or_default();
+        let svc = service_props.get(&service);
+
+        let next_run = props.get("NextElapseUSecRealtime").and_then(|v| parse_unix_timestamp(v));
+        let last_run = props.get("LastTriggerUSec").and_then(|v| parse_unix_timestamp(v));
+        let last_result = svc.and_then(|s| s.get("Result").cloned()).filter(|r| !r.is_empty());
+
+        let timer_state = props.get("ActiveState").map(|s| s.as_str()).unwrap_or("");
+        let svc_failed = svc.map(|s| {
+            s.get("ActiveState").map(|a| a == "failed").unwrap_or(false)
+                || s.get("Result").map(|r| !r.is_empty() && r != "success").unwrap_or(false)
+        }).unwrap_or(false);
+
+        let health = if timer_state == "failed" || svc_failed {
+            "failed"
+        } else if timer_state == "active" && next_run.map(|t| t < now - MISSED_GRACE_SECS).unwrap_or(false) {
+            "missed"
+        } else if timer_state == "active" {
+            "ok"
+        } else {
+            "unknown"
+        };
+
+        let schedule = props.get("TimersCalendar").and_then(|v| extract_timer_spec(v, "OnCalendar"))
+            .or_else(|| props.get("TimersMonotonic").and_then(|v| extract_monotonic_spec(v)))
+            .unwrap_or_else(|| "-".to_string());
+
+        Some(ScheduledJob {
+            kind: "timer".to_string(),
+            name: unit.clone(),
+            schedule,
+            command: service,
+            user: None,
+            source: "systemd".to_string(),
+            next_run,
+            last_run,
+            last_result,
+            health: health.to_string(),
+        })
+    }).collect()
+}
+
+/// Runs `systemctl show` for several units at once and returns their properties keyed by unit Id.
+fn systemctl_show(units: &[String], props: &str) -> HashMap<String, HashMap<String, String>> {
+    if units.is_empty() {
+        return HashMap::new();
+    }
+
+    // `--timestamp=unix` needs systemd 248+, fall back to the default format on older hosts
+    let mut output = Command::new("systemctl")
+        .args(["show", "--timestamp=unix", "-p", props])
+        .args(units)
+        .output();
+    if !matches!(&output, Ok(out) if out.status.success()) {
+        output = Command::new("systemctl")
+            .args(["show", "-p", props])
+            .args(units)
+            .output();
+    }
+
+    let stdout = match output {
+        Ok(out) => String::from_utf8_lossy(&out.stdout).to_string(),
+        Err(_) => return HashMap::new(),
+    };
+
+    // Each unit is a block of Key=Value lines separated by a blank line
+    stdout.split("\n\n").filter_map(|block| {
+        let props: HashMap<String, String> = block.lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        props.get("Id").cloned().map(|id| (id, props))
+    }).collect()
+}
+
+/// systemd prints unix timestamps as "@1700000000"; anything else ("n/a", empty) is None.
+fn parse_unix_timestamp(value: &str) -> Option<i64> {
+    value.strip_prefix('@')?.trim().parse::<i64>().ok().filter(|t| *t > 0)
+}
+
+/// Pulls the spec out of "{ OnCalendar=*-*-* 00:00:00 ; next_elapse=... }".
+fn extract_timer_spec(value: &str, key: &str) -> Option<String> {
+    let start = value.find(&format!("{}=", key))? + key.len() + 1;
+    let rest = &value[start..];
+    let end = rest.find(" ;").unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Pulls "OnUnitActiveSec=1h" style specs out of TimersMonotonic.
+fn extract_monotonic_spec(value: &str) -> Option<String> {
+    let start = value.find("{ ")? + 2;
+    let rest = &value[start..];
+    let end = rest.find(" ;")?;
+    Some(rest[..end].trim().to_string()).filter(|s| !s.is_empty())
+}
+
+// === CRONTABS ===
+
+pub fn list_cron_jobs() -> Vec<ScheduledJob> {
+    let mut jobs = Vec::new();
+
+    // System crontabs carry a user column
+    jobs.extend(parse_crontab_file(Path::new("/etc/crontab"), None));
+    if let Ok(entries) = fs::read_dir("/etc/cron.d") {
+        for entry in entries.flatten() {
+            jobs.extend(parse_crontab_file(&entry.path(), None));
+        }
+    }
+
+    // User spools (Debian and RHEL layouts), the file name is the owner.
+    // These are usually root-only, unreadable ones are skipped.
+    for dir in ["/var/spool/cron/crontabs", "/var/spool/cron"] {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !path.is_file() {
+                    continue;
+                }
+                let owner = entry.file_name().to_string_lossy().to_string();
+                jobs.extend(parse_crontab_file(&path, Some(owner)));
+            }
+        }
+    }
+
+    jobs
+}
+
+fn parse_crontab_file(path: &Path, owner: Option<String>) -> Vec<ScheduledJob> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => return vec![],
+    };
+    let source = path.display().to_string();
+    let env_line = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*\s*=").unwrap();
+
+    content.lines().filter_map(|raw| {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || env_line.is_match(line) {
+            return None;
+        }
+
+        // Macros (@daily) take one field, regular schedules take five
+        let schedule_fields = if line.starts_with('@') { 1 } else { 5 };
+
+        let mut parts = line.split_whitespace();
+        let schedule: Vec<&str> = parts.by_ref().take(schedule_fields).collect();
+        if schedule.len() < schedule_fields {
+            return None;
+        }
+        let user = match &owner {
+            Some(o) => o.clone(),
+            None => parts.next()?.to_string(),
+        };
+        let command = parts.collect::<Vec<&str>>().join(" ");
+        if command.is_empty() {
+            return None;
+        }
+
+        let schedule = schedule.join(" ");
+        let next_run = next_cron_run(&schedule, Local::now().timestamp());
+        // Name the job after the program of the last chained command,
+        // so "test -e /run/systemd/system || FOO=1 /sbin/foo -x" becomes "foo"
+        let last_cmd = command.rsplit(['|', '&', ';']).next().unwrap_or(&command);
+        let program = last_cmd.split_whitespace()
+            .find(|t| !t.contains('='))
+            .unwrap_or("");
+        let name = program.rsplit('/').next().unwrap_or(program).to_string();
+
+        Some(ScheduledJob {
+            kind: "cron".to_string(),
+            name,
+            schedule,
+            command,
+            user: Some(user),
+            source: source.clone(),
+            next_run,
+            // cron keeps no run history we can read without the syslog
+            last_run: None,
+            last_result: None,
+            health: if next_run.is_some() { "ok" } else { "unknown" }.to_string(),
+        })
+    }).collect()
+}
+
+// === CRON SCHEDULE EVALUATION ===
+
+struct CronSpec {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn expand_macro(spec: &str) -> Option<&'static str> {
+    match spec {
+        "@yearly" | "@annually" => Some("0 0 1 1 *"),
+        "@monthly" => Some("0 0 1 * *"),
+        "@weekly" => Some("0 0 * * 0"),
+        "@daily" | "@midnight" => Some("0 0 * * *"),
+        "@hourly" => Some("0 * * * *"),
+        _ => None, // @reboot has no next run
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<Vec<bool>> {
+    let mut allowed = vec![false; max as usize + 1];
+    let value = |s: &str| -> Option<u32> {
+        s.parse::<u32>().ok().or_else(|| {
+            names.iter().position(|n| n.eq_ignore_ascii_case(s)).map(|i| i as u32 + min)
+        })
+    };
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else {
+            let v = value(range)?;
+            // "5/10" means "from 5 every 10"
+            if item.contains('/') { (v, max) } else { (v, v) }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for v in (start..=end).step_by(step as usize) {
+            allowed[v as usize] = true;
+        }
+    }
+    Some(allowed)
+}
+
+fn parse_cron_spec(spec: &str) -> Option<CronSpec> {
+    let spec = expand_macro(spec).unwrap_or(spec);
+    let fields: Vec<&str> = spec.split_whitespace().collect();
+    if fields.len() != 5 {
+        return None;
+    }
+
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+    let mut weekdays = parse_cron_field(fields[4], 0, 7, &DAYS)?;
+    // Both 0 and 7 mean Sunday
+    if weekdays[7] {
+        weekdays[0] = true;
+    }
+
+    Some(CronSpec {
+        minutes: parse_cron_field(fields[0], 0, 59, &[])?,
+        hours: parse_cron_field(fields[1], 0, 23, &[])?,
+        days: parse_cron_field(fields[2], 1, 31, &[])?,
+        months: parse_cron_field(fields[3], 1, 12, &MONTHS)?,
+        weekdays,
+        days_restricted: !fields[2].starts_with('*'),
+        weekdays_restricted: !fields[4].starts_with('*'),
+    })
+}
+
+/// Returns the next time (Unix seconds, local timezone) the cron schedule fires after `after`.
+pub fn next_cron_run(spec: &str, after: i64) -> Option<i64> {
+    let cron = parse_cron_spec(spec)?;
+    let start = Local.timestamp_opt(after, 0).single()? + Duration::minutes(1);
+    let start = start.with_second(0)?;
+
+    // Walk day by day for a year, then pick the first matching hour/minute on a matching day
+    for day_offset in 0..=366 {
+        let day = start + Duration::days(day_offset);
+        if !cron.months[day.month() as usize] {
+            continue;
+        }
+        let dom = cron.days[day.day() as usize];
+        let dow = cron.weekdays[day.weekday().num_days_from_sunday() as usize];
+        // cron ORs day-of-month and day-of-week when both are restricted
+        let day_matches = if cron.days_restricted && cron.weekdays_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        };
+        if !day_matches {
+            continue;
+        }
+
+        let first_minute = if day_offset == 0 { start.hour() * 60 + start.minute() } else { 0 };
+        for minute_of_day in first_minute..24 * 60 {
+            let (h, m) = (minute_of_day / 60, minute_of_day % 60);
+            if cron.hours[h as usize] && cron.minutes[m as usize] {
+                let date = day.date_naive().and_hms_opt(h, m, 0)?;
+                // Skip times that fall into a DST gap
+                if let Some(t) = Local.from_local_datetime(&date).earliest() {
+                    return Some(t.timestamp());
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> i64 {
+        Local.with_ymd_and_hms(y, mo, d, h, mi, s).single().unwrap().timestamp()
+    }
+
+    #[test]
+    fn steps_within_the_hour() {
+        let after = local(2024, 5, 1, 10, 7, 30);
+        assert_eq!(next_cron_run("*/15 * * * *", after), Some(local(2024, 5, 1, 10, 15, 0)));
+        assert_eq!(next_cron_run("5/20 * * * *", after), Some(local(2024, 5, 1, 10, 25, 0)));
+    }
+
+    #[test]
+    fn never_returns_the_current_minute() {
+        let after = local(2024, 5, 1, 10, 30, 0);
+        assert_eq!(next_cron_run("30 10 * * *", after), Some(local(2024, 5, 2, 10, 30, 0)));
+    }
+
+    #[test]
+    fn skips_to_the_next_weekday() {
+        // Saturday
+        let after = local(2024, 5, 4, 12, 0, 0);
+        assert_eq!(next_cron_run("0 9 * * 1-5", after), Some(local(2024, 5, 6, 9, 0, 0)));
+        assert_eq!(next_cron_run("0 9 * * mon", after), Some(local(2024, 5, 6, 9, 0, 0)));
+        // 7 is Sunday too
+        assert_eq!(next_cron_run("0 9 * * 7", after), Some(local(2024, 5, 5, 9, 0, 0)));
+    }
+
+    #[test]
+    fn day_of_month_or_weekday_when_both_are_set() {
+        // The 13th or any Friday: Friday the 6th comes first
+        let after = local(2024, 9, 1, 0, 0, 0);
+        assert_eq!(next_cron_run("0 0 13 * 5", after), Some(local(2024, 9, 6, 0, 0, 0)));
+    }
+
+    #[test]
+    fn month_names_and_macros() {
+        let after = local(2024, 5, 1, 10, 0, 0);
+        assert_eq!(next_cron_run("0 12 1 jun *", after), Some(local(2024, 6, 1, 12, 0, 0)));
+        assert_eq!(next_cron_run("@daily", after), Some(local(2024, 5, 2, 0, 0, 0)));
+        assert_eq!(next_cron_run("@yearly", after), Some(local(2025, 1, 1, 0, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_and_impossible_schedules() {
+        let after = local(2024, 5, 1, 10, 0, 0);
+        assert_eq!(next_cron_run("@reboot", after), None);
+        assert_eq!(next_cron_run("61 * * * *", after), None);
+        assert_eq!(next_cron_run("* * * *", after), None);
+        assert_eq!(next_cron_run("*/0 * * * *", after), None);
+        // February never has 30 days
+        assert_eq!(next_cron_run("0 0 30 2 *", after), None);
+    }
+}
@@ -9,6 +9,11 @@ fn validate_service_name(name: &str) -> bool {
     re.is_match(name)
 }
 
+/// Whether this host booted with systemd, which the service manager needs.
+pub fn systemd_available() -> bool {
+    std::path::Path::new("/run/systemd/system").exists()
+}
+
 pub fn get_service_status(name: &str) -> Result<String, String> {
     if !validate_service_name(name) {
         return Err("Invalid service name. Only alphanumeric, dots, and hyphens allowed.".to_string());
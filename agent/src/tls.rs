@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::Router;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use crate::config::AgentConfig;
+
+/// TLS settings for the HTTP API from `tls_cert`/`tls_key`. With `tls_client_ca` set, callers
+/// must present a client certificate signed by that CA (mutual TLS). Also returns the SHA-256
+/// fingerprint of the agent certificate, which the master can pin for self-signed certificates.
+pub fn server_config(config: &AgentConfig) -> Result<Option<(Arc<ServerConfig>, String)>, String> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+        return Ok(None);
+    };
+    let certs = read_certs(cert_path)?;
+    let fingerprint = certs.first()
+        .map(|c| hex::encode(Sha256::digest(&c.0)))
+        .ok_or(format!("'{}' holds no certificate", cert_path))?;
+    let key = read_key(key_path)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.tls_client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_path)? {
+                roots.add(&cert).map_err(|e| format!("Invalid certificate in '{}': {}", ca_path, e))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key).map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    Ok(Some((Arc::new(config), fingerprint)))
+}
+
+/// `axum::serve` over TLS. Handshake failures (e.g. a caller without a client certificate)
+/// only drop that connection.
+pub async fn serve(addr: SocketAddr, app: Router, config: Arc<ServerConfig>) {
+    let acceptor = tokio_rustls::TlsAcceptor::from(config);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    loop {
+        let Ok((stream, _)) = listener.accept().await else { continue };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let Ok(stream) = acceptor.accept(stream).await else { return };
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app))
+                .with_upgrades()
+                .await;
+        });
+    }
+}
+
+/// New TLS key and a certificate request for it, for the master's CA to sign at enrollment.
+/// Returns (request PEM, key PEM).
+pub fn generate_csr(hostname: &str) -> Result<(String, String), String> {
+    let key = rcgen::KeyPair::generate().map_err(|e| e.to_string())?;
+    let csr = rcgen::CertificateParams::new(vec![hostname.to_string()])
+        .and_then(|params| params.serialize_request(&key))
+        .and_then(|csr| csr.pem())
+        .map_err(|e| format!("Cannot create certificate request: {}", e))?;
+    Ok((csr, key.serialize_pem()))
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice()).map_err(|e| format!("Invalid PEM in '{}': {}", path, e))?;
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &str) -> Result<PrivateKey, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+    let mut reader = pem.as_slice();
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| format!("Invalid PEM in '{}': {}", path, e))? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(format!("'{}' holds no private key", path)),
+        }
+    }
+}
@@ -76,7 +76,7 @@ EOF
 {
   "port": 3001,
   "hostname": "localhost",
-  "auth_token": "${AGENT_TOKEN:-change_me_please}"
+  "auth_token": "${AGENT_TOKEN:-}"
 }
 CJ
         chmod 600 "$ASSETS_DIR/config.json"
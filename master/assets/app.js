@@ -93,14 +93,15 @@ function generateUUID() {
 }
 
 // Expose these to window so HTML onclick="..." can find them
-window.openNodeModal = function(id = null, name = '', url = '', token = '') {
+window.openNodeModal = function(id = null, name = '', url = '', tlsFingerprint = '', tags = '') {
     const modal = document.getElementById('node-modal');
     const title = document.getElementById('node-modal-title');
     
     document.getElementById('node-id').value = id || generateUUID();
     document.getElementById('node-name').value = name;
     document.getElementById('node-url').value = url;
-    document.getElementById('node-token').value = token;
+    document.getElementById('node-tls-fingerprint').value = tlsFingerprint;
+    document.getElementById('node-tags').value = tags;
     
     title.innerText = id ? 'Edit Node' : 'Add New Node';
     modal.classList.remove('hidden');
@@ -117,15 +118,13 @@ window.saveNode = function(event) {
     const id = document.getElementById('node-id').value;
     const name = document.getElementById('node-name').value;
     const url = document.getElementById('node-url').value;
-    const token = document.getElementById('node-token').value;
+    const tls_fingerprint = document.getElementById('node-tls-fingerprint').value;
+    const tags = document.getElementById('node-tags').value;
 
     fetch('/api/nodes/save', {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ 
-            id, name, url, 
-            token: token.trim() === "" ? null : token 
-        })
+        body: JSON.stringify({ id, name, url, tls_fingerprint, tags })
     }).then(() => {
         window.closeNodeModal();
         // Reload to update the sidebar list
@@ -133,6 +132,17 @@ window.saveNode = function(event) {
     });
 }
 
+window.revokeNode = function(id) {
+    if(!confirm("Revoke this node's credential? Its agent is locked out until enrolled again.")) return;
+
+    fetch(`/api/nodes/revoke/${id}`, { method: 'POST' })
+    .then(r => r.text())
+    .then(message => {
+        alert(message);
+        window.location.reload();
+    });
+}
+
 window.deleteNode = function(id) {
     if(!confirm("Are you sure you want to delete this node?")) return;
     
@@ -0,0 +1,44 @@
+// === CSRF TOKEN ===
+// The master refuses POSTs of a logged-in session without its token. It keeps the token in
+// the csrf_token cookie, this sends it along with every HTMX request, fetch and form.
+
+function csrfToken() {
+    const match = document.cookie.match(/(?:^|;\s*)csrf_token=([^;]*)/);
+    return match ? decodeURIComponent(match[1]) : '';
+}
+
+// The token never goes to another site
+function sameOrigin(url) {
+    try {
+        return new URL(url, location.href).origin === location.origin;
+    } catch (e) {
+        return false;
+    }
+}
+
+document.addEventListener('htmx:configRequest', function(event) {
+    if (sameOrigin(event.detail.path)) event.detail.headers['X-CSRF-Token'] = csrfToken();
+});
+
+const plainFetch = window.fetch;
+window.fetch = function(resource, options = {}) {
+    const url = resource instanceof Request ? resource.url : String(resource);
+    if (!sameOrigin(url)) return plainFetch(resource, options);
+    const headers = new Headers(options.headers || (resource instanceof Request ? resource.headers : {}));
+    if (!headers.has('X-CSRF-Token')) headers.set('X-CSRF-Token', csrfToken());
+    return plainFetch(resource, { ...options, headers });
+};
+
+// Plain forms can't set headers, they get a hidden field instead
+document.addEventListener('submit', function(event) {
+    const form = event.target;
+    if (form.method.toLowerCase() !== 'post' || !sameOrigin(form.action)) return;
+    let field = form.querySelector('input[name="csrf_token"]');
+    if (!field) {
+        field = document.createElement('input');
+        field.type = 'hidden';
+        field.name = 'csrf_token';
+        form.appendChild(field);
+    }
+    field.value = csrfToken();
+}, true);
@@ -2,21 +2,115 @@ CREATE TABLE IF NOT EXISTS users (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     username TEXT NOT NULL UNIQUE,
     password_hash TEXT NOT NULL,
-    role TEXT DEFAULT 'admin',
-    must_change_password BOOLEAN DEFAULT 0
+    role TEXT DEFAULT 'admin', -- viewer, operator or admin
+    must_change_password BOOLEAN DEFAULT 0,
+    node_tags TEXT NOT NULL DEFAULT '', -- comma-separated, limits the user to nodes with one of these tags
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    totp_secret TEXT, -- base32; set while enrolling, used once totp_enabled
+    totp_enabled BOOLEAN NOT NULL DEFAULT 0,
+    totp_last_step INTEGER, -- time step of the last accepted code, which can't be used again
+    sso BOOLEAN NOT NULL DEFAULT 0 -- logs in through OpenID Connect, password_hash is empty
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    code_hash TEXT NOT NULL, -- SHA-256 of the code without dashes
+    used_at DATETIME
+);
+
+-- Hashes of passwords users had before, which they may not pick again
+CREATE TABLE IF NOT EXISTS password_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    changed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_history_username ON password_history (username);
+
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS nodes (
     id TEXT PRIMARY KEY,
     name TEXT NOT NULL,
     url TEXT NOT NULL,
-    token TEXT
+    token TEXT, -- legacy shared token, cleared once the node is enrolled
+    credential_hash TEXT,
+    credential_salt TEXT,
+    tls_fingerprint TEXT, -- SHA-256 pin of a self-signed agent certificate
+    tags TEXT NOT NULL DEFAULT '' -- comma-separated
+);
+
+-- One-time tokens an agent trades for its node credential, stored as SHA-256 hashes
+CREATE TABLE IF NOT EXISTS join_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    node_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id_hash TEXT PRIMARY KEY, -- SHA-256 of the id in the session cookie
+    username TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix seconds
+    last_seen INTEGER NOT NULL,
+    source_ip TEXT,
+    user_agent TEXT
 );
 
+CREATE INDEX IF NOT EXISTS idx_sessions_username ON sessions (username);
+
 CREATE TABLE IF NOT EXISTS audit_logs (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     user_id INTEGER,
     action TEXT NOT NULL,
     target TEXT,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+    username TEXT, -- as typed for failed logins, kept after the user is renamed or deleted
+    node TEXT,
+    result TEXT, -- ok, denied or failed
+    detail TEXT,
+    source_ip TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_logs_timestamp ON audit_logs (timestamp);
+
+
+CREATE TABLE IF NOT EXISTS container_stats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    node_id TEXT NOT NULL,
+    container_id TEXT NOT NULL,
+    container_name TEXT,
+    cpu_percent REAL,
+    mem_usage_bytes INTEGER,
+    mem_limit_bytes INTEGER,
+    net_rx_bytes INTEGER,
+    net_tx_bytes INTEGER,
+    block_read_bytes INTEGER,
+    block_write_bytes INTEGER,
+    pids INTEGER,
     timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
 );
+
+CREATE INDEX IF NOT EXISTS idx_container_stats_lookup ON container_stats (node_id, container_id, timestamp);
+
+CREATE TABLE IF NOT EXISTS container_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    node_id TEXT NOT NULL,
+    container_id TEXT NOT NULL,
+    container_name TEXT,
+    image TEXT,
+    action TEXT NOT NULL,
+    detail TEXT,
+    time_nano INTEGER NOT NULL,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Also dedupes events fetched again after a master restart
+CREATE UNIQUE INDEX IF NOT EXISTS idx_container_events_unique ON container_events (node_id, container_id, action, time_nano);
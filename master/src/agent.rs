@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use port_sentinel_shared::signing;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use crate::auth::{Forbidden, User};
+use crate::state::{AppState, NodeConfig};
+use crate::tunnel::{TunnelHandle, PUSH_SCHEME};
+
+/// Default time an agent call may take; stats sampling on the agent alone takes about a second.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Talks to one agent, whether the master polls it over HTTP (`http(s)://host:port`) or the
+/// agent keeps a push-mode tunnel open (`push://<node-id>`). Handlers only ever see this.
+pub struct AgentClient {
+    node_url: String,
+    target: Target,
+    timeout: Duration,
+    /// Name of this master and the dashboard user the calls are made for, for the agent's audit log
+    master: String,
+    user: Option<String>,
+    /// Requests are signed with the node credential, when it has one
+    auth: Option<String>,
+    legacy_agents: Arc<LegacyAgents>,
+}
+
+enum Target {
+    Http(reqwest::Client),
+    /// None while the agent is not connected
+    Push(Option<Arc<TunnelHandle>>),
+}
+
+/// Nodes the admin listed with `--legacy-agents` as still running an agent from before signed
+/// requests, which only takes its credential as a bare `Authorization` token and serves its API
+/// under `/api` instead of `/api/v1`. Only these are ever called that way: what an agent answers
+/// can't move a node onto this list, or an impostor could ask for the credential in clear.
+#[derive(Default)]
+pub struct LegacyAgents {
+    nodes: HashSet<String>,
+}
+
+impl LegacyAgents {
+    pub fn new<I: IntoIterator<Item = String>>(node_urls: I) -> Self {
+        let nodes = node_urls.into_iter()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        LegacyAgents { nodes }
+    }
+
+    pub fn node_urls(&self) -> impl Iterator<Item = &str> {
+        self.nodes.iter().map(String::as_str)
+    }
+
+    fn contains(&self, node_url: &str) -> bool {
+        self.nodes.contains(node_url.trim_end_matches('/'))
+    }
+}
+
+/// Status and body of an agent answer.
+pub struct AgentResponse {
+    pub status: u16,
+    body: String,
+}
+
+impl AgentResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_str(&self.body).map_err(|e| format!("Invalid response from agent: {}", e))
+    }
+}
+
+impl AgentClient {
+    /// Client for the node registered under `node_url`, authenticated with its credential and
+    /// acting for `user`, if the user's node tags reach that node.
+    pub async fn for_user(state: &AppState, user: &User, node_url: &str) -> Result<Self, Forbidden> {
+        let node = crate::db::get_node_by_url(&state.db, node_url).await;
+        if !user.can_access(node.as_ref()) {
+            return Err(Forbidden(format!("'{}' has no access to node {}", user.username, node_url)));
+        }
+        let client = match node {
+            Some(node) => AgentClient::new(state, &node),
+            None => AgentClient::with_auth(state, node_url, None, None),
+        };
+        Ok(client.acting_as(user))
+    }
+
+    pub fn new(state: &AppState, node: &NodeConfig) -> Self {
+        AgentClient::with_auth(state, &node.url, crate::enroll::node_auth(state, node), node.tls_fingerprint.as_deref())
+    }
+
+    fn with_auth(state: &AppState, node_url: &str, auth: Option<String>, tls_pin: Option<&str>) -> Self {
+        let target = if node_url.starts_with(PUSH_SCHEME) {
+            Target::Push(state.tunnels.get(node_url))
+        } else {
+            Target::Http(crate::handlers::build_client(state.pki.client_config(tls_pin)))
+        };
+        AgentClient {
+            node_url: node_url.to_string(),
+            target,
+            timeout: DEFAULT_TIMEOUT,
+            master: state.name.clone(),
+            user: None,
+            auth,
+            legacy_agents: state.legacy_agents.clone(),
+        }
+    }
+
+    /// Makes the calls on behalf of `user`, who then shows up in the agent's audit log.
+    pub fn acting_as(mut self, user: &User) -> Self {
+        self.user = Some(user.username.clone());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn is_push(&self) -> bool {
+        matches!(self.target, Target::Push(_))
+    }
+
+    pub async fn get(&self, path: &str) -> Result<AgentResponse, String> {
+        self.request("GET", path).await
+    }
+
+    pub async fn post(&self, path: &str) -> Result<AgentResponse, String> {
+        self.request("POST", path).await
+    }
+
+    /// `path` includes the query string. Errors mean the agent could not be reached at all.
+    pub async fn request(&self, method: &str, path: &str) -> Result<AgentResponse, String> {
+        if has_dot_segment(path) {
+            return Err(format!("Refused agent path {}", path));
+        }
+        match &self.target {
+            Target::Http(client) => {
+                let legacy = self.auth.is_some() && self.legacy_agents.contains(&self.node_url);
+                self.send_http(client, method, path, legacy).await
+            }
+            Target::Push(None) => Err(format!("Agent {} is not connected", self.node_url)),
+            Target::Push(Some(tunnel)) => {
+                // Pushed stats are answered locally, they are what the agent would return anyway
+                if method == "GET" && path == "/api/v1/stats" {
+                    if let Some(stats) = tunnel.stats() {
+                        return Ok(AgentResponse { status: 200, body: serde_json::to_string(&stats).unwrap_or_default() });
+                    }
+                }
+                let headers = self.auth.as_deref()
+                    .map(|key| signature_headers(key, method, path, b"", &self.master, self.user.as_deref()))
+                    .unwrap_or_default();
+                let (status, body) = tunnel.request(method, path, self.user.as_deref(), headers, self.timeout).await
+                    .map_err(|e| format!("{}: {}", self.node_url, e))?;
+                Ok(AgentResponse { status, body })
+            }
+        }
+    }
+
+    /// One HTTP call, signed, or with the bare token on the pre-`/api/v1` path for a `legacy` agent.
+    async fn send_http(&self, client: &reqwest::Client, method: &str, path: &str, legacy: bool) -> Result<AgentResponse, String> {
+        let path = match path.strip_prefix("/api/v1/") {
+            Some(rest) if legacy => format!("/api/{}", rest),
+            _ => path.to_string(),
+        };
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+        let mut req = client.request(method, format!("{}{}", self.node_url, path))
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+        match &self.auth {
+            Some(key) if legacy => {
+                if let Ok(value) = reqwest::header::HeaderValue::from_str(key) {
+                    req.headers_mut().insert(reqwest::header::AUTHORIZATION, value);
+                }
+            }
+            Some(key) => sign(&mut req, key, &self.master, self.user.as_deref()),
+            None => {}
+        }
+        let resp = client.execute(req)
+            .await
+            .map_err(|_| format!("Cannot reach Agent at {}", self.node_url))?;
+        let status = resp.status().as_u16();
+        let body = resp.text().await.unwrap_or_default();
+        Ok(AgentResponse { status, body })
+    }
+
+    /// GETs `path` and decodes it; a failing agent turns into the message it sent.
+    pub async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        let resp = self.get(path).await?;
+        if resp.is_success() {
+            resp.json()
+        } else {
+            Err(error_text(resp))
+        }
+    }
+}
+
+/// URL parsing resolves `.` and `..` segments (also percent-encoded), so a value that became
+/// one could reach another endpoint than the one the handler built the path for.
+fn has_dot_segment(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or_default();
+    path.split('/').any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+/// Adds the timestamp, nonce and signature headers the agent checks instead of a bare token,
+/// so a captured request is useless once its nonce is spent or its timestamp is stale.
+fn sign(req: &mut reqwest::Request, key: &str, master: &str, user: Option<&str>) {
+    let url = req.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let body = req.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+    let headers = signature_headers(key, req.method().as_str(), &path, body, master, user);
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (reqwest::header::HeaderName::from_bytes(name.as_bytes()), reqwest::header::HeaderValue::from_str(&value)) {
+            req.headers_mut().insert(name, value);
+        }
+    }
+}
+
+/// The signing headers of a request, also sent along with tunneled ones: the agent checks
+/// those the same way, so a tunnel peer that isn't the master can't run anything.
+fn signature_headers(key: &str, method: &str, path: &str, body: &[u8], master: &str, user: Option<&str>) -> BTreeMap<String, String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+
+    // Only what fits in a header is signed, the agent sees nothing else
+    let header_safe = |v: &&str| reqwest::header::HeaderValue::from_str(v).is_ok();
+    let master = Some(master).filter(header_safe).unwrap_or_default();
+    let user = user.filter(header_safe).unwrap_or_default();
+    let signature = signing::sign(key, &signing::RequestParts {
+        method,
+        path,
+        body,
+        timestamp,
+        nonce: &nonce,
+        master,
+        user,
+    });
+
+    [
+        (signing::HEADER_TIMESTAMP, timestamp.to_string()),
+        (signing::HEADER_NONCE, nonce),
+        (signing::HEADER_SIGNATURE, signature),
+        (signing::HEADER_MASTER, master.to_string()),
+        (signing::HEADER_USER, user.to_string()),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// The agent's explanation of a failed call, e.g. "Docker daemon unreachable".
+pub fn error_text(resp: AgentResponse) -> String {
+    if resp.body.trim().is_empty() {
+        format!("Agent returned status {}", resp.status)
+    } else {
+        resp.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+
+    /// An agent from before signed requests: it refuses everything but the bare `token`, with an
+    /// empty 401 as they did. Returns its URL and the (path, Authorization, signed) of each call.
+    async fn old_agent(token: &'static str) -> (String, Arc<std::sync::Mutex<Vec<(String, Option<String>, bool)>>>) {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = calls.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri, headers: HeaderMap| {
+            let seen = seen.clone();
+            async move {
+                let auth = headers.get("Authorization").and_then(|v| v.to_str().ok()).map(str::to_string);
+                let ok = auth.as_deref() == Some(token) && uri.path().starts_with("/api/") && !uri.path().starts_with("/api/v1/");
+                seen.lock().unwrap().push((uri.to_string(), auth, headers.contains_key(signing::HEADER_SIGNATURE)));
+                if ok { (axum::http::StatusCode::OK, "[]") } else { (axum::http::StatusCode::UNAUTHORIZED, "") }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, calls)
+    }
+
+    #[tokio::test]
+    async fn an_empty_401_never_downgrades_to_a_bare_token() {
+        let state = AppState::for_tests().await;
+        let (url, calls) = old_agent("node-secret").await;
+        let client = AgentClient::with_auth(&state, &url, Some("node-secret".to_string()), None);
+
+        for _ in 0..2 {
+            let resp = client.get("/api/v1/containers").await.unwrap();
+            assert_eq!(resp.status, 401);
+        }
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(calls.iter().all(|(path, auth, signed)| path == "/api/v1/containers" && auth.is_none() && *signed));
+    }
+
+    #[tokio::test]
+    async fn listed_legacy_agents_get_the_bare_token_on_the_old_paths() {
+        let mut state = AppState::for_tests().await;
+        let (url, calls) = old_agent("node-secret").await;
+        state.legacy_agents = Arc::new(LegacyAgents::new([format!("{}/", url), "https://other:3001".to_string()]));
+        let client = AgentClient::with_auth(&state, &url, Some("node-secret".to_string()), None);
+
+        let resp = client.get("/api/v1/containers?all=true").await.unwrap();
+        assert!(resp.is_success());
+        let calls = calls.lock().unwrap();
+        assert_eq!(*calls, [("/api/containers?all=true".to_string(), Some("node-secret".to_string()), false)]);
+    }
+
+    #[tokio::test]
+    async fn nodes_without_a_credential_are_never_sent_one() {
+        let mut state = AppState::for_tests().await;
+        let (url, calls) = old_agent("node-secret").await;
+        state.legacy_agents = Arc::new(LegacyAgents::new([url.clone()]));
+        let client = AgentClient::with_auth(&state, &url, None, None);
+
+        assert_eq!(client.get("/api/v1/containers").await.unwrap().status, 401);
+        assert_eq!(*calls.lock().unwrap(), [("/api/v1/containers".to_string(), None, false)]);
+    }
+
+    #[test]
+    fn legacy_agents_are_matched_by_url() {
+        let legacy = LegacyAgents::new(["http://10.0.0.5:3001/".to_string(), " ".to_string()]);
+        assert!(legacy.contains("http://10.0.0.5:3001"));
+        assert!(!legacy.contains("http://10.0.0.6:3001"));
+        assert_eq!(legacy.node_urls().count(), 1);
+        assert!(!LegacyAgents::default().contains("http://10.0.0.5:3001"));
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::SocketAddr;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use crate::agent::AgentResponse;
+use crate::db::AuditEntry;
+use crate::state::AppState;
+
+/// Address a dashboard request came from. With `--trust-forwarded-for` it is the client the
+/// reverse proxy appended to `X-Forwarded-For`, earlier entries can be made up by anyone.
+pub struct ClientIp(pub String);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let forwarded = parts.headers.get("X-Forwarded-For")
+            .filter(|_| state.trust_forwarded_for)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(|ip| ip.trim().to_string());
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip().to_string());
+        Ok(ClientIp(forwarded.or(peer).unwrap_or_default()))
+    }
+}
+
+/// How an audited action ended.
+pub enum Outcome {
+    Ok,
+    /// Refused, e.g. by the node's action policy
+    Denied(String),
+    Failed(String),
+}
+
+impl Outcome {
+    /// From an agent's answer; a 403 is the node's action policy.
+    pub fn of(resp: &Result<AgentResponse, String>) -> Self {
+        match resp {
+            Ok(resp) if resp.is_success() => Outcome::Ok,
+            Ok(resp) if resp.status == 403 => Outcome::Denied(resp.body().to_string()),
+            Ok(resp) => Outcome::Failed(format!("Agent returned status {}: {}", resp.status, resp.body())),
+            Err(e) => Outcome::Failed(e.clone()),
+        }
+    }
+}
+
+/// Adds an entry to the audit log (`/view/audit`). `node` is the node URL. Failing to write
+/// it is reported on stdout, the action itself has already happened.
+pub async fn record(
+    state: &AppState,
+    ip: &ClientIp,
+    user: &str,
+    action: &str,
+    node: Option<&str>,
+    target: Option<&str>,
+    outcome: Outcome,
+) {
+    let (result, detail) = match outcome {
+        Outcome::Ok => ("ok", None),
+        Outcome::Denied(reason) => ("denied", Some(reason)),
+        Outcome::Failed(e) => ("failed", Some(e)),
+    };
+    let entry = AuditEntry {
+        timestamp: String::new(),
+        username: Some(user.to_string()),
+        action: action.to_string(),
+        node: node.map(str::to_string),
+        target: target.map(str::to_string),
+        result: Some(result.to_string()),
+        detail: detail.filter(|d| !d.is_empty()),
+        source_ip: Some(ip.0.clone()).filter(|ip| !ip.is_empty()),
+    };
+    if let Err(e) = crate::db::insert_audit_entry(&state.db, &entry).await {
+        println!("⚠️ Could not write audit log entry '{}' by {}: {}", action, user, e);
+    }
+}
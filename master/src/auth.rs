@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::sync::{Arc, RwLock};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::state::NodeConfig;
 
 const USERS_FILE: &str = "users.json";
 
@@ -17,6 +20,93 @@ pub struct User {
     pub role: String,
     #[serde(default = "default_must_change")]
     pub must_change_password: bool,
+    /// Comma-separated node tags this user is limited to; empty reaches every node. Admins are never limited.
+    #[serde(default)]
+    pub node_tags: String,
+    /// Can't log in; existing sessions end with the next request
+    #[serde(default)]
+    pub disabled: bool,
+    /// Logs in with a TOTP code after the password
+    #[serde(default)]
+    pub totp_enabled: bool,
+    /// Logs in through the OpenID Connect provider only, has no password here
+    #[serde(default)]
+    pub sso: bool,
+}
+
+/// What a user may do, each role including the ones below it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    /// Sees everything, changes nothing
+    Viewer,
+    /// Also kills processes and controls services and containers
+    Operator,
+    /// Also manages nodes, users and reads the audit log
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Operator, Role::Admin];
+
+    pub fn parse(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|r| r.as_str() == name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// A request the user's role or node tags don't allow, answered with 403.
+pub struct Forbidden(pub String);
+
+impl IntoResponse for Forbidden {
+    fn into_response(self) -> Response {
+        (StatusCode::FORBIDDEN, self.0).into_response()
+    }
+}
+
+impl From<Forbidden> for (StatusCode, String) {
+    fn from(forbidden: Forbidden) -> Self {
+        (StatusCode::FORBIDDEN, forbidden.0)
+    }
+}
+
+impl User {
+    /// Unknown roles (e.g. a typo in the database) get the least access.
+    pub fn role(&self) -> Role {
+        Role::parse(&self.role).unwrap_or(Role::Viewer)
+    }
+
+    pub fn require(&self, role: Role) -> Result<(), Forbidden> {
+        if self.role() >= role {
+            Ok(())
+        } else {
+            Err(Forbidden(format!("This needs the {} role, '{}' is {}", role.as_str(), self.username, self.role().as_str())))
+        }
+    }
+
+    /// Whether the user's node tags reach `node`. Unregistered node URLs only for admins, who
+    /// could register them anyway; anyone else could point the master at any address.
+    pub fn can_access(&self, node: Option<&NodeConfig>) -> bool {
+        if self.role() == Role::Admin {
+            return true;
+        }
+        let Some(node) = node else {
+            return false;
+        };
+        let scope = split_tags(&self.node_tags);
+        scope.is_empty() || split_tags(&node.tags).iter().any(|t| scope.contains(t))
+    }
+}
+
+/// "db, prod" -> ["db", "prod"]
+pub fn split_tags(tags: &str) -> Vec<&str> {
+    tags.split(',').map(str::trim).filter(|t| !t.is_empty()).collect()
 }
 
 #[derive(Clone)]
@@ -45,6 +135,10 @@ impl AuthState {
             password_hash: hash,
             role: "admin".to_string(),
             must_change_password: true, // Forces change on first login
+            node_tags: String::new(),
+            disabled: false,
+            totp_enabled: false,
+            sso: false,
         }
     }
 
@@ -78,4 +172,73 @@ impl AuthState {
         // Save immediately
         Self::save_to_disk(&users);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl User {
+    /// `<role>-user` with `node_tags`, who has no password.
+    pub fn for_tests(role: &str, node_tags: &str) -> User {
+        User {
+            username: format!("{}-user", role),
+            password_hash: String::new(),
+            role: role.to_string(),
+            must_change_password: false,
+            node_tags: node_tags.to_string(),
+            disabled: false,
+            totp_enabled: false,
+            sso: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(role: &str, node_tags: &str) -> User {
+        User::for_tests(role, node_tags)
+    }
+
+    fn node(tags: &str) -> NodeConfig {
+        NodeConfig {
+            id: "n1".to_string(),
+            name: "db-1".to_string(),
+            url: "http://10.0.0.5:3001".to_string(),
+            token: None,
+            credential_hash: None,
+            credential_salt: None,
+            tls_fingerprint: None,
+            tags: tags.to_string(),
+        }
+    }
+
+    #[test]
+    fn roles_include_the_ones_below() {
+        let (viewer, operator, admin) = (user("viewer", ""), user("operator", ""), user("admin", ""));
+        assert!(viewer.require(Role::Viewer).is_ok());
+        assert!(viewer.require(Role::Operator).is_err());
+        assert!(operator.require(Role::Operator).is_ok());
+        assert!(operator.require(Role::Admin).is_err());
+        assert!(admin.require(Role::Admin).is_ok());
+        // A typo in the database gets the least access
+        assert_eq!(user("Admin", "").role(), Role::Viewer);
+    }
+
+    #[test]
+    fn node_tags_limit_what_users_reach() {
+        let operator = user("operator", "db, cache");
+        assert!(operator.can_access(Some(&node("prod,db"))));
+        assert!(!operator.can_access(Some(&node("prod, web"))));
+        assert!(!operator.can_access(Some(&node(""))));
+        assert!(user("viewer", "").can_access(Some(&node("prod, web"))));
+        // Admins are never limited
+        assert!(user("admin", "db").can_access(Some(&node("web"))));
+    }
+
+    #[test]
+    fn only_admins_reach_unregistered_nodes() {
+        assert!(!user("viewer", "").can_access(None));
+        assert!(!user("operator", "").can_access(None));
+        assert!(!user("operator", "db").can_access(None));
+        assert!(user("admin", "").can_access(None));
+    }
+}
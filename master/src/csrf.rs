@@ -0,0 +1,210 @@
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar, SameSite};
+use hmac::{Hmac, Mac};
+use port_sentinel_shared::signing::secrets_equal;
+use sha2::Sha256;
+use crate::state::AppState;
+
+/// Header HTMX and `fetch` send the token in (`/assets/csrf.js` adds it to every request).
+pub const HEADER: &str = "X-CSRF-Token";
+/// Readable by the dashboard's scripts, unlike the session cookie. Another site can't read it,
+/// so it can't send the header.
+pub const COOKIE: &str = "csrf_token";
+/// Form field for plain HTML forms, which can't set headers
+pub const FIELD: &str = "csrf_token";
+
+/// Larger form bodies aren't searched for the token (the dashboard's forms are tiny).
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+/// Token of the session whose id is `session_id`: a MAC, so nothing needs storing and it
+/// changes with every login.
+pub fn token(key: &Key, session_id: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.signing()).expect("HMAC takes keys of any length");
+    mac.update(b"csrf\0");
+    mac.update(session_id.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Whether a browser request comes from a dashboard page: its `Origin` (or `Referer`, which
+/// older browsers send instead) names the host it was sent to. Requests with neither come from
+/// agents and scripts rather than pages.
+fn same_origin(trust_forwarded_for: bool, headers: &HeaderMap) -> bool {
+    let get = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+    let Some(origin) = get(header::ORIGIN).or_else(|| get(header::REFERER)) else {
+        return true;
+    };
+    // Browsers send "null" for sandboxed frames, file:// pages and the like
+    let Some((_, rest)) = origin.split_once("://") else {
+        return false;
+    };
+    let origin_host = rest.split('/').next().unwrap_or_default();
+    let host = get(header::HeaderName::from_static("x-forwarded-host"))
+        .filter(|_| trust_forwarded_for)
+        .or_else(|| get(header::HOST));
+    host.is_some_and(|host| host.eq_ignore_ascii_case(origin_host))
+}
+
+/// The token from a urlencoded form body; the body is put back for the handler.
+async fn form_token(request: Request) -> Result<(Option<String>, Request), Response> {
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_FORM_BYTES).await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Form is too large").into_response())?;
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+        .ok()
+        .and_then(|fields| fields.into_iter().find(|(k, _)| k == FIELD).map(|(_, v)| v));
+    Ok((token, Request::from_parts(parts, Body::from(bytes))))
+}
+
+/// Refuses state-changing requests another site could have made the browser send: those
+/// from another origin, and those authenticated by the session cookie without its token.
+/// Keeps the token cookie in step with the session.
+pub async fn middleware(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    guard(&state.key, state.trust_forwarded_for, state.secure_cookies, jar, request, next).await
+}
+
+async fn guard(
+    key: &Key,
+    trust_forwarded_for: bool,
+    secure: bool,
+    jar: PrivateCookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let expected = jar.get(crate::session::COOKIE).map(|c| token(key, c.value()));
+
+    if !is_safe(request.method()) {
+        if !same_origin(trust_forwarded_for, request.headers()) {
+            println!("🚫 Refused cross-site {} {}", request.method(), request.uri().path());
+            return (StatusCode::FORBIDDEN, "Cross-site request refused").into_response();
+        }
+        if let Some(expected) = &expected {
+            let mut sent = request.headers().get(HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+            let is_form = request.headers().get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+            if sent.is_none() && is_form {
+                (sent, request) = match form_token(request).await {
+                    Ok(found) => found,
+                    Err(resp) => return resp,
+                };
+            }
+            if !sent.is_some_and(|sent| secrets_equal(&sent, expected)) {
+                println!("🚫 Refused {} {} without a valid CSRF token", request.method(), request.uri().path());
+                return (StatusCode::FORBIDDEN, "Missing or invalid CSRF token, reload the page").into_response();
+            }
+        }
+    }
+
+    let has_cookie = request.headers().get_all(header::COOKIE).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| Cookie::parse(c.trim().to_string()).ok())
+        .any(|c| c.name() == COOKIE && Some(c.value()) == expected.as_deref());
+    let mut response = next.run(request).await;
+    if let Some(expected) = expected.filter(|_| !has_cookie) {
+        let cookie = Cookie::build((COOKIE, expected)).path("/").secure(secure).same_site(SameSite::Strict).build();
+        if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    async fn test_middleware(State(key): State<Key>, jar: PrivateCookieJar, request: Request, next: Next) -> Response {
+        guard(&key, false, false, jar, request, next).await
+    }
+
+    fn app(key: &Key) -> Router {
+        Router::new()
+            .route("/action", post(|| async { "done" }))
+            .layer(axum::middleware::from_fn_with_state(key.clone(), test_middleware))
+            .with_state(key.clone())
+    }
+
+    /// `name=value` of the encrypted session cookie for `session_id`, as the browser sends it.
+    fn session_cookie(key: &Key, session_id: &str) -> String {
+        let jar = PrivateCookieJar::new(key.clone()).add(Cookie::new(crate::session::COOKIE, session_id.to_string()));
+        let response = jar.into_response();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    fn post_request(origin: &str, cookie: Option<&str>, csrf_header: Option<&str>, body: &str) -> Request {
+        let mut builder = Request::post("/action")
+            .header(header::HOST, "sentinel.example.com")
+            .header(header::ORIGIN, origin)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(cookie) = cookie {
+            builder = builder.header(header::COOKIE, cookie);
+        }
+        if let Some(csrf_header) = csrf_header {
+            builder = builder.header(HEADER, csrf_header);
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cross_origin_post_is_refused() {
+        let key = Key::generate();
+        let cookie = session_cookie(&key, "session-1");
+        let token = token(&key, "session-1");
+        let request = post_request("https://evil.example.net", Some(&cookie), Some(&token), "");
+        let response = app(&key).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn session_post_without_token_is_refused() {
+        let key = Key::generate();
+        let cookie = session_cookie(&key, "session-1");
+        let request = post_request("https://sentinel.example.com", Some(&cookie), None, "name=web");
+        let response = app(&key).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn another_sessions_token_is_refused() {
+        let key = Key::generate();
+        let cookie = session_cookie(&key, "session-1");
+        let token = token(&key, "session-2");
+        let request = post_request("https://sentinel.example.com", Some(&cookie), Some(&token), "");
+        let response = app(&key).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn same_origin_post_with_token_passes() {
+        let key = Key::generate();
+        let cookie = session_cookie(&key, "session-1");
+        let token = token(&key, "session-1");
+
+        let request = post_request("https://sentinel.example.com", Some(&cookie), Some(&token), "");
+        let response = app(&key).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let form = format!("name=web&{}={}", FIELD, token);
+        let request = post_request("https://sentinel.example.com", Some(&cookie), None, &form);
+        let response = app(&key).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
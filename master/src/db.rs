@@ -1,6 +1,8 @@
 use sqlx::{SqlitePool, Row};
 use crate::state::NodeConfig;
 use crate::auth::User;
+use port_sentinel_shared::{ContainerStats, ContainerEvent};
+use serde::{Deserialize, Serialize};
 
 // === INITIALIZATION ===
 
@@ -9,6 +11,36 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     // For simplicity without sqlx-cli, we execute the raw SQL string
     let schema = include_str!("../db/schema.sql");
     sqlx::query(schema).execute(pool).await?;
+
+    // Columns added after the table first shipped; SQLite has no ADD COLUMN IF NOT EXISTS
+    add_column_if_missing(pool, "nodes", "credential_hash", "TEXT").await?;
+    add_column_if_missing(pool, "nodes", "credential_salt", "TEXT").await?;
+    add_column_if_missing(pool, "nodes", "tls_fingerprint", "TEXT").await?;
+    add_column_if_missing(pool, "nodes", "tags", "TEXT NOT NULL DEFAULT ''").await?;
+    add_column_if_missing(pool, "users", "node_tags", "TEXT NOT NULL DEFAULT ''").await?;
+    add_column_if_missing(pool, "users", "disabled", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "users", "totp_secret", "TEXT").await?;
+    add_column_if_missing(pool, "users", "totp_enabled", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "users", "totp_last_step", "INTEGER").await?;
+    add_column_if_missing(pool, "users", "sso", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "users", "sso_issuer", "TEXT").await?;
+    add_column_if_missing(pool, "users", "sso_subject", "TEXT").await?;
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS users_sso_identity ON users (sso_issuer, sso_subject)").execute(pool).await?;
+    for column in ["username", "node", "result", "detail", "source_ip"] {
+        add_column_if_missing(pool, "audit_logs", column, "TEXT").await?;
+    }
+    Ok(())
+}
+
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, kind: &str) -> Result<(), sqlx::Error> {
+    let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await?;
+    if exists == 0 {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, kind)).execute(pool).await?;
+    }
     Ok(())
 }
 
@@ -16,7 +48,7 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
 
 pub async fn get_user_by_username(pool: &SqlitePool, username: &str) -> Option<User> {
     sqlx::query_as::<_, User>(
-        "SELECT username, password_hash, role, must_change_password FROM users WHERE username = ?"
+        "SELECT username, password_hash, role, must_change_password, node_tags, disabled, totp_enabled, sso FROM users WHERE username = ?"
     )
     .bind(username)
     .fetch_optional(pool)
@@ -27,12 +59,15 @@ pub async fn get_user_by_username(pool: &SqlitePool, username: &str) -> Option<U
 
 pub async fn create_user(pool: &SqlitePool, user: &User) -> Result<(), sqlx::Error> {
     sqlx::query(
-        "INSERT INTO users (username, password_hash, role, must_change_password) VALUES (?, ?, ?, ?)"
+        "INSERT INTO users (username, password_hash, role, must_change_password, node_tags, disabled, sso) VALUES (?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(&user.username)
     .bind(&user.password_hash)
     .bind(&user.role)
     .bind(user.must_change_password)
+    .bind(&user.node_tags)
+    .bind(user.disabled)
+    .bind(user.sso)
     .execute(pool)
     .await?;
     Ok(())
@@ -58,20 +93,368 @@ pub async fn update_user_credentials(pool: &SqlitePool, old_username: &str, new_
     .bind(old_username)
     .execute(pool)
     .await?;
+    // Sessions, recovery codes and old passwords follow a rename
+    for table in ["sessions", "recovery_codes", "password_history"] {
+        sqlx::query(&format!("UPDATE {} SET username = ? WHERE username = ?", table))
+            .bind(new_username)
+            .bind(old_username)
+            .execute(pool)
+            .await?;
+    }
     Ok(())
 }
 
 pub async fn get_all_users(pool: &SqlitePool) -> Result<Vec<User>, sqlx::Error> {
-    sqlx::query_as::<_, User>("SELECT username, password_hash, role, must_change_password FROM users")
+    sqlx::query_as::<_, User>("SELECT username, password_hash, role, must_change_password, node_tags, disabled, totp_enabled, sso FROM users ORDER BY username")
         .fetch_all(pool)
         .await
 }
 
+/// Holds for a user who can be demoted, disabled or deleted without leaving the master with no
+/// enabled admin. Checked in the same statement as the change, so two concurrent ones can't both
+/// take away the last admin.
+const KEEPS_AN_ADMIN: &str = "(role != 'admin' OR disabled OR EXISTS (SELECT 1 FROM users AS other \
+    WHERE other.username != users.username AND other.role = 'admin' AND NOT other.disabled))";
+
+/// False if the user is the last enabled admin and `role` would demote them.
+pub async fn update_user_role(pool: &SqlitePool, username: &str, role: &str, node_tags: &str) -> Result<bool, sqlx::Error> {
+    let sql = format!("UPDATE users SET role = ?, node_tags = ? WHERE username = ? AND (? = 'admin' OR {})", KEEPS_AN_ADMIN);
+    let result = sqlx::query(&sql)
+        .bind(role)
+        .bind(node_tags)
+        .bind(username)
+        .bind(role)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The role of a single sign-on user, which their identity provider's groups decide.
+/// False if the user is the last enabled admin and `role` would demote them.
+pub async fn set_user_role(pool: &SqlitePool, username: &str, role: &str) -> Result<bool, sqlx::Error> {
+    let sql = format!("UPDATE users SET role = ? WHERE username = ? AND (? = 'admin' OR {})", KEEPS_AN_ADMIN);
+    let result = sqlx::query(&sql)
+        .bind(role)
+        .bind(username)
+        .bind(role)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The SSO account the provider's `issuer` and `subject` (the `iss` and `sub` claims, which
+/// never change for a user, unlike their name) are linked to.
+pub async fn get_sso_username(pool: &SqlitePool, issuer: &str, subject: &str) -> Option<String> {
+    sqlx::query_scalar("SELECT username FROM users WHERE sso AND sso_issuer = ? AND sso_subject = ?")
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Links an SSO account to the provider's user at their first login. False if it is already
+/// linked to someone else, or not an SSO account.
+pub async fn link_sso_identity(pool: &SqlitePool, username: &str, issuer: &str, subject: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET sso_issuer = ?, sso_subject = ? WHERE username = ? AND sso AND sso_subject IS NULL"
+    )
+    .bind(issuer)
+    .bind(subject)
+    .bind(username)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Disabling also ends the user's sessions.
+/// False if the user is the last enabled admin and would be disabled.
+pub async fn set_user_disabled(pool: &SqlitePool, username: &str, disabled: bool) -> Result<bool, sqlx::Error> {
+    let sql = format!("UPDATE users SET disabled = ? WHERE username = ? AND (NOT ? OR {})", KEEPS_AN_ADMIN);
+    let result = sqlx::query(&sql)
+        .bind(disabled)
+        .bind(username)
+        .bind(disabled)
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    if disabled {
+        delete_user_sessions(pool, username, None).await?;
+    }
+    Ok(true)
+}
+
+/// Sets a password chosen by an admin, which the user has to change at the next login, and
+/// ends the sessions opened with the old one.
+pub async fn reset_user_password(pool: &SqlitePool, username: &str, password_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = ?, must_change_password = 1 WHERE username = ?")
+        .bind(password_hash)
+        .bind(username)
+        .execute(pool)
+        .await?;
+    delete_user_sessions(pool, username, None).await
+}
+
+/// False if the user is the last enabled admin.
+pub async fn delete_user(pool: &SqlitePool, username: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(&format!("DELETE FROM users WHERE username = ? AND {}", KEEPS_AN_ADMIN))
+        .bind(username)
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    // A user created later under the same name must not inherit them
+    for table in ["recovery_codes", "password_history"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE username = ?", table))
+            .bind(username)
+            .execute(pool)
+            .await?;
+    }
+    delete_user_sessions(pool, username, None).await?;
+    Ok(true)
+}
+
+/// Hashes of the user's last `limit` replaced passwords, newest first.
+pub async fn get_password_history(pool: &SqlitePool, username: &str, limit: usize) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT password_hash FROM password_history WHERE username = ? ORDER BY id DESC LIMIT ?")
+        .bind(username)
+        .bind(limit as i64)
+        .fetch_all(pool)
+        .await
+}
+
+/// Remembers a replaced password, keeping the last `keep` of the user.
+pub async fn add_password_history(pool: &SqlitePool, username: &str, password_hash: &str, keep: usize) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO password_history (username, password_hash) VALUES (?, ?)")
+        .bind(username)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "DELETE FROM password_history WHERE username = ? AND id NOT IN \
+         (SELECT id FROM password_history WHERE username = ? ORDER BY id DESC LIMIT ?)"
+    )
+    .bind(username)
+    .bind(username)
+    .bind(keep as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// === TWO-FACTOR AUTHENTICATION ===
+
+#[derive(sqlx::FromRow)]
+pub struct TotpState {
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+}
+
+pub async fn get_totp(pool: &SqlitePool, username: &str) -> Option<TotpState> {
+    sqlx::query_as::<_, TotpState>("SELECT totp_secret, totp_enabled, totp_last_step FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Stores the secret of an enrollment that still has to be confirmed with a code.
+pub async fn set_pending_totp(pool: &SqlitePool, username: &str, secret: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET totp_secret = ?, totp_enabled = 0, totp_last_step = NULL WHERE username = ?")
+        .bind(secret)
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn enable_totp(pool: &SqlitePool, username: &str, step: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET totp_enabled = 1, totp_last_step = ? WHERE username = ? AND totp_secret IS NOT NULL")
+        .bind(step)
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Records the step of an accepted code; false if that or a later step was used meanwhile.
+pub async fn use_totp_step(pool: &SqlitePool, username: &str, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = ? WHERE username = ? AND (totp_last_step IS NULL OR totp_last_step < ?)"
+    )
+    .bind(step)
+    .bind(username)
+    .bind(step)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Turns 2FA off and forgets the secret and recovery codes.
+pub async fn disable_totp(pool: &SqlitePool, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn replace_recovery_codes(pool: &SqlitePool, username: &str, code_hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+    for hash in code_hashes {
+        sqlx::query("INSERT INTO recovery_codes (username, code_hash) VALUES (?, ?)")
+            .bind(username)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Marks a recovery code used; false if it is unknown or was used before.
+pub async fn use_recovery_code(pool: &SqlitePool, username: &str, code_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE username = ? AND code_hash = ? AND used_at IS NULL"
+    )
+    .bind(username)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn count_recovery_codes(pool: &SqlitePool, username: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM recovery_codes WHERE username = ? AND used_at IS NULL")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+}
+
+// === SETTINGS ===
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> Option<String> {
+    sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value")
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// === SESSIONS ===
+
+#[derive(Serialize, sqlx::FromRow, Clone)]
+pub struct SessionRecord {
+    pub id_hash: String,
+    pub username: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub async fn create_session(pool: &SqlitePool, session: &SessionRecord) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO sessions (id_hash, username, created_at, last_seen, source_ip, user_agent) VALUES (?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&session.id_hash)
+    .bind(&session.username)
+    .bind(session.created_at)
+    .bind(session.last_seen)
+    .bind(&session.source_ip)
+    .bind(&session.user_agent)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_session(pool: &SqlitePool, id_hash: &str) -> Option<SessionRecord> {
+    sqlx::query_as::<_, SessionRecord>(
+        "SELECT id_hash, username, created_at, last_seen, source_ip, user_agent FROM sessions WHERE id_hash = ?"
+    )
+    .bind(id_hash)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Most recently used first.
+pub async fn get_user_sessions(pool: &SqlitePool, username: &str) -> Result<Vec<SessionRecord>, sqlx::Error> {
+    sqlx::query_as::<_, SessionRecord>(
+        "SELECT id_hash, username, created_at, last_seen, source_ip, user_agent FROM sessions
+         WHERE username = ? ORDER BY last_seen DESC"
+    )
+    .bind(username)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn touch_session(pool: &SqlitePool, id_hash: &str, now: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET last_seen = ? WHERE id_hash = ?")
+        .bind(now)
+        .bind(id_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_session(pool: &SqlitePool, id_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE id_hash = ?")
+        .bind(id_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Ends all sessions of `username`, except `keep` (the one asking, when it should stay logged in).
+pub async fn delete_user_sessions(pool: &SqlitePool, username: &str, keep: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE username = ? AND id_hash != ?")
+        .bind(username)
+        .bind(keep.unwrap_or_default())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Drops sessions idle since before `idle_before` or created before `created_before`.
+pub async fn delete_expired_sessions(pool: &SqlitePool, idle_before: i64, created_before: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE last_seen < ? OR created_at < ?")
+        .bind(idle_before)
+        .bind(created_before)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 // === NODE MANAGEMENT ===
 
 pub async fn get_all_nodes(pool: &SqlitePool) -> Result<Vec<NodeConfig>, sqlx::Error> {
     sqlx::query_as::<_, NodeConfig>(
-        "SELECT id, name, url, token FROM nodes"
+        "SELECT id, name, url, token, credential_hash, credential_salt, tls_fingerprint, tags FROM nodes"
     )
     .fetch_all(pool)
     .await
@@ -91,10 +474,387 @@ pub async fn upsert_node(pool: &SqlitePool, node: &NodeConfig) -> Result<(), sql
     Ok(())
 }
 
+pub async fn get_node_by_url(pool: &SqlitePool, url: &str) -> Option<NodeConfig> {
+    sqlx::query_as::<_, NodeConfig>("SELECT id, name, url, token, credential_hash, credential_salt, tls_fingerprint, tags FROM nodes WHERE url = ?")
+        .bind(url)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Saves name, URL, certificate pin and tags from the node modal, leaving credentials alone.
+pub async fn save_node(pool: &SqlitePool, id: &str, name: &str, url: &str, tls_fingerprint: Option<&str>, tags: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO nodes (id, name, url, tls_fingerprint, tags) VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET name=excluded.name, url=excluded.url, tls_fingerprint=excluded.tls_fingerprint, tags=excluded.tags"
+    )
+    .bind(id)
+    .bind(name)
+    .bind(url)
+    .bind(tls_fingerprint)
+    .bind(tags)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Points a node at its `https://` URL once it holds a certificate from the built-in CA,
+/// which replaces any certificate pin.
+pub async fn set_node_tls(pool: &SqlitePool, id: &str, url: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE nodes SET url = ?, tls_fingerprint = NULL WHERE id = ?")
+        .bind(url)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_node_by_id(pool: &SqlitePool, id: &str) -> Option<NodeConfig> {
+    sqlx::query_as::<_, NodeConfig>("SELECT id, name, url, token, credential_hash, credential_salt, tls_fingerprint, tags FROM nodes WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
 pub async fn delete_node(pool: &SqlitePool, id: &str) -> Result<(), sqlx::Error> {
     sqlx::query("DELETE FROM nodes WHERE id = ?")
         .bind(id)
         .execute(pool)
         .await?;
+    sqlx::query("DELETE FROM join_tokens WHERE node_id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// === ENROLLMENT ===
+
+pub async fn create_join_token(pool: &SqlitePool, node_id: &str, token_hash: &str, ttl_minutes: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO join_tokens (node_id, token_hash, expires_at) VALUES (?, ?, datetime('now', ?))")
+        .bind(node_id)
+        .bind(token_hash)
+        .bind(format!("+{} minutes", ttl_minutes))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Marks a join token used and returns its node, or None if it is unknown, expired or already used.
+pub async fn redeem_join_token(pool: &SqlitePool, token_hash: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "UPDATE join_tokens SET used_at = CURRENT_TIMESTAMP
+         WHERE token_hash = ? AND used_at IS NULL AND expires_at > datetime('now')
+         RETURNING node_id"
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Stores a newly issued credential, replacing the legacy token and any earlier credential.
+pub async fn set_node_credential(pool: &SqlitePool, id: &str, credential_hash: &str, credential_salt: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE nodes SET token = NULL, credential_hash = ?, credential_salt = ? WHERE id = ?")
+        .bind(credential_hash)
+        .bind(credential_salt)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Forgets the node's credential (and legacy token) and cancels its unused join tokens.
+pub async fn revoke_node_credential(pool: &SqlitePool, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE nodes SET token = NULL, credential_hash = NULL, credential_salt = NULL WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM join_tokens WHERE node_id = ? AND used_at IS NULL")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// === CONTAINER STATS HISTORY ===
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ContainerStatsSample {
+    pub cpu_percent: f64,
+    pub mem_usage_bytes: i64,
+    pub mem_limit_bytes: i64,
+    pub net_rx_bytes: i64,
+    pub net_tx_bytes: i64,
+    pub block_read_bytes: i64,
+    pub block_write_bytes: i64,
+    pub pids: i64,
+    pub timestamp: String,
+}
+
+pub async fn insert_container_stats(pool: &SqlitePool, node_id: &str, stats: &ContainerStats) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO container_stats (node_id, container_id, container_name, cpu_percent, mem_usage_bytes, mem_limit_bytes,
+         net_rx_bytes, net_tx_bytes, block_read_bytes, block_write_bytes, pids) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(node_id)
+    .bind(&stats.id)
+    .bind(&stats.name)
+    .bind(stats.cpu_percent)
+    .bind(stats.mem_usage_bytes as i64)
+    .bind(stats.mem_limit_bytes as i64)
+    .bind(stats.net_rx_bytes as i64)
+    .bind(stats.net_tx_bytes as i64)
+    .bind(stats.block_read_bytes as i64)
+    .bind(stats.block_write_bytes as i64)
+    .bind(stats.pids as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_container_stats_history(pool: &SqlitePool, node_id: &str, container_id: &str, days: u32) -> Result<Vec<ContainerStatsSample>, sqlx::Error> {
+    sqlx::query_as::<_, ContainerStatsSample>(
+        "SELECT cpu_percent, mem_usage_bytes, mem_limit_bytes, net_rx_bytes, net_tx_bytes, block_read_bytes,
+         block_write_bytes, pids, strftime('%Y-%m-%dT%H:%M:%SZ', timestamp) AS timestamp FROM container_stats
+         WHERE node_id = ? AND container_id = ? AND timestamp >= datetime('now', ?)
+         ORDER BY timestamp"
+    )
+    .bind(node_id)
+    .bind(container_id)
+    .bind(format!("-{} days", days))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn prune_container_stats(pool: &SqlitePool, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM container_stats WHERE timestamp < datetime('now', ?)")
+        .bind(format!("-{} days", retention_days))
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// === CONTAINER EVENTS ===
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ContainerEventRecord {
+    pub node_id: String,
+    pub container_id: String,
+    pub container_name: String,
+    pub action: String,
+    pub detail: Option<String>,
+    /// Unix seconds
+    pub time: i64,
+}
+
+pub async fn insert_container_event(pool: &SqlitePool, node_id: &str, event: &ContainerEvent) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO container_events (node_id, container_id, container_name, image, action, detail, time_nano)
+         VALUES (?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(node_id)
+    .bind(&event.container_id)
+    .bind(&event.container_name)
+    .bind(&event.image)
+    .bind(&event.action)
+    .bind(&event.detail)
+    .bind(event.time_nano)
+    .execute(pool)
+    .await?;
     Ok(())
 }
+
+pub async fn get_container_events(pool: &SqlitePool, node_id: &str, container_id: &str, limit: u32) -> Result<Vec<ContainerEventRecord>, sqlx::Error> {
+    sqlx::query_as::<_, ContainerEventRecord>(
+        "SELECT node_id, container_id, COALESCE(container_name, container_id) AS container_name, action, detail,
+         time_nano / 1000000000 AS time FROM container_events
+         WHERE node_id = ? AND container_id = ?
+         ORDER BY time_nano DESC LIMIT ?"
+    )
+    .bind(node_id)
+    .bind(container_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Exits and OOM kills across all nodes in the last `secs` seconds, oldest first.
+pub async fn get_recent_failures(pool: &SqlitePool, secs: i64) -> Result<Vec<ContainerEventRecord>, sqlx::Error> {
+    sqlx::query_as::<_, ContainerEventRecord>(
+        "SELECT node_id, container_id, COALESCE(container_name, container_id) AS container_name, action, detail,
+         time_nano / 1000000000 AS time FROM container_events
+         WHERE action IN ('die', 'oom') AND time_nano >= (CAST(strftime('%s', 'now') AS INTEGER) - ?) * 1000000000
+         ORDER BY time_nano"
+    )
+    .bind(secs)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn prune_container_events(pool: &SqlitePool, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM container_events WHERE timestamp < datetime('now', ?)")
+        .bind(format!("-{} days", retention_days))
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// === AUDIT LOG ===
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    /// UTC, "YYYY-MM-DD HH:MM:SS"
+    pub timestamp: String,
+    pub username: Option<String>,
+    pub action: String,
+    pub node: Option<String>,
+    pub target: Option<String>,
+    pub result: Option<String>,
+    pub detail: Option<String>,
+    pub source_ip: Option<String>,
+}
+
+/// Empty fields don't filter.
+#[derive(Deserialize, Default)]
+pub struct AuditFilter {
+    #[serde(default)]
+    pub user: String,
+    /// Prefix, e.g. "docker_" or "login"
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub node: String,
+    #[serde(default)]
+    pub result: String,
+    /// Dates (YYYY-MM-DD), both included
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+    /// Text in the target or detail
+    #[serde(default)]
+    pub q: String,
+}
+
+/// `timestamp` is left to the database.
+pub async fn insert_audit_entry(pool: &SqlitePool, entry: &AuditEntry) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_logs (user_id, username, action, node, target, result, detail, source_ip)
+         VALUES ((SELECT id FROM users WHERE username = ?1), ?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+    )
+    .bind(&entry.username)
+    .bind(&entry.action)
+    .bind(&entry.node)
+    .bind(&entry.target)
+    .bind(&entry.result)
+    .bind(&entry.detail)
+    .bind(&entry.source_ip)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Newest first.
+pub async fn get_audit_entries(pool: &SqlitePool, filter: &AuditFilter, limit: u32) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as::<_, AuditEntry>(
+        "SELECT timestamp, username, action, node, target, result, detail, source_ip FROM audit_logs
+         WHERE (?1 = '' OR username = ?1)
+           AND (?2 = '' OR action LIKE ?2 || '%')
+           AND (?3 = '' OR node = ?3)
+           AND (?4 = '' OR result = ?4)
+           AND (?5 = '' OR timestamp >= date(?5))
+           AND (?6 = '' OR timestamp < date(?6, '+1 day'))
+           AND (?7 = '' OR target LIKE '%' || ?7 || '%' OR detail LIKE '%' || ?7 || '%')
+         ORDER BY id DESC LIMIT ?8"
+    )
+    .bind(&filter.user)
+    .bind(&filter.action)
+    .bind(&filter.node)
+    .bind(&filter.result)
+    .bind(&filter.from)
+    .bind(&filter.to)
+    .bind(&filter.q)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Users and nodes that appear in the log, for the filter dropdowns.
+pub async fn get_audit_facets(pool: &SqlitePool) -> Result<(Vec<String>, Vec<String>), sqlx::Error> {
+    let users = sqlx::query_scalar("SELECT DISTINCT username FROM audit_logs WHERE username IS NOT NULL ORDER BY username")
+        .fetch_all(pool)
+        .await?;
+    let nodes = sqlx::query_scalar("SELECT DISTINCT node FROM audit_logs WHERE node IS NOT NULL ORDER BY node")
+        .fetch_all(pool)
+        .await?;
+    Ok((users, nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn pool_with(users: &[(&str, &str)]) -> SqlitePool {
+        let pool = crate::state::AppState::for_tests().await.db;
+        for (username, role) in users {
+            let user = User { username: username.to_string(), ..User::for_tests(role, "") };
+            create_user(&pool, &user).await.unwrap();
+        }
+        pool
+    }
+
+    async fn role(pool: &SqlitePool, username: &str) -> Option<String> {
+        get_user_by_username(pool, username).await.map(|u| u.role)
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cannot_be_demoted() {
+        let pool = pool_with(&[("root", "admin"), ("ops", "operator")]).await;
+        assert!(!update_user_role(&pool, "root", "operator", "").await.unwrap());
+        assert_eq!(role(&pool, "root").await.as_deref(), Some("admin"));
+        // Keeping the role while changing the tags is fine
+        assert!(update_user_role(&pool, "root", "admin", "db").await.unwrap());
+
+        assert!(update_user_role(&pool, "ops", "admin", "").await.unwrap());
+        assert!(update_user_role(&pool, "root", "viewer", "").await.unwrap());
+        assert!(!update_user_role(&pool, "ops", "viewer", "").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cannot_be_disabled() {
+        let pool = pool_with(&[("root", "admin"), ("backup", "admin")]).await;
+        assert!(set_user_disabled(&pool, "backup", true).await.unwrap());
+        // A disabled admin doesn't count
+        assert!(!set_user_disabled(&pool, "root", true).await.unwrap());
+        assert!(!get_user_by_username(&pool, "root").await.unwrap().disabled);
+        assert!(!update_user_role(&pool, "root", "operator", "").await.unwrap());
+
+        // Enabling is always allowed
+        assert!(set_user_disabled(&pool, "backup", false).await.unwrap());
+        assert!(set_user_disabled(&pool, "root", true).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cannot_be_deleted() {
+        let pool = pool_with(&[("root", "admin"), ("backup", "admin"), ("ops", "operator")]).await;
+        assert!(delete_user(&pool, "ops").await.unwrap());
+        assert!(delete_user(&pool, "backup").await.unwrap());
+        assert!(!delete_user(&pool, "root").await.unwrap());
+        assert_eq!(role(&pool, "root").await.as_deref(), Some("admin"));
+    }
+
+    #[tokio::test]
+    async fn two_admins_demoting_each_other_leave_one() {
+        let pool = pool_with(&[("alice", "admin"), ("bob", "admin")]).await;
+        let (a, b) = tokio::join!(
+            update_user_role(&pool, "alice", "viewer", ""),
+            update_user_role(&pool, "bob", "viewer", ""),
+        );
+        assert_eq!([a.unwrap(), b.unwrap()].iter().filter(|done| **done).count(), 1);
+        let admins = [role(&pool, "alice").await, role(&pool, "bob").await];
+        assert_eq!(admins.iter().filter(|r| r.as_deref() == Some("admin")).count(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use axum_extra::extract::cookie::Key;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use port_sentinel_shared::{EnrollRequest, EnrollResponse};
+use port_sentinel_shared::signing::secrets_equal;
+use crate::state::{AppState, NodeConfig};
+
+/// How long a join token can be redeemed after it was created.
+pub const JOIN_TOKEN_TTL_MINUTES: i64 = 60;
+
+/// Prefix that makes join tokens recognizable in configs and shell history.
+const JOIN_TOKEN_PREFIX: &str = "psj_";
+
+/// Secret the node credentials are derived from. The database only keeps a hash of each
+/// credential, but polling an agent means presenting the credential itself, so the master
+/// recomputes it from this key (kept in its own file, readable only by the master).
+pub struct MasterKey([u8; 32]);
+
+impl MasterKey {
+    /// Reads the key from `path`, creating it on first start.
+    pub fn load_or_create(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let bytes = hex::decode(content.trim()).map_err(|e| format!("{} is not a valid key: {}", path, e))?;
+                let key: [u8; 32] = bytes.try_into().map_err(|_| format!("{} must hold 32 bytes of hex", path))?;
+                Ok(MasterKey(key))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut key = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                let mut file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)
+                    .map_err(|e| format!("Cannot create {}: {}", path, e))?;
+                writeln!(file, "{}", hex::encode(key)).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+                println!("🔑 Generated new master key at '{}'", path);
+                Ok(MasterKey(key))
+            }
+            Err(e) => Err(format!("Cannot read {}: {}", path, e)),
+        }
+    }
+
+    /// The credential issued to `node_id` at the enrollment that picked `salt`.
+    pub fn node_credential(&self, node_id: &str, salt: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+        mac.update(b"node-credential\0");
+        mac.update(node_id.as_bytes());
+        mac.update(b"\0");
+        mac.update(salt.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Key of the dashboard's encrypted cookies. Derived rather than random at startup, so
+    /// a restart doesn't log everyone out.
+    pub fn cookie_key(&self) -> Key {
+        let mut material = Vec::with_capacity(64);
+        for block in [1u8, 2] {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+            mac.update(b"cookie-key\0");
+            mac.update(&[block]);
+            material.extend_from_slice(&mac.finalize().into_bytes());
+        }
+        Key::from(&material)
+    }
+}
+
+/// 24 random bytes as hex, behind `prefix`.
+fn random_token(prefix: &str) -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", prefix, hex::encode(bytes))
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Creates a join token for `node_id`. Only its hash is stored, the token itself is shown once.
+/// It ends in the CA fingerprint, which the agent checks the CA it is sent against.
+pub async fn create_join_token(state: &AppState, node_id: &str) -> Result<String, sqlx::Error> {
+    let token = format!("{}.{}", random_token(JOIN_TOKEN_PREFIX), state.pki.ca_fingerprint());
+    crate::db::create_join_token(&state.db, node_id, &hash_secret(&token), JOIN_TOKEN_TTL_MINUTES).await?;
+    Ok(token)
+}
+
+/// What the master sends as `Authorization` to a node: its enrolled credential, or the
+/// legacy shared token for nodes that were set up before enrollment.
+pub fn node_auth(state: &AppState, node: &NodeConfig) -> Option<String> {
+    match (&node.credential_hash, &node.credential_salt) {
+        (Some(_), Some(salt)) => Some(state.master_key.node_credential(&node.id, salt)),
+        _ => node.token.clone(),
+    }
+}
+
+/// Checks the credential a push-mode agent presents when it connects.
+pub fn verify_node(node: &NodeConfig, presented: &str) -> bool {
+    match &node.credential_hash {
+        Some(hash) => secrets_equal(&hash_secret(presented), hash),
+        None => node.token.as_deref().is_some_and(|token| secrets_equal(token, presented)),
+    }
+}
+
+/// `POST /api/agent/enroll`: an agent redeems a join token and receives its node credential.
+/// Public, the join token is the authentication.
+pub async fn agent_enroll(
+    State(state): State<AppState>,
+    Json(req): Json<EnrollRequest>,
+) -> Result<Json<EnrollResponse>, (StatusCode, String)> {
+    let node_id = crate::db::redeem_join_token(&state.db, &hash_secret(&req.join_token)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some(node_id) = node_id else {
+        println!("🚫 Rejected enrollment from '{}': invalid join token", req.hostname);
+        return Err((StatusCode::UNAUTHORIZED, "Join token is invalid, expired or already used".to_string()));
+    };
+    let Some(node) = crate::db::get_node_by_id(&state.db, &node_id).await else {
+        return Err((StatusCode::NOT_FOUND, "The node this join token was created for no longer exists".to_string()));
+    };
+
+    let salt = random_token("");
+    let credential = state.master_key.node_credential(&node.id, &salt);
+    crate::db::set_node_credential(&state.db, &node.id, &hash_secret(&credential), &salt).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // A push agent still connected with the old credential has to come back with the new one
+    state.tunnels.disconnect(&node.url);
+    println!("🔑 Node '{}' enrolled by {} (agent v{})", node.name, req.hostname, req.version);
+
+    let mut node_url = node.url;
+    let mut certificate = None;
+    if let (Some(csr), Ok(url)) = (&req.csr, reqwest::Url::parse(&node_url)) {
+        if matches!(url.scheme(), "http" | "https") {
+            let mut names = vec![req.hostname.clone()];
+            if let Some(host) = url.host_str() {
+                names.push(host.trim_matches(|c| c == '[' || c == ']').to_string());
+            }
+            names.dedup();
+            let cert = state.pki.sign_agent_csr(csr, &names).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+            // The agent serves TLS from now on
+            if let Some(rest) = node_url.strip_prefix("http://") {
+                node_url = format!("https://{}", rest);
+            }
+            crate::db::set_node_tls(&state.db, &node.id, &node_url).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            println!("🔏 Issued TLS certificate for {} to node '{}'", names.join(", "), node.name);
+            certificate = Some(cert);
+        }
+    }
+
+    Ok(Json(EnrollResponse {
+        node_id: node.id,
+        node_url,
+        credential,
+        ca_certificate: certificate.as_ref().map(|_| state.pki.ca_pem().to_string()),
+        certificate,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn state_with_node() -> AppState {
+        let state = AppState::for_tests().await;
+        crate::db::save_node(&state.db, "web1", "Web 1", "http://10.0.0.5:3001", None, "").await.unwrap();
+        state
+    }
+
+    fn enroll_request(join_token: &str) -> Json<EnrollRequest> {
+        Json(EnrollRequest {
+            join_token: join_token.to_string(),
+            hostname: "web1".to_string(),
+            version: "0.1.0".to_string(),
+            csr: None,
+        })
+    }
+
+    async fn node(state: &AppState) -> NodeConfig {
+        crate::db::get_node_by_id(&state.db, "web1").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn join_token_buys_a_credential_once() {
+        let state = state_with_node().await;
+        let token = create_join_token(&state, "web1").await.unwrap();
+        assert!(token.starts_with(JOIN_TOKEN_PREFIX));
+        assert_eq!(port_sentinel_shared::join_token_ca_pin(&token), Some(state.pki.ca_fingerprint()));
+
+        let Json(enrolled) = agent_enroll(State(state.clone()), enroll_request(&token)).await.unwrap();
+        assert_eq!(enrolled.node_id, "web1");
+        assert_eq!(enrolled.node_url, "http://10.0.0.5:3001");
+        assert_eq!(enrolled.certificate, None);
+
+        // Only the hash is stored, the master re-derives the credential to call the agent
+        let node = node(&state).await;
+        assert_eq!(node.credential_hash.as_deref(), Some(hash_secret(&enrolled.credential).as_str()));
+        assert_eq!(node.token, None);
+        assert_eq!(node_auth(&state, &node).as_deref(), Some(enrolled.credential.as_str()));
+        assert!(verify_node(&node, &enrolled.credential));
+        assert!(!verify_node(&node, &token));
+
+        let (status, _) = agent_enroll(State(state.clone()), enroll_request(&token)).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unknown_and_expired_join_tokens_are_refused() {
+        let state = state_with_node().await;
+        let (status, _) = agent_enroll(State(state.clone()), enroll_request("psj_made_up")).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        sqlx::query("INSERT INTO join_tokens (node_id, token_hash, expires_at) VALUES ('web1', ?, datetime('now', '-1 minutes'))")
+            .bind(hash_secret("psj_expired"))
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let (status, _) = agent_enroll(State(state.clone()), enroll_request("psj_expired")).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(node(&state).await.credential_hash, None);
+    }
+
+    #[tokio::test]
+    async fn enrolling_again_replaces_the_credential() {
+        let state = state_with_node().await;
+        let first = create_join_token(&state, "web1").await.unwrap();
+        let Json(first) = agent_enroll(State(state.clone()), enroll_request(&first)).await.unwrap();
+        let second = create_join_token(&state, "web1").await.unwrap();
+        let Json(second) = agent_enroll(State(state.clone()), enroll_request(&second)).await.unwrap();
+
+        assert_ne!(first.credential, second.credential);
+        let node = node(&state).await;
+        assert!(!verify_node(&node, &first.credential));
+        assert!(verify_node(&node, &second.credential));
+    }
+
+    #[tokio::test]
+    async fn revoking_forgets_the_credential_and_pending_join_tokens() {
+        let state = state_with_node().await;
+        let token = create_join_token(&state, "web1").await.unwrap();
+        let Json(enrolled) = agent_enroll(State(state.clone()), enroll_request(&token)).await.unwrap();
+        let pending = create_join_token(&state, "web1").await.unwrap();
+
+        crate::db::revoke_node_credential(&state.db, "web1").await.unwrap();
+        let node = node(&state).await;
+        assert_eq!(node_auth(&state, &node), None);
+        assert!(!verify_node(&node, &enrolled.credential));
+
+        let (status, _) = agent_enroll(State(state.clone()), enroll_request(&pending)).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn legacy_tokens_must_match_exactly() {
+        let node = |token: Option<&str>| NodeConfig {
+            id: "old".to_string(),
+            name: "Old".to_string(),
+            url: "http://10.0.0.9:3001".to_string(),
+            token: token.map(String::from),
+            credential_hash: None,
+            credential_salt: None,
+            tls_fingerprint: None,
+            tags: String::new(),
+        };
+        assert!(verify_node(&node(Some("s3cret-token")), "s3cret-token"));
+        assert!(!verify_node(&node(Some("s3cret-token")), "s3cret"));
+        assert!(!verify_node(&node(Some("s3cret-token")), ""));
+        assert!(!verify_node(&node(None), ""));
+    }
+
+    #[test]
+    fn master_key_is_kept_across_restarts() {
+        let path = std::env::temp_dir().join(format!("ps-master-key-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let first = MasterKey::load_or_create(path).unwrap();
+        let again = MasterKey::load_or_create(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(first.node_credential("web1", "salt"), again.node_credential("web1", "salt"));
+        assert_ne!(first.node_credential("web1", "salt"), first.node_credential("web1", "other salt"));
+        assert_ne!(first.node_credential("web1", "salt"), first.node_credential("web2", "salt"));
+    }
+}
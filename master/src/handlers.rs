@@ -1,14 +1,20 @@
 use axum::{
-    extract::{Path, Query, State, Request},
+    extract::{Extension, Path, Query, State, Request},
     response::{IntoResponse, Redirect, Response},
     middleware::Next,
     Json, Form,
 };
-use axum_extra::extract::cookie::{Cookie, PrivateCookieJar};
+use axum_extra::extract::cookie::PrivateCookieJar;
 use serde::{Deserialize, Serialize};
 use askama::Template;
-use port_sentinel_shared::{SystemStats, ProcessInfo};
+use port_sentinel_shared::{
+    SystemStats, ProcessInfo, ContainerInfo, ContainerStats, ContainerDetails, ImageInfo, VolumeInfo, NetworkInfo, PruneReport,
+    ContainerLogLine, ScheduledJob, AgentInfo, API_VERSION,
+};
+use crate::auth::{Forbidden, Role, User};
 use crate::state::{AppState, NodeConfig};
+use crate::agent::{AgentClient, error_text};
+use crate::audit::{self, ClientIp, Outcome};
 use std::fs;
 use std::time::Duration;
 use reqwest::StatusCode;
@@ -18,20 +24,26 @@ use reqwest::StatusCode;
 pub async fn auth_middleware(
     State(state): State<AppState>,
     jar: PrivateCookieJar,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Response {
-    if let Some(user_cookie) = jar.get("session_user") {
-        let username = user_cookie.value();
-        // DB Call to get user
-        if let Some(user) = crate::db::get_user_by_username(&state.db, username).await {
+    if let Some(session) = crate::session::current(&state, &jar).await {
+        // DB Call to get user; a disabled account is logged out
+        if let Some(user) = crate::db::get_user_by_username(&state.db, &session.username).await.filter(|u| !u.disabled) {
+            let path = request.uri().path();
             // Force password change logic
-            if user.must_change_password {
-                let path = request.uri().path();
-                if path != "/change-password" && path != "/logout" {
-                    return Redirect::to("/change-password").into_response();
-                }
+            if user.must_change_password && path != "/change-password" && path != "/logout" {
+                return Redirect::to("/change-password").into_response();
             }
+            // Operators and admins set up 2FA first when the policy asks for it
+            if !user.totp_enabled && !path.starts_with("/account/2fa") && path != "/logout"
+                && two_factor_required(&state, &user).await
+            {
+                return Redirect::to("/account/2fa").into_response();
+            }
+            // Handlers acting on agents pass it on for the agents' audit logs
+            request.extensions_mut().insert(user);
+            request.extensions_mut().insert(session);
             return next.run(request).await;
         }
     }
@@ -42,12 +54,20 @@ pub async fn auth_middleware(
 
 #[derive(Template)]
 #[template(path = "login.html")]
-struct LoginTemplate { error: Option<String> }
+struct LoginTemplate {
+    error: Option<String>,
+    /// The password was right, ask for the TOTP or a recovery code
+    two_factor: bool,
+    /// Offer the "Sign in with SSO" button
+    sso: bool,
+}
 
 #[derive(Template)]
 #[template(path = "change_password.html")]
 struct ChangePwTemplate {
     username: String,
+    min_length: usize,
+    error: Option<String>,
 }
 
 #[derive(Template)]
@@ -60,6 +80,8 @@ struct StatsTemplate {
     error: Option<String>,
     current_node: String,
     nodes: Vec<NodeConfig>,
+    /// Shows the node management controls
+    is_admin: bool,
     status_class: String,
     status_text: String,
 }
@@ -70,6 +92,7 @@ struct RowsTemplate {
     processes: Vec<ProcessInfo>,
     trigger: String,
     current_node: String,
+    features: NodeFeatures,
 }
 
 #[derive(Template)]
@@ -87,7 +110,15 @@ struct LogReadTemplate {
 // === PAYLOAD STRUCTS ===
 
 #[derive(Deserialize)]
-pub struct AuthPayload { username: String, password: String }
+pub struct AuthPayload {
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    /// Second step of a login with 2FA
+    #[serde(default)]
+    code: String,
+}
 
 #[derive(Deserialize)]
 pub struct PwPayload { 
@@ -102,62 +133,68 @@ pub struct NodeParams { node: Option<String>, q: Option<String>, rate: Option<St
 pub struct ReadParams { path: String, rate: Option<String>, node: Option<String> }
 
 #[derive(Deserialize)]
-pub struct NodeForm { id: String, name: String, url: String, token: Option<String> }
+pub struct NodeForm {
+    id: String,
+    name: String,
+    url: String,
+    #[serde(default)]
+    tls_fingerprint: String,
+    /// Comma-separated, e.g. "prod, db"
+    #[serde(default)]
+    tags: String,
+}
 
 // === HELPER FUNCTIONS (Internal) ===
 
-fn build_client(token: Option<&String>) -> reqwest::Client {
-    let mut headers = reqwest::header::HeaderMap::new();
-    if let Some(t) = token {
-        if let Ok(val) = reqwest::header::HeaderValue::from_str(t) {
-            headers.insert("Authorization", val);
-        }
-    }
-    reqwest::Client::builder().default_headers(headers).build().unwrap()
+pub(crate) fn build_client(tls: rustls::ClientConfig) -> reqwest::Client {
+    reqwest::Client::builder().use_preconfigured_tls(tls).build().unwrap()
 }
 
-async fn get_token_for_url(state: &AppState, url: &str) -> Option<String> {
-    // DB Call to get nodes
-    if let Ok(nodes) = crate::db::get_all_nodes(&state.db).await {
-        return nodes.iter().find(|n| n.url == url).and_then(|n| n.token.clone());
+/// Registered nodes the user's node tags reach.
+async fn visible_nodes(state: &AppState, user: &User) -> Vec<NodeConfig> {
+    let mut nodes = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
+    nodes.retain(|n| user.can_access(Some(n)));
+    nodes
+}
+
+/// The node registered under `node_url`, if the user may see it.
+async fn visible_node(state: &AppState, user: &User, node_url: &str) -> Result<Option<NodeConfig>, Forbidden> {
+    let node = crate::db::get_node_by_url(&state.db, node_url).await;
+    if !user.can_access(node.as_ref()) {
+        return Err(Forbidden(format!("'{}' has no access to node {}", user.username, node_url)));
     }
-    None
+    Ok(node)
 }
 
-async fn fetch_stats(base_url: &str, token: Option<&String>) -> Option<SystemStats> {
-    let client = build_client(token);
-    let url = format!("{}/api/stats", base_url);
-    client.get(&url).send().await.ok()?.json::<SystemStats>().await.ok()
+/// Node a page opens on when none is picked.
+fn default_node_url(nodes: &[NodeConfig]) -> String {
+    nodes.first().map(|n| n.url.clone()).unwrap_or("http://127.0.0.1:3001".to_string())
 }
 
-async fn fetch_processes(base_url: &str, token: Option<&String>) -> Option<Vec<ProcessInfo>> {
-    let client = build_client(token);
-    let url = format!("{}/api/processes", base_url);
-    client.get(&url).send().await.ok()?.json::<Vec<ProcessInfo>>().await.ok()
+async fn fetch_stats(agent: &AgentClient) -> Option<SystemStats> {
+    agent.get_json("/api/v1/stats").await.ok()
 }
 
-async fn send_kill(base_url: &str, pid: &str, token: Option<&String>) {
-    let client = build_client(token);
-    let url = format!("{}/api/kill/{}", base_url, pid);
-    let _ = client.post(&url).send().await;
+async fn fetch_processes(agent: &AgentClient) -> Option<Vec<ProcessInfo>> {
+    agent.get_json("/api/v1/processes").await.ok()
 }
 
-async fn fetch_log_files(base_url: &str, pid: &str, token: Option<&String>) -> Vec<String> {
-    let url = format!("{}/api/logs/check/{}", base_url, pid);
-    let client = build_client(token);
-    if let Ok(response) = client.get(&url).send().await {
-        if let Ok(files) = response.json::<Vec<String>>().await { return files; }
-    }
-    vec![]
+async fn fetch_schedules(agent: &AgentClient) -> Result<Vec<ScheduledJob>, String> {
+    agent.get_json("/api/v1/schedules").await
 }
 
-async fn fetch_log_lines(base_url: &str, path: &str, token: Option<&String>) -> Vec<String> {
-    let client = build_client(token);
-    let url = format!("{}/api/logs/read", base_url);
-    if let Ok(response) = client.get(&url).query(&[("path", path), ("lines", "50")]).send().await {
-        if let Ok(lines) = response.json::<Vec<String>>().await { return lines; }
-    }
-    vec!["Error reading remote log".to_string()]
+async fn send_kill(agent: &AgentClient, pid: &str) -> Result<crate::agent::AgentResponse, String> {
+    agent.post(&format!("/api/v1/kill/{}", url_encode(pid))).await
+}
+
+async fn fetch_log_files(agent: &AgentClient, pid: &str) -> Vec<String> {
+    agent.get_json(&format!("/api/v1/logs/check/{}", url_encode(pid))).await.unwrap_or_default()
+}
+
+async fn fetch_log_lines(agent: &AgentClient, path: &str) -> Vec<String> {
+    let query = serde_urlencoded::to_string([("path", path), ("lines", "50")]).unwrap_or_default();
+    agent.get_json(&format!("/api/v1/logs/read?{}", query)).await
+        .unwrap_or_else(|_| vec!["Error reading remote log".to_string()])
 }
 
 // === HANDLERS (Public) ===
@@ -166,74 +203,223 @@ pub async fn dashboard_handler() -> impl IntoResponse {
     axum::response::Html(include_str!("../assets/index.html"))
 }
 
-pub async fn login_page() -> impl IntoResponse {
-    LoginTemplate { error: None }
+pub async fn login_page(State(state): State<AppState>) -> impl IntoResponse {
+    LoginTemplate { error: None, two_factor: false, sso: state.oidc.is_some() }
 }
 
 pub async fn login_submit(
     State(state): State<AppState>,
+    ip: ClientIp,
+    headers: axum::http::HeaderMap,
     jar: PrivateCookieJar,
     Form(payload): Form<AuthPayload>
 ) -> impl IntoResponse {
+    let user_agent = headers.get(axum::http::header::USER_AGENT).and_then(|h| h.to_str().ok());
+
+    // Second step: the code for the user whose password was checked before
+    if !payload.code.is_empty() {
+        let Some(username) = crate::session::pending_user(&jar) else {
+            let error = Some("The login timed out, please sign in again".to_string());
+            return LoginTemplate { error, two_factor: false, sso: state.oidc.is_some() }.into_response();
+        };
+        // Codes are guessable too, they count like passwords
+        if let Err(wait) = state.login_throttle.begin(&ip.0, &username) {
+            return login_throttled(&state, &ip, &username, wait, true).await;
+        }
+        let verified = verify_second_factor(&state, &username, &payload.code).await;
+        if !matches!(verified, Ok(false)) {
+            state.login_throttle.passed(&ip.0, &username);
+        }
+        return match verified {
+            Ok(true) => {
+                let jar = jar.remove(axum_extra::extract::cookie::Cookie::from(crate::session::PENDING_COOKIE));
+                open_session(&state, &ip, user_agent, jar, &username).await
+            }
+            Ok(false) => login_failed(&state, &ip, &username, "Invalid 2FA code", "Invalid code", true).await,
+            Err(e) => {
+                println!("❌ Cannot check the 2FA code of '{}': {}", username, e);
+                LoginTemplate { error: Some("Cannot check the code, see the master log".to_string()), two_factor: true, sso: state.oidc.is_some() }.into_response()
+            }
+        };
+    }
+
+    // Checked before bcrypt, which is what an attacker wants to keep busy
+    if let Err(wait) = state.login_throttle.begin(&ip.0, &payload.username) {
+        return login_throttled(&state, &ip, &payload.username, wait, false).await;
+    }
+
     // Verify user against DB
     if let Some(user) = crate::db::get_user_by_username(&state.db, &payload.username).await {
         if bcrypt::verify(&payload.password, &user.password_hash).unwrap_or(false) {
-            let cookie = Cookie::build(("session_user", payload.username))
-                .path("/")
-                .secure(false).http_only(true).build();
-            let updated_jar = jar.add(cookie);
-            return (updated_jar, Redirect::to("/")).into_response();
+            state.login_throttle.passed(&ip.0, &payload.username);
+            if user.disabled {
+                audit::record(&state, &ip, &payload.username, "login", None, None, Outcome::Denied("Account disabled".to_string())).await;
+                return LoginTemplate { error: Some("This account is disabled".to_string()), two_factor: false, sso: state.oidc.is_some() }.into_response();
+            }
+            if user.totp_enabled {
+                let jar = jar.add(crate::session::pending_cookie(&state, &user.username));
+                return (jar, LoginTemplate { error: None, two_factor: true, sso: state.oidc.is_some() }).into_response();
+            }
+            return open_session(&state, &ip, user_agent, jar, &user.username).await;
         }
     }
-    LoginTemplate { error: Some("Invalid credentials".to_string()) }.into_response()
+    login_failed(&state, &ip, &payload.username, "Invalid credentials", "Invalid credentials", false).await
 }
 
-pub async fn logout_handler(jar: PrivateCookieJar) -> impl IntoResponse {
-    let updated_jar = jar.remove(Cookie::from("session_user"));
-    (updated_jar, Redirect::to("/login"))
+/// Audits a failed login, which the throttle counted already, then shows the form again with `message`.
+async fn login_failed(state: &AppState, ip: &ClientIp, username: &str, reason: &str, message: &str, two_factor: bool) -> Response {
+    let mut reason = reason.to_string();
+    if state.login_throttle.failed(&ip.0, username) {
+        println!("🔒 Too many failed logins for '{}' from {}, locked out", username, ip.0);
+        reason += ", locked out";
+    }
+    audit::record(state, ip, username, "login", None, None, Outcome::Failed(reason)).await;
+    LoginTemplate { error: Some(message.to_string()), two_factor, sso: state.oidc.is_some() }.into_response()
 }
 
-pub async fn change_password_page(jar: PrivateCookieJar) -> impl IntoResponse {
-    let username = if let Some(cookie) = jar.get("session_user") {
-        cookie.value().to_string()
-    } else {
-        "".to_string()
+/// Refuses a login attempt that came too soon after failed ones, without checking it.
+async fn login_throttled(state: &AppState, ip: &ClientIp, username: &str, wait_secs: i64, two_factor: bool) -> Response {
+    audit::record(state, ip, username, "login", None, None, Outcome::Denied("Too many failed logins".to_string())).await;
+    let error = Some(format!("Too many failed logins, try again in {}", wait_text(wait_secs)));
+    (axum::http::StatusCode::TOO_MANY_REQUESTS, LoginTemplate { error, two_factor, sso: state.oidc.is_some() }).into_response()
+}
+
+/// "45 seconds", "3 minutes"
+fn wait_text(wait_secs: i64) -> String {
+    let (n, unit) = if wait_secs > 60 { ((wait_secs + 59) / 60, "minute") } else { (wait_secs, "second") };
+    format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Completes a login: opens the session and sends the user to the dashboard.
+async fn open_session(state: &AppState, ip: &ClientIp, user_agent: Option<&str>, jar: PrivateCookieJar, username: &str) -> Response {
+    let cookie = match crate::session::start(state, username, &ip.0, user_agent).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            println!("❌ Cannot open session for '{}': {}", username, e);
+            let error = Some("Cannot open a session, see the master log".to_string());
+            return LoginTemplate { error, two_factor: false, sso: state.oidc.is_some() }.into_response();
+        }
     };
-    ChangePwTemplate { username }
+    state.login_throttle.succeeded(username);
+    audit::record(state, ip, username, "login", None, None, Outcome::Ok).await;
+    (jar.add(cookie), Redirect::to("/")).into_response()
+}
+
+/// Checks a TOTP code, or a recovery code (which is used up), of a user with 2FA enabled.
+async fn verify_second_factor(state: &AppState, username: &str, code: &str) -> Result<bool, sqlx::Error> {
+    let Some(totp) = crate::db::get_totp(&state.db, username).await.filter(|t| t.totp_enabled) else {
+        return Ok(false);
+    };
+    let secret = totp.totp_secret.unwrap_or_default();
+    if let Some(step) = crate::totp::verify(&secret, code, totp.totp_last_step) {
+        return crate::db::use_totp_step(&state.db, username, step).await;
+    }
+    let hash = crate::enroll::hash_secret(&crate::totp::normalize_recovery_code(code));
+    crate::db::use_recovery_code(&state.db, username, &hash).await
+}
+
+/// Setting that makes operators and admins enroll in 2FA.
+const REQUIRE_2FA_SETTING: &str = "require_2fa";
+
+async fn two_factor_required(state: &AppState, user: &User) -> bool {
+    // The identity provider is in charge of their second factor
+    !user.sso && user.role() >= Role::Operator && crate::db::get_setting(&state.db, REQUIRE_2FA_SETTING).await.as_deref() == Some("1")
+}
+
+pub async fn logout_handler(State(state): State<AppState>, ip: ClientIp, jar: PrivateCookieJar) -> impl IntoResponse {
+    let (session, updated_jar) = crate::session::end(&state, jar).await;
+    if let Some(session) = session {
+        audit::record(&state, &ip, &session.username, "logout", None, None, Outcome::Ok).await;
+    }
+    (updated_jar, Redirect::to("/login"))
+}
+
+pub async fn change_password_page(State(state): State<AppState>, jar: PrivateCookieJar) -> impl IntoResponse {
+    let username = crate::session::current(&state, &jar).await.map(|s| s.username).unwrap_or_default();
+    ChangePwTemplate { username, min_length: state.password_policy.min_length, error: None }
 }
 
 pub async fn change_password_submit(
     State(state): State<AppState>,
+    ip: ClientIp,
     jar: PrivateCookieJar,
     Form(payload): Form<PwPayload>
 ) -> impl IntoResponse {
-    if let Some(cookie) = jar.get("session_user") {
-        let current_username = cookie.value();
-        if let Ok(hash) = bcrypt::hash(&payload.password, bcrypt::DEFAULT_COST) {
-            // Update both username and password
-            let _ = crate::db::update_user_credentials(&state.db, current_username, &payload.username, &hash).await;
-            
-            // If username changed, we MUST update the cookie
-            let new_cookie = Cookie::build(("session_user", payload.username))
-                .path("/")
-                .secure(false).http_only(true).build();
-            let updated_jar = jar.add(new_cookie);
-            
-            return (updated_jar, Redirect::to("/")).into_response();
+    let Some(session) = crate::session::current(&state, &jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let current_username = session.username.as_str();
+    let new_username = payload.username.trim();
+    // Renames show up as the target
+    let target = (new_username != current_username).then_some(new_username);
+    let old_hash = match check_new_credentials(&state, current_username, new_username, &payload.password).await {
+        Ok(old_hash) => old_hash,
+        Err(error) => {
+            audit::record(&state, &ip, current_username, "password_change", None, target, Outcome::Denied(error.clone())).await;
+            let page = ChangePwTemplate { username: new_username.to_string(), min_length: state.password_policy.min_length, error: Some(error) };
+            return (axum::http::StatusCode::BAD_REQUEST, page).into_response();
         }
-        return Redirect::to("/").into_response();
+    };
+    if let Ok(hash) = bcrypt::hash(&payload.password, bcrypt::DEFAULT_COST) {
+        // Update both username and password; the session follows a rename
+        let mut result = crate::db::update_user_credentials(&state.db, current_username, new_username, &hash).await;
+        let keep = state.password_policy.replaced_to_keep();
+        if result.is_ok() && keep > 0 {
+            result = crate::db::add_password_history(&state.db, new_username, &old_hash, keep).await;
+        }
+        if result.is_ok() {
+            // Other sessions were opened with the old password
+            result = crate::db::delete_user_sessions(&state.db, new_username, Some(&session.id_hash)).await;
+        }
+        let outcome = match result {
+            Ok(()) => Outcome::Ok,
+            Err(e) => Outcome::Failed(e.to_string()),
+        };
+        audit::record(&state, &ip, current_username, "password_change", None, target, outcome).await;
     }
-    Redirect::to("/login").into_response()
+    Redirect::to("/").into_response()
 }
 
-pub async fn stats_handler(State(state): State<AppState>, Query(params): Query<NodeParams>) -> impl IntoResponse {
-    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    
+/// Checks a new username and password against the password policy, returning the hash of
+/// the password being replaced.
+async fn check_new_credentials(state: &AppState, current_username: &str, new_username: &str, password: &str) -> Result<String, String> {
+    if !crate::password::valid_username(new_username) {
+        return Err("Usernames can only have letters, digits and . _ - @ (at most 64)".to_string());
+    }
+    if new_username != current_username && crate::db::get_user_by_username(&state.db, new_username).await.is_some() {
+        return Err(format!("The username '{}' is taken", new_username));
+    }
+    let policy = &state.password_policy;
+    policy.check(new_username, password)?;
+    let user = crate::db::get_user_by_username(&state.db, current_username).await
+        .ok_or_else(|| "Your account no longer exists".to_string())?;
+    if user.sso {
+        return Err("Your password is managed by your identity provider".to_string());
+    }
+    if policy.history > 0 {
+        let mut used = crate::db::get_password_history(&state.db, current_username, policy.replaced_to_keep()).await
+            .map_err(|e| e.to_string())?;
+        used.insert(0, user.password_hash.clone());
+        if used.iter().any(|hash| bcrypt::verify(password, hash).unwrap_or(false)) {
+            return Err(format!("Pick a password that isn't one of your last {}", policy.history));
+        }
+    }
+    Ok(user.password_hash)
+}
+
+pub async fn stats_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<NodeParams>,
+) -> Result<impl IntoResponse, Forbidden> {
     // Fetch fresh list of nodes for the sidebar
-    let nodes_list = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
+    let nodes_list = visible_nodes(&state, &user).await;
+    let is_admin = user.role() == Role::Admin;
+
+    let node_url = params.node.unwrap_or_else(|| default_node_url(&nodes_list));
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
     
-    match fetch_stats(&node_url, token.as_ref()).await {
+    Ok(match fetch_stats(&agent).await {
         Some(stats) => {
             // === ONLINE LOGIC ===
             let ram_pct = if stats.total_memory > 0 { (stats.used_memory as f64 / stats.total_memory as f64) * 100.0 } else { 0.0 };
@@ -256,7 +442,7 @@ pub async fn stats_handler(State(state): State<AppState>, Query(params): Query<N
                 disk_txt, disk_pct,
                 used_swap: stats.used_swap, total_swap: stats.total_swap, swap_pct,
                 cpu_usage: stats.cpu_usage, cpu_cores: cpu_cores_data,
-                error: None, current_node: node_url, nodes: nodes_list,
+                error: None, current_node: node_url, nodes: nodes_list, is_admin,
                 
                 // Set Online Status
                 status_class: "bg-green-500 animate-pulse shadow-[0_0_10px_rgba(34,197,94,0.5)]".to_string(),
@@ -269,97 +455,222 @@ pub async fn stats_handler(State(state): State<AppState>, Query(params): Query<N
                 used_memory: 0, total_memory: 0, ram_pct: 0.0, ram_color: "bg-gray-500".into(),
                 disk_txt: "OFFLINE".into(), disk_pct: 0.0, used_swap: 0, total_swap: 0, swap_pct: 0.0,
                 cpu_usage: 0.0, cpu_cores: vec![],
-                error: Some(format!("Cannot reach Agent at {}", node_url)), current_node: node_url, nodes: nodes_list,
+                error: Some(format!("Cannot reach Agent at {}", node_url)), current_node: node_url, nodes: nodes_list, is_admin,
                 
                 // Set Offline Status
                 status_class: "bg-red-500 shadow-none".to_string(),
                 status_text: "OFFLINE".to_string(),
             }
         }
-    }
+    })
 }
 
-pub async fn rows_handler(State(state): State<AppState>, Query(params): Query<NodeParams>) -> impl IntoResponse {
+pub async fn rows_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<NodeParams>,
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let processes = fetch_processes(&node_url, token.as_ref()).await.unwrap_or_default();
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let (processes, features) = tokio::join!(fetch_processes(&agent), NodeFeatures::fetch(&agent, &user));
+    let processes = processes.unwrap_or_default();
     let query = params.q.unwrap_or_default().to_lowercase();
     let rate_str = params.rate.unwrap_or("5".to_string());
     let filtered: Vec<ProcessInfo> = processes.into_iter().filter(|p| {
         query.is_empty() || p.name.to_lowercase().contains(&query) || p.pid.contains(&query)
+            || p.container.as_ref().map(|c| c.name.to_lowercase().contains(&query)).unwrap_or(false)
     }).collect();
     let trigger = if rate_str == "0" { "refresh".to_string() } else { format!("every {}s, refresh", rate_str) };
-    RowsTemplate { processes: filtered, trigger, current_node: node_url }
+    Ok(RowsTemplate { processes: filtered, trigger, current_node: node_url, features })
 }
 
-pub async fn kill_process_api(State(state): State<AppState>, Path(pid): Path<String>, Query(params): Query<NodeParams>) -> impl IntoResponse {
+pub async fn kill_process_api(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(pid): Path<String>,
+    Query(params): Query<NodeParams>,
+) -> Result<Response, Forbidden> {
+    user.require(Role::Operator)?;
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    send_kill(&node_url, &pid, token.as_ref()).await;
-    "Signal Sent"
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let resp = send_kill(&agent, &pid).await;
+    audit::record(&state, &ip, &user.username, "kill", Some(&node_url), Some(&pid), Outcome::of(&resp)).await;
+    Ok(match resp {
+        Ok(resp) if resp.is_success() => "Signal Sent".into_response(),
+        Ok(resp) => (axum::http::StatusCode::BAD_GATEWAY, error_text(resp)).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_GATEWAY, e).into_response(),
+    })
 }
 
-pub async fn check_logs_handler(State(state): State<AppState>, Path(pid): Path<String>, Query(params): Query<NodeParams>) -> impl IntoResponse {
+pub async fn check_logs_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(pid): Path<String>,
+    Query(params): Query<NodeParams>,
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let files = fetch_log_files(&node_url, &pid, token.as_ref()).await;
-    LogModalTemplate { pid, files, current_node: node_url }
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let files = fetch_log_files(&agent, &pid).await;
+    Ok(LogModalTemplate { pid, files, current_node: node_url })
 }
 
-pub async fn read_log_handler(State(state): State<AppState>, Query(params): Query<ReadParams>) -> impl IntoResponse {
+pub async fn read_log_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<ReadParams>,
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let lines = fetch_log_lines(&node_url, &params.path, token.as_ref()).await;
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let lines = fetch_log_lines(&agent, &params.path).await;
     let rate = params.rate.unwrap_or("2".to_string());
-    LogReadTemplate { path: params.path, lines, rate, current_node: node_url }
+    Ok(LogReadTemplate { path: params.path, lines, rate, current_node: node_url })
+}
+
+pub async fn save_node_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Json(payload): Json<NodeForm>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let pin = payload.tls_fingerprint.trim();
+    let pin = (!pin.is_empty()).then_some(pin);
+    let outcome = match crate::db::save_node(&state.db, &payload.id, &payload.name, &payload.url, pin, payload.tags.trim()).await {
+        Ok(()) => Outcome::Ok,
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    audit::record(&state, &ip, &user.username, "node_save", Some(&payload.url), Some(&payload.name), outcome).await;
+    Ok("Saved")
 }
 
-pub async fn save_node_handler(State(state): State<AppState>, Json(payload): Json<NodeForm>) -> impl IntoResponse {
-    let node = NodeConfig {
-        id: payload.id,
-        name: payload.name,
-        url: payload.url,
-        token: payload.token,
+pub async fn delete_node_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let node = crate::db::get_node_by_id(&state.db, &id).await;
+    let outcome = match crate::db::delete_node(&state.db, &id).await {
+        Ok(()) => Outcome::Ok,
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    let (url, name) = node.map(|n| (Some(n.url), n.name)).unwrap_or((None, id));
+    audit::record(&state, &ip, &user.username, "node_delete", url.as_deref(), Some(&name), outcome).await;
+    Ok("Deleted")
+}
+
+#[derive(Template)]
+#[template(path = "enroll_modal.html")]
+pub struct EnrollModalTemplate {
+    node_name: String,
+    push: bool,
+    token: Option<String>,
+    master_url: String,
+    ttl_minutes: i64,
+    error: Option<String>,
+}
+
+/// Creates a one-time join token for a node and shows the command that enrolls its agent.
+pub async fn node_join_token_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    // The agent reaches the master the same way the browser did
+    let host = headers.get(axum::http::header::HOST).and_then(|h| h.to_str().ok()).unwrap_or("127.0.0.1:7878");
+    let scheme = headers.get("X-Forwarded-Proto").and_then(|h| h.to_str().ok()).unwrap_or("http");
+    let master_url = format!("{}://{}", scheme, host);
+
+    let Some(node) = crate::db::get_node_by_id(&state.db, &id).await else {
+        return Ok(EnrollModalTemplate {
+            node_name: id, push: false, token: None, master_url, ttl_minutes: 0,
+            error: Some("Node not found".to_string()),
+        });
     };
-    let _ = crate::db::upsert_node(&state.db, &node).await;
-    "Saved"
+
+    let (token, error) = match crate::enroll::create_join_token(&state, &node.id).await {
+        Ok(token) => (Some(token), None),
+        Err(e) => (None, Some(format!("Failed to create join token: {}", e))),
+    };
+    let outcome = error.clone().map_or(Outcome::Ok, Outcome::Failed);
+    audit::record(&state, &ip, &user.username, "node_join_token", Some(&node.url), Some(&node.name), outcome).await;
+    Ok(EnrollModalTemplate {
+        push: node.url.starts_with(crate::tunnel::PUSH_SCHEME),
+        node_name: node.name,
+        token,
+        master_url,
+        ttl_minutes: crate::enroll::JOIN_TOKEN_TTL_MINUTES,
+        error,
+    })
 }
 
-pub async fn delete_node_handler(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
-    let _ = crate::db::delete_node(&state.db, &id).await;
-    "Deleted"
+/// Revokes a node's credential; its agent is locked out until enrolled again.
+pub async fn revoke_node_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let Some(node) = crate::db::get_node_by_id(&state.db, &id).await else {
+        return Ok((axum::http::StatusCode::NOT_FOUND, "Node not found".to_string()));
+    };
+    // The agent checks requests against its own copy of the credential, it has to drop it too.
+    // Asked first: afterwards the master can't sign the request anymore.
+    let agent = AgentClient::new(&state, &node).acting_as(&user).timeout(Duration::from_secs(5));
+    let agent_error = match crate::enroll::node_auth(&state, &node) {
+        // Nothing left to revoke
+        None => None,
+        Some(_) => match agent.post("/api/v1/credential/revoke").await {
+            Ok(resp) if resp.is_success() => None,
+            Ok(resp) => Some(error_text(resp)),
+            Err(e) => Some(e),
+        },
+    };
+    if let Err(e) = crate::db::revoke_node_credential(&state.db, &node.id).await {
+        let outcome = Outcome::Failed(e.to_string());
+        audit::record(&state, &ip, &user.username, "node_revoke", Some(&node.url), Some(&node.name), outcome).await;
+        return Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke credential".to_string()));
+    }
+    let (outcome, message) = match agent_error {
+        None => (Outcome::Ok, "Revoked on the master and the agent".to_string()),
+        Some(e) => (
+            Outcome::Failed(format!("Revoked on the master only, the agent answered: {}", e)),
+            format!("Revoked on the master. The agent couldn't be told ({}) and accepts the old credential \
+                     until it is enrolled again, which replaces it.", e),
+        ),
+    };
+    audit::record(&state, &ip, &user.username, "node_revoke", Some(&node.url), Some(&node.name), outcome).await;
+    state.tunnels.disconnect(&node.url);
+    println!("⛔ Revoked credential of node '{}'", node.name);
+    Ok((axum::http::StatusCode::OK, message))
 }
 
 pub async fn check_node_status(
     State(state): State<AppState>, 
+    Extension(user): Extension<User>,
     Query(params): Query<NodeParams>
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or_default();
-    let token = get_token_for_url(&state, &node_url).await;
 
     // 1. Setup Client with a strict timeout
     // If the agent takes >2 seconds to respond, we consider it "Laggy" or Offline
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_millis(2000)) 
-        .build()
-        .unwrap();
-
-    let url = format!("{}/api/stats", node_url);
-    
-    let mut request = client.get(&url);
-    if let Some(t) = token {
-        request = request.header("Authorization", t);
-    }
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?.timeout(Duration::from_millis(2000));
 
     // 2. Perform the Check
-    match request.send().await {
+    Ok(match agent.get("/api/v1/stats").await {
         Ok(resp) => {
-            if resp.status().is_success() {
+            if resp.is_success() {
                 // === ONLINE (Green) ===
                 axum::response::Html(
                     r#"<div class="w-2.5 h-2.5 rounded-full bg-green-500 animate-pulse shadow-[0_0_8px_rgba(34,197,94,0.8)]" title="Online"></div>"#
                 )
-            } else if resp.status() == StatusCode::UNAUTHORIZED {
+            } else if resp.status == StatusCode::UNAUTHORIZED.as_u16() {
                 // === AUTH ERROR (Orange) ===
                 // The node is UP, but our token is wrong
                 axum::response::Html(
@@ -368,7 +679,7 @@ pub async fn check_node_status(
             } else {
                 // === OTHER ERROR (Yellow) ===
                 // Node is up but returning 500s or 404s
-                println!("⚠️ Node {} returned status: {}", node_url, resp.status());
+                println!("⚠️ Node {} returned status: {}", node_url, resp.status);
                 axum::response::Html(
                     r#"<div class="w-2.5 h-2.5 rounded-full bg-yellow-500" title="Server Error"></div>"#
                 )
@@ -383,9 +694,105 @@ pub async fn check_node_status(
                 r#"<div class="w-2.5 h-2.5 rounded-full bg-red-600 border border-red-800" title="Offline"></div>"#
             )
         }
+    })
+}
+
+/// What a node supports according to its `/api/v1/info`, to leave out UI it can't serve.
+/// Unknown (agent unreachable) shows everything; the agent refuses what it can't do anyway.
+pub struct NodeFeatures {
+    info: Option<AgentInfo>,
+    /// The user is a viewer: every change is greyed out, whatever the node allows
+    view_only: bool,
+}
+
+impl NodeFeatures {
+    pub async fn fetch(agent: &AgentClient, user: &User) -> Self {
+        NodeFeatures {
+            info: agent.get_json("/api/v1/info").await.ok(),
+            view_only: user.role() < Role::Operator,
+        }
+    }
+
+    pub fn collects(&self, collector: &str) -> bool {
+        self.info.as_ref().is_none_or(|info| info.collects(collector))
+    }
+
+    pub fn supports(&self, action: &str) -> bool {
+        self.info.as_ref().is_none_or(|info| info.supports(action))
+    }
+
+    /// Supported, but switched off by the node's action policy or the user's role: shown greyed out.
+    pub fn denies(&self, action: &str) -> bool {
+        (self.view_only && action != "log_read") || self.info.as_ref().is_some_and(|info| info.denies(action))
+    }
+
+    pub fn denies_container(&self, names: &str) -> bool {
+        self.view_only || self.info.as_ref().is_some_and(|info| info.denies_container(names))
+    }
+
+    /// Tooltip of the greyed out controls.
+    pub fn denied_title(&self) -> &'static str {
+        if self.view_only { "Your role can only view" } else { "Disabled by this node's action policy" }
+    }
+
+    /// Compose actions need every container of the project or service to be allowed.
+    pub fn denies_rows<'a>(&self, rows: impl IntoIterator<Item = &'a ContainerRow>) -> bool {
+        self.denies("docker_control") || rows.into_iter().any(|r| self.denies_container(&r.c.names))
+    }
+
+    pub fn denies_group(&self, group: &ProjectGroup) -> bool {
+        self.denies_rows(group.services.iter().flat_map(|s| &s.rows))
+    }
+
+    /// Services control is limited to, for the service manager to list.
+    pub fn allowed_services(&self) -> Option<String> {
+        self.info.as_ref().and_then(|info| info.allowed_services.as_ref()).map(|s| s.join(", "))
+    }
+}
+
+/// Why an agent may not work fully with this master, if it doesn't.
+fn version_warning(info: &AgentInfo) -> Option<String> {
+    let master_version = env!("CARGO_PKG_VERSION");
+    if info.api_version != API_VERSION {
+        Some(format!("Agent speaks API v{}, this master expects v{}", info.api_version, API_VERSION))
+    } else if info.agent_version != master_version {
+        Some(format!("Agent v{} differs from master v{}", info.agent_version, master_version))
+    } else {
+        None
     }
 }
 
+#[derive(Template)]
+#[template(path = "node_runtime.html")]
+pub struct NodeRuntimeTemplate {
+    pub info: Option<AgentInfo>,
+    pub warning: Option<String>,
+}
+
+/// Small badge naming the container runtime (Docker / Podman) an agent detected, with a
+/// warning when the agent's version doesn't match the master's.
+pub async fn node_runtime_badge(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<NodeParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.unwrap_or_default();
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?.timeout(Duration::from_millis(2000));
+
+    let (info, warning) = match agent.get("/api/v1/info").await {
+        Ok(resp) if resp.is_success() => match resp.json::<AgentInfo>() {
+            Ok(info) => {
+                let warning = version_warning(&info);
+                (Some(info), warning)
+            }
+            Err(_) => (None, None),
+        },
+        Ok(resp) if resp.status == 404 => (None, Some("Agent is older than API v1, please upgrade it".to_string())),
+        _ => (None, None),
+    };
+    Ok(NodeRuntimeTemplate { info, warning })
+}
+
 // === SERVICE MANAGER HANDLERS ===
 
 #[derive(Deserialize)]
@@ -399,167 +806,1994 @@ pub struct ServiceParams {
 struct ServicesTemplate {
     nodes: Vec<NodeConfig>,
     current_node: String,
+    features: NodeFeatures,
 }
 
 pub async fn services_page_handler(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
     Query(params): Query<NodeParams>
-) -> impl IntoResponse {
-    let nodes_list = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
-    let current_node = params.node.unwrap_or_else(|| {
-        nodes_list.first().map(|n| n.url.clone()).unwrap_or("http://127.0.0.1:3001".to_string())
-    });
+) -> Result<impl IntoResponse, Forbidden> {
+    let nodes_list = visible_nodes(&state, &user).await;
+    let current_node = params.node.unwrap_or_else(|| default_node_url(&nodes_list));
+
+    let agent = AgentClient::for_user(&state, &user, &current_node).await?.timeout(Duration::from_millis(2000));
+    let features = NodeFeatures::fetch(&agent, &user).await;
 
-    ServicesTemplate {
+    Ok(ServicesTemplate {
         nodes: nodes_list,
         current_node,
-    }
+        features,
+    })
 }
 
 // Helper to make the proxy request
 async fn proxy_service_command(
     state: &AppState,
+    user: &User,
+    ip: &ClientIp,
     node: Option<String>,
-    path_suffix: &str,
-    method: reqwest::Method,
-) -> String {
+    action: &str,
+    name: &str,
+) -> Result<String, Forbidden> {
+    let method = if action == "status" { "GET" } else { "POST" };
+    if method == "POST" {
+        user.require(Role::Operator)?;
+    }
     let node_url = node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(state, &node_url).await;
-    let client = build_client(token.as_ref());
+    let agent = AgentClient::for_user(state, user, &node_url).await?;
     
-    let url = format!("{}/api/service/{}", node_url, path_suffix);
+    let path = format!("/api/v1/service/{}/{}", action, url_encode(name));
     
-    let request = client.request(method, &url);
-    
-    match request.send().await {
+    let resp = agent.request(method, &path).await;
+    if method == "POST" {
+        let action = format!("service_{}", action);
+        audit::record(state, ip, &user.username, &action, Some(&node_url), Some(name), Outcome::of(&resp)).await;
+    }
+    Ok(match resp {
         Ok(resp) => {
-            if resp.status().is_success() {
-                resp.json::<String>().await.unwrap_or("Error parsing response".to_string())
+            if resp.is_success() {
+                resp.json::<String>().unwrap_or("Error parsing response".to_string())
             } else {
-                format!("Error: Remote Agent returned status {}", resp.status())
+                // e.g. the service is outside the node's allowed_services
+                format!("Error: {}", error_text(resp))
             }
         },
         Err(e) => format!("Connection Error: {}", e),
-    }
+    })
 }
 
 pub async fn service_status_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
     Query(params): Query<ServiceParams>
 ) -> impl IntoResponse {
-    let suffix = format!("status/{}", params.name);
-    proxy_service_command(&state, params.node, &suffix, reqwest::Method::GET).await
+    proxy_service_command(&state, &user, &ip, params.node, "status", &params.name).await
 }
 
 pub async fn service_start_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
     Form(params): Form<ServiceParams>
 ) -> impl IntoResponse {
-    let suffix = format!("start/{}", params.name);
-    proxy_service_command(&state, params.node, &suffix, reqwest::Method::POST).await
+    proxy_service_command(&state, &user, &ip, params.node, "start", &params.name).await
 }
 
 pub async fn service_stop_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
     Form(params): Form<ServiceParams>
 ) -> impl IntoResponse {
-    let suffix = format!("stop/{}", params.name);
-    proxy_service_command(&state, params.node, &suffix, reqwest::Method::POST).await
+    proxy_service_command(&state, &user, &ip, params.node, "stop", &params.name).await
 }
 
 pub async fn service_restart_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
     Form(params): Form<ServiceParams>
 ) -> impl IntoResponse {
-    let suffix = format!("restart/{}", params.name);
-    proxy_service_command(&state, params.node, &suffix, reqwest::Method::POST).await
+    proxy_service_command(&state, &user, &ip, params.node, "restart", &params.name).await
 }
 
 // === DOCKER MANAGER HANDLERS ===
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ContainerInfo {
-    pub id: String,
-    pub image: String,
-    pub status: String,
-    pub names: String,
-    pub state: String,
-}
-
 #[derive(Template)]
 #[template(path = "containers.html")]
 struct ContainersTemplate {
     nodes: Vec<NodeConfig>,
     current_node: String,
+    inspect: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ContainersPageParams { node: Option<String>, inspect: Option<String> }
+
+pub struct ContainerRow {
+    c: ContainerInfo,
+    stats: Option<ContainerStatsView>,
+    alerts: Vec<ContainerAlert>,
+}
+
+pub struct ContainerStatsView {
+    cpu: String,
+    mem: String,
+    mem_pct: f64,
+    net: String,
+    block: String,
+    pids: u64,
+}
+
+impl From<&ContainerStats> for ContainerStatsView {
+    fn from(s: &ContainerStats) -> Self {
+        let mem_pct = if s.mem_limit_bytes > 0 { s.mem_usage_bytes as f64 / s.mem_limit_bytes as f64 * 100.0 } else { 0.0 };
+        ContainerStatsView {
+            cpu: format!("{:.1}%", s.cpu_percent),
+            mem: format!("{} / {}", fmt_size(s.mem_usage_bytes), fmt_size(s.mem_limit_bytes)),
+            mem_pct,
+            net: format!("{} / {}", fmt_size(s.net_rx_bytes), fmt_size(s.net_tx_bytes)),
+            block: format!("{} / {}", fmt_size(s.block_read_bytes), fmt_size(s.block_write_bytes)),
+            pids: s.pids,
+        }
+    }
+}
+
+fn fmt_size(b: u64) -> String {
+    if b >= 1024 * 1024 * 1024 {
+        format!("{:.1} GiB", b as f64 / 1073741824.0)
+    } else if b >= 1024 * 1024 {
+        format!("{:.1} MiB", b as f64 / 1048576.0)
+    } else if b >= 1024 {
+        format!("{:.1} KiB", b as f64 / 1024.0)
+    } else {
+        format!("{} B", b)
+    }
+}
+
+/// Containers of one compose project (or the standalone ones when `name` is None).
+pub struct ProjectGroup {
+    name: Option<String>,
+    health: String,
+    running: usize,
+    total: usize,
+    services: Vec<ServiceGroup>,
+}
+
+pub struct ServiceGroup {
+    name: Option<String>,
+    rows: Vec<ContainerRow>,
 }
 
 #[derive(Template)]
 #[template(path = "containers_rows.html")]
 struct ContainersRowsTemplate {
-    containers: Vec<ContainerInfo>,
+    groups: Vec<ProjectGroup>,
+    node: String,
+    error: Option<String>,
+    features: NodeFeatures,
+}
+
+/// Groups containers by compose project and service. Standalone containers come last.
+fn group_by_project(rows: Vec<ContainerRow>) -> Vec<ProjectGroup> {
+    let mut projects: std::collections::BTreeMap<Option<String>, std::collections::BTreeMap<Option<String>, Vec<ContainerRow>>> =
+        std::collections::BTreeMap::new();
+    for row in rows {
+        projects.entry(row.c.project.clone()).or_default()
+            .entry(row.c.service.clone()).or_default()
+            .push(row);
+    }
+
+    let mut groups: Vec<ProjectGroup> = projects.into_iter().map(|(name, services)| {
+        let all: Vec<&ContainerRow> = services.values().flatten().collect();
+        let total = all.len();
+        let running = all.iter().filter(|r| r.c.state == "running").count();
+        let unhealthy = all.iter().any(|r| r.c.health.as_deref() == Some("unhealthy"));
+        let health = if running == 0 {
+            "down"
+        } else if running < total || unhealthy {
+            "degraded"
+        } else {
+            "healthy"
+        }.to_string();
+
+        ProjectGroup {
+            name,
+            health,
+            running,
+            total,
+            services: services.into_iter().map(|(name, rows)| ServiceGroup { name, rows }).collect(),
+        }
+    }).collect();
+
+    // BTreeMap sorts None first
+    let standalone = groups.iter().take_while(|g| g.name.is_none()).count();
+    groups.rotate_left(standalone);
+    groups
 }
 
 pub async fn containers_page_handler(
     State(state): State<AppState>,
-    Query(params): Query<NodeParams>
+    Extension(user): Extension<User>,
+    Query(params): Query<ContainersPageParams>
 ) -> impl IntoResponse {
-    let nodes_list = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
-    let current_node = params.node.unwrap_or_else(|| {
-        nodes_list.first().map(|n| n.url.clone()).unwrap_or("http://127.0.0.1:3001".to_string())
-    });
+    let nodes_list = visible_nodes(&state, &user).await;
+    let current_node = params.node.unwrap_or_else(|| default_node_url(&nodes_list));
 
     ContainersTemplate {
         nodes: nodes_list,
         current_node,
+        // Set when arriving from a port in the process table
+        inspect: params.inspect,
     }
 }
 
 pub async fn containers_list_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
     Query(params): Query<NodeParams>
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let client = build_client(token.as_ref());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
     
-    let url = format!("{}/api/docker/containers", node_url);
-    
-    let containers = match client.get(&url).send().await {
-        Ok(resp) => resp.json::<Vec<ContainerInfo>>().await.unwrap_or_default(),
-        Err(_) => vec![],
+    // Stats take about a second to sample on the agent, fetch them alongside the list
+    let (list_resp, stats_resp, features) = tokio::join!(
+        agent.get("/api/v1/docker/containers"),
+        agent.get("/api/v1/docker/stats"),
+        NodeFeatures::fetch(&agent, &user)
+    );
+    if !features.collects("docker") {
+        let error = Some("Neither Docker nor Podman is available on this node".to_string());
+        return Ok(ContainersRowsTemplate { groups: vec![], node: node_url, error, features });
+    }
+
+    let stats: Vec<ContainerStats> = match stats_resp {
+        Ok(resp) if resp.is_success() => resp.json().unwrap_or_default(),
+        _ => vec![],
     };
 
-    ContainersRowsTemplate { containers }
+    let node_id = crate::db::get_node_by_url(&state.db, &node_url).await.map(|n| n.id);
+    let alerts: Vec<ContainerAlert> = container_alerts(&state).await
+        .into_iter()
+        .filter(|a| Some(&a.node_id) == node_id.as_ref())
+        .collect();
+
+    Ok(match list_resp {
+        Ok(resp) if resp.is_success() => {
+            let containers = resp.json::<Vec<ContainerInfo>>().unwrap_or_default()
+                .into_iter()
+                .map(|c| {
+                    let stats = stats.iter().find(|s| s.id == c.id).map(ContainerStatsView::from);
+                    let alerts = alerts.iter().filter(|a| a.container_id == c.id).cloned().collect();
+                    ContainerRow { c, stats, alerts }
+                })
+                .collect();
+            ContainersRowsTemplate { groups: group_by_project(containers), node: node_url, error: None, features }
+        },
+        // The agent explains why (e.g. Docker daemon unreachable) in the body
+        Ok(resp) => ContainersRowsTemplate {
+            groups: vec![],
+            error: Some(error_text(resp)),
+            node: node_url,
+            features,
+        },
+        Err(e) => ContainersRowsTemplate {
+            groups: vec![],
+            error: Some(e),
+            node: node_url,
+            features,
+        },
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DockerLogParams {
+    #[serde(skip_serializing)]
+    node: Option<String>,
+    tail: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    timestamps: Option<bool>,
+    /// stdout or stderr to show only one of them, handled on the master
+    #[serde(skip_serializing)]
+    stream: Option<String>,
+    #[serde(skip_serializing)]
+    rate: Option<String>,
+}
+
+async fn fetch_container_logs(agent: &AgentClient, id: &str, params: &DockerLogParams) -> Result<Vec<ContainerLogLine>, String> {
+    let query = serde_urlencoded::to_string(params).map_err(|e| e.to_string())?;
+    agent.get_json(&format!("/api/v1/docker/logs/{}?{}", url_encode(id), query)).await
 }
 
 pub async fn docker_logs_proxy(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Query(params): Query<DockerLogParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.clone().unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+
+    Ok(match fetch_container_logs(&agent, &id, &params).await {
+        Ok(lines) => Json(lines).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_GATEWAY, e).into_response(),
+    })
+}
+
+/// Percent-encodes `s`, also as a path segment of an agent URL: a `/` or `?` in a container id or
+/// service name must not reach another endpoint.
+pub(crate) fn url_encode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[derive(Template)]
+#[template(path = "container_logs.html")]
+struct ContainerLogsTemplate {
+    id: String,
+    query: String,
+    rate: String,
+    lines: Vec<ContainerLogLine>,
+    error: Option<String>,
+}
+
+/// Renders the tail of a container's output, re-polled every `rate` seconds like file logs.
+pub async fn container_logs_view(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Query(params): Query<DockerLogParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.clone().unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let rate = params.rate.clone().unwrap_or("2".to_string());
+
+    // Same parameters for the next poll
+    let mut query: Vec<(&str, String)> = vec![("node", node_url.clone()), ("rate", rate.clone())];
+    for (key, value) in [("tail", &params.tail), ("since", &params.since), ("until", &params.until), ("stream", &params.stream)] {
+        if let Some(v) = value {
+            query.push((key, v.clone()));
+        }
+    }
+    if params.timestamps == Some(true) {
+        query.push(("timestamps", "true".to_string()));
+    }
+    let query = query.iter()
+        .map(|(k, v)| format!("{}={}", k, url_encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    Ok(match fetch_container_logs(&agent, &id, &params).await {
+        Ok(lines) => ContainerLogsTemplate {
+            lines: match params.stream.as_deref() {
+                Some(only @ ("stdout" | "stderr")) => lines.into_iter().filter(|l| l.stream == only).collect(),
+                _ => lines,
+            },
+            id,
+            query,
+            rate,
+            error: None,
+        },
+        Err(e) => ContainerLogsTemplate { id, query, rate, lines: vec![], error: Some(e) },
+    })
+}
+
+#[derive(Template)]
+#[template(path = "container_inspect.html")]
+struct ContainerInspectTemplate {
+    details: Option<ContainerDetails>,
+    error: Option<String>,
+}
+
+pub async fn container_inspect_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
     Query(params): Query<NodeParams>
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let client = build_client(token.as_ref());
-    
-    let url = format!("{}/api/docker/logs/{}", node_url, id);
-    
-    match client.get(&url).send().await {
-        Ok(resp) => Json(resp.json::<Vec<String>>().await.unwrap_or_default()),
-        Err(_) => Json(vec!["Error fetching logs".to_string()]),
-    } 
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+
+    Ok(match agent.get_json::<ContainerDetails>(&format!("/api/v1/docker/inspect/{}", url_encode(&id))).await {
+        Ok(details) => ContainerInspectTemplate { details: Some(details), error: None },
+        Err(e) => ContainerInspectTemplate { details: None, error: Some(e) },
+    })
 }
 
-pub async fn docker_control_proxy(
+#[derive(Deserialize)]
+pub struct HistoryParams { node: Option<String>, days: Option<u32> }
+
+pub async fn container_history_api(
     State(state): State<AppState>,
-    Path((action, id)): Path<(String, String)>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Query(params): Query<HistoryParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let days = params.days.unwrap_or(7);
+
+    let samples = match visible_node(&state, &user, &node_url).await? {
+        Some(node) => crate::db::get_container_stats_history(&state.db, &node.id, &id, days).await.unwrap_or_default(),
+        None => vec![],
+    };
+    Ok(Json(samples))
+}
+
+/// Exits within this window that make a container count as crash looping.
+const CRASH_LOOP_WINDOW_SECS: i64 = 600;
+const CRASH_LOOP_MIN_EXITS: usize = 3;
+/// How long an OOM kill stays flagged.
+const OOM_WINDOW_SECS: i64 = 3600;
+
+#[derive(Serialize, Clone)]
+pub struct ContainerAlert {
+    node_id: String,
+    node_name: String,
+    container_id: String,
+    container_name: String,
+    kind: String, // crash_loop or oom
+    count: usize,
+    last_seen: i64,
+}
+
+/// Derives crash loop and OOM alerts from the stored Docker events.
+async fn container_alerts(state: &AppState) -> Vec<ContainerAlert> {
+    let events = crate::db::get_recent_failures(&state.db, OOM_WINDOW_SECS.max(CRASH_LOOP_WINDOW_SECS)).await.unwrap_or_default();
+    let nodes = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let mut alerts: Vec<ContainerAlert> = Vec::new();
+    for (kind, action, window, min_count) in [
+        ("crash_loop", "die", CRASH_LOOP_WINDOW_SECS, CRASH_LOOP_MIN_EXITS),
+        ("oom", "oom", OOM_WINDOW_SECS, 1),
+    ] {
+        let mut per_container: std::collections::BTreeMap<(&str, &str), Vec<&crate::db::ContainerEventRecord>> = Default::default();
+        for e in events.iter().filter(|e| e.action == action && e.time >= now - window) {
+            per_container.entry((e.node_id.as_str(), e.container_id.as_str())).or_default().push(e);
+        }
+
+        for ((node_id, container_id), hits) in per_container {
+            if hits.len() < min_count {
+                continue;
+            }
+            let last = hits[hits.len() - 1];
+            alerts.push(ContainerAlert {
+                node_id: node_id.to_string(),
+                node_name: nodes.iter().find(|n| n.id == node_id).map(|n| n.name.clone()).unwrap_or_default(),
+                container_id: container_id.to_string(),
+                container_name: last.container_name.clone(),
+                kind: kind.to_string(),
+                count: hits.len(),
+                last_seen: last.time,
+            });
+        }
+    }
+    alerts
+}
+
+/// Returns containers currently crash looping or recently OOM killed, for polling by external alerting.
+pub async fn docker_alerts_api(State(state): State<AppState>, Extension(user): Extension<User>) -> impl IntoResponse {
+    let nodes = visible_nodes(&state, &user).await;
+    let alerts: Vec<ContainerAlert> = container_alerts(&state).await
+        .into_iter()
+        .filter(|a| nodes.iter().any(|n| n.id == a.node_id))
+        .collect();
+    Json(alerts)
+}
+
+pub struct EventRow {
+    action: String,
+    detail: String,
+    when: String,
+}
+
+#[derive(Template)]
+#[template(path = "container_events.html")]
+struct ContainerEventsTemplate {
+    events: Vec<EventRow>,
+    error: Option<String>,
+}
+
+pub async fn container_events_view(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
     Query(params): Query<NodeParams>
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Forbidden> {
     let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
-    let token = get_token_for_url(&state, &node_url).await;
-    let client = build_client(token.as_ref());
-    
-    let url = format!("{}/api/docker/{}/{}", node_url, action, id);
-    
-    let _ = client.post(&url).send().await;
-    "Action Sent"
-}
\ No newline at end of file
+
+    let Some(node) = visible_node(&state, &user, &node_url).await? else {
+        return Ok(ContainerEventsTemplate { events: vec![], error: Some(format!("Unknown node {}", node_url)) });
+    };
+    Ok(match crate::db::get_container_events(&state.db, &node.id, &id, 200).await {
+        Ok(records) => ContainerEventsTemplate {
+            events: records.into_iter().map(|r| EventRow {
+                detail: match (r.action.as_str(), r.detail) {
+                    ("die", Some(code)) if code == "137" => "exit code 137 (killed)".to_string(),
+                    ("die", Some(code)) => format!("exit code {}", code),
+                    (_, detail) => detail.unwrap_or_default(),
+                },
+                when: fmt_relative(Some(r.time)),
+                action: r.action,
+            }).collect(),
+            error: None,
+        },
+        Err(e) => ContainerEventsTemplate { events: vec![], error: Some(e.to_string()) },
+    })
+}
+
+pub async fn docker_control_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path((action, id)): Path<(String, String)>,
+    Query(params): Query<NodeParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Operator)?;
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    
+    let resp = agent.post(&format!("/api/v1/docker/{}/{}", url_encode(&action), url_encode(&id))).await;
+    let action = format!("docker_{}", action);
+    audit::record(&state, &ip, &user.username, &action, Some(&node_url), Some(&id), Outcome::of(&resp)).await;
+    Ok("Action Sent")
+}
+
+#[derive(Deserialize)]
+pub struct RecreateParams { node: Option<String>, pull: Option<bool> }
+
+/// POSTs a Docker action to the agent and records it in the audit log as `action` on `target`.
+async fn proxy_docker_post(
+    state: &AppState,
+    user: &User,
+    ip: &ClientIp,
+    node_url: &str,
+    path: &str,
+    action: &str,
+    target: &str,
+) -> Result<(axum::http::StatusCode, String), Forbidden> {
+    user.require(Role::Operator)?;
+    let agent = AgentClient::for_user(state, user, node_url).await?;
+
+    let resp = agent.post(path).await;
+    audit::record(state, ip, &user.username, action, Some(node_url), Some(target), Outcome::of(&resp)).await;
+    Ok(match resp {
+        Ok(resp) if resp.is_success() => (axum::http::StatusCode::OK, resp.json::<String>().unwrap_or_default()),
+        Ok(resp) => (axum::http::StatusCode::BAD_GATEWAY, error_text(resp)),
+        Err(e) => (axum::http::StatusCode::BAD_GATEWAY, e),
+    })
+}
+
+pub async fn docker_project_control_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path((project, action)): Path<(String, String)>,
+    Query(params): Query<NodeParams>
+) -> impl IntoResponse {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let path = format!("/api/v1/docker/compose/{}/{}", url_encode(&project), url_encode(&action));
+    proxy_docker_post(&state, &user, &ip, &node_url, &path, &format!("compose_{}", action), &project).await
+}
+
+pub async fn docker_recreate_service_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path((project, service)): Path<(String, String)>,
+    Query(params): Query<RecreateParams>
+) -> impl IntoResponse {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let path = format!("/api/v1/docker/compose/{}/recreate/{}?pull={}", url_encode(&project), url_encode(&service), params.pull.unwrap_or(false));
+    let target = format!("{}/{}", project, service);
+    proxy_docker_post(&state, &user, &ip, &node_url, &path, "compose_recreate", &target).await
+}
+
+// === DOCKER IMAGES / VOLUMES / NETWORKS ===
+
+#[derive(Template)]
+#[template(path = "docker_resources.html")]
+struct DockerResourcesTemplate {
+    nodes: Vec<NodeConfig>,
+    current_node: String,
+    kind: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResourceParams { node: Option<String>, kind: Option<String> }
+
+pub struct ImageRow {
+    img: ImageInfo,
+    size_txt: String,
+    created_txt: String,
+}
+
+pub struct VolumeRow {
+    vol: VolumeInfo,
+    size_txt: String,
+}
+
+#[derive(Template)]
+#[template(path = "docker_resources_rows.html")]
+struct DockerResourcesRowsTemplate {
+    kind: String,
+    node: String,
+    images: Vec<ImageRow>,
+    volumes: Vec<VolumeRow>,
+    networks: Vec<NetworkInfo>,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "prune_modal.html")]
+struct PruneTemplate {
+    kind: String,
+    node: String,
+    report: Option<PruneReport>,
+    reclaim_txt: String,
+    error: Option<String>,
+    /// The node's action policy or the user's role forbids pruning, only the preview is shown
+    denied: bool,
+    denied_title: &'static str,
+}
+
+fn resource_kind(kind: Option<String>) -> String {
+    match kind.as_deref() {
+        Some("volumes") => "volumes".to_string(),
+        Some("networks") => "networks".to_string(),
+        _ => "images".to_string(),
+    }
+}
+
+/// GETs `path` from the agent, turning failures into the message shown in the table.
+async fn fetch_docker_json<T: serde::de::DeserializeOwned>(agent: &AgentClient, path: &str) -> Result<T, String> {
+    agent.get_json(path).await
+}
+
+pub async fn docker_resources_page_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<ResourceParams>
+) -> impl IntoResponse {
+    let nodes_list = visible_nodes(&state, &user).await;
+    let current_node = params.node.unwrap_or_else(|| default_node_url(&nodes_list));
+
+    DockerResourcesTemplate {
+        nodes: nodes_list,
+        current_node,
+        kind: resource_kind(params.kind),
+    }
+}
+
+pub async fn docker_resources_list_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<ResourceParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+    let kind = resource_kind(params.kind);
+
+    let mut tpl = DockerResourcesRowsTemplate {
+        kind: kind.clone(),
+        node: node_url.clone(),
+        images: vec![],
+        volumes: vec![],
+        networks: vec![],
+        error: None,
+    };
+
+    let result = match kind.as_str() {
+        "images" => fetch_docker_json::<Vec<ImageInfo>>(&agent, "/api/v1/docker/images").await.map(|images| {
+            tpl.images = images.into_iter().map(|img| ImageRow {
+                size_txt: fmt_size(img.size_bytes),
+                created_txt: fmt_relative(Some(img.created)),
+                img,
+            }).collect();
+        }),
+        "volumes" => fetch_docker_json::<Vec<VolumeInfo>>(&agent, "/api/v1/docker/volumes").await.map(|volumes| {
+            tpl.volumes = volumes.into_iter().map(|vol| VolumeRow {
+                size_txt: vol.size_bytes.map(fmt_size).unwrap_or_else(|| "-".to_string()),
+                vol,
+            }).collect();
+        }),
+        _ => fetch_docker_json::<Vec<NetworkInfo>>(&agent, "/api/v1/docker/networks").await.map(|networks| {
+            tpl.networks = networks;
+        }),
+    };
+
+    if let Err(e) = result {
+        tpl.error = Some(e);
+    }
+    Ok(tpl)
+}
+
+pub async fn docker_resource_rm_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path((kind, id)): Path<(String, String)>,
+    Query(params): Query<NodeParams>
+) -> impl IntoResponse {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+
+    if kind != "images" && kind != "volumes" {
+        return Ok((axum::http::StatusCode::BAD_REQUEST, "Invalid resource type".to_string()));
+    }
+    // e.g. a 409 from the agent when an image is still used by a container
+    let path = format!("/api/v1/docker/{}/rm/{}", kind, url_encode(&id));
+    // "images" -> docker_image_rm
+    let action = format!("docker_{}_rm", kind.trim_end_matches('s'));
+    proxy_docker_post(&state, &user, &ip, &node_url, &path, &action, &id).await
+}
+
+/// What the agent can prune.
+const PRUNE_KINDS: &[&str] = &["containers", "images", "volumes"];
+
+async fn proxy_prune(
+    state: &AppState,
+    user: &User,
+    ip: &ClientIp,
+    node: Option<String>,
+    kind: String,
+    dry_run: bool,
+) -> Result<PruneTemplate, Forbidden> {
+    // Even the preview is a POST to the agent
+    user.require(Role::Operator)?;
+    if !PRUNE_KINDS.contains(&kind.as_str()) {
+        return Err(Forbidden(format!("Unknown prune kind '{}'", kind)));
+    }
+    let node_url = node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(state, user, &node_url).await?;
+
+    let features = NodeFeatures::fetch(&agent, user).await;
+    let denied = features.denies("docker_prune");
+    let denied_title = features.denied_title();
+    let result = if !features.supports("docker_prune") {
+        Err("Pruning is not available on this node".to_string())
+    } else {
+        let resp = agent.post(&format!("/api/v1/docker/prune/{}?dry_run={}", kind, dry_run)).await;
+        if !dry_run {
+            audit::record(state, ip, &user.username, "docker_prune", Some(&node_url), Some(&kind), Outcome::of(&resp)).await;
+        }
+        match resp {
+            Ok(resp) if resp.is_success() => resp.json::<PruneReport>(),
+            Ok(resp) => Err(error_text(resp)),
+            Err(e) => Err(e),
+        }
+    };
+
+    Ok(match result {
+        Ok(report) => PruneTemplate {
+            kind,
+            node: node_url,
+            reclaim_txt: fmt_size(report.reclaimable_bytes),
+            report: Some(report),
+            error: None,
+            denied,
+            denied_title,
+        },
+        Err(e) => PruneTemplate {
+            kind, node: node_url, report: None, reclaim_txt: String::new(), error: Some(e), denied, denied_title,
+        },
+    })
+}
+
+/// Shows what a prune would remove, without touching anything.
+pub async fn docker_prune_preview(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(kind): Path<String>,
+    Query(params): Query<NodeParams>
+) -> impl IntoResponse {
+    proxy_prune(&state, &user, &ip, params.node, kind, true).await
+}
+
+pub async fn docker_prune_execute(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(kind): Path<String>,
+    Query(params): Query<NodeParams>
+) -> impl IntoResponse {
+    proxy_prune(&state, &user, &ip, params.node, kind, false).await
+}
+
+// === SCHEDULED JOBS HANDLERS ===
+
+pub struct ScheduleRow {
+    job: ScheduledJob,
+    next_txt: String,
+    last_txt: String,
+}
+
+#[derive(Template)]
+#[template(path = "schedules.html")]
+struct SchedulesTemplate {
+    nodes: Vec<NodeConfig>,
+    current_node: String,
+}
+
+#[derive(Template)]
+#[template(path = "schedules_rows.html")]
+struct SchedulesRowsTemplate {
+    rows: Vec<ScheduleRow>,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "schedules_alerts.html")]
+struct ScheduleAlertsTemplate {
+    alerts: Vec<ScheduleAlert>,
+}
+
+#[derive(Serialize)]
+pub struct ScheduleAlert {
+    node_id: String,
+    node_name: String,
+    /// The missed or failed job; None if the node's jobs couldn't be fetched
+    job: Option<ScheduledJob>,
+    /// Why they couldn't, e.g. the agent is unreachable
+    error: Option<String>,
+}
+
+/// Formats a Unix timestamp relative to now, e.g. "in 3h 5m" or "12m ago".
+fn fmt_relative(ts: Option<i64>) -> String {
+    let ts = match ts {
+        Some(t) => t,
+        None => return "-".to_string(),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let diff = (ts - now).abs();
+    let span = if diff >= 86400 {
+        format!("{}d {}h", diff / 86400, (diff % 86400) / 3600)
+    } else if diff >= 3600 {
+        format!("{}h {}m", diff / 3600, (diff % 3600) / 60)
+    } else if diff >= 60 {
+        format!("{}m", diff / 60)
+    } else {
+        format!("{}s", diff)
+    };
+    if ts >= now { format!("in {}", span) } else { format!("{} ago", span) }
+}
+
+pub async fn schedules_page_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<NodeParams>
+) -> impl IntoResponse {
+    let nodes_list = visible_nodes(&state, &user).await;
+    let current_node = params.node.unwrap_or_else(|| default_node_url(&nodes_list));
+
+    SchedulesTemplate {
+        nodes: nodes_list,
+        current_node,
+    }
+}
+
+pub async fn schedules_list_proxy(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<NodeParams>
+) -> Result<impl IntoResponse, Forbidden> {
+    let node_url = params.node.unwrap_or("http://127.0.0.1:3001".to_string());
+    let agent = AgentClient::for_user(&state, &user, &node_url).await?;
+
+    Ok(match fetch_schedules(&agent).await {
+        Ok(jobs) => {
+            let rows = jobs.into_iter().map(|job| ScheduleRow {
+                next_txt: fmt_relative(job.next_run),
+                last_txt: fmt_relative(job.last_run),
+                job,
+            }).collect();
+            SchedulesRowsTemplate { rows, error: None }
+        },
+        Err(e) => SchedulesRowsTemplate {
+            rows: vec![],
+            error: Some(e),
+        },
+    })
+}
+
+/// Every missed or failed timer across the user's nodes, and the nodes whose jobs are unknown
+/// because they couldn't be asked: an unreachable node mustn't look like one without problems.
+async fn schedule_alerts(state: &AppState, user: &User) -> Vec<ScheduleAlert> {
+    let nodes = visible_nodes(state, user).await;
+    let mut alerts = Vec::new();
+
+    for node in nodes {
+        let agent = AgentClient::new(state, &node).acting_as(user);
+        match fetch_schedules(&agent).await {
+            Ok(jobs) => {
+                for job in jobs {
+                    if job.health == "missed" || job.health == "failed" {
+                        alerts.push(ScheduleAlert {
+                            node_id: node.id.clone(),
+                            node_name: node.name.clone(),
+                            job: Some(job),
+                            error: None,
+                        });
+                    }
+                }
+            }
+            Err(e) => alerts.push(ScheduleAlert { node_id: node.id, node_name: node.name, job: None, error: Some(e) }),
+        }
+    }
+    alerts
+}
+
+/// Problems across all nodes as JSON, for polling by external alerting.
+pub async fn schedules_alerts_api(State(state): State<AppState>, Extension(user): Extension<User>) -> impl IntoResponse {
+    Json(schedule_alerts(&state, &user).await)
+}
+
+/// The same problems above the schedules table.
+pub async fn schedules_alerts_view(State(state): State<AppState>, Extension(user): Extension<User>) -> impl IntoResponse {
+    ScheduleAlertsTemplate { alerts: schedule_alerts(&state, &user).await }
+}
+
+// === AUDIT LOG ===
+
+/// Entries the audit page shows at most; exports go further back.
+const AUDIT_PAGE_LIMIT: u32 = 500;
+const AUDIT_EXPORT_LIMIT: u32 = 100_000;
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+struct AuditTemplate {
+    users: Vec<String>,
+    /// (URL, name) of the nodes in the log
+    nodes: Vec<(String, String)>,
+}
+
+pub struct AuditRow {
+    entry: crate::db::AuditEntry,
+    node_name: String,
+}
+
+#[derive(Template)]
+#[template(path = "audit_rows.html")]
+struct AuditRowsTemplate {
+    rows: Vec<AuditRow>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditExportParams {
+    #[serde(default)]
+    format: String,
+    #[serde(flatten)]
+    filter: crate::db::AuditFilter,
+}
+
+/// Node names by URL; nodes deleted since are shown by URL.
+async fn node_names(state: &AppState) -> std::collections::HashMap<String, String> {
+    crate::db::get_all_nodes(&state.db).await.unwrap_or_default()
+        .into_iter()
+        .map(|n| (n.url, n.name))
+        .collect()
+}
+
+pub async fn audit_page_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let (users, node_urls) = crate::db::get_audit_facets(&state.db).await.unwrap_or_default();
+    let names = node_names(&state).await;
+    let nodes = node_urls.into_iter()
+        .map(|url| {
+            let name = names.get(&url).cloned().unwrap_or_else(|| url.clone());
+            (url, name)
+        })
+        .collect();
+    Ok(AuditTemplate { users, nodes })
+}
+
+pub async fn audit_list_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(filter): Query<crate::db::AuditFilter>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let names = node_names(&state).await;
+    Ok(match crate::db::get_audit_entries(&state.db, &filter, AUDIT_PAGE_LIMIT).await {
+        Ok(entries) => {
+            let rows = entries.into_iter().map(|entry| {
+                let node_name = entry.node.as_ref()
+                    .map(|url| names.get(url).cloned().unwrap_or_else(|| url.clone()))
+                    .unwrap_or_else(|| "-".to_string());
+                AuditRow { entry, node_name }
+            }).collect();
+            AuditRowsTemplate { rows, error: None }
+        }
+        Err(e) => AuditRowsTemplate { rows: vec![], error: Some(format!("Cannot read audit log: {}", e)) },
+    })
+}
+
+/// The filtered log as `format=csv` (default) or `format=json`, as a download.
+pub async fn audit_export_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(params): Query<AuditExportParams>,
+) -> Response {
+    if let Err(forbidden) = user.require(Role::Admin) {
+        return forbidden.into_response();
+    }
+    let entries = match crate::db::get_audit_entries(&state.db, &params.filter, AUDIT_EXPORT_LIMIT).await {
+        Ok(entries) => entries,
+        Err(e) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let (content_type, extension, body) = if params.format == "json" {
+        ("application/json", "json", serde_json::to_string_pretty(&entries).unwrap_or_default())
+    } else {
+        ("text/csv", "csv", audit_csv(&entries))
+    };
+    let disposition = format!("attachment; filename=\"audit.{}\"", extension);
+    (
+        [
+            (axum::http::header::CONTENT_TYPE, content_type.to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ).into_response()
+}
+
+fn audit_csv(entries: &[crate::db::AuditEntry]) -> String {
+    // Quoted, and text a spreadsheet would run as a formula is prefixed with '
+    fn field(value: Option<&str>) -> String {
+        let value = value.unwrap_or_default();
+        let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }
+
+    let mut csv = String::from("timestamp,user,action,node,target,result,detail,source_ip\n");
+    for e in entries {
+        let fields = [
+            Some(e.timestamp.as_str()),
+            e.username.as_deref(),
+            Some(e.action.as_str()),
+            e.node.as_deref(),
+            e.target.as_deref(),
+            e.result.as_deref(),
+            e.detail.as_deref(),
+            e.source_ip.as_deref(),
+        ];
+        csv += &fields.map(field).join(",");
+        csv.push('\n');
+    }
+    csv
+}
+
+// === USER MANAGEMENT ===
+
+/// A user as listed to admins, without the password hash.
+#[derive(Serialize)]
+pub struct UserSummary {
+    username: String,
+    role: String,
+    node_tags: String,
+    disabled: bool,
+    must_change_password: bool,
+    totp_enabled: bool,
+    sso: bool,
+}
+
+impl UserSummary {
+    fn has_role(&self, role: &str) -> bool {
+        self.role == role
+    }
+}
+
+impl From<User> for UserSummary {
+    fn from(u: User) -> Self {
+        UserSummary {
+            username: u.username,
+            role: u.role,
+            node_tags: u.node_tags,
+            disabled: u.disabled,
+            must_change_password: u.must_change_password,
+            totp_enabled: u.totp_enabled,
+            sso: u.sso,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    roles: Vec<&'static str>,
+    require_2fa: bool,
+    /// Single sign-on is configured
+    sso: bool,
+}
+
+#[derive(Template)]
+#[template(path = "users_rows.html")]
+struct UsersRowsTemplate {
+    users: Vec<UserSummary>,
+    roles: Vec<&'static str>,
+    /// The admin looking at the list, who can't disable or delete themselves
+    current: String,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewUserForm {
+    username: String,
+    #[serde(default)]
+    password: String,
+    role: String,
+    #[serde(default)]
+    node_tags: String,
+    /// Logs in through the identity provider, `password` is ignored
+    #[serde(default)]
+    sso: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RoleForm {
+    role: String,
+    #[serde(default)]
+    node_tags: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetForm {
+    password: String,
+}
+
+type UserResult = Result<&'static str, (axum::http::StatusCode, String)>;
+
+fn parse_role(name: &str) -> Result<Role, (axum::http::StatusCode, String)> {
+    Role::parse(name.trim()).ok_or_else(|| (axum::http::StatusCode::BAD_REQUEST, format!("Unknown role '{}'", name)))
+}
+
+/// "prod,  db," -> "prod, db"
+fn normalize_tags(tags: &str) -> String {
+    crate::auth::split_tags(tags).join(", ")
+}
+
+/// Loads the user an admin action is about.
+async fn target_user(state: &AppState, username: &str) -> Result<User, (axum::http::StatusCode, String)> {
+    crate::db::get_user_by_username(&state.db, username).await
+        .ok_or_else(|| (axum::http::StatusCode::NOT_FOUND, format!("No user '{}'", username)))
+}
+
+/// Turns a change the database refused because it would leave no enabled admin to manage the
+/// master (the `Ok(false)` of the user updates) into the error saying so.
+fn keep_an_admin(result: Result<bool, sqlx::Error>, username: &str) -> Result<Result<(), sqlx::Error>, (axum::http::StatusCode, String)> {
+    match result {
+        Ok(false) => Err((axum::http::StatusCode::CONFLICT, format!("'{}' is the last enabled admin", username))),
+        result => Ok(result.map(|_| ())),
+    }
+}
+
+/// Records an admin's change to `target` and turns a database error into the response.
+async fn record_user_change(
+    state: &AppState,
+    ip: &ClientIp,
+    admin: &User,
+    action: &str,
+    target: &str,
+    result: Result<(), sqlx::Error>,
+    done: &'static str,
+) -> UserResult {
+    let outcome = match &result {
+        Ok(()) => Outcome::Ok,
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    audit::record(state, ip, &admin.username, action, None, Some(target), outcome).await;
+    match result {
+        Ok(()) => Ok(done),
+        Err(e) => Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+pub async fn users_page_handler(State(state): State<AppState>, Extension(user): Extension<User>) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let require_2fa = crate::db::get_setting(&state.db, REQUIRE_2FA_SETTING).await.as_deref() == Some("1");
+    Ok(UsersTemplate { roles: Role::ALL.map(Role::as_str).to_vec(), require_2fa, sso: state.oidc.is_some() })
+}
+
+pub async fn users_list_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, Forbidden> {
+    user.require(Role::Admin)?;
+    let roles = Role::ALL.map(Role::as_str).to_vec();
+    Ok(match crate::db::get_all_users(&state.db).await {
+        Ok(users) => UsersRowsTemplate {
+            users: users.into_iter().map(UserSummary::from).collect(),
+            roles,
+            current: user.username,
+            error: None,
+        },
+        Err(e) => UsersRowsTemplate { users: vec![], roles, current: user.username, error: Some(e.to_string()) },
+    })
+}
+
+pub async fn users_api(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
+    user.require(Role::Admin)?;
+    let users = crate::db::get_all_users(&state.db).await
+        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(users.into_iter().map(UserSummary::from).collect::<Vec<_>>()))
+}
+
+pub async fn user_create_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Json(form): Json<NewUserForm>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    let username = form.username.trim();
+    if !crate::password::valid_username(username) {
+        return Err((axum::http::StatusCode::BAD_REQUEST, "Usernames can only have letters, digits and . _ - @ (at most 64)".to_string()));
+    }
+    if !form.sso {
+        state.password_policy.check(username, &form.password)
+            .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, e))?;
+    }
+    let role = parse_role(&form.role)?;
+    if crate::db::get_user_by_username(&state.db, username).await.is_some() {
+        return Err((axum::http::StatusCode::CONFLICT, format!("User '{}' already exists", username)));
+    }
+    // An empty hash matches no password
+    let password_hash = match form.sso {
+        true => String::new(),
+        false => bcrypt::hash(&form.password, bcrypt::DEFAULT_COST)
+            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+    };
+    let user = User {
+        username: username.to_string(),
+        password_hash,
+        role: role.as_str().to_string(),
+        // The admin picked the password, the user should pick their own
+        must_change_password: !form.sso,
+        node_tags: normalize_tags(&form.node_tags),
+        disabled: false,
+        totp_enabled: false,
+        sso: form.sso,
+    };
+    let result = crate::db::create_user(&state.db, &user).await;
+    record_user_change(&state, &ip, &admin, "user_create", username, result, "Created").await
+}
+
+pub async fn user_role_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+    Json(form): Json<RoleForm>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    let role = parse_role(&form.role)?;
+    target_user(&state, &username).await?;
+    let result = crate::db::update_user_role(&state.db, &username, role.as_str(), &normalize_tags(&form.node_tags)).await;
+    let result = keep_an_admin(result, &username)?;
+    record_user_change(&state, &ip, &admin, "user_role", &username, result, "Saved").await
+}
+
+pub async fn user_disable_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    if username == admin.username {
+        return Err((axum::http::StatusCode::CONFLICT, "You can't disable your own account".to_string()));
+    }
+    target_user(&state, &username).await?;
+    let result = keep_an_admin(crate::db::set_user_disabled(&state.db, &username, true).await, &username)?;
+    record_user_change(&state, &ip, &admin, "user_disable", &username, result, "Disabled").await
+}
+
+pub async fn user_enable_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    target_user(&state, &username).await?;
+    let result = crate::db::set_user_disabled(&state.db, &username, false).await.map(|_| ());
+    record_user_change(&state, &ip, &admin, "user_enable", &username, result, "Enabled").await
+}
+
+pub async fn user_password_reset_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+    Json(form): Json<PasswordResetForm>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    state.password_policy.check(&username, &form.password)
+        .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, e))?;
+    let target = target_user(&state, &username).await?;
+    if target.sso {
+        return Err((axum::http::StatusCode::BAD_REQUEST, format!("'{}' logs in through the identity provider", username)));
+    }
+    let password_hash = bcrypt::hash(&form.password, bcrypt::DEFAULT_COST)
+        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let keep = state.password_policy.replaced_to_keep();
+    let mut result = Ok(());
+    if keep > 0 {
+        // So the user can't go back to it after the reset
+        result = crate::db::add_password_history(&state.db, &username, &target.password_hash, keep).await;
+    }
+    if result.is_ok() {
+        result = crate::db::reset_user_password(&state.db, &username, &password_hash).await;
+    }
+    record_user_change(&state, &ip, &admin, "user_password_reset", &username, result, "Password reset").await
+}
+
+pub async fn user_delete_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    if username == admin.username {
+        return Err((axum::http::StatusCode::CONFLICT, "You can't delete your own account".to_string()));
+    }
+    target_user(&state, &username).await?;
+    let result = keep_an_admin(crate::db::delete_user(&state.db, &username).await, &username)?;
+    record_user_change(&state, &ip, &admin, "user_delete", &username, result, "Deleted").await
+}
+
+// === SESSIONS ===
+
+#[derive(Deserialize)]
+pub struct SessionsParams {
+    /// Whose sessions; others than your own only for admins
+    user: Option<String>,
+}
+
+pub struct SessionRow {
+    record: crate::db::SessionRecord,
+    created_txt: String,
+    last_seen_txt: String,
+    current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+struct SessionsTemplate {
+    username: String,
+    /// The list is the viewer's own, ending all of them logs them out
+    own: bool,
+    sessions: Vec<SessionRow>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SessionSummary {
+    id: String,
+    created_at: i64,
+    last_seen: i64,
+    source_ip: Option<String>,
+    user_agent: Option<String>,
+    current: bool,
+}
+
+/// The user whose sessions a request is about.
+fn sessions_owner(user: &User, params: SessionsParams) -> Result<String, Forbidden> {
+    match params.user.filter(|u| !u.is_empty() && *u != user.username) {
+        Some(other) => {
+            user.require(Role::Admin)?;
+            Ok(other)
+        }
+        None => Ok(user.username.clone()),
+    }
+}
+
+pub async fn sessions_page_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<crate::db::SessionRecord>,
+    Query(params): Query<SessionsParams>,
+) -> Result<impl IntoResponse, Forbidden> {
+    let username = sessions_owner(&user, params)?;
+    let (sessions, error) = match crate::db::get_user_sessions(&state.db, &username).await {
+        Ok(records) => (records, None),
+        Err(e) => (vec![], Some(e.to_string())),
+    };
+    let sessions = sessions.into_iter().map(|record| SessionRow {
+        created_txt: fmt_relative(Some(record.created_at)),
+        last_seen_txt: fmt_relative(Some(record.last_seen)),
+        current: record.id_hash == session.id_hash,
+        record,
+    }).collect();
+    Ok(SessionsTemplate { own: username == user.username, username, sessions, error })
+}
+
+pub async fn sessions_api(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<crate::db::SessionRecord>,
+    Query(params): Query<SessionsParams>,
+) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
+    let username = sessions_owner(&user, params)?;
+    let records = crate::db::get_user_sessions(&state.db, &username).await
+        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(records.into_iter().map(|r| SessionSummary {
+        current: r.id_hash == session.id_hash,
+        id: r.id_hash,
+        created_at: r.created_at,
+        last_seen: r.last_seen,
+        source_ip: r.source_ip,
+        user_agent: r.user_agent,
+    }).collect::<Vec<_>>()))
+}
+
+/// Ends one session: your own, or anyone's for admins.
+pub async fn session_revoke_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Path(id): Path<String>,
+) -> UserResult {
+    let Some(target) = crate::db::get_session(&state.db, &id).await else {
+        return Err((axum::http::StatusCode::NOT_FOUND, "No such session".to_string()));
+    };
+    if target.username != user.username {
+        user.require(Role::Admin)?;
+    }
+    let result = crate::db::delete_session(&state.db, &id).await;
+    record_user_change(&state, &ip, &user, "session_revoke", &target.username, result, "Revoked").await
+}
+
+/// "Log out everywhere": ends every session of the user, the one asking included.
+pub async fn sessions_revoke_all_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Query(params): Query<SessionsParams>,
+) -> UserResult {
+    let username = sessions_owner(&user, params)?;
+    let result = crate::db::delete_user_sessions(&state.db, &username, None).await;
+    record_user_change(&state, &ip, &user, "logout_everywhere", &username, result, "Logged out everywhere").await
+}
+
+// === TWO-FACTOR AUTHENTICATION ===
+
+#[derive(Template)]
+#[template(path = "two_factor.html")]
+struct TwoFactorTemplate {
+    enabled: bool,
+    /// The policy requires 2FA for this user's role, so it can't be turned off
+    required: bool,
+    /// While enrolling: the QR code, the URI it holds and the secret to type in by hand
+    qr_svg: Option<String>,
+    uri: String,
+    secret: String,
+    /// Codes just generated, shown this once
+    recovery_codes: Vec<String>,
+    remaining_codes: i64,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeForm {
+    code: String,
+}
+
+/// Issuer shown in authenticator apps.
+const TOTP_ISSUER: &str = "PortSentinel";
+
+/// Renders the 2FA page for `user`, starting an enrollment when 2FA is off.
+async fn two_factor_page(state: &AppState, user: &User, recovery_codes: Vec<String>, message: Option<String>, error: Option<String>) -> TwoFactorTemplate {
+    let required = two_factor_required(state, user).await;
+    let mut page = TwoFactorTemplate {
+        enabled: false, required, qr_svg: None, uri: String::new(), secret: String::new(),
+        recovery_codes, remaining_codes: 0, message, error,
+    };
+    let totp = crate::db::get_totp(&state.db, &user.username).await;
+    if totp.as_ref().is_some_and(|t| t.totp_enabled) {
+        page.enabled = true;
+        page.remaining_codes = crate::db::count_recovery_codes(&state.db, &user.username).await.unwrap_or(0);
+        return page;
+    }
+    // Keep the secret of an enrollment in progress, the app may have scanned it already
+    let secret = match totp.and_then(|t| t.totp_secret) {
+        Some(secret) => secret,
+        None => {
+            let secret = crate::totp::generate_secret();
+            if let Err(e) = crate::db::set_pending_totp(&state.db, &user.username, &secret).await {
+                page.error = Some(format!("Cannot start 2FA enrollment: {}", e));
+                return page;
+            }
+            secret
+        }
+    };
+    page.uri = crate::totp::provisioning_uri(TOTP_ISSUER, &user.username, &secret);
+    page.qr_svg = crate::totp::qr_svg(&page.uri).ok();
+    page.secret = secret;
+    page
+}
+
+/// Generates and stores new recovery codes, returning them for showing once.
+async fn new_recovery_codes(state: &AppState, username: &str) -> Result<Vec<String>, sqlx::Error> {
+    let codes = crate::totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter()
+        .map(|c| crate::enroll::hash_secret(&crate::totp::normalize_recovery_code(c)))
+        .collect();
+    crate::db::replace_recovery_codes(&state.db, username, &hashes).await?;
+    Ok(codes)
+}
+
+/// Checks a code from the user's app against their secret, enrolled or not yet.
+async fn check_totp_code(state: &AppState, username: &str, code: &str) -> Option<i64> {
+    let totp = crate::db::get_totp(&state.db, username).await?;
+    let step = crate::totp::verify(&totp.totp_secret?, code, totp.totp_last_step)?;
+    crate::db::use_totp_step(&state.db, username, step).await.ok().filter(|used| *used).map(|_| step)
+}
+
+/// `check_totp_code` behind the login throttle: wrong codes count like failed logins, so a
+/// hijacked session can't guess its way to turning 2FA off.
+async fn throttled_totp_code(state: &AppState, ip: &ClientIp, username: &str, code: &str) -> Result<i64, String> {
+    if let Err(wait_secs) = state.login_throttle.begin(&ip.0, username) {
+        return Err(format!("Too many wrong codes, try again in {}", wait_text(wait_secs)));
+    }
+    match check_totp_code(state, username, code).await {
+        Some(step) => {
+            state.login_throttle.passed(&ip.0, username);
+            Ok(step)
+        }
+        None => {
+            if state.login_throttle.failed(&ip.0, username) {
+                println!("🔒 Too many wrong 2FA codes for '{}' from {}, locked out", username, ip.0);
+            }
+            Err("Invalid code, check the time on your phone".to_string())
+        }
+    }
+}
+
+pub async fn two_factor_page_handler(State(state): State<AppState>, Extension(user): Extension<User>) -> impl IntoResponse {
+    two_factor_page(&state, &user, vec![], None, None).await
+}
+
+/// Confirms an enrollment with the first code from the app.
+pub async fn two_factor_enable_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    let step = match throttled_totp_code(&state, &ip, &user.username, &form.code).await {
+        Ok(step) => step,
+        Err(e) => return two_factor_page(&state, &user, vec![], None, Some(e)).await,
+    };
+    let result = match crate::db::enable_totp(&state.db, &user.username, step).await {
+        Ok(()) => new_recovery_codes(&state, &user.username).await,
+        Err(e) => Err(e),
+    };
+    let outcome = result.as_ref().map_or_else(|e| Outcome::Failed(e.to_string()), |_| Outcome::Ok);
+    audit::record(&state, &ip, &user.username, "2fa_enable", None, Some(&user.username), outcome).await;
+    match result {
+        Ok(codes) => two_factor_page(&state, &user, codes, Some("Two-factor authentication is on".to_string()), None).await,
+        Err(e) => two_factor_page(&state, &user, vec![], None, Some(e.to_string())).await,
+    }
+}
+
+pub async fn two_factor_recovery_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    if let Err(e) = throttled_totp_code(&state, &ip, &user.username, &form.code).await {
+        return two_factor_page(&state, &user, vec![], None, Some(e)).await;
+    }
+    let result = new_recovery_codes(&state, &user.username).await;
+    let outcome = result.as_ref().map_or_else(|e| Outcome::Failed(e.to_string()), |_| Outcome::Ok);
+    audit::record(&state, &ip, &user.username, "2fa_recovery_codes", None, Some(&user.username), outcome).await;
+    match result {
+        Ok(codes) => two_factor_page(&state, &user, codes, Some("New recovery codes, the old ones no longer work".to_string()), None).await,
+        Err(e) => two_factor_page(&state, &user, vec![], None, Some(e.to_string())).await,
+    }
+}
+
+pub async fn two_factor_disable_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ip: ClientIp,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    if two_factor_required(&state, &user).await {
+        return two_factor_page(&state, &user, vec![], None, Some("Your role requires two-factor authentication".to_string())).await;
+    }
+    if let Err(e) = throttled_totp_code(&state, &ip, &user.username, &form.code).await {
+        return two_factor_page(&state, &user, vec![], None, Some(e)).await;
+    }
+    let result = crate::db::disable_totp(&state.db, &user.username).await;
+    let outcome = result.as_ref().map_or_else(|e| Outcome::Failed(e.to_string()), |_| Outcome::Ok);
+    audit::record(&state, &ip, &user.username, "2fa_disable", None, Some(&user.username), outcome).await;
+    match result {
+        Ok(()) => two_factor_page(&state, &user, vec![], Some("Two-factor authentication is off".to_string()), None).await,
+        Err(e) => two_factor_page(&state, &user, vec![], None, Some(e.to_string())).await,
+    }
+}
+
+/// For a user who lost their phone and recovery codes: they log in with the password alone
+/// (and enroll again if the policy requires it).
+pub async fn user_two_factor_reset_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Path(username): Path<String>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    target_user(&state, &username).await?;
+    let result = crate::db::disable_totp(&state.db, &username).await;
+    record_user_change(&state, &ip, &admin, "2fa_reset", &username, result, "2FA reset").await
+}
+
+#[derive(Deserialize)]
+pub struct SecurityPolicyForm {
+    require_2fa: bool,
+}
+
+pub async fn security_policy_handler(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    ip: ClientIp,
+    Json(form): Json<SecurityPolicyForm>,
+) -> UserResult {
+    admin.require(Role::Admin)?;
+    let value = if form.require_2fa { "1" } else { "0" };
+    let result = crate::db::set_setting(&state.db, REQUIRE_2FA_SETTING, value).await;
+    let target = format!("require_2fa={}", form.require_2fa);
+    record_user_change(&state, &ip, &admin, "2fa_policy", &target, result, "Saved").await
+}
+
+// === SINGLE SIGN-ON ===
+
+/// Encrypted cookie holding the state, nonce and PKCE verifier of a login at the identity provider.
+const OIDC_COOKIE: &str = "oidc_login";
+
+pub async fn oidc_login_handler(State(state): State<AppState>, jar: PrivateCookieJar) -> Response {
+    let Some(oidc) = state.oidc.clone() else {
+        return Redirect::to("/login").into_response();
+    };
+    match oidc.start().await {
+        Ok((url, login)) => {
+            let cookie = crate::session::cookie(&state, OIDC_COOKIE, login.to_cookie_value());
+            (jar.add(cookie), Redirect::to(&url)).into_response()
+        }
+        Err(e) => {
+            println!("❌ Single sign-on is unavailable: {}", e);
+            let error = Some("Single sign-on is unavailable, see the master log".to_string());
+            LoginTemplate { error, two_factor: false, sso: state.oidc.is_some() }.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Where the identity provider sends the browser back to with the code.
+pub async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    ip: ClientIp,
+    headers: axum::http::HeaderMap,
+    jar: PrivateCookieJar,
+    Query(params): Query<OidcCallbackParams>,
+) -> Response {
+    let Some(oidc) = state.oidc.clone() else {
+        return Redirect::to("/login").into_response();
+    };
+    let login = jar.get(OIDC_COOKIE).and_then(|c| crate::oidc::PendingLogin::from_cookie_value(c.value()));
+    let jar = jar.remove(axum_extra::extract::cookie::Cookie::from(OIDC_COOKIE));
+    let refused = |error: String| {
+        let page = LoginTemplate { error: Some(error), two_factor: false, sso: true };
+        (axum::http::StatusCode::FORBIDDEN, jar.clone(), page).into_response()
+    };
+
+    let identity = match (params.error, login, params.code) {
+        (Some(error), _, _) => Err(format!("The identity provider refused the login: {}", params.error_description.unwrap_or(error))),
+        (None, None, _) => Err("The login timed out, please try again".to_string()),
+        (None, Some(login), Some(code))
+            if params.state.as_deref().is_some_and(|s| port_sentinel_shared::signing::secrets_equal(s, &login.state)) =>
+        {
+            oidc.finish(&code, &login).await
+        }
+        _ => Err("The login didn't start in this browser, please try again".to_string()),
+    };
+    let identity = match identity {
+        Ok(identity) => identity,
+        Err(e) => {
+            println!("❌ Single sign-on failed: {}", e);
+            return refused(e);
+        }
+    };
+
+    let username = match sso_account(&state, &oidc, &ip, &identity).await {
+        Ok(username) => username,
+        Err(reason) => {
+            println!("🚫 Single sign-on of '{}' refused: {}", identity.username, reason);
+            audit::record(&state, &ip, &identity.username, "login", None, Some("sso"), Outcome::Denied(reason.clone())).await;
+            return refused(reason);
+        }
+    };
+    let user_agent = headers.get(axum::http::header::USER_AGENT).and_then(|h| h.to_str().ok());
+    open_session(&state, &ip, user_agent, jar, &username).await
+}
+
+/// Finds the SSO account of the provider's user and gives it the role their groups give,
+/// creating it if auto-provisioning is on. Returns its username.
+async fn sso_account(state: &AppState, oidc: &crate::oidc::Oidc, ip: &ClientIp, identity: &crate::oidc::Identity) -> Result<String, String> {
+    let Some(role) = identity.role else {
+        return Err(format!("None of the groups [{}] has access to PortSentinel", identity.groups.join(", ")));
+    };
+    // The name can change at the provider, or be taken by someone else there; the subject can't
+    let username = match crate::db::get_sso_username(&state.db, &identity.issuer, &identity.subject).await {
+        Some(username) => username,
+        None => sso_first_login(state, oidc, ip, identity, role).await?,
+    };
+    let user = crate::db::get_user_by_username(&state.db, &username).await
+        .ok_or_else(|| format!("The account '{}' no longer exists", username))?;
+    if user.disabled {
+        return Err("This account is disabled".to_string());
+    }
+    if user.role != role.as_str() {
+        match crate::db::set_user_role(&state.db, &user.username, role.as_str()).await {
+            Ok(true) => println!("👥 '{}' is now {} through their identity provider groups", user.username, role.as_str()),
+            // Refused rather than let in as an admin the provider no longer vouches for
+            Ok(false) => return Err(format!("'{}' is the last enabled admin, and the identity provider no longer makes them one", user.username)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(username)
+}
+
+/// Links the provider's user to the SSO account of their name an admin created for them, or
+/// creates one if auto-provisioning is on.
+async fn sso_first_login(state: &AppState, oidc: &crate::oidc::Oidc, ip: &ClientIp, identity: &crate::oidc::Identity, role: Role) -> Result<String, String> {
+    if !crate::password::valid_username(&identity.username) {
+        return Err(format!("'{}' can't be a PortSentinel username", identity.username));
+    }
+    let link = || crate::db::link_sso_identity(&state.db, &identity.username, &identity.issuer, &identity.subject);
+    match crate::db::get_user_by_username(&state.db, &identity.username).await {
+        // The provider vouches for its users only, not for local accounts of the same name
+        Some(user) if !user.sso => Err("A local account has this name, log in with its password".to_string()),
+        Some(user) => match link().await.map_err(|e| e.to_string())? {
+            true => {
+                println!("🔗 '{}' is now linked to subject '{}' of {}", user.username, identity.subject, identity.issuer);
+                Ok(user.username)
+            }
+            false => Err(format!("'{}' belongs to another user of the identity provider", user.username)),
+        },
+        None if oidc.config.auto_provision => {
+            let user = User {
+                username: identity.username.clone(),
+                password_hash: String::new(),
+                role: role.as_str().to_string(),
+                must_change_password: false,
+                node_tags: String::new(),
+                disabled: false,
+                totp_enabled: false,
+                sso: true,
+            };
+            let mut result = crate::db::create_user(&state.db, &user).await;
+            if result.is_ok() {
+                result = link().await.map(|_| ());
+            }
+            let outcome = result.as_ref().map_or_else(|e| Outcome::Failed(e.to_string()), |_| Outcome::Ok);
+            audit::record(state, ip, &user.username, "user_create", None, Some(&user.username), outcome).await;
+            result.map(|_| user.username).map_err(|e| e.to_string())
+        }
+        None => Err(format!("There is no PortSentinel account for '{}', ask an admin", identity.username)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    /// Nothing listens there: a call that gets past the checks comes back as 502.
+    const DEAD_AGENT: &str = "http://127.0.0.1:9";
+
+    async fn state_with_node(tags: &str) -> AppState {
+        let state = AppState::for_tests().await;
+        crate::db::save_node(&state.db, "n1", "db-1", DEAD_AGENT, None, tags).await.unwrap();
+        state
+    }
+
+    fn ip() -> ClientIp {
+        ClientIp("127.0.0.1".to_string())
+    }
+
+    fn on(node_url: &str) -> Query<NodeParams> {
+        Query(NodeParams { node: Some(node_url.to_string()), q: None, rate: None })
+    }
+
+    fn status(resp: impl IntoResponse) -> StatusCode {
+        resp.into_response().status()
+    }
+
+    async fn kill(state: &AppState, user: &User, node_url: &str) -> StatusCode {
+        status(kill_process_api(State(state.clone()), Extension(user.clone()), ip(), Path("4242".to_string()), on(node_url)).await)
+    }
+
+    async fn stop(state: &AppState, user: &User, node_url: &str) -> StatusCode {
+        let path = Path(("stop".to_string(), "web".to_string()));
+        status(docker_control_proxy(State(state.clone()), Extension(user.clone()), ip(), path, on(node_url)).await)
+    }
+
+    #[tokio::test]
+    async fn viewers_cannot_act() {
+        let state = state_with_node("").await;
+        let viewer = User::for_tests("viewer", "");
+        assert_eq!(kill(&state, &viewer, DEAD_AGENT).await, StatusCode::FORBIDDEN);
+        assert_eq!(stop(&state, &viewer, DEAD_AGENT).await, StatusCode::FORBIDDEN);
+        let service = proxy_service_command(&state, &viewer, &ip(), Some(DEAD_AGENT.to_string()), "restart", "nginx").await;
+        assert!(service.is_err());
+
+        // Operators get through to the agent
+        let operator = User::for_tests("operator", "");
+        assert_eq!(kill(&state, &operator, DEAD_AGENT).await, StatusCode::BAD_GATEWAY);
+        assert_ne!(stop(&state, &operator, DEAD_AGENT).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn operators_cannot_administer() {
+        let state = state_with_node("").await;
+        let operator = User::for_tests("operator", "");
+
+        let form: NodeForm = serde_json::from_value(serde_json::json!({ "id": "n2", "name": "web-1", "url": "http://10.0.0.6:3001" })).unwrap();
+        assert_eq!(status(save_node_handler(State(state.clone()), Extension(operator.clone()), ip(), Json(form)).await), StatusCode::FORBIDDEN);
+        assert!(crate::db::get_node_by_id(&state.db, "n2").await.is_none());
+
+        assert_eq!(status(delete_node_handler(State(state.clone()), Extension(operator.clone()), ip(), Path("n1".to_string())).await), StatusCode::FORBIDDEN);
+        assert!(crate::db::get_node_by_id(&state.db, "n1").await.is_some());
+
+        let form: NewUserForm = serde_json::from_value(serde_json::json!({ "username": "mallory", "password": "a long passphrase", "role": "admin" })).unwrap();
+        assert_eq!(status(user_create_handler(State(state.clone()), Extension(operator.clone()), ip(), Json(form)).await), StatusCode::FORBIDDEN);
+        assert!(crate::db::get_user_by_username(&state.db, "mallory").await.is_none());
+
+        assert_eq!(status(users_list_handler(State(state.clone()), Extension(operator.clone())).await), StatusCode::FORBIDDEN);
+        assert_eq!(status(audit_list_handler(State(state.clone()), Extension(operator), Query(Default::default())).await), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn node_tags_and_unregistered_urls_are_refused() {
+        let state = state_with_node("prod, db").await;
+        assert_eq!(kill(&state, &User::for_tests("operator", "web"), DEAD_AGENT).await, StatusCode::FORBIDDEN);
+        assert_eq!(kill(&state, &User::for_tests("operator", "db"), DEAD_AGENT).await, StatusCode::BAD_GATEWAY);
+
+        let unregistered = "http://169.254.169.254:80";
+        assert_eq!(kill(&state, &User::for_tests("operator", ""), unregistered).await, StatusCode::FORBIDDEN);
+        assert_eq!(stop(&state, &User::for_tests("operator", "db"), unregistered).await, StatusCode::FORBIDDEN);
+    }
+
+    async fn login(state: &AppState, ip: &str, username: &str, password: &str) -> StatusCode {
+        let form = serde_json::from_value(serde_json::json!({ "username": username, "password": password })).unwrap();
+        let jar = PrivateCookieJar::new(state.key.clone());
+        let resp = login_submit(State(state.clone()), ClientIp(ip.to_string()), Default::default(), jar, Form(form)).await;
+        status(resp)
+    }
+
+    #[tokio::test]
+    async fn parallel_logins_cant_get_past_the_throttle() {
+        let mut state = AppState::for_tests().await;
+        // Locked after the free attempts, so how long the test takes doesn't matter
+        state.login_throttle = std::sync::Arc::new(crate::throttle::LoginThrottle::new(crate::throttle::Limits {
+            free_attempts: 3,
+            max_per_account: 3,
+            max_per_ip: 50,
+            lockout_secs: 900,
+        }));
+        let user = User { username: "alice".to_string(), password_hash: bcrypt::hash("right password", 4).unwrap(), ..User::for_tests("operator", "") };
+        crate::db::create_user(&state.db, &user).await.unwrap();
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let state = state.clone();
+            tasks.spawn(async move { login(&state, "10.0.0.1", "alice", "wrong password").await });
+        }
+        let mut attempts = Vec::new();
+        while let Some(attempt) = tasks.join_next().await {
+            attempts.push(attempt.unwrap());
+        }
+        // Only the free attempts are let through, the rest wait without reaching bcrypt
+        assert_eq!(attempts.iter().filter(|s| **s == StatusCode::TOO_MANY_REQUESTS).count(), 5);
+        assert_eq!(login(&state, "10.0.0.2", "alice", "right password").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn right_passwords_dont_count_as_failures() {
+        let state = AppState::for_tests().await;
+        let user = User { username: "alice".to_string(), password_hash: bcrypt::hash("right password", 4).unwrap(), ..User::for_tests("operator", "") };
+        crate::db::create_user(&state.db, &user).await.unwrap();
+        for _ in 0..5 {
+            assert_eq!(login(&state, "10.0.0.1", "alice", "right password").await, StatusCode::SEE_OTHER);
+        }
+    }
+
+    fn identity(subject: &str, username: &str, role: Role) -> crate::oidc::Identity {
+        crate::oidc::Identity {
+            issuer: "https://login.example.com".to_string(),
+            subject: subject.to_string(),
+            username: username.to_string(),
+            role: Some(role),
+            groups: vec![],
+        }
+    }
+
+    fn oidc(auto_provision: bool) -> crate::oidc::Oidc {
+        crate::oidc::Oidc::new(crate::oidc::OidcConfig { auto_provision, ..crate::oidc::OidcConfig::for_tests("https://login.example.com") })
+    }
+
+    #[tokio::test]
+    async fn sso_accounts_follow_the_subject_not_the_name() {
+        let state = AppState::for_tests().await;
+        let oidc = oidc(true);
+        let jdoe = identity("sub-1", "jdoe", Role::Operator);
+        assert_eq!(sso_account(&state, &oidc, &ip(), &jdoe).await.unwrap(), "jdoe");
+        assert!(crate::db::get_user_by_username(&state.db, "jdoe").await.unwrap().sso);
+
+        // Renamed at the provider: still the same account
+        assert_eq!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "john.doe", Role::Operator)).await.unwrap(), "jdoe");
+        // Someone else who took the name there
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-2", "jdoe", Role::Admin)).await.is_err());
+        assert_eq!(crate::db::get_user_by_username(&state.db, "jdoe").await.unwrap().role, "operator");
+    }
+
+    #[tokio::test]
+    async fn sso_logins_link_accounts_created_for_them_and_leave_local_ones_alone() {
+        let state = AppState::for_tests().await;
+        let oidc = oidc(false);
+        let local = User { username: "admin".to_string(), ..User::for_tests("admin", "") };
+        let invited = User { username: "jdoe".to_string(), sso: true, ..User::for_tests("viewer", "") };
+        crate::db::create_user(&state.db, &local).await.unwrap();
+        crate::db::create_user(&state.db, &invited).await.unwrap();
+
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "admin", Role::Admin)).await.is_err());
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "nobody", Role::Admin)).await.is_err());
+        assert_eq!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "jdoe", Role::Operator)).await.unwrap(), "jdoe");
+        assert_eq!(crate::db::get_user_by_username(&state.db, "jdoe").await.unwrap().role, "operator");
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-2", "jdoe", Role::Operator)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn groups_cant_demote_the_last_admin() {
+        let state = AppState::for_tests().await;
+        let oidc = oidc(true);
+        assert_eq!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "jdoe", Role::Admin)).await.unwrap(), "jdoe");
+
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "jdoe", Role::Viewer)).await.is_err());
+        assert_eq!(crate::db::get_user_by_username(&state.db, "jdoe").await.unwrap().role, "admin");
+
+        let other = User { username: "root".to_string(), ..User::for_tests("admin", "") };
+        crate::db::create_user(&state.db, &other).await.unwrap();
+        assert!(sso_account(&state, &oidc, &ip(), &identity("sub-1", "jdoe", Role::Viewer)).await.is_ok());
+        assert_eq!(crate::db::get_user_by_username(&state.db, "jdoe").await.unwrap().role, "viewer");
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use port_sentinel_shared::{ContainerStats, ContainerEventBatch};
+use crate::agent::AgentClient;
+use crate::state::AppState;
+
+/// Periodically samples container stats from every node into the `container_stats` table,
+/// and drops samples older than `retention_days`.
+pub async fn run_collector(state: AppState, interval_secs: u64, retention_days: u32) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs.max(10)));
+
+    loop {
+        ticker.tick().await;
+
+        let nodes = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
+        for node in nodes {
+            let agent = AgentClient::new(&state, &node).timeout(Duration::from_secs(15));
+
+            let stats = match agent.get("/api/v1/docker/stats").await {
+                Ok(resp) if resp.is_success() => resp.json::<Vec<ContainerStats>>().unwrap_or_default(),
+                // Offline nodes and nodes without Docker are simply skipped
+                _ => continue,
+            };
+
+            for s in &stats {
+                if let Err(e) = crate::db::insert_container_stats(&state.db, &node.id, s).await {
+                    println!("⚠️ Failed to store container stats for {}: {}", node.name, e);
+                }
+            }
+        }
+
+        let _ = crate::db::prune_container_stats(&state.db, retention_days).await;
+        let _ = crate::db::prune_container_events(&state.db, retention_days).await;
+    }
+}
+
+/// Collects Docker events buffered by each agent into the `container_events` table.
+/// Keeps a cursor per node so each poll only transfers what's new. Push-mode agents send their
+/// events over the tunnel themselves and are skipped.
+pub async fn run_event_collector(state: AppState, interval_secs: u64) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+    // node id -> (agent stream id, last seq stored)
+    let mut cursors: HashMap<String, (String, u64)> = HashMap::new();
+
+    loop {
+        ticker.tick().await;
+
+        let nodes = crate::db::get_all_nodes(&state.db).await.unwrap_or_default();
+        for node in nodes {
+            let agent = AgentClient::new(&state, &node).timeout(Duration::from_secs(10));
+            if agent.is_push() {
+                continue;
+            }
+            let since = cursors.get(&node.id).map(|c| c.1).unwrap_or(0);
+
+            let batch = match agent.get(&format!("/api/v1/docker/events?since={}", since)).await {
+                Ok(resp) if resp.is_success() => match resp.json::<ContainerEventBatch>() {
+                    Ok(batch) => batch,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+
+            // The agent restarted, its numbering started over
+            if cursors.get(&node.id).is_some_and(|c| c.0 != batch.stream_id) && since > 0 {
+                cursors.insert(node.id.clone(), (batch.stream_id, 0));
+                continue;
+            }
+
+            let mut last = since;
+            for event in &batch.events {
+                if let Err(e) = crate::db::insert_container_event(&state.db, &node.id, event).await {
+                    println!("⚠️ Failed to store container event for {}: {}", node.name, e);
+                }
+                last = last.max(event.seq);
+            }
+            cursors.insert(node.id.clone(), (batch.stream_id, last));
+        }
+    }
+}
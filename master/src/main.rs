@@ -15,6 +15,18 @@ mod auth;
 mod state;
 mod handlers;
 mod db;
+mod history;
+mod agent;
+mod tunnel;
+mod enroll;
+mod pki;
+mod audit;
+mod session;
+mod totp;
+mod throttle;
+mod password;
+mod csrf;
+mod oidc;
 
 use crate::auth::AuthState;
 use crate::state::{AppState, NodeConfig};
@@ -30,6 +42,118 @@ struct Args {
     /// Port to run the dashboard on
     #[arg(short, long, default_value_t = 7878)]
     port: u16,
+
+    /// Seconds between container stats samples stored in the history
+    #[arg(long, default_value_t = 60)]
+    stats_interval: u64,
+
+    /// Days of container stats and events history to keep
+    #[arg(long, default_value_t = 7)]
+    history_days: u32,
+
+    /// Seconds between polls of the agents' Docker event buffers
+    #[arg(long, default_value_t = 5)]
+    events_interval: u64,
+
+    /// File holding the secret node credentials are derived from, created on first start
+    #[arg(long, default_value = "master.key")]
+    key_file: String,
+
+    /// Directory of the built-in CA that issues agent certificates, created on first start
+    #[arg(long, default_value = "ca")]
+    ca_dir: String,
+
+    /// Extra PEM CA bundle trusted for agent certificates (e.g. a company CA)
+    #[arg(long)]
+    agent_ca: Option<String>,
+
+    /// Name agents record this master under in their audit logs, defaults to the hostname
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Behind a reverse proxy: log the client address it appends to X-Forwarded-For instead of the proxy's
+    #[arg(long)]
+    trust_forwarded_for: bool,
+
+    /// The dashboard is reached over https: only send its cookies over https (implied by --trust-forwarded-for)
+    #[arg(long)]
+    secure_cookies: bool,
+
+    /// Minutes without a request after which a dashboard session ends
+    #[arg(long, default_value_t = 60)]
+    session_idle_minutes: u64,
+
+    /// Hours after login after which a dashboard session ends, however active
+    #[arg(long, default_value_t = 24)]
+    session_max_hours: u64,
+
+    /// Failed logins after which each further attempt for the account or address waits twice as long
+    #[arg(long, default_value_t = 3)]
+    login_free_attempts: u32,
+
+    /// Failed logins after which an account is locked
+    #[arg(long, default_value_t = 10)]
+    login_max_failures: u32,
+
+    /// Failed logins after which a client address is locked
+    #[arg(long, default_value_t = 30)]
+    login_max_failures_per_ip: u32,
+
+    /// Minutes a lockout lasts
+    #[arg(long, default_value_t = 15)]
+    login_lockout_minutes: u64,
+
+    /// Minimum length of dashboard passwords
+    #[arg(long, default_value_t = 8)]
+    password_min_length: usize,
+
+    /// How many of a user's last passwords, the current one included, can't be picked again (0 to allow reuse)
+    #[arg(long, default_value_t = 5)]
+    password_history: usize,
+
+    /// File of refused passwords, one per line, on top of a built-in list of common ones
+    #[arg(long)]
+    password_denylist: Option<String>,
+
+    /// OpenID Connect issuer URL, turns on single sign-on (e.g. https://login.example.com/realms/ops)
+    #[arg(long)]
+    oidc_issuer: Option<String>,
+
+    /// Client ID registered with the identity provider
+    #[arg(long, default_value = "portsentinel")]
+    oidc_client_id: String,
+
+    /// File holding the client secret; without it the master is a public client (PKCE only)
+    #[arg(long)]
+    oidc_client_secret_file: Option<String>,
+
+    /// Scopes requested from the identity provider
+    #[arg(long, default_value = "openid profile email groups")]
+    oidc_scopes: String,
+
+    /// URL of /auth/oidc/callback as browsers reach the dashboard, registered with the provider
+    #[arg(long)]
+    oidc_redirect_url: Option<String>,
+
+    /// ID token claim holding the username
+    #[arg(long, default_value = "preferred_username")]
+    oidc_username_claim: String,
+
+    /// ID token claim holding the user's groups
+    #[arg(long, default_value = "groups")]
+    oidc_groups_claim: String,
+
+    /// Groups and the roles they give, e.g. "ps-admins=admin,ps-ops=operator,staff=viewer"
+    #[arg(long, default_value = "")]
+    oidc_role_map: String,
+
+    /// Create users on their first single sign-on instead of refusing unknown ones
+    #[arg(long)]
+    oidc_auto_provision: bool,
+
+    /// Node URLs still running an agent from before signed requests, sent a bare token until upgraded (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    legacy_agents: Vec<String>,
 }
 
 fn load_nodes_from_disk() -> Vec<NodeConfig> {
@@ -45,10 +169,22 @@ fn default_node() -> NodeConfig {
         id: "local".to_string(), 
         name: "Local Agent".to_string(), 
         url: "http://127.0.0.1:3001".to_string(),
-        token: Some("change_me_please".to_string()),
+        token: None,
+        credential_hash: None,
+        credential_salt: None,
+        tls_fingerprint: None,
+        tags: String::new(),
     }
 }
 
+fn host_name() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "master".to_string())
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
@@ -100,7 +236,11 @@ async fn main() {
              username: "admin".to_string(), 
              password_hash: hash,
              role: "admin".to_string(),
-             must_change_password: true 
+             must_change_password: true,
+             node_tags: String::new(),
+             disabled: false,
+             totp_enabled: false,
+             sso: false,
         };
         let _ = db::create_user(&db_pool, &admin).await;
         println!("⚠️ No users found. Created default 'admin' user (password: admin)");
@@ -128,17 +268,75 @@ async fn main() {
             name: "Local Server".to_string(),
             url: "http://127.0.0.1:3001".to_string(),
             token: None,
+            credential_hash: None,
+            credential_salt: None,
+            tls_fingerprint: None,
+            tags: String::new(),
         };
         let _ = db::upsert_node(&db_pool, &local_node).await;
     }
 
+    let master_key = enroll::MasterKey::load_or_create(&args.key_file).expect("Failed to load master key");
+    let pki = pki::Pki::load_or_create(&args.ca_dir, args.agent_ca.as_deref()).expect("Failed to load CA");
+    let password_policy = password::PasswordPolicy::load(args.password_min_length, args.password_history, args.password_denylist.as_deref())
+        .expect("Failed to load password policy");
+    let oidc = args.oidc_issuer.as_ref().map(|issuer| {
+        let client_secret = args.oidc_client_secret_file.as_ref().map(|path| {
+            fs::read_to_string(path).expect("Failed to read OIDC client secret").trim().to_string()
+        });
+        let config = oidc::OidcConfig {
+            issuer: issuer.clone(),
+            client_id: args.oidc_client_id.clone(),
+            client_secret,
+            scopes: args.oidc_scopes.clone(),
+            redirect_url: args.oidc_redirect_url.clone()
+                .unwrap_or_else(|| format!("http://localhost:{}/auth/oidc/callback", args.port)),
+            username_claim: args.oidc_username_claim.clone(),
+            groups_claim: args.oidc_groups_claim.clone(),
+            role_map: oidc::OidcConfig::parse_role_map(&args.oidc_role_map).expect("Invalid --oidc-role-map"),
+            auto_provision: args.oidc_auto_provision,
+        };
+        println!("🔑 Single sign-on through {}", issuer);
+        Arc::new(oidc::Oidc::new(config))
+    });
+
+    let legacy_agents = agent::LegacyAgents::new(args.legacy_agents.clone());
+    for node_url in legacy_agents.node_urls() {
+        println!("⚠️ Sending the agent at {} a bare token until it is upgraded to signed requests", node_url);
+    }
+
     // Initialize State with DB Pool
-    let key = Key::generate(); 
+    let key: Key = master_key.cookie_key();
     let shared_state = AppState {
         db: db_pool,
         key,
+        tunnels: Default::default(),
+        master_key: Arc::new(master_key),
+        pki: Arc::new(pki),
+        name: args.name.clone().unwrap_or_else(host_name),
+        trust_forwarded_for: args.trust_forwarded_for,
+        // A proxy in front usually terminates TLS, and an https callback means browsers use https
+        secure_cookies: args.secure_cookies || args.trust_forwarded_for
+            || args.oidc_redirect_url.as_deref().is_some_and(|url| url.starts_with("https://")),
+        session_timeouts: session::Timeouts {
+            idle_secs: args.session_idle_minutes as i64 * 60,
+            absolute_secs: args.session_max_hours as i64 * 3600,
+        },
+        login_throttle: Arc::new(throttle::LoginThrottle::new(throttle::Limits {
+            free_attempts: args.login_free_attempts,
+            max_per_account: args.login_max_failures,
+            max_per_ip: args.login_max_failures_per_ip,
+            lockout_secs: args.login_lockout_minutes as i64 * 60,
+        })),
+        password_policy: Arc::new(password_policy),
+        oidc,
+        legacy_agents: Arc::new(legacy_agents),
     };
 
+    // Background sampler feeding the container stats history
+    tokio::spawn(history::run_collector(shared_state.clone(), args.stats_interval, args.history_days));
+    tokio::spawn(history::run_event_collector(shared_state.clone(), args.events_interval));
+
     // --- 1. Protected Router ---
     // These routes REQUIRE the auth_middleware
     let protected_routes = Router::new()
@@ -150,7 +348,10 @@ async fn main() {
         .route("/logs/read", get(read_log_handler))
         .route("/api/nodes/save", post(save_node_handler))
         .route("/api/nodes/delete/:id", post(delete_node_handler))
+        .route("/api/nodes/enroll/:id", post(node_join_token_handler))
+        .route("/api/nodes/revoke/:id", post(revoke_node_handler))
         .route("/api/check-status", get(check_node_status))
+        .route("/api/node-runtime", get(node_runtime_badge))
         // === Service Manager Routes ===
         .route("/view/services", get(services_page_handler))
         .route("/api/proxy/service/status", get(service_status_proxy))
@@ -160,8 +361,50 @@ async fn main() {
         // === Docker Manager Routes ===
         .route("/view/containers", get(containers_page_handler))
         .route("/view/containers/list", get(containers_list_proxy))
+        .route("/view/containers/inspect/:id", get(container_inspect_proxy))
         .route("/api/proxy/docker/logs/:id", get(docker_logs_proxy))
+        .route("/view/containers/logs/:id", get(container_logs_view))
+        .route("/api/history/container/:id", get(container_history_api))
+        .route("/view/containers/events/:id", get(container_events_view))
+        .route("/api/docker/alerts", get(docker_alerts_api))
         .route("/api/proxy/docker/:action/:id", post(docker_control_proxy))
+        .route("/api/proxy/docker/compose/:project/:action", post(docker_project_control_proxy))
+        .route("/api/proxy/docker/compose/:project/recreate/:service", post(docker_recreate_service_proxy))
+        .route("/view/containers/resources", get(docker_resources_page_handler))
+        .route("/view/containers/resources/list", get(docker_resources_list_proxy))
+        .route("/api/proxy/docker/:kind/rm/:id", post(docker_resource_rm_proxy))
+        .route("/view/containers/prune/:kind", get(docker_prune_preview).post(docker_prune_execute))
+        // === Scheduled Jobs Routes ===
+        .route("/view/schedules", get(schedules_page_handler))
+        .route("/view/schedules/list", get(schedules_list_proxy))
+        .route("/api/schedules/alerts", get(schedules_alerts_api))
+        .route("/view/schedules/alerts", get(schedules_alerts_view))
+        // === Audit Log Routes ===
+        .route("/view/audit", get(audit_page_handler))
+        .route("/view/audit/list", get(audit_list_handler))
+        .route("/api/audit/export", get(audit_export_handler))
+        // === User Management Routes ===
+        .route("/view/users", get(users_page_handler))
+        .route("/view/users/list", get(users_list_handler))
+        .route("/api/users", get(users_api))
+        .route("/api/users/create", post(user_create_handler))
+        .route("/api/users/role/:username", post(user_role_handler))
+        .route("/api/users/disable/:username", post(user_disable_handler))
+        .route("/api/users/enable/:username", post(user_enable_handler))
+        .route("/api/users/password/:username", post(user_password_reset_handler))
+        .route("/api/users/delete/:username", post(user_delete_handler))
+        .route("/api/users/2fa-reset/:username", post(user_two_factor_reset_handler))
+        .route("/api/users/policy", post(security_policy_handler))
+        // === Two-Factor Routes ===
+        .route("/account/2fa", get(two_factor_page_handler))
+        .route("/account/2fa/enable", post(two_factor_enable_handler))
+        .route("/account/2fa/recovery", post(two_factor_recovery_handler))
+        .route("/account/2fa/disable", post(two_factor_disable_handler))
+        // === Session Routes ===
+        .route("/view/sessions", get(sessions_page_handler))
+        .route("/api/sessions", get(sessions_api))
+        .route("/api/sessions/revoke/:id", post(session_revoke_handler))
+        .route("/api/sessions/revoke-all", post(sessions_revoke_all_handler))
         // We apply the layer ONLY to this router block
         // We use from_fn_with_state to inject the state into the middleware
         .layer(middleware::from_fn_with_state(shared_state.clone(), auth_middleware));
@@ -172,17 +415,26 @@ async fn main() {
         .route("/login", get(login_page).post(login_submit))
         .route("/logout", get(logout_handler))
         .route("/change-password", get(change_password_page).post(change_password_submit))
+        // Single sign-on; local logins keep working as a fallback
+        .route("/auth/oidc/login", get(oidc_login_handler))
+        .route("/auth/oidc/callback", get(oidc_callback_handler))
+        // Agents authenticate with a join token or their node credential instead of a session
+        .route("/api/agent/enroll", post(enroll::agent_enroll))
+        .route("/api/agent/connect", get(tunnel::agent_connect))
         .nest_service("/assets", serve_dir);
 
     // --- 3. Merge & Launch ---
     let app = Router::new()
         .merge(protected_routes)
         .merge(public_routes)
+        // Every route, the login form can be forged too
+        .layer(middleware::from_fn_with_state(shared_state.clone(), csrf::middleware))
         .with_state(shared_state);
 
         let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
         println!("🚀 Master Dashboard available at http://{}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // The peer address goes into the audit log
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,481 @@
+use std::time::{Duration, Instant};
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use crate::auth::Role;
+
+/// How long the provider's metadata and signing keys are used before fetching them again.
+/// Keys are fetched earlier when a token names one that isn't known yet (key rotation).
+const PROVIDER_TTL: Duration = Duration::from_secs(3600);
+
+/// How long the user has to log in at the provider.
+const LOGIN_TIMEOUT_SECS: i64 = 600;
+
+/// Signature algorithms accepted on ID tokens. Never `none` or the HMAC ones, which would
+/// make the client secret a signing key.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+/// Single sign-on settings (`--oidc-*` options).
+pub struct OidcConfig {
+    /// e.g. "https://login.example.com/realms/ops", where `/.well-known/openid-configuration` is found
+    pub issuer: String,
+    pub client_id: String,
+    /// None for a public client, which relies on PKCE alone
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    /// `/auth/oidc/callback` as the browser reaches it, registered with the provider
+    pub redirect_url: String,
+    /// ID token claim the username is taken from
+    pub username_claim: String,
+    /// ID token claim listing the user's groups
+    pub groups_claim: String,
+    /// Group names and the role they give; the highest one wins
+    pub role_map: Vec<(String, Role)>,
+    /// Create unknown users on their first login, instead of refusing them
+    pub auto_provision: bool,
+}
+
+impl OidcConfig {
+    /// Parses `--oidc-role-map`, e.g. "ps-admins=admin, ps-ops=operator, staff=viewer".
+    pub fn parse_role_map(map: &str) -> Result<Vec<(String, Role)>, String> {
+        map.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (group, role) = entry.split_once('=')
+                    .ok_or_else(|| format!("'{}' in the OIDC role map is not group=role", entry))?;
+                let role = Role::parse(role.trim())
+                    .ok_or_else(|| format!("Unknown role '{}' in the OIDC role map", role.trim()))?;
+                Ok((group.trim().to_string(), role))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl OidcConfig {
+    /// A confidential client of `issuer`, mapping "ps-admins", "ps-ops" and "staff" to the three roles.
+    pub fn for_tests(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: "portsentinel".to_string(),
+            client_secret: Some("client-secret".to_string()),
+            scopes: "openid profile groups".to_string(),
+            redirect_url: "https://dashboard.example.com/auth/oidc/callback".to_string(),
+            username_claim: "preferred_username".to_string(),
+            groups_claim: "groups".to_string(),
+            role_map: OidcConfig::parse_role_map("ps-admins=admin, ps-ops=operator, staff=viewer").unwrap(),
+            auto_provision: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct Provider {
+    discovery: Discovery,
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// A login on its way through the provider, kept in an encrypted cookie until the callback.
+pub struct PendingLogin {
+    /// Ties the callback to the browser that started the login
+    pub state: String,
+    /// Ties the ID token to this login
+    pub nonce: String,
+    /// PKCE: the code is worthless without it
+    pub verifier: String,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl PendingLogin {
+    pub fn to_cookie_value(&self) -> String {
+        format!("{}\n{}\n{}\n{}", now(), self.state, self.nonce, self.verifier)
+    }
+
+    /// None if the value is damaged or the login timed out.
+    pub fn from_cookie_value(value: &str) -> Option<Self> {
+        let mut parts = value.split('\n');
+        let issued: i64 = parts.next()?.parse().ok()?;
+        if now() - issued > LOGIN_TIMEOUT_SECS {
+            return None;
+        }
+        let login = PendingLogin {
+            state: parts.next()?.to_string(),
+            nonce: parts.next()?.to_string(),
+            verifier: parts.next()?.to_string(),
+        };
+        Some(login)
+    }
+}
+
+/// Who the provider says logged in.
+pub struct Identity {
+    /// `iss` and `sub`: who the user is for good. Accounts are linked to these, the username can change.
+    pub issuer: String,
+    pub subject: String,
+    pub username: String,
+    /// None if none of the user's groups is in the role map
+    pub role: Option<Role>,
+    pub groups: Vec<String>,
+}
+
+/// OpenID Connect authorization code flow with PKCE against one provider.
+pub struct Oidc {
+    pub config: OidcConfig,
+    http: reqwest::Client,
+    provider: Mutex<Option<Provider>>,
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+        Oidc { config, http, provider: Mutex::new(None) }
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let resp = self.http.get(url).send().await.map_err(|e| format!("Cannot reach {}: {}", url, e))?;
+        if !resp.status().is_success() {
+            return Err(format!("{} returned status {}", url, resp.status()));
+        }
+        resp.json().await.map_err(|e| format!("Invalid answer from {}: {}", url, e))
+    }
+
+    async fn fetch_provider(&self) -> Result<Provider, String> {
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+        let discovery: Discovery = self.get_json(&url).await?;
+        // The metadata must be the issuer's own (OpenID Connect Discovery, section 4.3)
+        if discovery.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            return Err(format!("The provider calls itself '{}', not '{}'", discovery.issuer, self.config.issuer));
+        }
+        let keys: JwkSet = self.get_json(&discovery.jwks_uri).await?;
+        Ok(Provider { discovery, keys, fetched: Instant::now() })
+    }
+
+    /// Runs `f` on the provider's metadata, fetching it first if it is missing, old or
+    /// `refresh` is set.
+    async fn with_provider<T>(&self, refresh: bool, f: impl FnOnce(&Provider) -> T) -> Result<T, String> {
+        let mut provider = self.provider.lock().await;
+        if refresh || provider.as_ref().is_none_or(|p| p.fetched.elapsed() > PROVIDER_TTL) {
+            *provider = Some(self.fetch_provider().await?);
+        }
+        Ok(f(provider.as_ref().expect("provider was just fetched")))
+    }
+
+    /// Where to send the browser to log in, and what to remember until it comes back.
+    pub async fn start(&self) -> Result<(String, PendingLogin), String> {
+        let login = PendingLogin { state: random_string(), nonce: random_string(), verifier: random_string() };
+        let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(login.verifier.as_bytes()));
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("scope", self.config.scopes.as_str()),
+            ("state", login.state.as_str()),
+            ("nonce", login.nonce.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ])
+        .map_err(|e| e.to_string())?;
+        let endpoint = self.with_provider(false, |p| p.discovery.authorization_endpoint.clone()).await?;
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        Ok((format!("{}{}{}", endpoint, separator, query), login))
+    }
+
+    /// Redeems the code the provider sent the browser back with and checks the ID token.
+    pub async fn finish(&self, code: &str, login: &PendingLogin) -> Result<Identity, String> {
+        let token_endpoint = self.with_provider(false, |p| p.discovery.token_endpoint.clone()).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        let mut request = self.http.post(&token_endpoint);
+        match &self.config.client_secret {
+            Some(secret) => request = request.basic_auth(&self.config.client_id, Some(secret)),
+            None => form.push(("client_id", self.config.client_id.as_str())),
+        }
+        let resp = request.form(&form).send().await.map_err(|e| format!("Cannot reach {}: {}", token_endpoint, e))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!("The provider refused the code ({}): {}", status, body));
+        }
+        let tokens: TokenResponse = resp.json().await.map_err(|e| format!("Invalid token response: {}", e))?;
+        let id_token = tokens.id_token.ok_or("The provider sent no ID token, is the 'openid' scope requested?")?;
+        let claims = self.verify_id_token(&id_token, &login.nonce).await?;
+        self.identity(&claims)
+    }
+
+    /// Checks the ID token's signature, issuer, audience, expiry and nonce, returning its claims.
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<Value, String> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(|e| format!("Invalid ID token: {}", e))?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(format!("ID token signed with {:?}, which is not accepted", header.alg));
+        }
+        let find_key = |p: &Provider| match &header.kid {
+            Some(kid) => p.keys.find(kid).cloned(),
+            None => p.keys.keys.first().cloned(),
+        };
+        let jwk = match self.with_provider(false, find_key).await? {
+            Some(jwk) => jwk,
+            // Probably rotated since the keys were fetched
+            None => self.with_provider(true, find_key).await?.ok_or("The ID token's signing key is unknown")?,
+        };
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| format!("Unusable signing key: {}", e))?;
+        let issuer = self.with_provider(false, |p| p.discovery.issuer.clone()).await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<Value>(id_token, &key, &validation)
+            .map_err(|e| format!("ID token rejected: {}", e))?
+            .claims;
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err("ID token nonce doesn't match the login".to_string());
+        }
+        Ok(claims)
+    }
+
+    fn identity(&self, claims: &Value) -> Result<Identity, String> {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).filter(|value| !value.is_empty());
+        let issuer = claim("iss").ok_or("The ID token has no issuer")?.to_string();
+        let subject = claim("sub").ok_or("The ID token has no subject")?.to_string();
+        let username = claims.get(&self.config.username_claim)
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| format!("The ID token has no '{}' claim", self.config.username_claim))?
+            .to_string();
+        // A list of names, or a single one
+        let groups: Vec<String> = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => vec![],
+        };
+        let role = self.config.role_map.iter()
+            .filter(|(group, _)| groups.contains(group))
+            .map(|(_, role)| *role)
+            .max();
+        Ok(Identity { issuer, subject, username, role, groups })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    /// An identity provider on a local port: discovery, one ES256 signing key and a token
+    /// endpoint answering with `id_token`.
+    struct MockIdp {
+        issuer: String,
+        key: EncodingKey,
+        id_token: Arc<std::sync::Mutex<String>>,
+        /// Form of the last token request
+        token_request: Arc<std::sync::Mutex<String>>,
+    }
+
+    impl MockIdp {
+        async fn start() -> MockIdp {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let key_pair = rcgen::KeyPair::generate().unwrap();
+            let (x, y) = key_pair.public_key_raw()[1..].split_at(32);
+            let jwks = json!({ "keys": [{
+                "kty": "EC", "crv": "P-256", "kid": "k1", "alg": "ES256", "use": "sig",
+                "x": BASE64URL_NOPAD.encode(x), "y": BASE64URL_NOPAD.encode(y),
+            }] });
+            let discovery = json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/auth", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            });
+            let id_token = Arc::new(std::sync::Mutex::new(String::new()));
+            let token_request = Arc::new(std::sync::Mutex::new(String::new()));
+            let (answer, seen) = (id_token.clone(), token_request.clone());
+            let app = axum::Router::new()
+                .route("/.well-known/openid-configuration", axum::routing::get(move || async move { axum::Json(discovery) }))
+                .route("/jwks", axum::routing::get(move || async move { axum::Json(jwks) }))
+                .route("/token", axum::routing::post(move |body: String| async move {
+                    *seen.lock().unwrap() = body;
+                    let id_token = answer.lock().unwrap().clone();
+                    axum::Json(json!({ "access_token": "at", "token_type": "Bearer", "id_token": id_token }))
+                }));
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            let key = EncodingKey::from_ec_pem(key_pair.serialize_pem().as_bytes()).unwrap();
+            MockIdp { issuer, key, id_token, token_request }
+        }
+
+        /// Claims of a valid ID token for the login with `nonce`.
+        fn claims(&self, nonce: &str) -> Value {
+            json!({
+                "iss": self.issuer,
+                "aud": "portsentinel",
+                "sub": "f3a1c2d4-0001",
+                "exp": now() + 300,
+                "iat": now(),
+                "nonce": nonce,
+                "preferred_username": "jdoe",
+                "groups": ["staff", "ps-ops"],
+            })
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some("k1".to_string());
+            jsonwebtoken::encode(&header, claims, &self.key).unwrap()
+        }
+
+        fn oidc(&self) -> Oidc {
+            Oidc::new(OidcConfig::for_tests(&self.issuer))
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_id_tokens_give_the_identity() {
+        let idp = MockIdp::start().await;
+        let oidc = idp.oidc();
+        let claims = oidc.verify_id_token(&idp.sign(&idp.claims("n-1")), "n-1").await.unwrap();
+        let identity = oidc.identity(&claims).unwrap();
+        assert_eq!((identity.issuer.as_str(), identity.subject.as_str()), (idp.issuer.as_str(), "f3a1c2d4-0001"));
+        assert_eq!(identity.username, "jdoe");
+        assert_eq!(identity.role, Some(Role::Operator));
+    }
+
+    #[tokio::test]
+    async fn only_asymmetric_signatures_are_accepted() {
+        let idp = MockIdp::start().await;
+        let oidc = idp.oidc();
+        // Signed with the client secret, which the provider shares with us
+        let hmac = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &idp.claims("n-1"), &EncodingKey::from_secret(b"client-secret")).unwrap();
+        assert!(oidc.verify_id_token(&hmac, "n-1").await.unwrap_err().contains("not accepted"));
+
+        let unsigned = format!("{}.{}.",
+            BASE64URL_NOPAD.encode(br#"{"alg":"none","typ":"JWT"}"#),
+            BASE64URL_NOPAD.encode(idp.claims("n-1").to_string().as_bytes()));
+        assert!(oidc.verify_id_token(&unsigned, "n-1").await.is_err());
+
+        // The right key id, another key
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("k1".to_string());
+        let other = EncodingKey::from_ec_pem(rcgen::KeyPair::generate().unwrap().serialize_pem().as_bytes()).unwrap();
+        let forged = jsonwebtoken::encode(&header, &idp.claims("n-1"), &other).unwrap();
+        assert!(oidc.verify_id_token(&forged, "n-1").await.unwrap_err().contains("rejected"));
+
+        header.kid = Some("k2".to_string());
+        let unknown = jsonwebtoken::encode(&header, &idp.claims("n-1"), &idp.key).unwrap();
+        assert_eq!(oidc.verify_id_token(&unknown, "n-1").await.unwrap_err(), "The ID token's signing key is unknown");
+    }
+
+    #[tokio::test]
+    async fn tokens_for_another_login_issuer_or_client_are_refused() {
+        let idp = MockIdp::start().await;
+        let oidc = idp.oidc();
+        assert_eq!(oidc.verify_id_token(&idp.sign(&idp.claims("n-1")), "n-2").await.unwrap_err(), "ID token nonce doesn't match the login");
+
+        let tampered = [
+            ("iss", json!("https://login.example.com/realms/other")),
+            ("aud", json!("another-client")),
+            ("exp", json!(now() - 3600)),
+        ];
+        for (claim, value) in tampered {
+            let mut claims = idp.claims("n-1");
+            claims[claim] = value;
+            let error = oidc.verify_id_token(&idp.sign(&claims), "n-1").await.unwrap_err();
+            assert!(error.starts_with("ID token rejected"), "{}: {}", claim, error);
+        }
+        for claim in ["sub", "exp", "nonce"] {
+            let mut claims = idp.claims("n-1");
+            claims.as_object_mut().unwrap().remove(claim);
+            assert!(oidc.verify_id_token(&idp.sign(&claims), "n-1").await.is_err(), "without {}", claim);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_code_is_redeemed_with_the_pkce_verifier() {
+        let idp = MockIdp::start().await;
+        let oidc = idp.oidc();
+        let (url, login) = oidc.start().await.unwrap();
+        let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(login.verifier.as_bytes()));
+        assert!(url.starts_with(&format!("{}/auth?", idp.issuer)));
+        assert!(url.contains(&format!("code_challenge={}", challenge)) && url.contains("code_challenge_method=S256"));
+        assert!(url.contains(&format!("nonce={}", login.nonce)) && url.contains(&format!("state={}", login.state)));
+
+        *idp.id_token.lock().unwrap() = idp.sign(&idp.claims(&login.nonce));
+        let identity = oidc.finish("code-1", &login).await.unwrap();
+        assert_eq!(identity.username, "jdoe");
+        let form: Vec<(String, String)> = serde_urlencoded::from_str(&idp.token_request.lock().unwrap()).unwrap();
+        assert!(form.contains(&("code".to_string(), "code-1".to_string())));
+        assert!(form.contains(&("code_verifier".to_string(), login.verifier.clone())));
+
+        // An ID token obtained for another login
+        *idp.id_token.lock().unwrap() = idp.sign(&idp.claims("someone-elses-nonce"));
+        assert!(oidc.finish("code-2", &login).await.is_err());
+    }
+
+    #[test]
+    fn groups_map_to_the_highest_role() {
+        let oidc = Oidc::new(OidcConfig::for_tests("https://login.example.com"));
+        let role = |groups: Value| {
+            let claims = json!({ "iss": "https://login.example.com", "sub": "1", "preferred_username": "jdoe", "groups": groups });
+            oidc.identity(&claims).unwrap().role
+        };
+        assert_eq!(role(json!(["staff", "ps-admins", "ps-ops"])), Some(Role::Admin));
+        assert_eq!(role(json!(["staff"])), Some(Role::Viewer));
+        assert_eq!(role(json!("ps-ops")), Some(Role::Operator));
+        assert_eq!(role(json!(["PS-ADMINS", "ps-admins-old"])), None);
+        assert_eq!(role(json!(null)), None);
+
+        assert!(oidc.identity(&json!({ "iss": "https://login.example.com", "sub": "1" })).is_err());
+        assert!(oidc.identity(&json!({ "iss": "https://login.example.com", "preferred_username": "jdoe" })).is_err());
+    }
+
+    #[test]
+    fn role_maps() {
+        let map = OidcConfig::parse_role_map(" ps-admins = admin ,, staff=viewer ").unwrap();
+        assert_eq!(map, [("ps-admins".to_string(), Role::Admin), ("staff".to_string(), Role::Viewer)]);
+        assert!(OidcConfig::parse_role_map("ps-admins").is_err());
+        assert!(OidcConfig::parse_role_map("ps-admins=root").is_err());
+        assert!(OidcConfig::parse_role_map("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+/// Passwords attackers try first, refused whatever the denylist file says.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "1234567", "12345678", "123456789", "1234567890", "111111", "000000", "123123",
+    "654321", "password", "password1", "password123", "passw0rd", "qwerty", "qwerty123", "qwertyuiop",
+    "abc123", "abcd1234", "iloveyou", "welcome", "welcome1", "letmein", "monkey", "dragon",
+    "sunshine", "princess", "football", "baseball", "master", "admin", "admin123", "administrator",
+    "root", "toor", "changeme", "change_me", "changeme123", "secret", "default", "guest", "test", "test123",
+    "portsentinel",
+];
+
+/// What a new dashboard password has to satisfy (`--password-*` options).
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// How many of a user's passwords, the current one included, can't be picked again; 0 to allow any
+    pub history: usize,
+    /// Lowercase
+    denylist: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// The built-in list of common passwords plus one per line from `denylist_file`.
+    pub fn load(min_length: usize, history: usize, denylist_file: Option<&str>) -> Result<Self, String> {
+        let mut denylist: HashSet<String> = COMMON_PASSWORDS.iter().map(|p| p.to_string()).collect();
+        if let Some(path) = denylist_file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read password denylist '{}': {}", path, e))?;
+            denylist.extend(content.lines().map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty()));
+        }
+        Ok(PasswordPolicy { min_length, history, denylist })
+    }
+
+    /// How many replaced passwords are kept to check against, besides the current one.
+    pub fn replaced_to_keep(&self) -> usize {
+        self.history.saturating_sub(1)
+    }
+
+    /// Why `password` can't be used by `username`, if it can't. Reuse is checked separately
+    /// against the stored hashes.
+    pub fn check(&self, username: &str, password: &str) -> Result<(), String> {
+        if password.chars().count() < self.min_length {
+            return Err(format!("The password must have at least {} characters", self.min_length));
+        }
+        let lower = password.to_lowercase();
+        if self.denylist.contains(&lower) {
+            return Err("This password is too common, pick another one".to_string());
+        }
+        if lower == username.to_lowercase() {
+            return Err("The password can't be the username".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Allowed dashboard usernames: letters, digits and `.`, `_`, `-`, `@`, at most 64.
+pub fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 64
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(history: usize) -> PasswordPolicy {
+        PasswordPolicy::load(10, history, None).unwrap()
+    }
+
+    #[test]
+    fn refuses_short_common_and_username_passwords() {
+        let policy = policy(5);
+        assert!(policy.check("alice", "short").unwrap_err().contains("at least 10"));
+        assert!(policy.check("alice", "Password123").unwrap_err().contains("too common"));
+        assert!(policy.check("alice.admin", "Alice.Admin").unwrap_err().contains("username"));
+        assert_eq!(policy.check("alice", "correct horse battery"), Ok(()));
+    }
+
+    #[test]
+    fn length_counts_characters_not_bytes() {
+        assert!(policy(5).check("alice", "ééééé").is_err());
+        assert_eq!(policy(5).check("alice", "éééééééééé"), Ok(()));
+    }
+
+    #[test]
+    fn denylist_file_adds_to_the_built_in_list() {
+        let path = std::env::temp_dir().join(format!("ps-denylist-{}", std::process::id()));
+        std::fs::write(&path, "CompanyName2024\n\n  sentinel-rocks  \n").unwrap();
+        let policy = PasswordPolicy::load(8, 0, path.to_str()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(policy.check("alice", "companyname2024").is_err());
+        assert!(policy.check("alice", "Sentinel-Rocks").is_err());
+        assert!(policy.check("alice", "qwertyuiop").is_err());
+        assert_eq!(policy.check("alice", "a fine password"), Ok(()));
+        assert!(PasswordPolicy::load(8, 0, Some("/nonexistent/denylist")).is_err());
+    }
+
+    #[test]
+    fn history_counts_the_current_password() {
+        assert_eq!(policy(5).replaced_to_keep(), 4);
+        assert_eq!(policy(1).replaced_to_keep(), 0);
+        assert_eq!(policy(0).replaced_to_keep(), 0);
+    }
+
+    #[test]
+    fn usernames() {
+        assert!(valid_username("alice.smith@example.com"));
+        assert!(valid_username("ops_bot-2"));
+        assert!(!valid_username(""));
+        assert!(!valid_username("alice smith"));
+        assert!(!valid_username("../etc"));
+        assert!(!valid_username(&"a".repeat(65)));
+    }
+}
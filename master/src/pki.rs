@@ -0,0 +1,334 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateSigningRequestParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use rustls::client::{Resumption, ServerCertVerified, ServerCertVerifier};
+use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+
+/// Small built-in CA that issues agent server certificates at enrollment, plus the client
+/// certificate the master presents to agents that require one. Lives in `--ca-dir`:
+/// `ca.pem`/`ca-key.pem` and `master.pem`/`master-key.pem`, all created on first start.
+pub struct Pki {
+    ca_cert: Certificate,
+    ca_key: KeyPair,
+    ca_pem: String,
+    /// SHA-256 of `ca.pem`, carried in join tokens
+    ca_fingerprint: String,
+    /// Trusts the public roots, the built-in CA and `--agent-ca`, and authenticates as the master
+    client_config: ClientConfig,
+}
+
+impl Pki {
+    pub fn load_or_create(dir: &str, extra_ca: Option<&str>) -> Result<Self, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir, e))?;
+        let dir = Path::new(dir);
+
+        let (ca_cert, ca_key) = match read_pair(&dir.join("ca.pem"), &dir.join("ca-key.pem"))? {
+            Some((cert_pem, key_pem)) => {
+                let key = KeyPair::from_pem(&key_pem).map_err(|e| format!("Invalid CA key: {}", e))?;
+                // Re-signing the stored parameters gives an issuer with the same name and key
+                let params = CertificateParams::from_ca_cert_pem(&cert_pem).map_err(|e| format!("Invalid CA certificate: {}", e))?;
+                (params.self_signed(&key).map_err(|e| e.to_string())?, key)
+            }
+            None => {
+                let key = KeyPair::generate().map_err(|e| e.to_string())?;
+                let mut params = CertificateParams::default();
+                params.distinguished_name = name("PortSentinel CA");
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+                expire_in_years(&mut params, 10);
+                let cert = params.self_signed(&key).map_err(|e| e.to_string())?;
+                write_pair(&dir.join("ca.pem"), &cert.pem(), &dir.join("ca-key.pem"), &key.serialize_pem())?;
+                println!("🔏 Created built-in CA in '{}'", dir.display());
+                (cert, key)
+            }
+        };
+        let ca_pem = std::fs::read_to_string(dir.join("ca.pem")).map_err(|e| e.to_string())?;
+
+        let (client_pem, client_key_pem) = match read_pair(&dir.join("master.pem"), &dir.join("master-key.pem"))? {
+            Some(pair) => pair,
+            None => {
+                let key = KeyPair::generate().map_err(|e| e.to_string())?;
+                let mut params = CertificateParams::default();
+                params.distinguished_name = name("PortSentinel Master");
+                params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+                expire_in_years(&mut params, 10);
+                let cert = params.signed_by(&key, &ca_cert, &ca_key).map_err(|e| e.to_string())?;
+                write_pair(&dir.join("master.pem"), &cert.pem(), &dir.join("master-key.pem"), &key.serialize_pem())?;
+                (cert.pem(), key.serialize_pem())
+            }
+        };
+
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+        }));
+        let ca_ders = pem_certs(&ca_pem)?;
+        let ca_fingerprint = ca_ders.first().map(|der| fingerprint(der)).ok_or("ca.pem holds no certificate")?;
+        for der in ca_ders {
+            roots.add(&rustls::Certificate(der)).map_err(|e| format!("Invalid CA certificate: {}", e))?;
+        }
+        if let Some(path) = extra_ca {
+            let pem = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+            for der in pem_certs(&pem)? {
+                roots.add(&rustls::Certificate(der)).map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+            }
+        }
+
+        let client_certs = pem_certs(&client_pem)?.into_iter().map(rustls::Certificate).collect();
+        let client_key = rustls_pemfile::pkcs8_private_keys(&mut client_key_pem.as_bytes())
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("master-key.pem holds no private key")?;
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(client_certs, rustls::PrivateKey(client_key))
+            .map_err(|e| e.to_string())?;
+
+        Ok(Pki { ca_cert, ca_key, ca_pem, ca_fingerprint, client_config })
+    }
+
+    pub fn ca_pem(&self) -> &str {
+        &self.ca_pem
+    }
+
+    pub fn ca_fingerprint(&self) -> &str {
+        &self.ca_fingerprint
+    }
+
+    /// Signs an agent's certificate request. The names in the request are replaced by `names`
+    /// (hostnames or IPs the master knows the agent by), so an agent can't claim other hosts.
+    pub fn sign_agent_csr(&self, csr_pem: &str, names: &[String]) -> Result<String, String> {
+        let mut csr = CertificateSigningRequestParams::from_pem(csr_pem).map_err(|e| format!("Invalid certificate request: {}", e))?;
+        csr.params.subject_alt_names = names.iter()
+            .map(|n| match n.parse::<IpAddr>() {
+                Ok(ip) => Ok(SanType::IpAddress(ip)),
+                Err(_) => n.clone().try_into().map(SanType::DnsName).map_err(|e: rcgen::Error| e.to_string()),
+            })
+            .collect::<Result<_, _>>()?;
+        csr.params.is_ca = IsCa::NoCa;
+        csr.params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        expire_in_years(&mut csr.params, 5);
+        let cert = csr.signed_by(&self.ca_cert, &self.ca_key).map_err(|e| e.to_string())?;
+        Ok(cert.pem())
+    }
+
+    /// TLS settings for calling an agent. With a pin, only the agent certificate with that
+    /// SHA-256 fingerprint is accepted (self-signed is fine); otherwise it must chain to a trusted CA.
+    pub fn client_config(&self, pin: Option<&str>) -> ClientConfig {
+        let mut config = self.client_config.clone();
+        if let Some(pin) = pin {
+            let pin = pin.replace(':', "").to_lowercase();
+            config.dangerous().set_certificate_verifier(Arc::new(PinnedCert(pin)));
+            // A resumed session skips the certificate check, so a changed pin would go unnoticed
+            config.resumption = Resumption::disabled();
+        }
+        config
+    }
+}
+
+/// Accepts exactly one certificate, by fingerprint.
+struct PinnedCert(String);
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(&end_entity.0) == self.0 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("Agent certificate does not match the pinned fingerprint".to_string()))
+        }
+    }
+}
+
+/// SHA-256 of a DER certificate as lowercase hex, the format pins are entered in.
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+fn name(common_name: &str) -> DistinguishedName {
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, common_name);
+    dn
+}
+
+/// Lets the certificate expire on January 1st, `years` from the current year.
+fn expire_in_years(params: &mut CertificateParams, years: i32) {
+    let secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let year = 1970 + (secs / 31_556_952) as i32;
+    params.not_after = rcgen::date_time_ymd(year + years, 1, 1);
+}
+
+fn pem_certs(pem: &str) -> Result<Vec<Vec<u8>>, String> {
+    rustls_pemfile::certs(&mut pem.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn read_pair(cert: &Path, key: &Path) -> Result<Option<(String, String)>, String> {
+    if !cert.exists() || !key.exists() {
+        return Ok(None);
+    }
+    let cert_pem = std::fs::read_to_string(cert).map_err(|e| format!("Cannot read {}: {}", cert.display(), e))?;
+    let key_pem = std::fs::read_to_string(key).map_err(|e| format!("Cannot read {}: {}", key.display(), e))?;
+    Ok(Some((cert_pem, key_pem)))
+}
+
+fn write_pair(cert: &Path, cert_pem: &str, key: &Path, key_pem: &str) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::write(cert, cert_pem).map_err(|e| format!("Cannot write {}: {}", cert.display(), e))?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(key)
+        .map_err(|e| format!("Cannot write {}: {}", key.display(), e))?;
+    file.write_all(key_pem.as_bytes()).map_err(|e| format!("Cannot write {}: {}", key.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::server::AllowAnyAuthenticatedClient;
+    use rustls::{PrivateKey, ServerConfig};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    fn ca_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("ps-pki-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.display().to_string()
+    }
+
+    /// What the agent does at enrollment: a new key and a request naming whatever it likes.
+    fn agent_csr(claimed: &str) -> (String, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let csr = CertificateParams::new(vec![claimed.to_string()]).unwrap().serialize_request(&key).unwrap().pem().unwrap();
+        (csr, key)
+    }
+
+    /// An agent TLS endpoint answering "hello" on every connection; with `client_ca`, only to
+    /// callers presenting a certificate from that CA.
+    async fn agent(cert_pem: &str, key: &KeyPair, client_ca: Option<&str>) -> std::net::SocketAddr {
+        let certs = pem_certs(cert_pem).unwrap().into_iter().map(rustls::Certificate).collect();
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca {
+            Some(ca_pem) => {
+                let mut roots = RootCertStore::empty();
+                for der in pem_certs(ca_pem).unwrap() {
+                    roots.add(&rustls::Certificate(der)).unwrap();
+                }
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(certs, PrivateKey(key.serialize_der())).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let _ = stream.write_all(b"hello").await;
+                        let _ = stream.shutdown().await;
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// Connects as `server_name` and returns what the agent said, or the TLS error.
+    async fn call(config: ClientConfig, addr: std::net::SocketAddr, server_name: &str) -> Result<String, String> {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from(server_name).unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config)).connect(name, stream).await.map_err(|e| e.to_string())?;
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).await.map_err(|e| e.to_string())?;
+        Ok(answer)
+    }
+
+    #[tokio::test]
+    async fn ca_is_created_once_and_kept() {
+        let dir = ca_dir("reload");
+        let first = Pki::load_or_create(&dir, None).unwrap();
+        let again = Pki::load_or_create(&dir, None).unwrap();
+        assert_eq!(first.ca_pem(), again.ca_pem());
+        assert_eq!(first.ca_fingerprint(), again.ca_fingerprint());
+        assert_eq!(first.ca_fingerprint(), fingerprint(&pem_certs(first.ca_pem()).unwrap()[0]));
+
+        // Certificates issued after the restart chain to the CA agents already have
+        let (csr, key) = agent_csr("web1");
+        let cert = again.sign_agent_csr(&csr, &["web1".to_string()]).unwrap();
+        let addr = agent(&cert, &key, Some(first.ca_pem())).await;
+        assert_eq!(call(first.client_config(None), addr, "web1").await.unwrap(), "hello");
+        assert!(again.sign_agent_csr("not a csr", &["web1".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_with_an_issued_certificate() {
+        let pki = Pki::load_or_create(&ca_dir("mtls"), None).unwrap();
+        let (csr, key) = agent_csr("bank.example.com");
+        let cert = pki.sign_agent_csr(&csr, &["web1".to_string(), "10.0.0.5".to_string()]).unwrap();
+        let addr = agent(&cert, &key, Some(pki.ca_pem())).await;
+
+        assert_eq!(call(pki.client_config(None), addr, "web1").await.unwrap(), "hello");
+        assert_eq!(call(pki.client_config(None), addr, "10.0.0.5").await.unwrap(), "hello");
+        // The names in the request were replaced by the ones the master knows
+        assert!(call(pki.client_config(None), addr, "bank.example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn callers_without_the_master_certificate_are_refused() {
+        let pki = Pki::load_or_create(&ca_dir("no-client-cert"), None).unwrap();
+        let (csr, key) = agent_csr("web1");
+        let cert = pki.sign_agent_csr(&csr, &["web1".to_string()]).unwrap();
+        let addr = agent(&cert, &key, Some(pki.ca_pem())).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&rustls::Certificate(pem_certs(pki.ca_pem()).unwrap().remove(0))).unwrap();
+        let anonymous = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+        assert_ne!(call(anonymous, addr, "web1").await.ok().as_deref(), Some("hello"));
+
+        // Nor is a master with a certificate from another CA
+        let other = Pki::load_or_create(&ca_dir("other-master"), None).unwrap();
+        let mut config = other.client_config(None);
+        config.dangerous().set_certificate_verifier(Arc::new(PinnedCert(fingerprint(&pem_certs(&cert).unwrap()[0]))));
+        assert_ne!(call(config, addr, "web1").await.ok().as_deref(), Some("hello"));
+    }
+
+    #[tokio::test]
+    async fn pinned_self_signed_certificate() {
+        let pki = Pki::load_or_create(&ca_dir("pin"), None).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["web1".to_string()]).unwrap().self_signed(&key).unwrap();
+        let addr = agent(&cert.pem(), &key, None).await;
+        let pin = fingerprint(cert.der());
+
+        // Not trusted through any CA
+        assert!(call(pki.client_config(None), addr, "web1").await.is_err());
+        assert_eq!(call(pki.client_config(Some(&pin)), addr, "web1").await.unwrap(), "hello");
+        // Pins are accepted the way people paste them
+        let pasted = pin.to_uppercase().as_bytes().chunks(2).map(|c| std::str::from_utf8(c).unwrap()).collect::<Vec<_>>().join(":");
+        assert_eq!(call(pki.client_config(Some(&pasted)), addr, "any-name").await.unwrap(), "hello");
+        assert!(call(pki.client_config(Some(&"00".repeat(32))), addr, "web1").await.is_err());
+    }
+}
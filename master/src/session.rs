@@ -0,0 +1,189 @@
+use axum_extra::extract::cookie::{Cookie, PrivateCookieJar, SameSite};
+use rand::RngCore;
+use crate::db::SessionRecord;
+use crate::enroll::hash_secret;
+use crate::state::AppState;
+
+/// Cookie holding the session id. The database only keeps its hash.
+pub const COOKIE: &str = "session";
+
+/// Writing `last_seen` on every poll would be a write per request; this is close enough.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Without a request for this long, the session ends
+    pub idle_secs: i64,
+    /// Since login, however active
+    pub absolute_secs: i64,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Opens a session for `username` and returns the cookie that carries it.
+pub async fn start(state: &AppState, username: &str, source_ip: &str, user_agent: Option<&str>) -> Result<Cookie<'static>, sqlx::Error> {
+    let timeouts = state.session_timeouts;
+    let now = now();
+    // Nothing else cleans up, logins are frequent enough
+    crate::db::delete_expired_sessions(&state.db, now - timeouts.idle_secs, now - timeouts.absolute_secs).await?;
+
+    let mut id = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id = hex::encode(id);
+    let session = SessionRecord {
+        id_hash: hash_secret(&id),
+        username: username.to_string(),
+        created_at: now,
+        last_seen: now,
+        source_ip: Some(source_ip.to_string()).filter(|ip| !ip.is_empty()),
+        user_agent: user_agent.map(str::to_string),
+    };
+    crate::db::create_session(&state.db, &session).await?;
+    Ok(cookie(state, COOKIE, id))
+}
+
+/// A login cookie scripts can't read, kept off plain http when the dashboard is served over https.
+/// Lax, so it comes along when the identity provider sends the browser back.
+pub fn cookie(state: &AppState, name: &'static str, value: String) -> Cookie<'static> {
+    Cookie::build((name, value)).path("/").secure(state.secure_cookies).http_only(true).same_site(SameSite::Lax).build()
+}
+
+/// The live session the request's cookie belongs to. Expired sessions are deleted on the way.
+pub async fn current(state: &AppState, jar: &PrivateCookieJar) -> Option<SessionRecord> {
+    let id_hash = hash_secret(jar.get(COOKIE)?.value());
+    let session = crate::db::get_session(&state.db, &id_hash).await?;
+    let timeouts = state.session_timeouts;
+    let now = now();
+    if now - session.last_seen > timeouts.idle_secs || now - session.created_at > timeouts.absolute_secs {
+        let _ = crate::db::delete_session(&state.db, &id_hash).await;
+        return None;
+    }
+    if now - session.last_seen >= TOUCH_INTERVAL_SECS {
+        let _ = crate::db::touch_session(&state.db, &id_hash, now).await;
+    }
+    Some(session)
+}
+
+/// Ends the request's session, if any, and returns the jar without its cookie.
+pub async fn end(state: &AppState, jar: PrivateCookieJar) -> (Option<SessionRecord>, PrivateCookieJar) {
+    let session = current(state, &jar).await;
+    if let Some(session) = &session {
+        let _ = crate::db::delete_session(&state.db, &session.id_hash).await;
+    }
+    (session, jar.remove(Cookie::from(COOKIE)))
+}
+
+/// Encrypted cookie naming the user who got the password right and still owes a TOTP code.
+pub const PENDING_COOKIE: &str = "pending_login";
+
+/// How long the code can be entered after the password.
+const PENDING_SECS: i64 = 300;
+
+pub fn pending_cookie(state: &AppState, username: &str) -> Cookie<'static> {
+    cookie(state, PENDING_COOKIE, format!("{}\n{}", now(), username))
+}
+
+/// The user a pending login is for, unless it timed out.
+pub fn pending_user(jar: &PrivateCookieJar) -> Option<String> {
+    let cookie = jar.get(PENDING_COOKIE)?;
+    let (issued, username) = cookie.value().split_once('\n')?;
+    let issued: i64 = issued.parse().ok()?;
+    (now() - issued <= PENDING_SECS).then(|| username.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::ClientIp;
+    use axum::extract::State;
+
+    async fn login(state: &AppState, username: &str) -> PrivateCookieJar {
+        let cookie = start(state, username, "10.0.0.9", Some("test")).await.unwrap();
+        PrivateCookieJar::new(state.key.clone()).add(cookie)
+    }
+
+    /// Moves the request's session `secs` into the past.
+    async fn age(state: &AppState, jar: &PrivateCookieJar, column: &str, secs: i64) {
+        let id_hash = hash_secret(jar.get(COOKIE).unwrap().value());
+        sqlx::query(&format!("UPDATE sessions SET {} = {} - ? WHERE id_hash = ?", column, column))
+            .bind(secs)
+            .bind(&id_hash)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_end_when_idle() {
+        let state = AppState::for_tests().await;
+        let jar = login(&state, "alice").await;
+        age(&state, &jar, "last_seen", state.session_timeouts.idle_secs - 60).await;
+        assert!(current(&state, &jar).await.is_some());
+        // Seen again, so the idle time starts over
+        let id_hash = hash_secret(jar.get(COOKIE).unwrap().value());
+        assert!(crate::db::get_session(&state.db, &id_hash).await.unwrap().last_seen >= now() - 1);
+
+        age(&state, &jar, "last_seen", state.session_timeouts.idle_secs + 1).await;
+        assert!(current(&state, &jar).await.is_none());
+        assert!(crate::db::get_session(&state.db, &id_hash).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sessions_end_after_the_absolute_lifetime_however_active() {
+        let state = AppState::for_tests().await;
+        let jar = login(&state, "alice").await;
+        age(&state, &jar, "created_at", state.session_timeouts.absolute_secs - 60).await;
+        assert!(current(&state, &jar).await.is_some());
+        age(&state, &jar, "created_at", 61).await;
+        assert!(current(&state, &jar).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn logging_out_revokes_the_session() {
+        let state = AppState::for_tests().await;
+        let jar = login(&state, "alice").await;
+        let other_device = login(&state, "alice").await;
+
+        let _ = crate::handlers::logout_handler(State(state.clone()), ClientIp("10.0.0.9".to_string()), jar.clone()).await;
+        // A copy of the cookie is of no use anymore
+        assert!(current(&state, &jar).await.is_none());
+        assert!(current(&state, &other_device).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_revokes_the_other_sessions() {
+        let state = AppState::for_tests().await;
+        let user = crate::auth::User {
+            username: "alice".to_string(),
+            password_hash: bcrypt::hash("old password 1", 4).unwrap(),
+            ..crate::auth::User::for_tests("operator", "")
+        };
+        crate::db::create_user(&state.db, &user).await.unwrap();
+        let jar = login(&state, "alice").await;
+        let other_device = login(&state, "alice").await;
+
+        let form = serde_json::from_value(serde_json::json!({ "username": "alice", "password": "new password 2" })).unwrap();
+        let _ = crate::handlers::change_password_submit(State(state.clone()), ClientIp("10.0.0.9".to_string()), jar.clone(), axum::Form(form)).await;
+        assert!(current(&state, &other_device).await.is_none());
+        assert!(current(&state, &jar).await.is_some());
+
+        // Also when an admin resets it, for every session
+        crate::db::reset_user_password(&state.db, "alice", &user.password_hash).await.unwrap();
+        assert!(current(&state, &jar).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn cookies_are_secure_when_served_over_https() {
+        let mut state = AppState::for_tests().await;
+        assert_eq!(start(&state, "alice", "", None).await.unwrap().secure(), Some(false));
+        state.secure_cookies = true;
+        let cookie = start(&state, "alice", "", None).await.unwrap();
+        assert_eq!((cookie.secure(), cookie.http_only()), (Some(true), Some(true)));
+        assert_eq!(pending_cookie(&state, "alice").secure(), Some(true));
+    }
+}
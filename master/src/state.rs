@@ -10,12 +10,46 @@ pub struct NodeConfig {
     pub name: String,
     pub url: String,
     pub token: Option<String>, 
+    /// SHA-256 of the credential issued at enrollment; None until the node is enrolled
+    #[serde(default)]
+    pub credential_hash: Option<String>,
+    /// Input the credential is re-derived from (see `enroll::MasterKey`)
+    #[serde(default)]
+    pub credential_salt: Option<String>,
+    /// SHA-256 fingerprint the agent's TLS certificate must have, instead of chaining to a trusted CA
+    #[serde(default)]
+    pub tls_fingerprint: Option<String>,
+    /// Comma-separated tags, e.g. "prod, db", that users can be limited to
+    #[serde(default)]
+    pub tags: String,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: sqlx::SqlitePool,
     pub key: Key,
+    /// Push-mode agents currently connected
+    pub tunnels: Arc<crate::tunnel::Tunnels>,
+    /// Secret node credentials are derived from
+    pub master_key: Arc<crate::enroll::MasterKey>,
+    /// Built-in CA and the TLS settings used to call agents
+    pub pki: Arc<crate::pki::Pki>,
+    /// Name this master gives agents in signed requests, recorded in their audit logs
+    pub name: String,
+    /// Take the client address for the audit log from `X-Forwarded-For` (behind a reverse proxy)
+    pub trust_forwarded_for: bool,
+    /// Cookies are only sent over https (`--secure-cookies`, implied by `--trust-forwarded-for`)
+    pub secure_cookies: bool,
+    /// When dashboard sessions expire
+    pub session_timeouts: crate::session::Timeouts,
+    /// Failed logins per account and client address
+    pub login_throttle: Arc<crate::throttle::LoginThrottle>,
+    /// Rules for new dashboard passwords
+    pub password_policy: Arc<crate::password::PasswordPolicy>,
+    /// Single sign-on, when an issuer is configured
+    pub oidc: Option<Arc<crate::oidc::Oidc>>,
+    /// Nodes still running an agent from before signed requests (`--legacy-agents`)
+    pub legacy_agents: Arc<crate::agent::LegacyAgents>,
 }
 
 // This allows the PrivateCookieJar to extract the Key from AppState
@@ -23,4 +57,42 @@ impl FromRef<AppState> for Key {
     fn from_ref(state: &AppState) -> Self {
         state.key.clone()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl AppState {
+    /// A master on a fresh in-memory database, with its key and CA in a new temporary directory.
+    pub async fn for_tests() -> AppState {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("ps-master-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Every connection to ":memory:" is its own database, so keep just one
+        let db = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        crate::db::init_db(&db).await.unwrap();
+        let master_key = crate::enroll::MasterKey::load_or_create(dir.join("master.key").to_str().unwrap()).unwrap();
+        let pki = crate::pki::Pki::load_or_create(dir.join("ca").to_str().unwrap(), None).unwrap();
+
+        AppState {
+            db,
+            key: master_key.cookie_key(),
+            tunnels: Default::default(),
+            master_key: Arc::new(master_key),
+            pki: Arc::new(pki),
+            name: "test-master".to_string(),
+            trust_forwarded_for: false,
+            secure_cookies: false,
+            session_timeouts: crate::session::Timeouts { idle_secs: 1800, absolute_secs: 12 * 3600 },
+            login_throttle: Arc::new(crate::throttle::LoginThrottle::new(crate::throttle::Limits {
+                free_attempts: 3,
+                max_per_account: 10,
+                max_per_ip: 50,
+                lockout_secs: 900,
+            })),
+            password_policy: Arc::new(crate::password::PasswordPolicy::load(10, 5, None).unwrap()),
+            oidc: None,
+            legacy_agents: Default::default(),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Failed logins allowed before each further one has to wait, 1s, 2s, 4s...
+    pub free_attempts: u32,
+    /// Failed logins for one account after which it is locked
+    pub max_per_account: u32,
+    /// Failed logins from one address after which it is locked. Higher, several users can share one (NAT).
+    pub max_per_ip: u32,
+    /// How long a lockout lasts, and how long failures are remembered
+    pub lockout_secs: i64,
+}
+
+struct Failures {
+    count: u32,
+    last: i64,
+}
+
+/// Failed dashboard logins per account and per client address, kept in memory: a restart
+/// forgets them, which an attacker can't cause. An attempt counts as failed from the moment it
+/// is let through, before bcrypt, so a blocked attempt costs nothing and attempts sent in
+/// parallel can't all get in under the limit; one that turns out right is taken back.
+pub struct LoginThrottle {
+    limits: Limits,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn account_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+impl LoginThrottle {
+    pub fn new(limits: Limits) -> Self {
+        LoginThrottle { limits, failures: Mutex::new(HashMap::new()) }
+    }
+
+    /// The counters a login for `username` from `ip` goes to, with the failures that lock each.
+    fn keys(&self, ip: &str, username: &str) -> [(String, u32); 2] {
+        [(account_key(username), self.limits.max_per_account), (ip_key(ip), self.limits.max_per_ip)]
+    }
+
+    /// Seconds `count` failures make the next attempt wait after the last one.
+    fn backoff(&self, count: u32, max: u32) -> i64 {
+        let limits = self.limits;
+        if count >= max {
+            limits.lockout_secs
+        } else if count >= limits.free_attempts {
+            let exp = (count - limits.free_attempts).min(30);
+            (1i64 << exp).min(limits.lockout_secs)
+        } else {
+            0
+        }
+    }
+
+    /// Lets a login for `username` from `ip` be tried now and counts it as failed until
+    /// `passed` says otherwise; if it may not, the seconds to wait.
+    pub fn begin(&self, ip: &str, username: &str) -> Result<(), i64> {
+        self.begin_at(ip, username, now())
+    }
+
+    fn begin_at(&self, ip: &str, username: &str, now: i64) -> Result<(), i64> {
+        let lockout = self.limits.lockout_secs;
+        let mut failures = self.failures.lock().unwrap();
+        // Forget failures once a lockout would have ended, so the map doesn't grow
+        failures.retain(|_, f| now - f.last < lockout);
+        let keys = self.keys(ip, username);
+        let wait = keys.iter()
+            .filter_map(|(key, max)| failures.get(key).map(|f| f.last + self.backoff(f.count, *max) - now))
+            .max()
+            .unwrap_or(0);
+        if wait > 0 {
+            return Err(wait);
+        }
+        for (key, _) in keys {
+            let entry = failures.entry(key).or_insert(Failures { count: 0, last: now });
+            entry.count += 1;
+            entry.last = now;
+        }
+        Ok(())
+    }
+
+    /// The attempt let through by `begin` failed, it stays counted. True if the account or
+    /// address is locked now.
+    pub fn failed(&self, ip: &str, username: &str) -> bool {
+        let failures = self.failures.lock().unwrap();
+        self.keys(ip, username).iter().any(|(key, max)| failures.get(key).is_some_and(|f| f.count >= *max))
+    }
+
+    /// The attempt let through by `begin` was no failure (e.g. the right password, with the
+    /// 2FA code still to come), so it is taken back.
+    pub fn passed(&self, ip: &str, username: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        for (key, _) in self.keys(ip, username) {
+            if let Some(entry) = failures.get_mut(&key) {
+                entry.count = entry.count.saturating_sub(1);
+                if entry.count == 0 {
+                    failures.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// A successful login clears the account's failures. The address keeps its own, a valid
+    /// account of the attacker mustn't reset them.
+    pub fn succeeded(&self, username: &str) {
+        self.failures.lock().unwrap().remove(&account_key(username));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: i64 = 1_700_000_000;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(Limits { free_attempts: 3, max_per_account: 5, max_per_ip: 8, lockout_secs: 900 })
+    }
+
+    /// A wrong password at `now`, once it is let through; true if it locked.
+    fn fail(throttle: &LoginThrottle, ip: &str, username: &str, now: i64) -> bool {
+        throttle.begin_at(ip, username, now).unwrap();
+        throttle.failed(ip, username)
+    }
+
+    #[test]
+    fn free_attempts_then_backoff() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            assert!(!fail(&throttle, "10.0.0.1", "alice", T));
+        }
+        assert_eq!(throttle.begin_at("10.0.0.1", "alice", T), Err(1));
+        assert!(!fail(&throttle, "10.0.0.1", "alice", T + 1));
+        assert_eq!(throttle.begin_at("10.0.0.1", "alice", T + 2), Err(1));
+        assert_eq!(throttle.begin_at("10.0.0.1", "alice", T + 3), Ok(()));
+    }
+
+    #[test]
+    fn locks_the_account_from_any_address() {
+        let throttle = throttle();
+        let locked: Vec<bool> = [T, T, T, T + 1, T + 3].iter().map(|now| fail(&throttle, "10.0.0.1", "alice", *now)).collect();
+        assert_eq!(locked, [false, false, false, false, true]);
+        assert_eq!(throttle.begin_at("10.0.0.2", "alice", T + 3), Err(900));
+        // The address has its own, higher limit
+        assert_eq!(throttle.begin_at("10.0.0.1", "bob", T + 3), Err(4));
+        assert_eq!(throttle.begin_at("10.0.0.2", "bob", T + 3), Ok(()));
+        // Until the lockout is over
+        assert_eq!(throttle.begin_at("10.0.0.2", "alice", T + 903), Ok(()));
+    }
+
+    #[test]
+    fn locks_an_address_trying_many_accounts() {
+        let throttle = throttle();
+        let mut now = T;
+        let locked: Vec<bool> = (0..8).map(|i| {
+            while let Err(wait) = throttle.begin_at("10.0.0.1", &format!("user{}", i), now) {
+                now += wait;
+            }
+            throttle.failed("10.0.0.1", &format!("user{}", i))
+        }).collect();
+        assert_eq!(locked.iter().filter(|l| **l).count(), 1);
+        assert!(locked[7]);
+        assert_eq!(throttle.begin_at("10.0.0.1", "someone", now), Err(900));
+        assert_eq!(throttle.begin_at("10.0.0.2", "user0", now), Ok(()));
+    }
+
+    #[test]
+    fn attempts_in_flight_count_against_the_limit() {
+        let throttle = throttle();
+        // Five requests at once, none has failed yet when the next arrives
+        let admitted = (0..5).filter(|_| throttle.begin_at("10.0.0.1", "alice", T).is_ok()).count();
+        assert_eq!(admitted, 3);
+        for _ in 0..admitted {
+            throttle.failed("10.0.0.1", "alice");
+        }
+        assert_eq!(throttle.begin_at("10.0.0.2", "alice", T), Err(1));
+    }
+
+    #[test]
+    fn a_right_password_is_taken_back() {
+        let throttle = throttle();
+        for _ in 0..2 {
+            fail(&throttle, "10.0.0.1", "alice", T);
+        }
+        // The password before the 2FA code, for someone else behind the same address
+        throttle.begin_at("10.0.0.1", "bob", T).unwrap();
+        throttle.passed("10.0.0.1", "bob");
+        assert_eq!(throttle.begin_at("10.0.0.1", "carol", T), Ok(()));
+        throttle.passed("10.0.0.1", "carol");
+        assert!(!throttle.failures.lock().unwrap().contains_key(&account_key("bob")));
+        assert_eq!(throttle.failures.lock().unwrap().get(&ip_key("10.0.0.1")).map(|f| f.count), Some(2));
+    }
+
+    #[test]
+    fn success_clears_the_account_but_not_the_address() {
+        let throttle = throttle();
+        for now in [T, T, T, T + 1] {
+            fail(&throttle, "10.0.0.1", "alice", now);
+        }
+        throttle.begin_at("10.0.0.1", "alice", T + 3).unwrap();
+        throttle.passed("10.0.0.1", "alice");
+        throttle.succeeded("alice");
+        assert_eq!(throttle.begin_at("10.0.0.2", "alice", T + 3), Ok(()));
+        assert_eq!(throttle.begin_at("10.0.0.1", "alice", T + 3), Err(2));
+    }
+}
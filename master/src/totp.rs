@@ -0,0 +1,195 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// RFC 6238 with the parameters every authenticator app supports: SHA-1, 6 digits, 30 seconds.
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from one step before or after are accepted too, for clocks that drift a little.
+const SKEW_STEPS: u64 = 1;
+
+/// How many recovery codes a user gets, each usable once.
+pub const RECOVERY_CODES: usize = 10;
+
+/// New shared secret, base32 as authenticator apps expect it.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// `otpauth://` URI for the QR code an authenticator app scans.
+pub fn provisioning_uri(issuer: &str, username: &str, secret: &str) -> String {
+    let label = format!("{}:{}", issuer, username);
+    let query = serde_urlencoded::to_string([
+        ("secret", secret),
+        ("issuer", issuer),
+        ("algorithm", "SHA1"),
+        ("digits", "6"),
+        ("period", "30"),
+    ])
+    .unwrap_or_default();
+    format!("otpauth://totp/{}?{}", crate::handlers::url_encode(&label), query)
+}
+
+/// The provisioning URI as an SVG QR code.
+pub fn qr_svg(uri: &str) -> Result<String, String> {
+    let code = qrcode::QrCode::new(uri.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(code.render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .quiet_zone(true)
+        .build())
+}
+
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation, RFC 4226 section 5.3
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// The time step `code` belongs to, if it is valid now and from a later step than
+/// `last_step` (so a code that was seen can't be used a second time).
+pub fn verify(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    verify_at(secret, code, last_step, now)
+}
+
+fn verify_at(secret: &str, code: &str, last_step: Option<i64>, unix_time: u64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let now = unix_time / STEP_SECS;
+    (now.saturating_sub(SKEW_STEPS)..=now + SKEW_STEPS)
+        .filter(|step| last_step.is_none_or(|last| *step as i64 > last))
+        .find(|step| code_at(&secret, *step) == code)
+        .map(|step| step as i64)
+}
+
+/// Fresh recovery codes, e.g. "K7QD-M2XA-PJ4T-V9RB" (80 bits each, stored as SHA-256 like join tokens).
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 10];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes);
+            code.as_bytes().chunks(4).map(|c| String::from_utf8_lossy(c).into_owned()).collect::<Vec<_>>().join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes as typed: case and dashes don't matter.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 6238 appendix B key for SHA-1, base32.
+    fn rfc_secret() -> String {
+        BASE32_NOPAD.encode(b"12345678901234567890")
+    }
+
+    fn step_now() -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() / STEP_SECS
+    }
+
+    fn code(secret: &str, step: u64) -> String {
+        format!("{:06}", code_at(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), step))
+    }
+
+    #[test]
+    fn rfc_6238_sha1_vectors() {
+        // The RFC lists 8 digits, these are their last 6
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, expected) in vectors {
+            assert_eq!(code(&rfc_secret(), time / STEP_SECS), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn codes_from_the_neighbouring_steps_are_accepted() {
+        let secret = rfc_secret();
+        // Late in step 100, then early in it
+        for time in [100 * STEP_SECS + 29, 100 * STEP_SECS] {
+            for step in [99, 100, 101] {
+                assert_eq!(verify_at(&secret, &code(&secret, step), None, time), Some(step as i64));
+            }
+            assert_eq!(verify_at(&secret, &code(&secret, 98), None, time), None);
+            assert_eq!(verify_at(&secret, &code(&secret, 102), None, time), None);
+        }
+        assert!(verify(&secret, &code(&secret, step_now()), None).is_some());
+    }
+
+    #[test]
+    fn malformed_codes_are_refused() {
+        let secret = generate_secret();
+        let valid = code(&secret, step_now());
+        assert!(verify(&secret, &format!(" {} {} ", &valid[..3], &valid[3..]), None).is_some());
+        for bad in ["", "12345", "1234567", "12345a", "-12345"] {
+            assert_eq!(verify(&secret, bad, None), None, "{:?}", bad);
+        }
+        assert_eq!(verify("not base32!", &valid, None), None);
+    }
+
+    #[test]
+    fn a_used_step_is_not_accepted_again() {
+        let secret = rfc_secret();
+        let time = 100 * STEP_SECS;
+        assert_eq!(verify_at(&secret, &code(&secret, 100), Some(100), time), None);
+        assert_eq!(verify_at(&secret, &code(&secret, 99), Some(100), time), None);
+        assert_eq!(verify_at(&secret, &code(&secret, 101), Some(100), time), Some(101));
+    }
+
+    #[tokio::test]
+    async fn steps_are_used_once_even_by_concurrent_logins() {
+        let db = crate::state::AppState::for_tests().await.db;
+        crate::db::create_user(&db, &crate::auth::User::for_tests("operator", "")).await.unwrap();
+        let (a, b) = tokio::join!(
+            crate::db::use_totp_step(&db, "operator-user", 100),
+            crate::db::use_totp_step(&db, "operator-user", 100),
+        );
+        assert!(a.unwrap() ^ b.unwrap());
+        assert!(!crate::db::use_totp_step(&db, "operator-user", 99).await.unwrap());
+        assert!(crate::db::use_totp_step(&db, "operator-user", 101).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn recovery_codes_are_single_use() {
+        let db = crate::state::AppState::for_tests().await.db;
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(codes.iter().collect::<std::collections::HashSet<_>>().len(), RECOVERY_CODES);
+        let hash = |code: &str| crate::enroll::hash_secret(&normalize_recovery_code(code));
+        let hashes: Vec<String> = codes.iter().map(|c| hash(c)).collect();
+        crate::db::replace_recovery_codes(&db, "alice", &hashes).await.unwrap();
+
+        // Typed in lower case without dashes
+        let typed = codes[0].replace('-', "").to_lowercase();
+        assert!(crate::db::use_recovery_code(&db, "alice", &hash(&typed)).await.unwrap());
+        assert!(!crate::db::use_recovery_code(&db, "alice", &hash(&codes[0])).await.unwrap());
+        assert!(!crate::db::use_recovery_code(&db, "bob", &hash(&codes[1])).await.unwrap());
+        assert_eq!(crate::db::count_recovery_codes(&db, "alice").await.unwrap(), RECOVERY_CODES as i64 - 1);
+
+        // New codes replace the old ones
+        crate::db::replace_recovery_codes(&db, "alice", &[hash("AAAA-BBBB")]).await.unwrap();
+        assert!(!crate::db::use_recovery_code(&db, "alice", &hash(&codes[1])).await.unwrap());
+        assert!(crate::db::use_recovery_code(&db, "alice", &hash("aaaabbbb")).await.unwrap());
+    }
+}
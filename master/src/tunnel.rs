@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::IntoResponse;
+use tokio::sync::{mpsc, oneshot, Notify};
+use port_sentinel_shared::{SystemStats, TunnelMessage};
+use crate::state::AppState;
+
+/// URL scheme of nodes whose agent dials in instead of being polled, e.g. `push://web-01`.
+pub const PUSH_SCHEME: &str = "push://";
+
+/// How long the agent has to introduce itself after connecting.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pushed stats older than this are treated as missing.
+const STATS_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// One connected push-mode agent.
+pub struct TunnelHandle {
+    tx: mpsc::Sender<TunnelMessage>,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<(u16, String)>>>,
+    last_stats: Mutex<Option<(Instant, SystemStats)>>,
+    closed: Notify,
+}
+
+impl TunnelHandle {
+    /// Sends an agent API call down the tunnel and waits for its answer.
+    /// `headers` are the request's signing headers, which the agent checks before running it.
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        user: Option<&str>,
+        headers: BTreeMap<String, String>,
+        timeout: Duration,
+    ) -> Result<(u16, String), String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let msg = TunnelMessage::Request { id, method: method.to_string(), path: path.to_string(), user: user.map(str::to_string), headers };
+        if self.tx.send(msg).await.is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err("Agent disconnected".to_string());
+        }
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(resp)) => Ok(resp),
+            Ok(Err(_)) => Err("Agent disconnected".to_string()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err("Agent did not answer in time".to_string())
+            }
+        }
+    }
+
+    /// The latest stats the agent pushed, if recent enough.
+    pub fn stats(&self) -> Option<SystemStats> {
+        self.last_stats.lock().unwrap().as_ref()
+            .filter(|(at, _)| at.elapsed() < STATS_MAX_AGE)
+            .map(|(_, s)| s.clone())
+    }
+}
+
+/// Connected push-mode agents, keyed by node URL (`push://<node-id>`).
+#[derive(Default)]
+pub struct Tunnels {
+    inner: RwLock<HashMap<String, Arc<TunnelHandle>>>,
+}
+
+impl Tunnels {
+    pub fn get(&self, node_url: &str) -> Option<Arc<TunnelHandle>> {
+        self.inner.read().unwrap().get(node_url).cloned()
+    }
+
+    /// Drops the node's connection, e.g. after its credential was revoked.
+    pub fn disconnect(&self, node_url: &str) {
+        if let Some(handle) = self.inner.write().unwrap().remove(node_url) {
+            handle.closed.notify_one();
+        }
+    }
+}
+
+/// `GET /api/agent/connect`: push-mode agents open their WebSocket here. Authentication is the
+/// node credential in the first message, so this route sits outside the dashboard login.
+pub async fn agent_connect(State(state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_agent(state, socket))
+}
+
+async fn handle_agent(state: AppState, mut socket: WebSocket) {
+    let hello = match tokio::time::timeout(HELLO_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<TunnelMessage>(&text).ok(),
+        _ => None,
+    };
+    let Some(TunnelMessage::Hello { node_id, token, hostname, version }) = hello else {
+        return;
+    };
+
+    let node = crate::db::get_node_by_url(&state.db, &format!("{}{}", PUSH_SCHEME, node_id)).await;
+    let node = match node {
+        Some(node) if crate::enroll::verify_node(&node, &token) => node,
+        _ => {
+            println!("🚫 Rejected push agent '{}' ({}): unknown node or wrong credential", node_id, hostname);
+            let reject = TunnelMessage::Rejected { reason: "Unknown node or invalid credential".to_string() };
+            let _ = socket.send(Message::Text(serde_json::to_string(&reject).unwrap_or_default())).await;
+            return;
+        }
+    };
+    if socket.send(Message::Text(serde_json::to_string(&TunnelMessage::Accepted).unwrap_or_default())).await.is_err() {
+        return;
+    }
+    println!("🔌 Push agent '{}' connected ({} v{})", node.name, hostname, version);
+
+    let (tx, mut rx) = mpsc::channel::<TunnelMessage>(64);
+    let handle = Arc::new(TunnelHandle {
+        tx,
+        next_id: AtomicU64::new(1),
+        pending: Mutex::new(HashMap::new()),
+        last_stats: Mutex::new(None),
+        closed: Notify::new(),
+    });
+    // A reconnecting agent replaces its previous connection
+    state.tunnels.inner.write().unwrap().insert(node.url.clone(), handle.clone());
+
+    let mut ping = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        tokio::select! {
+            outgoing = rx.recv() => {
+                let Some(msg) = outgoing else { break };
+                let text = serde_json::to_string(&msg).unwrap_or_default();
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                match serde_json::from_str::<TunnelMessage>(&text) {
+                    Ok(TunnelMessage::Response { id, status, body }) => {
+                        if let Some(waiter) = handle.pending.lock().unwrap().remove(&id) {
+                            let _ = waiter.send((status, body));
+                        }
+                    }
+                    Ok(TunnelMessage::Stats { stats }) => {
+                        *handle.last_stats.lock().unwrap() = Some((Instant::now(), stats));
+                    }
+                    Ok(TunnelMessage::Events { batch }) => {
+                        // Re-sent events after a reconnect are ignored by the unique index
+                        for event in &batch.events {
+                            if let Err(e) = crate::db::insert_container_event(&state.db, &node.id, event).await {
+                                println!("⚠️ Failed to store container event for {}: {}", node.name, e);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ = handle.closed.notified() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Only forget the tunnel if a newer connection hasn't taken its place
+    let mut tunnels = state.tunnels.inner.write().unwrap();
+    if tunnels.get(&node.url).is_some_and(|h| Arc::ptr_eq(h, &handle)) {
+        tunnels.remove(&node.url);
+    }
+    println!("🔌 Push agent '{}' disconnected", node.name);
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// HMAC signatures on master → agent requests
+pub mod signing;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProcessInfo {
@@ -6,6 +10,16 @@ pub struct ProcessInfo {
     pub name: String,
     pub port: String,
     pub raw_line: String,
+    /// Container that owns this socket, when the agent could resolve one
+    #[serde(default)]
+    pub container: Option<ContainerRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerRef {
+    pub id: String,
+    pub name: String,
+    pub image: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -18,4 +32,323 @@ pub struct SystemStats {
     pub disk_used_bytes: u64,
     pub cpu_usage: f32,
     pub cpu_cores_usage: Vec<f32>,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub image: String,
+    pub status: String,
+    pub names: String,
+    pub state: String, // running, exited, etc.
+    /// `com.docker.compose.project` / `.service` labels, for containers started by compose
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub service: Option<String>,
+    /// healthy, unhealthy or starting, when the image defines a healthcheck
+    #[serde(default)]
+    pub health: Option<String>,
+}
+
+/// One line of container output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerLogLine {
+    pub stream: String, // stdout or stderr
+    /// RFC 3339 time Docker recorded the line at, when requested
+    pub timestamp: Option<String>,
+    pub text: String,
+}
+
+/// A container state change from the Docker events stream.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerEvent {
+    /// Position in the agent's event buffer, increasing within one `stream_id`
+    pub seq: u64,
+    pub time_nano: i64,
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub action: String, // die, oom, restart, health_status, start, stop
+    /// Exit code for `die`, new status for `health_status`
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerEventBatch {
+    /// Changes whenever the agent restarts, so the master knows to reset its cursor
+    pub stream_id: String,
+    pub events: Vec<ContainerEvent>,
+}
+
+/// Messages exchanged over the push-mode WebSocket (`/api/agent/connect` on the master),
+/// one JSON object per text frame.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TunnelMessage {
+    /// First message from the agent; `node_id` matches a node registered as `push://<node_id>`
+    Hello { node_id: String, token: String, hostname: String, version: String },
+    Accepted,
+    Rejected { reason: String },
+    /// An agent API call from the master, answered with a `Response` carrying the same id.
+    /// `user` is the dashboard user it is made for, recorded in the agent's audit log.
+    /// `headers` carry the request's signature (see `signing`), checked as for HTTP calls.
+    Request {
+        id: u64,
+        method: String,
+        path: String,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    Response { id: u64, status: u16, body: String },
+    Stats { stats: SystemStats },
+    Events { batch: ContainerEventBatch },
+}
+
+/// Join tokens end in `.<SHA-256 of the master's CA certificate>`, so an agent enrolling over
+/// plain http can tell the master's CA from one swapped in on the way. None for tokens without it.
+pub fn join_token_ca_pin(join_token: &str) -> Option<&str> {
+    let (_, pin) = join_token.rsplit_once('.')?;
+    (pin.len() == 64 && pin.bytes().all(|b| b.is_ascii_hexdigit())).then_some(pin)
+}
+
+/// Sent by `port_sentinel_agent enroll` to the master's `/api/agent/enroll`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnrollRequest {
+    pub join_token: String,
+    pub hostname: String,
+    pub version: String,
+    /// PEM certificate request for the agent's TLS key, signed by the master's built-in CA
+    #[serde(default)]
+    pub csr: Option<String>,
+}
+
+/// The node the join token was issued for and its freshly issued credential, shown only once.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnrollResponse {
+    pub node_id: String,
+    pub node_url: String,
+    pub credential: String,
+    /// Agent TLS certificate, when a certificate request was sent
+    #[serde(default)]
+    pub certificate: Option<String>,
+    /// CA the master's client certificate chains to, for the agent to require it
+    #[serde(default)]
+    pub ca_certificate: Option<String>,
+}
+
+/// Version of the agent HTTP API served under `/api/v1`, bumped on incompatible changes.
+pub const API_VERSION: u32 = 1;
+
+/// One change made by an agent, a line of its audit log (`/api/v1/audit`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditRecord {
+    /// Unix time (seconds)
+    pub ts: i64,
+    /// Master the request came from: its name for signed requests, its URL over the push tunnel,
+    /// "token" for bare-token callers
+    pub master: String,
+    /// Dashboard user the master acted for
+    #[serde(default)]
+    pub user: Option<String>,
+    /// e.g. "kill", "service_restart", "docker_stop", "docker_prune"
+    pub action: String,
+    /// PID, service, container, image, volume or compose project
+    pub target: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// "ok", "denied" (action policy) or "failed"
+    pub outcome: String,
+    /// Answer of the action, or why it was refused
+    #[serde(default)]
+    pub message: String,
+}
+
+/// What an agent is and what it can do, from `/api/v1/info`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentInfo {
+    pub agent_version: String,
+    pub api_version: u32,
+    pub hostname: String,
+    pub os: String,
+    pub kernel: String,
+    /// Data the agent can report: "system", "processes", "logs", "services", "schedules", "docker", "docker_events"
+    pub collectors: Vec<String>,
+    /// Actions it supports: "kill", "log_read", "service_control", "docker_control", "docker_prune", "docker_recreate"
+    pub actions: Vec<String>,
+    /// Supported actions the node's action policy turns off
+    #[serde(default)]
+    pub denied: Vec<String>,
+    /// Services and containers (by name) control is limited to, if any
+    #[serde(default)]
+    pub allowed_services: Option<Vec<String>>,
+    #[serde(default)]
+    pub allowed_containers: Option<Vec<String>>,
+    pub runtime: RuntimeInfo,
+}
+
+impl AgentInfo {
+    pub fn collects(&self, collector: &str) -> bool {
+        self.collectors.iter().any(|c| c == collector)
+    }
+
+    pub fn supports(&self, action: &str) -> bool {
+        self.actions.iter().any(|a| a == action)
+    }
+
+    /// Whether the policy forbids `action`; prune and recreate fall under Docker control.
+    pub fn denies(&self, action: &str) -> bool {
+        let category = match action {
+            "docker_prune" | "docker_recreate" => "docker_control",
+            other => other,
+        };
+        self.denied.iter().any(|d| d == category)
+    }
+
+    /// Whether controlling the container named `names` (comma-separated) is forbidden.
+    pub fn denies_container(&self, names: &str) -> bool {
+        self.denies("docker_control")
+            || self.allowed_containers.as_ref().is_some_and(|allowed| !names.split(',').any(|n| allowed.iter().any(|a| a == n)))
+    }
+}
+
+/// The container runtime an agent detected at startup, from `/api/capabilities`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuntimeInfo {
+    pub runtime: String, // docker, podman
+    pub version: Option<String>,
+    pub mode: String, // engine-api (unix socket) or cli
+    pub endpoint: String, // socket path or CLI binary
+    /// Docker features this runtime supports, e.g. "logs_follow", "prune", "events"
+    pub features: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerStats {
+    pub id: String,
+    pub name: String,
+    pub cpu_percent: f64,
+    pub mem_usage_bytes: u64,
+    pub mem_limit_bytes: u64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+    pub block_read_bytes: u64,
+    pub block_write_bytes: u64,
+    pub pids: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerDetails {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub created: String,
+    pub state: String,
+    pub restart_policy: String,
+    pub entrypoint: Vec<String>,
+    pub command: Vec<String>,
+    pub env: Vec<(String, String)>, // Values matching the agent's redaction patterns are masked
+    pub labels: BTreeMap<String, String>,
+    pub ports: Vec<PortBinding>,
+    pub mounts: Vec<MountInfo>,
+    pub networks: Vec<NetworkAttachment>,
+    pub health: Option<HealthInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortBinding {
+    pub container_port: String, // e.g. "80/tcp"
+    pub host_ip: Option<String>,
+    pub host_port: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MountInfo {
+    pub kind: String, // bind, volume, tmpfs
+    pub name: Option<String>,
+    pub source: String,
+    pub destination: String,
+    pub read_write: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkAttachment {
+    pub name: String,
+    pub ip_address: String,
+    pub gateway: String,
+    pub mac_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthInfo {
+    pub status: String,
+    pub failing_streak: u64,
+    pub log: Vec<HealthProbe>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthProbe {
+    pub start: String,
+    pub exit_code: i64,
+    pub output: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageInfo {
+    pub id: String, // Hex digest without the "sha256:" prefix
+    pub tags: Vec<String>,
+    pub size_bytes: u64,
+    pub created: i64, // Unix seconds
+    pub containers: Vec<String>, // Names of containers using this image
+    pub dangling: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VolumeInfo {
+    pub name: String,
+    pub driver: String,
+    pub mountpoint: String,
+    pub size_bytes: Option<u64>, // Only known when the daemon reports disk usage
+    pub containers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkInfo {
+    pub id: String,
+    pub name: String,
+    pub driver: String,
+    pub scope: String,
+    pub containers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PruneReport {
+    pub kind: String, // images, volumes or containers
+    pub dry_run: bool,
+    pub items: Vec<PruneItem>,
+    pub reclaimable_bytes: u64, // Reclaimed bytes when dry_run is false
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PruneItem {
+    pub id: String,
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledJob {
+    pub kind: String, // "timer" or "cron"
+    pub name: String,
+    pub schedule: String,
+    pub command: String,
+    pub user: Option<String>,
+    pub source: String,
+    pub next_run: Option<i64>, // Unix seconds
+    pub last_run: Option<i64>,
+    pub last_result: Option<String>,
+    pub health: String, // ok, missed, failed, unknown
+}
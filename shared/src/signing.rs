@@ -0,0 +1,123 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Unix time (seconds) the master signed the request at.
+pub const HEADER_TIMESTAMP: &str = "X-Sentinel-Timestamp";
+/// Random value, never reused by the master, so a captured request can't be sent again.
+pub const HEADER_NONCE: &str = "X-Sentinel-Nonce";
+/// Hex HMAC-SHA256 of the request, keyed with the node credential.
+pub const HEADER_SIGNATURE: &str = "X-Sentinel-Signature";
+/// Name of the master making the request, for the agent's audit log.
+pub const HEADER_MASTER: &str = "X-Sentinel-Master";
+/// Dashboard user the master acts for, for the agent's audit log.
+pub const HEADER_USER: &str = "X-Sentinel-User";
+
+/// What a signature covers. `path` includes the query string, exactly as sent; `master` and
+/// `user` are signed too so the audit log can't be pinned on someone else.
+pub struct RequestParts<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+    pub timestamp: i64,
+    pub nonce: &'a str,
+    pub master: &'a str,
+    /// Empty when the request is not made for a dashboard user (e.g. background polling)
+    pub user: &'a str,
+}
+
+/// Signs a master → agent request.
+pub fn sign(key: &str, req: &RequestParts) -> String {
+    hex::encode(mac(key, req).finalize().into_bytes())
+}
+
+/// Checks a signature made by `sign`, in constant time.
+pub fn verify(key: &str, req: &RequestParts, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    mac(key, req).verify_slice(&signature).is_ok()
+}
+
+/// Compares secrets without leaking through timing how much of them matched.
+pub fn secrets_equal(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+fn mac(key: &str, req: &RequestParts) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length");
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        req.method.to_ascii_uppercase(),
+        req.path,
+        hex::encode(Sha256::digest(req.body)),
+        req.timestamp,
+        req.nonce,
+        req.master,
+        req.user
+    );
+    mac.update(canonical.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request<'a>() -> RequestParts<'a> {
+        RequestParts {
+            method: "POST",
+            path: "/api/v1/containers/web/stop?t=5",
+            body: b"{}",
+            timestamp: 1_700_000_000,
+            nonce: "a1b2c3",
+            master: "master-1",
+            user: "alice",
+        }
+    }
+
+    #[test]
+    fn signatures_verify_with_the_same_key() {
+        let signature = sign("key", &request());
+        assert_eq!(signature.len(), 64);
+        assert!(verify("key", &request(), &signature));
+        assert!(verify("key", &request(), &signature.to_uppercase()));
+        assert!(verify("key", &RequestParts { method: "post", ..request() }, &signature));
+        assert!(!verify("another key", &request(), &signature));
+    }
+
+    #[test]
+    fn every_signed_field_counts() {
+        let signature = sign("key", &request());
+        let tampered = [
+            RequestParts { method: "DELETE", ..request() },
+            RequestParts { path: "/api/v1/containers/db/stop?t=5", ..request() },
+            RequestParts { path: "/api/v1/containers/web/stop?t=0", ..request() },
+            RequestParts { body: b"{\"force\":true}", ..request() },
+            RequestParts { timestamp: 1_700_000_001, ..request() },
+            RequestParts { nonce: "a1b2c4", ..request() },
+            RequestParts { master: "master-2", ..request() },
+            RequestParts { user: "", ..request() },
+        ];
+        for req in &tampered {
+            assert!(!verify("key", req, &signature), "{} {} still verifies", req.method, req.path);
+        }
+    }
+
+    #[test]
+    fn malformed_signatures_are_refused() {
+        let signature = sign("key", &request());
+        for bad in ["", "zz", &signature[..62], &format!("{}00", signature)] {
+            assert!(!verify("key", &request(), bad));
+        }
+    }
+
+    #[test]
+    fn secrets_compare_whole() {
+        assert!(secrets_equal("s3cret", "s3cret"));
+        assert!(!secrets_equal("s3cret", "s3cre"));
+        assert!(!secrets_equal("s3cret", "s3cret "));
+        assert!(!secrets_equal("s3cret", "S3cret"));
+        assert!(secrets_equal("", ""));
+    }
+}